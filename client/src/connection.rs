@@ -0,0 +1,83 @@
+use crate::{
+    read_server::listen_for_server,
+    util::{
+        config::{RECONNECT_BACKOFF_MAX_MS, RECONNECT_BACKOFF_MIN_MS},
+        types::{SessionToken, TuiUpdate, WsStreamMsg},
+    },
+    write_server::write_to_server,
+};
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use shared::types::{Chunk, ClientServerAuthMsg, ClientServerMsg};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    select,
+    sync::{mpsc, watch},
+};
+use tokio_tungstenite::connect_async;
+
+/// keeps the connection to the server alive, resuming the session after every reconnect
+pub async fn maintain_connection(
+    server_addr: String,
+    tx_ws_tui: mpsc::Sender<TuiUpdate>,
+    tx_ws_stream: mpsc::Sender<WsStreamMsg>,
+    mut rx_tui_ws_msg: mpsc::Receiver<ClientServerMsg>,
+    mut rx_tui_ws_file: mpsc::Receiver<Chunk>,
+    mut rx_tui_ws_auth: mpsc::Receiver<ClientServerAuthMsg>,
+) -> Result<()> {
+    let token: SessionToken = Arc::new(Mutex::new(None));
+    let mut backoff = RECONNECT_BACKOFF_MIN_MS;
+
+    loop {
+        let ws = match connect_async(&server_addr).await {
+            Ok((ws, _)) => ws,
+            Err(_) => {
+                tx_ws_tui.send(TuiUpdate::Disconnected).await?;
+                tokio::time::sleep(Duration::from_millis(backoff)).await;
+                backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX_MS);
+                continue;
+            }
+        };
+
+        backoff = RECONNECT_BACKOFF_MIN_MS;
+        let (mut ws_write, mut ws_read) = ws.split();
+
+        // chunks meant for the lost connection, interrupted streams send them again once
+        // the server told them where to go on
+        while rx_tui_ws_file.try_recv().is_ok() {}
+
+        let resume_token = *token.lock().unwrap();
+        if let Some(t) = resume_token {
+            let serialized = bincode::serialize(&ClientServerAuthMsg::Resume(t))?;
+            if ws_write.send(serialized.into()).await.is_err() {
+                continue;
+            }
+        }
+
+        tx_ws_tui.send(TuiUpdate::Connected).await?;
+
+        let (tx_authenticated, mut rx_authenticated) = watch::channel(false);
+        select! {
+            _ = listen_for_server(
+                &mut ws_read,
+                &tx_ws_tui,
+                &tx_ws_stream,
+                &token,
+                &tx_authenticated,
+            ) => {},
+            _ = write_to_server(
+                &mut ws_write,
+                &mut rx_tui_ws_msg,
+                &mut rx_tui_ws_file,
+                &mut rx_tui_ws_auth,
+                &token,
+                &mut rx_authenticated,
+            ) => {},
+        }
+
+        tx_ws_tui.send(TuiUpdate::Disconnected).await?;
+    }
+}
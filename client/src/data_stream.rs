@@ -1,71 +1,351 @@
 use super::util::{
-    config::FILES_DIR,
-    types::{ActiveStream, WsStreamMsg},
+    config::{DOWNLOAD_DIR_ENV, FILES_DIR},
+    types::{ActiveStream, TransferDirection, TransferReport, TuiUpdate, WsStreamMsg},
 };
 use anyhow::Result;
-use shared::types::{Chunk, FileMetadata};
-use std::{collections::HashMap, io::Write, path::Path};
+use sha2::{Digest, Sha256};
+use shared::{
+    config::STREAM_PROGRESS_INTERVAL,
+    types::{
+        Chunk, ClientServerMsg, FileMetadata, ResumePoint, ResumeRequest, StreamProgress, User,
+    },
+};
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
 use uuid::Uuid;
 
+/// longest file name kept, in bytes
+const MAX_FILENAME_BYTES: usize = 200;
+
+/// every finished or failed stream is reported to the tui. files land in a folder of
+/// the logged in user, which is known once `WsStreamMsg::Session` arrived. the sender
+/// of a stream only goes on once it learns through `tx_stream_ws` what was written
 pub async fn handle_file_stream(
     mut rx_ws_stream: tokio::sync::mpsc::Receiver<WsStreamMsg>,
+    tx_stream_tui: tokio::sync::mpsc::Sender<TuiUpdate>,
+    tx_stream_ws: tokio::sync::mpsc::Sender<ClientServerMsg>,
 ) -> Result<()> {
     let mut data_streams = HashMap::<Uuid, ActiveStream>::new();
+    let mut download_dir: Option<PathBuf> = None;
+    let mut session_user: Option<User> = None;
 
     while let Some(msg) = rx_ws_stream.recv().await {
-        match msg {
-            WsStreamMsg::FileMetadata(data) => handle_file_metadata(data, &mut data_streams)?,
-            WsStreamMsg::FileChunk(chunk) => handle_file_chunk(chunk, &mut data_streams)?,
+        let report = match msg {
+            WsStreamMsg::Session(user) => {
+                download_dir = Some(user_download_dir(&user));
+                session_user = Some(user);
+                None
+            }
+            WsStreamMsg::FileMetadata(data, progress) => {
+                handle_file_metadata(data, progress, download_dir.as_deref(), &mut data_streams)
+            }
+            WsStreamMsg::FileChunk(chunk) => {
+                let stream_id = chunk.stream_id;
+                let report = handle_file_chunk(chunk, &mut data_streams);
+                let progress = data_streams
+                    .get(&stream_id)
+                    .zip(session_user.as_ref())
+                    .and_then(|(stream, user)| due_progress(stream_id, stream, user));
+                if let Some(progress) = progress {
+                    tx_stream_ws
+                        .send(ClientServerMsg::StreamProgress(progress))
+                        .await?;
+                }
+                report
+            }
+            // a stream that is gone already ended, its report is on the way
+            WsStreamMsg::Resume { stream_id, file_id } => {
+                if let Some(req) = resume_request(stream_id, file_id, &data_streams) {
+                    tx_stream_ws
+                        .send(ClientServerMsg::ResumeTransfer(req))
+                        .await?;
+                }
+                None
+            }
+            // the tui already told the user
+            WsStreamMsg::Cancel(stream_id) => {
+                if let Some(stream) = data_streams.remove(&stream_id) {
+                    std::fs::remove_file(&stream.temp_path).ok();
+                }
+                None
+            }
+        };
+        if let Some(report) = report {
+            tx_stream_tui.send(TuiUpdate::Transfer(report)).await?;
         }
     }
 
     Ok(())
 }
 
-fn handle_file_chunk(chunk: Chunk, data_streams: &mut HashMap<Uuid, ActiveStream>) -> Result<()> {
-    let stream = match data_streams.get_mut(&chunk.stream_id) {
-        Some(s) => s,
-        None => return Ok(()),
+/// `DOWNLOAD_DIR` or `FILES_DIR`, with a folder per username
+fn user_download_dir(user: &User) -> PathBuf {
+    let base = std::env::var(DOWNLOAD_DIR_ENV).unwrap_or(String::from(FILES_DIR));
+    Path::new(&base).join(sanitize_filename(&user.username))
+}
+
+/// only the last path component of what the sender named the file is kept, without
+/// characters that are special on common file systems and without leading dots
+pub fn sanitize_filename(name: &str) -> String {
+    let last = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = last
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*'))
+        .collect();
+    let cleaned = cleaned
+        .trim()
+        .trim_start_matches('.')
+        .trim_end_matches(['.', ' ']);
+
+    let mut end = cleaned.len().min(MAX_FILENAME_BYTES);
+    while !cleaned.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    match &cleaned[..end] {
+        "" => String::from("file"),
+        name => String::from(name),
+    }
+}
+
+/// `name (1).ext`, `name (2).ext` and so on for as long as the name is taken
+fn unique_path(dir: &Path, filename: &str) -> PathBuf {
+    let path = dir.join(filename);
+    if !path.exists() {
+        return path;
+    }
+
+    let (stem, ext) = match filename.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{ext}")),
+        _ => (filename, String::new()),
     };
-    let bytes_to_write = std::cmp::min(chunk.data.len(), (stream.size - stream.written) as usize);
 
-    stream
-        .file_handle
-        .write_all(&chunk.data[0..bytes_to_write])?;
-    stream.written += bytes_to_write as u64;
+    (1..)
+        .map(|n| dir.join(format!("{stem} ({n}){ext}")))
+        .find(|p| !p.exists())
+        .unwrap()
+}
 
-    let written = stream.written;
-    let size = stream.size;
+/// reported every `STREAM_PROGRESS_INTERVAL` written chunks
+fn due_progress(stream_id: Uuid, stream: &ActiveStream, user: &User) -> Option<StreamProgress> {
+    if !stream.next_seq.is_multiple_of(STREAM_PROGRESS_INTERVAL) {
+        return None;
+    }
+
+    Some(StreamProgress {
+        stream_id,
+        seq: stream.next_seq,
+        from: user.clone(),
+        to: stream.sender,
+    })
+}
+
+/// the partial file stays as it is, the server sends what comes after it
+fn resume_request(
+    stream_id: Uuid,
+    file_id: Uuid,
+    data_streams: &HashMap<Uuid, ActiveStream>,
+) -> Option<ResumeRequest> {
+    let stream = data_streams.get(&stream_id)?;
+    Some(ResumeRequest {
+        stream_id,
+        sha256: stream.sha256.clone(),
+        file_id: Some(file_id),
+        point: ResumePoint {
+            seq: stream.next_seq,
+            offset: stream.written,
+        },
+    })
+}
+
+fn handle_file_chunk(
+    chunk: Chunk,
+    data_streams: &mut HashMap<Uuid, ActiveStream>,
+) -> Option<TransferReport> {
+    let stream = data_streams.get_mut(&chunk.stream_id)?;
 
-    if written == size {
-        data_streams.remove(&chunk.stream_id).unwrap();
+    // a gap or a swap would silently corrupt the file, so the stream ends at the first one
+    let res = if chunk.seq != stream.next_seq {
+        Err(format!(
+            "chunk {} missing or out of order, got {}",
+            stream.next_seq, chunk.seq
+        ))
+    } else if chunk.offset != stream.written {
+        Err(format!(
+            "chunk {} starts at byte {} instead of {}",
+            chunk.seq, chunk.offset, stream.written
+        ))
+    } else if stream.written + chunk.data.len() as u64 > stream.size {
+        Err(format!("larger than the announced {} bytes", stream.size))
+    } else {
+        stream
+            .file_handle
+            .write_all(&chunk.data)
+            .map_err(|err| format!("could not be written, {err}"))
+    };
+    if res.is_ok() {
+        stream.hasher.update(&chunk.data);
+        stream.written += chunk.data.len() as u64;
+        stream.next_seq += 1;
+        stream.progress.store(stream.written, Ordering::Relaxed);
     }
 
-    Ok(())
+    if res.is_ok() && !chunk.last {
+        return None;
+    }
+
+    let stream = data_streams.remove(&chunk.stream_id)?;
+    let res = match res {
+        Err(err) => Err(err),
+        Ok(_) if stream.written != stream.size => Err(format!(
+            "incomplete, {} of {} bytes arrived",
+            stream.written, stream.size
+        )),
+        Ok(_) if format!("{:x}", stream.hasher.finalize()) != stream.sha256 => {
+            Err(String::from("checksum mismatch"))
+        }
+        Ok(_) => Ok(()),
+    };
+
+    // the name is only taken once the file is complete, a broken one is never moved in place
+    let res = res.and_then(|_| {
+        let path = unique_path(&stream.dir, &stream.file_name);
+        std::fs::rename(&stream.temp_path, &path)
+            .map(|_| path)
+            .map_err(|err| format!("could not be saved, {err}"))
+    });
+    if res.is_err() {
+        std::fs::remove_file(&stream.temp_path).ok();
+    }
+
+    Some(TransferReport {
+        stream_id: chunk.stream_id,
+        filename: stream.file_name,
+        channel: stream.from,
+        direction: TransferDirection::Received,
+        res: res.map(Some),
+    })
 }
 
+/// the file is written to a hidden temp file next to where it ends up
 pub fn handle_file_metadata(
     meta: FileMetadata,
+    progress: Arc<AtomicU64>,
+    download_dir: Option<&Path>,
     data_streams: &mut HashMap<Uuid, ActiveStream>,
-) -> Result<()> {
-    let path = String::from(FILES_DIR) + &meta.filename;
-    let path = Path::new(&path);
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent)?;
-    }
+) -> Option<TransferReport> {
+    let file_name = sanitize_filename(&meta.filename);
+    let mut report = TransferReport {
+        stream_id: meta.stream_id,
+        filename: file_name.clone(),
+        channel: meta.from.clone(),
+        direction: TransferDirection::Received,
+        res: Ok(None),
+    };
 
-    let file = std::fs::File::create(path)?;
-    let stream_id = meta.stream_id;
+    let dir = match download_dir {
+        Some(dir) => dir.to_path_buf(),
+        None => {
+            report.res = Err(String::from("arrived before login"));
+            return Some(report);
+        }
+    };
+    let temp_path = dir.join(format!(".{}.part", meta.stream_id));
+
+    let file = std::fs::create_dir_all(&dir).and_then(|_| std::fs::File::create(&temp_path));
+    let file = match file {
+        Ok(file) => file,
+        Err(err) => {
+            report.res = Err(format!("could not be created, {err}"));
+            return Some(report);
+        }
+    };
 
     let stream = ActiveStream {
         file_handle: file,
         size: meta.size,
         written: 0,
-        file_name: meta.filename,
+        file_name,
         from: meta.from,
+        hasher: Sha256::new(),
+        sha256: meta.sha256,
+        dir,
+        temp_path,
+        next_seq: 0,
+        progress,
+        sender: meta.sender.id,
     };
 
-    data_streams.insert(stream_id, stream);
+    data_streams.insert(meta.stream_id, stream);
 
-    Ok(())
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_FILENAME_BYTES, sanitize_filename, unique_path};
+
+    #[test]
+    fn only_the_last_component_of_a_path_is_kept() {
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("..\\x"), "x");
+        assert_eq!(sanitize_filename("..\\..\\windows\\win.ini"), "win.ini");
+        assert_eq!(sanitize_filename("/etc/shadow"), "shadow");
+        assert_eq!(sanitize_filename("C:\\Users\\me\\notes.txt"), "notes.txt");
+        assert_eq!(sanitize_filename("dir/"), "file");
+    }
+
+    #[test]
+    fn names_do_not_hide_or_point_elsewhere() {
+        assert_eq!(sanitize_filename(".bashrc"), "bashrc");
+        assert_eq!(sanitize_filename("...hidden.txt"), "hidden.txt");
+        assert_eq!(sanitize_filename("report.pdf. . "), "report.pdf");
+        assert_eq!(
+            sanitize_filename("a<b>c:d\"e|f?g*h\u{7}.txt"),
+            "abcdefgh.txt"
+        );
+        for name in ["", "   ", ".", "..", ". .", "../..", "\t\n"] {
+            assert_eq!(sanitize_filename(name), "file", "{name:?}");
+        }
+    }
+
+    #[test]
+    fn long_names_are_cut_on_a_char_boundary() {
+        let name = "é".repeat(MAX_FILENAME_BYTES);
+        let cut = sanitize_filename(&name);
+        assert!(cut.len() <= MAX_FILENAME_BYTES);
+        assert!(cut.chars().all(|c| c == 'é'));
+    }
+
+    #[test]
+    fn taken_names_get_a_number() {
+        let dir = tempfile::tempdir().unwrap();
+        let free = unique_path(dir.path(), "photo.png");
+        assert_eq!(free, dir.path().join("photo.png"));
+
+        for expected in ["photo.png", "photo (1).png", "photo (2).png"] {
+            let path = unique_path(dir.path(), "photo.png");
+            assert_eq!(path, dir.path().join(expected));
+            assert!(!path.exists());
+            std::fs::write(&path, b"x").unwrap();
+        }
+
+        std::fs::write(dir.path().join("notes"), b"x").unwrap();
+        assert_eq!(
+            unique_path(dir.path(), "notes"),
+            dir.path().join("notes (1)")
+        );
+        std::fs::write(dir.path().join("archive.tar.gz"), b"x").unwrap();
+        assert_eq!(
+            unique_path(dir.path(), "archive.tar.gz"),
+            dir.path().join("archive.tar (1).gz")
+        );
+    }
 }
@@ -1,3 +1,4 @@
+pub mod connection;
 pub mod data_stream;
 pub mod global_states;
 pub mod read_server;
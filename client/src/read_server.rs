@@ -1,53 +1,137 @@
-use crate::util::types::{TuiUpdate, WsRead, WsStreamMsg};
+use crate::util::types::{SessionToken, TuiUpdate, WsRead, WsStreamMsg};
 use anyhow::Result;
 use futures::StreamExt;
 use shared::types::ServerClientMsg;
+use tokio::sync::watch;
 use tokio_tungstenite::tungstenite::Message;
 
+/// returns once the connection is closed, `authenticated` opens the way for everything the
+/// tui queued once the server accepted the session
 pub async fn listen_for_server(
-    mut ws: WsRead,
-    tx_wss_tui: tokio::sync::mpsc::Sender<TuiUpdate>,
-    tx_wss_stream: tokio::sync::mpsc::Sender<WsStreamMsg>,
+    ws: &mut WsRead,
+    tx_wss_tui: &tokio::sync::mpsc::Sender<TuiUpdate>,
+    tx_wss_stream: &tokio::sync::mpsc::Sender<WsStreamMsg>,
+    token: &SessionToken,
+    authenticated: &watch::Sender<bool>,
 ) -> Result<()> {
     loop {
-        if let Some(Ok(ws_msg)) = ws.next().await {
-            let server_msg: ServerClientMsg = match ws_msg {
-                Message::Binary(data) => bincode::deserialize(&data)?,
-                _ => unreachable!("unimplemented handler for web socket message"),
-            };
-            match server_msg {
-                ServerClientMsg::FileMetadata(data) => {
-                    tx_wss_stream.send(WsStreamMsg::FileMetadata(data)).await?
-                }
-                ServerClientMsg::FileChunk(chunk) => {
-                    tx_wss_stream.send(WsStreamMsg::FileChunk(chunk)).await?
-                }
-                ServerClientMsg::UserJoinedRoom(update) => {
-                    tx_wss_tui.send(TuiUpdate::UserJoinedRoom(update)).await?
-                }
-                ServerClientMsg::Text(msg) => tx_wss_tui.send(TuiUpdate::Text(msg)).await?,
-                ServerClientMsg::Init(data) => tx_wss_tui.send(TuiUpdate::Init(data)).await?,
-                ServerClientMsg::UserLeftRoom(update) => {
-                    tx_wss_tui.send(TuiUpdate::UserLeftRoom(update)).await?
-                }
-                ServerClientMsg::Auth(auth) => tx_wss_tui.send(TuiUpdate::Auth(auth)).await?,
-                ServerClientMsg::Register(res) => {
-                    tx_wss_tui.send(TuiUpdate::RegisterResponse(res)).await?
-                }
-                ServerClientMsg::UserConnected(user) => {
-                    tx_wss_tui.send(TuiUpdate::UserConnected(user)).await?
-                }
-                ServerClientMsg::UserDisconnected(user) => {
-                    tx_wss_tui.send(TuiUpdate::UserDisconnected(user)).await?
-                }
-                ServerClientMsg::CreateRoomResponse(res) => {
-                    tx_wss_tui.send(TuiUpdate::JoinRoom(res)).await?
-                }
-                ServerClientMsg::JoinRoomResponse(res) => {
-                    tx_wss_tui.send(TuiUpdate::JoinRoom(res)).await?
+        let ws_msg = match ws.next().await {
+            Some(Ok(ws_msg)) => ws_msg,
+            _ => return Ok(()),
+        };
+
+        let server_msg: ServerClientMsg = match ws_msg {
+            Message::Binary(data) => bincode::deserialize(&data)?,
+            Message::Close(_) => return Ok(()),
+            _ => continue,
+        };
+        match server_msg {
+            // the stream only starts once the user accepted the offer
+            ServerClientMsg::FileMetadata(data) => {
+                tx_wss_tui.send(TuiUpdate::FileOffer(data)).await?
+            }
+            ServerClientMsg::FileChunk(chunk) => {
+                tx_wss_stream.send(WsStreamMsg::FileChunk(chunk)).await?
+            }
+            ServerClientMsg::UserJoinedRoom(update) => {
+                tx_wss_tui.send(TuiUpdate::UserJoinedRoom(update)).await?
+            }
+            ServerClientMsg::Text(msg) => tx_wss_tui.send(TuiUpdate::Text(msg)).await?,
+            ServerClientMsg::Init(data) => tx_wss_tui.send(TuiUpdate::Init(data)).await?,
+            ServerClientMsg::UserLeftRoom(update) => {
+                tx_wss_tui.send(TuiUpdate::UserLeftRoom(update)).await?
+            }
+            ServerClientMsg::Auth(res) => {
+                *token.lock().unwrap() = res.as_ref().ok().map(|s| s.token);
+                authenticated.send_replace(res.is_ok());
+                if let Ok(session) = &res {
+                    let msg = WsStreamMsg::Session(session.user.clone());
+                    tx_wss_stream.send(msg).await?;
                 }
-                ServerClientMsg::ASCII(img) => tx_wss_tui.send(TuiUpdate::Img(img)).await?,
-            };
-        }
+                let auth = res.map(|s| s.user);
+                tx_wss_tui.send(TuiUpdate::Auth(auth)).await?
+            }
+            ServerClientMsg::Register(res) => {
+                tx_wss_tui.send(TuiUpdate::RegisterResponse(res)).await?
+            }
+            ServerClientMsg::UserConnected(user) => {
+                tx_wss_tui.send(TuiUpdate::UserConnected(user)).await?
+            }
+            ServerClientMsg::UserDisconnected(user) => {
+                tx_wss_tui.send(TuiUpdate::UserDisconnected(user)).await?
+            }
+            ServerClientMsg::CreateRoomResponse(res) => {
+                tx_wss_tui.send(TuiUpdate::JoinRoom(res)).await?
+            }
+            ServerClientMsg::JoinRoomResponse(res) => {
+                tx_wss_tui.send(TuiUpdate::JoinRoom(res)).await?
+            }
+            ServerClientMsg::ASCII(img) => tx_wss_tui.send(TuiUpdate::Img(img)).await?,
+            ServerClientMsg::History(page) => tx_wss_tui.send(TuiUpdate::History(page)).await?,
+            ServerClientMsg::MsgRefused(refusal) => {
+                tx_wss_tui.send(TuiUpdate::MsgRefused(refusal)).await?
+            }
+            ServerClientMsg::LeaveRoomResponse(res) => {
+                tx_wss_tui.send(TuiUpdate::RoomRemoval(res)).await?
+            }
+            ServerClientMsg::DeleteRoomResponse(res) => {
+                tx_wss_tui.send(TuiUpdate::RoomRemoval(res)).await?
+            }
+            ServerClientMsg::RoomDeleted(id) => tx_wss_tui.send(TuiUpdate::RoomDeleted(id)).await?,
+            ServerClientMsg::SetRoomPasswordResponse(res) => {
+                tx_wss_tui.send(TuiUpdate::RoomPassword(res)).await?
+            }
+            ServerClientMsg::ModerationResponse(res) => {
+                tx_wss_tui.send(TuiUpdate::Moderation(res)).await?
+            }
+            ServerClientMsg::RoomModerated(notice) => {
+                tx_wss_tui.send(TuiUpdate::RoomModerated(notice)).await?
+            }
+            ServerClientMsg::MessageEdited(msg) => {
+                tx_wss_tui.send(TuiUpdate::MessageEdited(msg)).await?
+            }
+            ServerClientMsg::MessageDeleted(deletion) => {
+                tx_wss_tui.send(TuiUpdate::MessageDeleted(deletion)).await?
+            }
+            ServerClientMsg::MsgUpdateResponse(res) => {
+                tx_wss_tui.send(TuiUpdate::MsgUpdate(res)).await?
+            }
+            ServerClientMsg::Ack(ack) => tx_wss_tui.send(TuiUpdate::Ack(ack)).await?,
+            ServerClientMsg::Delivered(notice) => {
+                tx_wss_tui.send(TuiUpdate::Delivered(notice)).await?
+            }
+            ServerClientMsg::Thread(res) => tx_wss_tui.send(TuiUpdate::Thread(res)).await?,
+            ServerClientMsg::Seen(receipt) => tx_wss_tui.send(TuiUpdate::Seen(receipt)).await?,
+            ServerClientMsg::Typing(notice) => tx_wss_tui.send(TuiUpdate::Typing(notice)).await?,
+            ServerClientMsg::PresenceChanged(presence) => {
+                tx_wss_tui
+                    .send(TuiUpdate::PresenceChanged(presence))
+                    .await?
+            }
+            ServerClientMsg::ChunkSize(size) => tx_wss_tui.send(TuiUpdate::ChunkSize(size)).await?,
+            ServerClientMsg::TransferControl(control) => {
+                tx_wss_tui.send(TuiUpdate::TransferControl(control)).await?
+            }
+            ServerClientMsg::StreamCredit(credit) => {
+                tx_wss_tui.send(TuiUpdate::StreamCredit(credit)).await?
+            }
+            ServerClientMsg::StreamFailed(failure) => {
+                tx_wss_tui.send(TuiUpdate::StreamFailed(failure)).await?
+            }
+            ServerClientMsg::Resync(channel) => tx_wss_tui.send(TuiUpdate::Resync(channel)).await?,
+            ServerClientMsg::Files(list) => tx_wss_tui.send(TuiUpdate::Files(list)).await?,
+            // the download was handed to the file stream when it was requested
+            ServerClientMsg::FileResponse(res) => {
+                tx_wss_tui.send(TuiUpdate::FileResponse(res)).await?
+            }
+            ServerClientMsg::TransferResumed(res) => {
+                tx_wss_tui.send(TuiUpdate::TransferResumed(res)).await?
+            }
+            ServerClientMsg::ReactionsChanged(reactions) => {
+                tx_wss_tui
+                    .send(TuiUpdate::ReactionsChanged(reactions))
+                    .await?
+            }
+        };
     }
 }
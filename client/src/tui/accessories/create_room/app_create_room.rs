@@ -4,7 +4,7 @@ use crate::{
 };
 use anyhow::Result;
 use ratatui::crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
-use shared::types::{ClientServerMsg, RoomUpdateTransit};
+use shared::types::{ClientServerMsg, RoomPasswordUpdate, RoomUpdateTransit};
 
 impl App {
     pub async fn handle_create_room_event(&mut self, event: Event) -> Result<()> {
@@ -63,14 +63,27 @@ impl App {
             Some(room_password)
         };
 
-        let transit = RoomUpdateTransit {
-            room_name,
-            room_password,
-        };
-
         let msg = match self.room_creator.active_action {
-            RoomAction::Create => ClientServerMsg::CreateRoom(transit),
-            RoomAction::Join => ClientServerMsg::JoinRoom(transit),
+            RoomAction::Create => ClientServerMsg::CreateRoom(RoomUpdateTransit {
+                room_name,
+                room_password,
+            }),
+            RoomAction::Join => ClientServerMsg::JoinRoom(RoomUpdateTransit {
+                room_name,
+                room_password,
+            }),
+            // the server knows rooms by id, names can change
+            RoomAction::Password => {
+                let Some(room) = self.room_channels.iter().find(|r| r.name == room_name) else {
+                    let msg = format!("You are not in a room named {room_name}.");
+                    self.room_creator.notification = Some(msg);
+                    return Ok(());
+                };
+                ClientServerMsg::SetRoomPassword(RoomPasswordUpdate {
+                    room_id: room.id,
+                    room_password,
+                })
+            }
         };
         self.tx_tui_ws_msg.send(msg).await.ok();
         Ok(())
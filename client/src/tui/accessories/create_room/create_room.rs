@@ -31,7 +31,8 @@ impl RoomCreator {
     pub fn switch_action(&mut self) {
         self.active_action = match self.active_action {
             RoomAction::Create => RoomAction::Join,
-            RoomAction::Join => RoomAction::Create,
+            RoomAction::Join => RoomAction::Password,
+            RoomAction::Password => RoomAction::Create,
         }
     }
 }
@@ -62,11 +62,17 @@ impl Widget for &mut RoomCreator {
         let title_text = match self.active_action {
             RoomAction::Create => " Create room ",
             RoomAction::Join => " Join room ",
+            RoomAction::Password => " Change room password ",
         };
 
         let span_outer_title = Span::styled(title_text, style_outer_title).bold();
         let span_name_title = Span::styled(" Room name ", style_input_title.clone()).bold();
-        let span_password_title = Span::styled(" Room password ", style_input_title.clone()).bold();
+        let password_title_text = match self.active_action {
+            RoomAction::Password => " New room password, empty removes it ",
+            _ => " Room password ",
+        };
+        let span_password_title =
+            Span::styled(password_title_text, style_input_title.clone()).bold();
 
         Block::bordered()
             .style(style_bg)
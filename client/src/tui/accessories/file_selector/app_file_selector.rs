@@ -1,15 +1,35 @@
 use crate::{
     tui::app::app::App,
-    util::types::{ChannelKind, FileAction, SelectorEntryKind},
+    util::{
+        ascii_art::ascii_art,
+        graphics::image_png,
+        types::{
+            ChannelKind, FileAction, SelectorEntryKind, Transfer, TransferDirection,
+            TransferReport, TransferState,
+        },
+    },
 };
 use anyhow::Result;
-use image::imageops::FilterType;
 use ratatui::crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
-use shared::{
-    config::TCP_CHUNK_BUFFER_SIZE,
-    types::{Channel, Chunk, ClientServerMsg, FileMetadata, ImgRender, User},
+use sha2::{Digest, Sha256};
+use shared::types::{
+    Channel, Chunk, ClientServerMsg, FileMetadata, FileTypePolicy, ImgRender, ResumePoint,
+    ResumeRequest, User,
+};
+use std::{
+    io::SeekFrom,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+    select,
+    sync::watch,
 };
-use std::{io::Read, os::linux::fs::MetadataExt, path::PathBuf};
 use uuid::Uuid;
 
 impl App {
@@ -94,20 +114,41 @@ impl App {
                         .unwrap(),
                     selected.name
                 );
+                let name = selected.name.clone();
+                let options = self.ascii_options.clone();
+                let cols = options.width.unwrap_or(self.messages_width);
                 let tx_tui_ws_msg = self.tx_tui_ws_msg.clone();
+                let tx_notice_tui = self.tx_notice_tui.clone();
 
                 tokio::spawn(async move {
-                    let image = image::open(path).expect("Failed to open image");
-                    let resized = image.resize_exact(50, 70, FilterType::Nearest);
-                    let conf = artem::config::ConfigBuilder::new().color(false).build();
-                    let ascii = artem::convert(resized, &conf);
-                    let img_render = ImgRender {
-                        cache: ascii,
-                        from,
-                        to,
-                    };
-                    let msg = ClientServerMsg::ASCII(img_render);
-                    tx_tui_ws_msg.send(msg).await.unwrap();
+                    // decoding and scaling large pictures takes a while
+                    let rendered = tokio::task::spawn_blocking(move || {
+                        let image = image::open(path).map_err(|err| err.to_string())?;
+                        Ok((ascii_art(&image, cols, &options), image_png(&image)))
+                    })
+                    .await
+                    .map_err(|err| err.to_string())
+                    .and_then(|res: Result<_, String>| res);
+
+                    match rendered {
+                        Ok((cache, image)) => {
+                            let img_render = ImgRender {
+                                id: Uuid::new_v4(),
+                                cache,
+                                image,
+                                from,
+                                to,
+                                sent_at: 0,
+                                reactions: vec![],
+                            };
+                            let msg = ClientServerMsg::ASCII(img_render);
+                            tx_tui_ws_msg.send(msg).await.ok();
+                        }
+                        Err(reason) => {
+                            let notice = format!("image {name} could not be read: {reason}");
+                            tx_notice_tui.send((to, notice)).await.ok();
+                        }
+                    }
                 });
             }
 
@@ -123,14 +164,31 @@ impl App {
         Ok(())
     }
 
+    /// the file is read twice, once for its hash that goes out with the metadata
+    /// and once more for the chunks. those only go out once the offer was accepted,
+    /// and no further than the server gave credit for. after an interruption the
+    /// chunks go on from where the server got
     async fn send_file(&mut self, path: PathBuf) {
         let id_to = match self.active_channel.id {
             None => return,
             Some(id) => id,
         };
 
+        let filename = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => return,
+        };
+        let mime = FileTypePolicy::mime_of(&filename);
+        if !self.file_policy.permits(&mime) {
+            let notice = format!("file {filename} not sent: files of type {mime} are not allowed");
+            self.push_active_notice(notice);
+            return;
+        }
+
         let tx_tui_ws_msg = self.tx_tui_ws_msg.clone();
         let tx_tui_ws_file = self.tx_tui_ws_file.clone();
+        let tx_transfer_tui = self.tx_transfer_tui.clone();
+        let chunk_size = self.chunk_size;
 
         let id_from = self.id;
         let username = self.username.clone();
@@ -145,50 +203,224 @@ impl App {
             ChannelKind::Room => Channel::Room(id_to),
         };
 
+        let stream_id = Uuid::new_v4();
+        let (tx_state, mut rx_state) = watch::channel(TransferState::Offered);
+        let (tx_credit, mut rx_credit) = watch::channel(0);
+        let (tx_resume, mut rx_resume) = watch::channel(None);
+        let progress = Arc::new(AtomicU64::new(0));
+        self.transfers.push(Transfer {
+            stream_id,
+            filename: filename.clone(),
+            size: std::fs::metadata(&path)
+                .map(|m| m.len())
+                .unwrap_or_default(),
+            channel: to.clone(),
+            peer: User {
+                id: id_from,
+                username: username.clone(),
+            },
+            direction: TransferDirection::Sent,
+            state: TransferState::Offered,
+            offer: None,
+            tx_state: Some(tx_state),
+            tx_credit: Some(tx_credit),
+            tx_resume: Some(tx_resume),
+            file_id: None,
+            progress: progress.clone(),
+            started_at: None,
+            ended_at: None,
+            path: Some(path.clone()),
+            reason: None,
+        });
+
         tokio::spawn(async move {
-            let mut file = match std::fs::File::open(&path) {
-                Ok(f) => f,
-                Err(_) => return,
+            let mut report = TransferReport {
+                stream_id,
+                filename: filename.clone(),
+                channel: to.clone(),
+                direction: TransferDirection::Sent,
+                res: Ok(None),
             };
 
-            let meta = match file.metadata() {
-                Ok(meta) => meta,
-                Err(_) => return,
+            // reading the whole file takes a while, it stays off the runtime
+            let hash_path = path.clone();
+            let hashed = tokio::task::spawn_blocking(move || hash_file(&hash_path))
+                .await
+                .map_err(|err| err.to_string())
+                .and_then(|res| res.map_err(|err| err.to_string()));
+            let (size, sha256) = match hashed {
+                Ok(hashed) => hashed,
+                Err(err) => {
+                    report.res = Err(format!("could not be read, {err}"));
+                    tx_transfer_tui.send(report).await.ok();
+                    return;
+                }
+            };
+
+            let mut file = match File::open(&path).await {
+                Ok(f) => f,
+                Err(err) => {
+                    report.res = Err(format!("could not be read, {err}"));
+                    tx_transfer_tui.send(report).await.ok();
+                    return;
+                }
             };
 
-            let stream_id = Uuid::new_v4();
-            let mut buffer = [0u8; TCP_CHUNK_BUFFER_SIZE];
+            let mut buffer = vec![0u8; chunk_size];
 
             let meta = FileMetadata {
-                filename: String::from(path.file_name().unwrap().to_str().unwrap()),
+                filename,
                 stream_id,
                 to: to.clone(),
-                size: meta.st_size(),
+                size,
                 from,
+                sender: User {
+                    id: id_from,
+                    username: username.clone(),
+                },
+                mime,
+                sha256: sha256.clone(),
             };
 
             let metadata = ClientServerMsg::FileMetadata(meta);
             tx_tui_ws_msg.send(metadata).await.ok();
 
+            // the app reports a declined or cancelled transfer itself
+            if !wait_while(&mut rx_state, TransferState::Offered).await {
+                return;
+            }
+
+            // an empty file still gets its last chunk, the receiver only finishes on that one
+            let mut sent = 0;
+            let mut seq = 0;
             loop {
-                let n = match file.read(&mut buffer) {
-                    Ok(f) => f,
+                let state = match rx_state.wait_for(|s| *s != TransferState::Paused).await {
+                    Ok(state) => *state,
                     Err(_) => return,
                 };
-                if n == 0 {
-                    break;
+                match state {
+                    TransferState::Running => {}
+                    TransferState::Interrupted => {
+                        let req = ResumeRequest {
+                            stream_id,
+                            sha256: sha256.clone(),
+                            file_id: None,
+                            point: ResumePoint { seq, offset: sent },
+                        };
+                        let point =
+                            match resume_point(&mut rx_state, &mut rx_resume, &tx_tui_ws_msg, req)
+                                .await
+                            {
+                                Some(point) => point,
+                                None => return,
+                            };
+                        if let Err(err) = file.seek(SeekFrom::Start(point.offset)).await {
+                            report.res = Err(format!("could not be read, {err}"));
+                            break;
+                        }
+                        seq = point.seq;
+                        sent = point.offset;
+                        progress.store(sent, Ordering::Relaxed);
+                        continue;
+                    }
+                    _ => return,
+                }
+                // the state changed while waiting, it is looked at again
+                if !wait_for_credit(&mut rx_credit, &mut rx_state, seq).await {
+                    continue;
                 }
+                let n = match file.read(&mut buffer).await {
+                    Ok(f) => f,
+                    Err(err) => {
+                        report.res = Err(format!("could not be read, {err}"));
+                        break;
+                    }
+                };
+                let last = n == 0 || sent + n as u64 >= size;
                 let chunk = Chunk {
-                    data: buffer.clone(),
+                    data: buffer[..n].to_vec(),
                     from: User {
                         id: id_from,
                         username: username.clone(),
                     },
                     to: to.clone(),
                     stream_id,
+                    seq,
+                    offset: sent,
+                    last,
                 };
                 tx_tui_ws_file.send(chunk).await.ok();
+                sent += n as u64;
+                progress.store(sent, Ordering::Relaxed);
+                seq += 1;
+                if last {
+                    break;
+                }
             }
+
+            tx_transfer_tui.send(report).await.ok();
         });
     }
 }
+
+/// false once the transfer was cancelled or the app dropped it
+async fn wait_while(rx_state: &mut watch::Receiver<TransferState>, state: TransferState) -> bool {
+    match rx_state.wait_for(|s| *s != state).await {
+        Ok(s) => *s == TransferState::Running,
+        Err(_) => false,
+    }
+}
+
+/// false once the transfer stopped running before the receivers had room for chunk `seq`
+async fn wait_for_credit(
+    rx_credit: &mut watch::Receiver<u64>,
+    rx_state: &mut watch::Receiver<TransferState>,
+    seq: u64,
+) -> bool {
+    select! {
+        res = rx_credit.wait_for(|credit| *credit > seq) => res.is_ok(),
+        _ = rx_state.wait_for(|s| !matches!(s, TransferState::Running | TransferState::Paused)) => false,
+    }
+}
+
+/// asks the server where to go on once the session is back, `None` once the transfer ended
+async fn resume_point(
+    rx_state: &mut watch::Receiver<TransferState>,
+    rx_resume: &mut watch::Receiver<Option<ResumePoint>>,
+    tx_tui_ws_msg: &tokio::sync::mpsc::Sender<ClientServerMsg>,
+    req: ResumeRequest,
+) -> Option<ResumePoint> {
+    loop {
+        let state = rx_state
+            .wait_for(|s| *s != TransferState::Interrupted)
+            .await
+            .ok()
+            .map(|s| *s)?;
+        if !matches!(state, TransferState::Running | TransferState::Paused) {
+            return None;
+        }
+
+        rx_resume.borrow_and_update();
+        let msg = ClientServerMsg::ResumeTransfer(req.clone());
+        tx_tui_ws_msg.send(msg).await.ok()?;
+
+        // the connection may drop again before the answer arrives
+        let state = select! {
+            res = rx_resume.changed() => return res.ok().and_then(|_| *rx_resume.borrow_and_update()),
+            state = rx_state.wait_for(|s| !matches!(s, TransferState::Running | TransferState::Paused)) => {
+                state.ok().map(|s| *s)?
+            }
+        };
+        if state != TransferState::Interrupted {
+            return None;
+        }
+    }
+}
+
+/// size and hex encoded SHA-256 of the file
+fn hash_file(path: &PathBuf) -> std::io::Result<(u64, String)> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let size = std::io::copy(&mut file, &mut hasher)?;
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
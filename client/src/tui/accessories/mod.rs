@@ -1,2 +1,3 @@
 pub mod create_room;
 pub mod file_selector;
+pub mod transfers;
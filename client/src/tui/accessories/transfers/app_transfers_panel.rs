@@ -0,0 +1,153 @@
+use crate::{
+    tui::app::{
+        app::App,
+        app_transfers::{can_apply, is_active},
+    },
+    util::types::TransferState,
+};
+use anyhow::Result;
+use crossterm::{clipboard::CopyToClipboard, execute};
+use ratatui::crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+use shared::types::TransferAction;
+use std::{
+    io::stdout,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// shows `dir` in the file manager of the desktop
+fn open_folder(dir: &Path) -> std::io::Result<()> {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(target_os = "windows") {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+
+    let mut child = Command::new(opener)
+        .arg(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // the opener may outlive the panel, it is waited for so it does not linger as a zombie
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+impl App {
+    pub async fn handle_transfers_panel_event(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
+                match key_event.code {
+                    KeyCode::Char('c') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        self.exit()
+                    }
+                    KeyCode::Char('p') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        self.display_transfers = false
+                    }
+                    KeyCode::Char('P') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        self.display_transfers = false
+                    }
+                    KeyCode::Esc => self.display_transfers = false,
+                    KeyCode::Up => self.transfers_panel.move_up(),
+                    KeyCode::Down => self.transfers_panel.move_down(self.transfers.len()),
+                    KeyCode::Char('a') => self.act_on_selected(TransferAction::Accept).await?,
+                    KeyCode::Char('d') => self.act_on_selected(TransferAction::Decline).await?,
+                    KeyCode::Char('p') => self.toggle_selected_pause().await?,
+                    KeyCode::Char('x') => self.act_on_selected(TransferAction::Cancel).await?,
+                    KeyCode::Char('o') => self.open_selected_folder(),
+                    KeyCode::Char('y') => self.copy_selected_folder(),
+                    _ => {}
+                };
+            }
+            _ => {}
+        };
+        Ok(())
+    }
+
+    /// index into `transfers` of the selected entry, the panel lists the newest first
+    pub fn selected_transfer_index(&self) -> Option<usize> {
+        let len = self.transfers.len();
+        let selected = self.transfers_panel.selected_index;
+        (selected < len).then(|| len - 1 - selected)
+    }
+
+    async fn act_on_selected(&mut self, action: TransferAction) -> Result<()> {
+        let index = match self.selected_transfer_index() {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+
+        if !can_apply(&self.transfers[index], action) {
+            let verb = match action {
+                TransferAction::Accept => "accepted",
+                TransferAction::Decline => "declined",
+                TransferAction::Pause => "paused",
+                TransferAction::Resume => "resumed",
+                TransferAction::Cancel => "cancelled",
+            };
+            self.transfers_panel.notification = Some(format!("This transfer can not be {verb}"));
+            return Ok(());
+        }
+
+        self.transfers_panel.notification = None;
+        self.apply_transfer_action(index, action).await
+    }
+
+    async fn toggle_selected_pause(&mut self) -> Result<()> {
+        let paused = self
+            .selected_transfer_index()
+            .is_some_and(|index| self.transfers[index].state == TransferState::Paused);
+        match paused {
+            true => self.act_on_selected(TransferAction::Resume).await,
+            false => self.act_on_selected(TransferAction::Pause).await,
+        }
+    }
+
+    /// folder holding the file of the selected transfer, once it completed
+    fn selected_folder(&mut self) -> Option<PathBuf> {
+        let transfer = &self.transfers[self.selected_transfer_index()?];
+        let folder = match &transfer.path {
+            Some(path) if transfer.state == TransferState::Done => path.parent(),
+            _ => None,
+        };
+
+        if folder.is_none() {
+            let notice = match is_active(transfer) {
+                true => "The transfer has not completed yet",
+                false => "Only completed transfers have a folder",
+            };
+            self.transfers_panel.notification = Some(String::from(notice));
+        }
+        folder.map(Path::to_path_buf)
+    }
+
+    fn open_selected_folder(&mut self) {
+        let dir = match self.selected_folder() {
+            Some(dir) => dir,
+            None => return,
+        };
+
+        let notice = match open_folder(&dir) {
+            Ok(_) => format!("Opened {}", dir.display()),
+            Err(err) => format!("Could not open {}: {err}", dir.display()),
+        };
+        self.transfers_panel.notification = Some(notice);
+    }
+
+    /// the terminal puts the path on the clipboard, which also works over ssh
+    fn copy_selected_folder(&mut self) {
+        let dir = match self.selected_folder() {
+            Some(dir) => dir.display().to_string(),
+            None => return,
+        };
+
+        let notice = match execute!(stdout(), CopyToClipboard::to_clipboard_from(&dir)) {
+            Ok(_) => format!("Copied {dir}"),
+            Err(err) => format!("Could not copy {dir}: {err}"),
+        };
+        self.transfers_panel.notification = Some(notice);
+    }
+}
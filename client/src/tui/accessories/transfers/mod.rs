@@ -0,0 +1,3 @@
+pub mod app_transfers_panel;
+pub mod transfers_panel;
+pub mod transfers_panel_display;
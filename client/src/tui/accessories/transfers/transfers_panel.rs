@@ -0,0 +1,20 @@
+#[derive(Default)]
+pub struct TransfersPanel {
+    /// position in the list, which shows the newest transfer first
+    pub selected_index: usize,
+    /// first entry shown, kept so the selected one stays in view
+    pub scroll_offset: usize,
+    pub notification: Option<String>,
+}
+
+impl TransfersPanel {
+    pub fn move_up(&mut self) {
+        self.selected_index = self.selected_index.saturating_sub(1);
+    }
+
+    pub fn move_down(&mut self, len: usize) {
+        if self.selected_index + 1 < len {
+            self.selected_index += 1;
+        }
+    }
+}
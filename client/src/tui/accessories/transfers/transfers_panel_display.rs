@@ -0,0 +1,275 @@
+use crate::{
+    tui::app::app::App,
+    util::{
+        config::{THEME_GRAY_GREEN_DARK, THEME_GREEN, THEME_YELLOW_DARK, THEME_YELLOW_LIGHT},
+        functions::{format_size, pad_line_to_width},
+        types::{Transfer, TransferDirection, TransferState},
+    },
+};
+use ratatui::{
+    buffer::Buffer,
+    layout::{Alignment, Constraint, Direction, Layout, Margin, Rect},
+    style::{Color, Style, Stylize},
+    symbols::border,
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Paragraph, Widget},
+};
+use shared::types::Channel;
+use std::{sync::atomic::Ordering, time::Instant};
+
+/// lines every transfer takes in the list
+const ENTRY_HEIGHT: usize = 3;
+const BAR_WIDTH: usize = 20;
+
+fn progress_bar(done: u64, size: u64) -> (String, u64) {
+    let percent = match size {
+        0 => 100,
+        size => (done.min(size) * 100) / size,
+    };
+    let filled = percent as usize * BAR_WIDTH / 100;
+    let bar = "█".repeat(filled) + &"░".repeat(BAR_WIDTH - filled);
+    (bar, percent)
+}
+
+/// average speed from the moment the transfer started running
+fn throughput(transfer: &Transfer, done: u64) -> String {
+    let started_at = match transfer.started_at {
+        Some(started_at) => started_at,
+        None => return String::from("-"),
+    };
+    let elapsed = transfer
+        .ended_at
+        .unwrap_or_else(Instant::now)
+        .duration_since(started_at)
+        .as_secs_f64();
+    if elapsed <= 0.0 {
+        return String::from("-");
+    }
+    format!("{}/s", format_size((done as f64 / elapsed) as u64))
+}
+
+fn state_label(state: TransferState) -> (&'static str, Color) {
+    match state {
+        TransferState::Offered => ("offered", Color::DarkGray),
+        TransferState::Running => ("running", Color::White),
+        TransferState::Paused => ("paused", Color::Yellow),
+        TransferState::Interrupted => ("interrupted", Color::Yellow),
+        TransferState::Done => (
+            "done",
+            Color::Rgb(THEME_GREEN.0, THEME_GREEN.1, THEME_GREEN.2),
+        ),
+        TransferState::Failed => ("failed", Color::LightRed),
+        TransferState::Cancelled => ("cancelled", Color::LightRed),
+    }
+}
+
+impl App {
+    fn channel_label(&self, channel: &Channel) -> String {
+        match channel {
+            Channel::Room(id) => self
+                .room_channels
+                .iter()
+                .find(|r| r.id == *id)
+                .map(|r| r.name.clone()),
+            Channel::User(id) => self
+                .direct_channels
+                .iter()
+                .find(|c| c.user.id == *id)
+                .map(|c| c.user.username.clone()),
+        }
+        .unwrap_or_else(|| String::from("unknown"))
+    }
+
+    fn transfer_lines(&self, transfer: &Transfer, selected: bool, width: u16) -> Vec<Line<'_>> {
+        let done = transfer.progress.load(Ordering::Relaxed);
+        let (bar, percent) = progress_bar(done, transfer.size);
+        let (state, state_color) = state_label(transfer.state);
+
+        let (arrow, peer) = match transfer.direction {
+            TransferDirection::Received => ("↓", format!("from {}", transfer.peer.username)),
+            TransferDirection::Sent => {
+                ("↑", format!("to {}", self.channel_label(&transfer.channel)))
+            }
+        };
+
+        let detail = match (&transfer.state, &transfer.reason, &transfer.path) {
+            (TransferState::Failed | TransferState::Cancelled, Some(reason), _) => reason.clone(),
+            (TransferState::Done, _, Some(path)) => path.display().to_string(),
+            _ => String::new(),
+        };
+
+        let style_row = match selected {
+            true => Style::new().bg(Color::Rgb(
+                THEME_YELLOW_LIGHT.0,
+                THEME_YELLOW_LIGHT.1,
+                THEME_YELLOW_LIGHT.2,
+            )),
+            false => Style::new(),
+        };
+
+        let name_line = Line::from(vec![
+            Span::from(format!(" {arrow} ")).style(Style::new().fg(Color::White)),
+            Span::from(transfer.filename.clone()).style(Style::new().fg(Color::White).bold()),
+            Span::from(format!("  {peer}")).style(Style::new().fg(Color::DarkGray)),
+        ]);
+
+        let progress_line = Line::from(vec![
+            Span::from(format!("   {bar} ")).style(Style::new().fg(state_color)),
+            Span::from(format!(
+                "{percent:>3}%  {} / {}  {}  ",
+                format_size(done),
+                format_size(transfer.size),
+                throughput(transfer, done)
+            ))
+            .style(Style::new().fg(Color::White)),
+            Span::from(state).style(Style::new().fg(state_color).bold()),
+        ]);
+
+        let detail_line =
+            Line::from(format!("   {detail}")).style(Style::new().fg(Color::DarkGray));
+
+        vec![name_line, progress_line, detail_line]
+            .into_iter()
+            .map(|line| pad_line_to_width(line, width).style(style_row))
+            .collect()
+    }
+
+    pub fn render_transfers_panel(&mut self, area: Rect, buf: &mut Buffer) {
+        let width = 80.min(area.width);
+        let height = 22.min(area.height);
+
+        let x = (area.width.saturating_sub(width)) / 2;
+        let y = (area.height.saturating_sub(height)) / 2;
+
+        let rect_outer = Rect::new(x, y, width, height);
+        Clear.render(rect_outer, buf);
+
+        let style_title = Style::new()
+            .fg(Color::Rgb(
+                THEME_GRAY_GREEN_DARK.0,
+                THEME_GRAY_GREEN_DARK.1,
+                THEME_GRAY_GREEN_DARK.2,
+            ))
+            .bg(Color::Rgb(
+                THEME_YELLOW_DARK.0,
+                THEME_YELLOW_DARK.1,
+                THEME_YELLOW_DARK.2,
+            ))
+            .bold();
+
+        let style_outer = Style::default().bg(Color::Rgb(
+            THEME_GRAY_GREEN_DARK.0,
+            THEME_GRAY_GREEN_DARK.1,
+            THEME_GRAY_GREEN_DARK.2,
+        ));
+
+        let style_border_content = Style::default().fg(Color::Rgb(
+            THEME_YELLOW_LIGHT.0,
+            THEME_YELLOW_LIGHT.1,
+            THEME_YELLOW_LIGHT.2,
+        ));
+
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .vertical_margin(1)
+            .constraints(vec![
+                Constraint::Fill(1),
+                Constraint::Length(1),
+                Constraint::Length(3),
+            ])
+            .split(rect_outer);
+
+        let rect_content = layout[0];
+        let rect_notification = layout[1];
+
+        Block::default()
+            .title(Span::styled(" Transfers ", style_title))
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_set(border::EMPTY)
+            .style(style_outer)
+            .render(rect_outer, buf);
+
+        let block_list = Block::default()
+            .borders(Borders::ALL)
+            .border_set(border::PLAIN)
+            .border_style(style_border_content);
+
+        let rect_list = rect_content.inner(Margin::new(1, 0));
+        let inner_width = rect_list.width.saturating_sub(2);
+        let visible = (rect_list.height.saturating_sub(2) as usize / ENTRY_HEIGHT).max(1);
+
+        let panel = &mut self.transfers_panel;
+        panel.selected_index = panel
+            .selected_index
+            .min(self.transfers.len().saturating_sub(1));
+        if panel.selected_index >= panel.scroll_offset + visible {
+            panel.scroll_offset = panel.selected_index + 1 - visible;
+        }
+        if panel.selected_index < panel.scroll_offset {
+            panel.scroll_offset = panel.selected_index;
+        }
+
+        let selected_index = panel.selected_index;
+        let lines: Vec<Line> = match self.transfers.is_empty() {
+            true => vec![
+                Line::from(" No files sent or received yet")
+                    .style(Style::new().fg(Color::DarkGray)),
+            ],
+            false => self
+                .transfers
+                .iter()
+                .rev()
+                .enumerate()
+                .flat_map(|(i, t)| self.transfer_lines(t, i == selected_index, inner_width))
+                .collect(),
+        };
+
+        let scroll_offset = (self.transfers_panel.scroll_offset * ENTRY_HEIGHT) as u16;
+        Paragraph::new(lines)
+            .block(block_list)
+            .scroll((scroll_offset, 0))
+            .render(rect_list, buf);
+
+        if let Some(notification) = &self.transfers_panel.notification {
+            Line::from(notification.as_str())
+                .style(Style::new().fg(Color::Yellow))
+                .centered()
+                .render(rect_notification, buf);
+        }
+
+        let hints = [
+            vec![
+                (" move: ", "< ↑,↓ >    "),
+                (" accept: ", "< a >    "),
+                (" decline: ", "< d >    "),
+                (" pause: ", "< p >"),
+            ],
+            vec![
+                (" cancel: ", "< x >    "),
+                (" open folder: ", "< o >    "),
+                (" copy folder: ", "< y >    "),
+                (" close: ", "< Esc >"),
+            ],
+        ];
+
+        for (i, hints) in hints.into_iter().enumerate() {
+            let line_rect = Rect {
+                x: rect_outer.x,
+                y: rect_outer.y + rect_outer.height.saturating_sub(3) + i as u16,
+                width: rect_outer.width,
+                height: 1,
+            };
+            let spans: Vec<Span> = hints
+                .into_iter()
+                .flat_map(|(name, keys)| {
+                    [
+                        Span::from(name).style(Style::new().fg(Color::DarkGray)),
+                        Span::from(keys).style(Style::new().fg(Color::White)),
+                    ]
+                })
+                .collect();
+            Line::from(spans).centered().render(line_rect, buf);
+        }
+    }
+}
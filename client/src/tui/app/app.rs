@@ -1,21 +1,28 @@
 use anyhow::{Result, bail};
+use chrono::Utc;
+use image::DynamicImage;
 use ratatui::{
     DefaultTerminal, Frame,
     crossterm::event::{self, Event},
+    layout::Rect,
     style::{Color, Style},
     widgets::Paragraph,
 };
 use shared::{
-    config::PUBLIC_ROOM_ID,
+    config::{PUBLIC_ROOM_ID, TCP_CHUNK_BUFFER_SIZE, UNREAD_COUNT_MAX},
     types::{
-        Channel, ChannelMsg, Chunk, ClientServerAuthMsg, ClientServerMsg, DirectChannel, ImgRender,
-        JoinRoomNotification, LeaveRoomNotification, RegisterResponse, RoomData, TextMsg, TuiRoom,
-        User, UserInitData,
+        Channel, ChannelMsg, Chunk, ClientServerAuthMsg, ClientServerMsg, DirectChannel, FileList,
+        FileTypePolicy, HistoryCursor, HistoryPage, HistoryRequest, ImgRender,
+        JoinRoomNotification, LeaveRoomNotification, ModerationAction, ModerationNotice,
+        ModerationRes, MsgEdit, MsgRefusal, MsgUpdateRes, Presence, PresenceStatus, PresenceUpdate,
+        ReadMarker, ReadReceipt, RegisterResponse, ReplyRef, RoomData, RoomPasswordRes,
+        RoomRemovalRes, TextMsg, TuiRoom, TypingNotice, User, UserInitData,
     },
 };
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     str::FromStr,
+    time::{Duration, Instant},
 };
 use tokio::select;
 use tui_textarea::TextArea;
@@ -24,12 +31,21 @@ use uuid::Uuid;
 use crate::{
     tui::accessories::{
         create_room::create_room::RoomCreator, file_selector::file_selector::FileSelector,
+        transfers::transfers_panel::TransfersPanel,
     },
     util::{
-        config::THEME_GRAY_GREEN_LIGHT,
+        ascii_art::ascii_options,
+        config::{
+            AWAY_AFTER_SECS, DEFAULT_CELL_PX, HISTORY_PAGE_SIZE, IMAGE_MAX_COLS,
+            PREFERRED_CHUNK_SIZE, THEME_GRAY_GREEN_LIGHT, TYPING_EXPIRY_MS,
+            TYPING_SEND_INTERVAL_MS,
+        },
+        functions::{newest_read_id, role_name},
+        graphics::detect_graphics,
         types::{
             ActiveChannel, ActiveCreateRoomInput, ActiveEntryInput, ActiveEntryScreen,
-            ActiveScreen, ActiveStream, ChannelKind, Focus, Notification, TuiUpdate,
+            ActiveScreen, AsciiOptions, ChannelKind, Focus, InlineImages, Notification, ThreadView,
+            Transfer, TransferDirection, TransferReport, TuiUpdate, TypingUser, WsStreamMsg,
         },
     },
 };
@@ -46,7 +62,6 @@ pub struct App {
     pub main_text_area: TextArea<'static>,
     pub room_channels: Vec<TuiRoom>,
     pub direct_channels: Vec<DirectChannel>,
-    pub data_streams: HashMap<Uuid, ActiveStream>,
     pub active_channel: ActiveChannel,
     pub active_screen: ActiveScreen,
     pub active_entry_input: ActiveEntryInput,
@@ -54,17 +69,63 @@ pub struct App {
     pub active_create_room_input: ActiveCreateRoomInput,
     pub display_file_selector: bool,
     pub display_room_creator: bool,
+    pub display_transfers: bool,
     pub file_selector: FileSelector,
     pub room_creator: RoomCreator,
+    pub transfers_panel: TransfersPanel,
     pub login_screen_notification: Option<Notification>,
     pub main_scroll_offset: usize,
+    pub messages_line_count: usize,
+    /// columns a line of the messages pane had in the last frame
+    pub messages_width: u16,
     pub tx_tui_ws_msg: tokio::sync::mpsc::Sender<ClientServerMsg>,
     pub tx_tui_ws_file: tokio::sync::mpsc::Sender<Chunk>,
     pub focus: Focus,
     pub rx_ws_tui: tokio::sync::mpsc::Receiver<TuiUpdate>,
     pub tx_events_tui: tokio::sync::mpsc::Sender<Event>,
     pub rx_events_tui: tokio::sync::mpsc::Receiver<Event>,
+    /// outcome of the files this client sends, the received ones come as `TuiUpdate::Transfer`
+    pub tx_transfer_tui: tokio::sync::mpsc::Sender<TransferReport>,
+    pub rx_transfer_tui: tokio::sync::mpsc::Receiver<TransferReport>,
+    /// notices of tasks the app spawned, shown in the conversation they are about
+    pub tx_notice_tui: tokio::sync::mpsc::Sender<(Channel, String)>,
+    pub rx_notice_tui: tokio::sync::mpsc::Receiver<(Channel, String)>,
+    /// pictures of images decoded off the runtime, by message id
+    pub tx_picture_tui: tokio::sync::mpsc::Sender<(Uuid, Option<DynamicImage>)>,
+    pub rx_picture_tui: tokio::sync::mpsc::Receiver<(Uuid, Option<DynamicImage>)>,
+    pub file_policy: FileTypePolicy,
+    /// agreed with the server after login, files are sent in chunks of at most this size
+    pub chunk_size: usize,
     pub tx_tui_ws_auth: tokio::sync::mpsc::Sender<ClientServerAuthMsg>,
+    pub reconnecting: bool,
+    /// text message picked in the messages panel, target of edit and delete
+    pub selected_msg: Option<Uuid>,
+    /// set while the input holds the new text of that message
+    pub editing_msg: Option<Uuid>,
+    /// own messages the server refused or never acked, by local id
+    pub failed_msgs: HashMap<Uuid, String>,
+    /// own direct messages queued on the server until their recipient logs in
+    pub undelivered_msgs: HashSet<Uuid>,
+    /// message quoted by the next one sent
+    pub replying_to: Option<TextMsg>,
+    /// the input posts into this thread while its conversation is active
+    pub open_thread: Option<ThreadView>,
+    pub typing_users: Vec<TypingUser>,
+    /// channel and time of the last typing event sent
+    pub last_typing_sent: Option<(Channel, Instant)>,
+    /// users without an entry are plain online or offline, the own one is kept here too
+    pub presence: HashMap<Uuid, Presence>,
+    pub last_input: Instant,
+    /// set while away was switched on by inactivity, the next input switches it off
+    pub auto_away: bool,
+    /// offered, running and finished file transfers of this session, oldest first
+    pub transfers: Vec<Transfer>,
+    /// last listing of stored files, `/download` picks from it
+    pub files: Option<FileList>,
+    /// received files are only streamed to disk once their offer was accepted
+    pub tx_tui_stream: tokio::sync::mpsc::Sender<WsStreamMsg>,
+    pub inline_images: InlineImages,
+    pub ascii_options: AsciiOptions,
 }
 
 impl App {
@@ -73,8 +134,12 @@ impl App {
         tx_tui_ws_file: tokio::sync::mpsc::Sender<Chunk>,
         tx_tui_ws_msg: tokio::sync::mpsc::Sender<ClientServerMsg>,
         tx_tui_ws_auth: tokio::sync::mpsc::Sender<ClientServerAuthMsg>,
+        tx_tui_stream: tokio::sync::mpsc::Sender<WsStreamMsg>,
     ) -> Self {
         let (tx_events_tui, rx_events_tui) = tokio::sync::mpsc::channel(20);
+        let (tx_transfer_tui, rx_transfer_tui) = tokio::sync::mpsc::channel(20);
+        let (tx_notice_tui, rx_notice_tui) = tokio::sync::mpsc::channel(20);
+        let (tx_picture_tui, rx_picture_tui) = tokio::sync::mpsc::channel(20);
         App {
             username: String::new(),
             id: Uuid::nil(),
@@ -91,17 +156,20 @@ impl App {
             },
             direct_channels: vec![],
             room_channels: vec![],
-            data_streams: HashMap::new(),
             active_screen: ActiveScreen::Entry,
             active_entry_screen: ActiveEntryScreen::ASLogin,
             active_entry_input: ActiveEntryInput::Username,
             active_create_room_input: ActiveCreateRoomInput::Name,
             display_file_selector: false,
             display_room_creator: false,
+            display_transfers: false,
             file_selector: FileSelector::new(),
             room_creator: RoomCreator::new(),
+            transfers_panel: TransfersPanel::default(),
             login_screen_notification: None,
             main_scroll_offset: 0,
+            messages_line_count: 0,
+            messages_width: IMAGE_MAX_COLS,
             rx_ws_tui,
             tx_tui_ws_msg,
             tx_tui_ws_file,
@@ -109,18 +177,56 @@ impl App {
             focus: Focus::Messages,
             rx_events_tui,
             tx_events_tui,
+            tx_transfer_tui,
+            rx_transfer_tui,
+            tx_notice_tui,
+            rx_notice_tui,
+            tx_picture_tui,
+            rx_picture_tui,
+            file_policy: FileTypePolicy::from_env(),
+            chunk_size: TCP_CHUNK_BUFFER_SIZE,
+            reconnecting: false,
+            selected_msg: None,
+            editing_msg: None,
+            failed_msgs: HashMap::new(),
+            undelivered_msgs: HashSet::new(),
+            replying_to: None,
+            open_thread: None,
+            typing_users: vec![],
+            last_typing_sent: None,
+            presence: HashMap::new(),
+            last_input: Instant::now(),
+            auto_away: false,
+            transfers: vec![],
+            files: None,
+            tx_tui_stream,
+            inline_images: InlineImages {
+                protocol: detect_graphics(),
+                cell: DEFAULT_CELL_PX,
+                max_cols: IMAGE_MAX_COLS,
+                pictures: HashMap::new(),
+                ascii: (None, HashMap::new()),
+                encoded: HashMap::new(),
+                placements: (Rect::default(), vec![]),
+                shown: (Rect::default(), vec![]),
+            },
+            ascii_options: ascii_options(),
         }
     }
 
     pub async fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
         self.listen_for_tui_events().await;
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
 
         while !self.exit {
             terminal.draw(|frame| self.draw(frame))?;
+            self.show_images(terminal)?;
 
             select! {
                 result = self.rx_events_tui.recv() => if let Some(e) = result {
-                    self.handle_events(e).await?
+                    self.note_activity().await?;
+                    self.handle_events(e).await?;
+                    self.load_active_channel_history().await?;
                 },
 
                 result = self.rx_ws_tui.recv() => if let Some(msg) = result {
@@ -131,19 +237,192 @@ impl App {
                         TuiUpdate::UserJoinedRoom(update) => self.handle_user_joined_room(update),
                         TuiUpdate::UserLeftRoom(update) => self.handle_user_left_room(update),
                         TuiUpdate::Text(msg) => self.handle_text_message(msg),
-                        TuiUpdate::Init(data) => self.handle_init_data(data),
+                        TuiUpdate::Init(data) => {
+                            self.handle_init_data(data).await?;
+                            self.resume_transfers().await?
+                        }
                         TuiUpdate::UserConnected(user) => self.handle_user_connected(user),
                         TuiUpdate::UserDisconnected(user) => self.handle_user_disconnected(user),
                         TuiUpdate::JoinRoom(res) => self.handle_join_room(res),
+                        TuiUpdate::History(page) => self.handle_history_page(page),
+                        TuiUpdate::MsgRefused(refusal) => self.handle_msg_refused(refusal),
+                        TuiUpdate::RoomRemoval(res) => self.handle_room_removal(res),
+                        TuiUpdate::RoomDeleted(id) => self.remove_room(id),
+                        TuiUpdate::Moderation(res) => self.handle_moderation_response(res),
+                        TuiUpdate::RoomPassword(res) => self.handle_room_password_response(res),
+                        TuiUpdate::RoomModerated(notice) => self.handle_room_moderated(notice),
+                        TuiUpdate::MessageEdited(msg) => self.handle_message_edited(msg),
+                        TuiUpdate::MessageDeleted(deletion) => self.handle_message_deleted(deletion),
+                        TuiUpdate::MsgUpdate(res) => self.handle_msg_update_response(res),
+                        TuiUpdate::Ack(ack) => self.handle_msg_ack(ack),
+                        TuiUpdate::Delivered(notice) => self.handle_delivered(notice),
+                        TuiUpdate::ReactionsChanged(reactions) => {
+                            self.handle_reactions_changed(reactions)
+                        }
+                        TuiUpdate::Thread(res) => self.handle_thread(res),
+                        TuiUpdate::Seen(receipt) => self.handle_seen(receipt),
+                        TuiUpdate::Typing(notice) => self.handle_typing(notice),
+                        TuiUpdate::PresenceChanged(presence) => {
+                            self.handle_presence_changed(presence)
+                        }
+                        TuiUpdate::Transfer(report) => self.handle_transfer_report(report),
+                        TuiUpdate::ChunkSize(size) => self.chunk_size = size as usize,
+                        TuiUpdate::FileOffer(meta) => self.handle_file_offer(meta),
+                        TuiUpdate::TransferControl(control) => {
+                            self.handle_transfer_control(control).await?
+                        }
+                        TuiUpdate::StreamCredit(credit) => self.handle_stream_credit(credit),
+                        TuiUpdate::StreamFailed(failure) => {
+                            self.handle_stream_failure(failure).await?
+                        }
+                        TuiUpdate::Resync(channel) => self.handle_resync(channel).await?,
+                        TuiUpdate::Files(list) => self.handle_file_list(list),
+                        TuiUpdate::FileResponse(res) => self.handle_file_response(res).await?,
+                        TuiUpdate::TransferResumed(res) => {
+                            self.handle_transfer_resumed(res).await?
+                        }
+                        TuiUpdate::Connected => self.reconnecting = false,
+                        TuiUpdate::Disconnected => {
+                            self.reconnecting = true;
+                            self.fail_pending_messages();
+                            self.interrupt_transfers().await?;
+                        }
                     }
                 },
 
+                result = self.rx_transfer_tui.recv() => if let Some(report) = result {
+                    self.handle_transfer_report(report);
+                },
+
+                result = self.rx_notice_tui.recv() => if let Some((channel, notice)) = result {
+                    self.push_notice(&channel, notice);
+                },
+
+                result = self.rx_picture_tui.recv() => if let Some((id, picture)) = result {
+                    self.inline_images.pictures.insert(id, picture);
+                },
+
+                _ = ticker.tick() => {
+                    let expiry = Duration::from_millis(TYPING_EXPIRY_MS);
+                    self.typing_users.retain(|t| t.at.elapsed() < expiry);
+                    self.check_auto_away().await?;
+                },
             }
+
+            self.mark_active_channel_read().await?;
         }
 
         Ok(())
     }
 
+    pub fn get_unread(&mut self, channel: &Channel) -> Option<&mut u32> {
+        match channel {
+            Channel::Room(id) => self
+                .room_channels
+                .iter_mut()
+                .find(|c| c.id == *id)
+                .map(|c| &mut c.unread),
+            Channel::User(id) => self
+                .direct_channels
+                .iter_mut()
+                .find(|c| c.user.id == *id)
+                .map(|c| &mut c.unread),
+        }
+    }
+
+    /// whatever is shown in the active channel counts as read, the server only hears of new markers
+    async fn mark_active_channel_read(&mut self) -> Result<()> {
+        if self.reconnecting || self.active_screen != ActiveScreen::Main {
+            return Ok(());
+        }
+
+        let channel = match self.get_active_channel() {
+            None => return Ok(()),
+            Some(c) => c,
+        };
+
+        let (messages, unread, last_read) = match &channel {
+            Channel::Room(id) => match self.room_channels.iter_mut().find(|c| c.id == *id) {
+                Some(c) => (&c.messages, &mut c.unread, &mut c.last_read),
+                None => return Ok(()),
+            },
+            Channel::User(id) => match self.direct_channels.iter_mut().find(|c| c.user.id == *id) {
+                Some(c) => (&c.messages, &mut c.unread, &mut c.last_read),
+                None => return Ok(()),
+            },
+        };
+
+        *unread = 0;
+        let id = match newest_read_id(messages) {
+            Some(id) if *last_read != Some(id) => id,
+            _ => return Ok(()),
+        };
+        *last_read = Some(id);
+
+        let msg = ClientServerMsg::MarkRead(ReadMarker { channel, id });
+        self.tx_tui_ws_msg.send(msg).await?;
+
+        Ok(())
+    }
+
+    fn handle_typing(&mut self, notice: TypingNotice) {
+        // rooms echo it back to the sender
+        if notice.from.id == self.id {
+            return;
+        }
+
+        let channel = match notice.to {
+            Channel::Room(id) => Channel::Room(id),
+            Channel::User(_) => Channel::User(notice.from.id),
+        };
+        self.typing_users
+            .retain(|t| !(t.user.id == notice.from.id && t.channel == channel));
+        self.typing_users.push(TypingUser {
+            user: notice.from,
+            channel,
+            at: Instant::now(),
+        });
+    }
+
+    /// lets the active channel know the user is typing, at most once per `TYPING_SEND_INTERVAL_MS`
+    pub async fn send_typing(&mut self) -> Result<()> {
+        if self.reconnecting || self.editing_msg.is_some() {
+            return Ok(());
+        }
+        let channel = match self.get_active_channel() {
+            None => return Ok(()),
+            Some(c) => c,
+        };
+        // room commands are not messages
+        if self.main_text_area.lines()[0].starts_with('/') {
+            return Ok(());
+        }
+
+        let interval = Duration::from_millis(TYPING_SEND_INTERVAL_MS);
+        if let Some((last_channel, at)) = &self.last_typing_sent
+            && *last_channel == channel
+            && at.elapsed() < interval
+        {
+            return Ok(());
+        }
+        self.last_typing_sent = Some((channel.clone(), Instant::now()));
+
+        self.tx_tui_ws_msg
+            .send(ClientServerMsg::Typing(channel))
+            .await?;
+        Ok(())
+    }
+
+    fn handle_seen(&mut self, receipt: ReadReceipt) {
+        if let Some(dir) = self
+            .direct_channels
+            .iter_mut()
+            .find(|d| d.user.id == receipt.by.id)
+        {
+            dir.seen = dir.seen.max(Some(receipt.id));
+        }
+    }
+
     fn handle_join_room(&mut self, res: Result<RoomData, String>) {
         match res {
             Err(msg) => self.room_creator.notification = Some(msg),
@@ -154,6 +433,10 @@ impl App {
                     messages: VecDeque::new(),
                     users: room.users,
                     users_online: room.users_online,
+                    history: HistoryCursor::Unloaded,
+                    roles: room.roles,
+                    unread: 0,
+                    last_read: None,
                 };
                 self.active_channel = ActiveChannel {
                     id: Some(room.id),
@@ -183,39 +466,99 @@ impl App {
         }
     }
 
+    /// a status they set follows as `PresenceChanged`
     fn handle_user_connected(&mut self, user: User) {
         for room in &mut self.room_channels {
             if room.users.contains(&user) && !room.users_online.contains(&user) {
-                if room.id == Uuid::from_str(PUBLIC_ROOM_ID).unwrap() {
-                    if user.username == self.username {
-                        continue;
-                    }
-                    let dr = DirectChannel {
-                        messages: VecDeque::new(),
-                        user: user.clone(),
-                    };
-                    self.direct_channels.push(dr);
-                }
                 room.users_online.push(user.clone());
             }
         }
-    }
 
-    fn handle_user_disconnected(&mut self, user: User) {
-        if let Some(id) = self.active_channel.id {
-            if user.id == id {
-                self.active_channel.id = None;
-            }
+        if let Some(presence) = self.presence.get_mut(&user.id)
+            && presence.status == PresenceStatus::Offline
+        {
+            presence.status = PresenceStatus::Online;
+            presence.last_seen = None;
         }
+    }
 
+    /// direct channels stay, messages to offline users are queued on the server
+    fn handle_user_disconnected(&mut self, user: User) {
         for room in &mut self.room_channels {
             if room.users.contains(&user) {
-                if room.id == Uuid::from_str(PUBLIC_ROOM_ID).unwrap() {
-                    self.direct_channels.retain(|dr| dr.user.id != user.id);
-                }
                 room.users_online.retain(|u| u.id != user.id);
             }
         }
+
+        let presence = Presence {
+            user,
+            status: PresenceStatus::Offline,
+            text: String::new(),
+            last_seen: Some(Utc::now().timestamp_millis()),
+        };
+        self.presence.insert(presence.user.id, presence);
+    }
+
+    /// going invisible or coming back from it shows as disconnecting and connecting
+    fn handle_presence_changed(&mut self, presence: Presence) {
+        if presence.user.id != self.id {
+            match presence.status {
+                PresenceStatus::Offline => self.handle_user_disconnected(presence.user.clone()),
+                _ => self.handle_user_connected(presence.user.clone()),
+            }
+        }
+        self.presence.insert(presence.user.id, presence);
+    }
+
+    pub fn own_presence(&self) -> PresenceUpdate {
+        match self.presence.get(&self.id) {
+            Some(p) => PresenceUpdate {
+                status: p.status,
+                text: p.text.clone(),
+            },
+            None => PresenceUpdate::default(),
+        }
+    }
+
+    pub async fn set_presence(&mut self, update: PresenceUpdate) -> Result<()> {
+        self.tx_tui_ws_msg
+            .send(ClientServerMsg::SetPresence(update))
+            .await?;
+        Ok(())
+    }
+
+    async fn note_activity(&mut self) -> Result<()> {
+        self.last_input = Instant::now();
+        if !self.auto_away || self.reconnecting {
+            return Ok(());
+        }
+
+        self.auto_away = false;
+        let update = PresenceUpdate {
+            status: PresenceStatus::Online,
+            text: self.own_presence().text,
+        };
+        self.set_presence(update).await
+    }
+
+    async fn check_auto_away(&mut self) -> Result<()> {
+        let idle = self.last_input.elapsed() >= Duration::from_secs(AWAY_AFTER_SECS);
+        let own = self.own_presence();
+        if !idle
+            || self.auto_away
+            || self.reconnecting
+            || self.active_screen != ActiveScreen::Main
+            || own.status != PresenceStatus::Online
+        {
+            return Ok(());
+        }
+
+        self.auto_away = true;
+        let update = PresenceUpdate {
+            status: PresenceStatus::Away,
+            text: own.text,
+        };
+        self.set_presence(update).await
     }
 
     fn handle_user_left_room(&mut self, update: LeaveRoomNotification) {
@@ -240,18 +583,49 @@ impl App {
         {
             room.users.push(update.user.clone());
         };
+
+        // every member of the public room can be messaged directly, online or not
+        if update.room_id == Uuid::from_str(PUBLIC_ROOM_ID).unwrap()
+            && update.user.id != self.id
+            && !self
+                .direct_channels
+                .iter()
+                .any(|d| d.user.id == update.user.id)
+        {
+            self.direct_channels.push(DirectChannel {
+                messages: VecDeque::new(),
+                user: update.user,
+                history: HistoryCursor::Unloaded,
+                unread: 0,
+                last_read: None,
+                seen: None,
+            });
+        }
     }
 
-    fn handle_init_data(&mut self, data: UserInitData) {
+    /// also sent again after a resumed session, so the channels are rebuilt from scratch
+    async fn handle_init_data(&mut self, data: UserInitData) -> Result<()> {
+        self.room_channels = vec![];
+        self.direct_channels = vec![];
+
+        // a new connection starts from the default size again
+        self.chunk_size = TCP_CHUNK_BUFFER_SIZE;
+        let msg = ClientServerMsg::ProposeChunkSize(PREFERRED_CHUNK_SIZE);
+        self.tx_tui_ws_msg.send(msg).await?;
+
         for room in data.rooms {
             if room.id == Uuid::from_str(PUBLIC_ROOM_ID).unwrap() {
-                for user in &room.users_online {
+                for user in &room.users {
                     if user.username == self.username {
                         continue;
                     }
                     let dir = DirectChannel {
                         messages: VecDeque::new(),
                         user: user.clone(),
+                        history: HistoryCursor::Unloaded,
+                        unread: 0,
+                        last_read: None,
+                        seen: None,
                     };
                     self.direct_channels.push(dir);
                 }
@@ -263,9 +637,157 @@ impl App {
                 messages: VecDeque::new(),
                 users: room.users,
                 users_online: room.users_online,
+                history: HistoryCursor::Unloaded,
+                roles: room.roles,
+                unread: 0,
+                last_read: None,
             };
 
+            let channel = Channel::Room(room.id);
             self.room_channels.push(room);
+            self.fetch_history(channel).await?;
+        }
+
+        for msg in data.queued_messages {
+            if let Some((from_id, _)) = msg.author_and_id()
+                && let Some(messages) = self.get_direct_messages(from_id)
+            {
+                messages.push_front(msg);
+            }
+        }
+        self.undelivered_msgs = data.undelivered.into_iter().collect();
+        self.presence = data.presence.into_iter().map(|p| (p.user.id, p)).collect();
+
+        for count in data.unread {
+            if let Some(unread) = self.get_unread(&count.channel) {
+                *unread = count.count;
+            }
+        }
+        for marker in data.seen {
+            if let Channel::User(id) = marker.channel
+                && let Some(dir) = self.direct_channels.iter_mut().find(|d| d.user.id == id)
+            {
+                dir.seen = Some(marker.id);
+            }
+        }
+
+        let active_exists = match (self.active_channel.id, &self.active_channel.kind) {
+            (None, _) => true,
+            (Some(id), ChannelKind::Room) => self.room_channels.iter().any(|r| r.id == id),
+            (Some(id), ChannelKind::Direct) => self.direct_channels.iter().any(|d| d.user.id == id),
+        };
+        if !active_exists {
+            self.active_channel.id = None;
+        }
+
+        Ok(())
+    }
+
+    fn get_history_cursor(&mut self, channel: &Channel) -> Option<&mut HistoryCursor> {
+        match channel {
+            Channel::Room(id) => self
+                .room_channels
+                .iter_mut()
+                .find(|c| c.id == *id)
+                .map(|c| &mut c.history),
+            Channel::User(id) => self
+                .direct_channels
+                .iter_mut()
+                .find(|c| c.user.id == *id)
+                .map(|c| &mut c.history),
+        }
+    }
+
+    pub async fn fetch_history(&mut self, channel: Channel) -> Result<()> {
+        let cursor = match self.get_history_cursor(&channel) {
+            None => return Ok(()),
+            Some(cursor) => cursor,
+        };
+
+        let before = match cursor {
+            HistoryCursor::Unloaded => None,
+            HistoryCursor::Before(id) => Some(*id),
+            HistoryCursor::Pending | HistoryCursor::Exhausted => return Ok(()),
+        };
+        *cursor = HistoryCursor::Pending;
+
+        let req = HistoryRequest {
+            channel,
+            before,
+            limit: HISTORY_PAGE_SIZE,
+        };
+        self.tx_tui_ws_msg
+            .send(ClientServerMsg::FetchHistory(req))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn load_active_channel_history(&mut self) -> Result<()> {
+        let channel = match self.get_active_channel() {
+            None => return Ok(()),
+            Some(c) => c,
+        };
+
+        if let Some(HistoryCursor::Unloaded) = self.get_history_cursor(&channel) {
+            self.fetch_history(channel).await?;
+        }
+
+        Ok(())
+    }
+
+    /// the server dropped messages of the channel, the newest page is loaded again
+    async fn handle_resync(&mut self, channel: Channel) -> Result<()> {
+        let messages = match &channel {
+            Channel::Room(id) => self.get_room_messages(*id),
+            Channel::User(id) => self.get_direct_messages(*id),
+        };
+        if let Some(messages) = messages {
+            messages.clear();
+        }
+        if let Some(cursor) = self.get_history_cursor(&channel) {
+            *cursor = HistoryCursor::Unloaded;
+        }
+
+        self.push_notice(
+            &channel,
+            String::from("Messages were missed, reloaded the conversation"),
+        );
+        self.fetch_history(channel).await
+    }
+
+    fn handle_history_page(&mut self, page: HistoryPage) {
+        let messages = match page.channel {
+            Channel::Room(id) => self.get_room_messages(id),
+            Channel::User(id) => self.get_direct_messages(id),
+        };
+
+        // messages that arrived live or were queued are already there
+        if let Some(messages) = messages {
+            let known: HashSet<Uuid> = messages
+                .iter()
+                .filter_map(|m| m.author_and_id().map(|(_, id)| id))
+                .collect();
+            messages.extend(
+                page.messages
+                    .into_iter()
+                    .filter(|m| m.author_and_id().is_none_or(|(_, id)| !known.contains(&id))),
+            );
+        }
+
+        if let Some(cursor) = self.get_history_cursor(&page.channel) {
+            *cursor = match page.next_before {
+                Some(id) => HistoryCursor::Before(id),
+                None => HistoryCursor::Exhausted,
+            };
+        }
+    }
+
+    pub fn get_active_channel(&self) -> Option<Channel> {
+        let id = self.active_channel.id?;
+        match self.active_channel.kind {
+            ChannelKind::Direct => Some(Channel::User(id)),
+            ChannelKind::Room => Some(Channel::Room(id)),
         }
     }
 
@@ -289,6 +811,12 @@ impl App {
     }
 
     fn handle_img_render(&mut self, img: ImgRender) -> Result<()> {
+        let from_other = img.from.id != self.id;
+        let unread_channel = match img.to {
+            Channel::Room(id) => Channel::Room(id),
+            Channel::User(_) => Channel::User(img.from.id),
+        };
+
         let messages = match img.to {
             Channel::Room(id) => self.get_room_messages(id),
             Channel::User(id) => self.get_direct_messages(id),
@@ -299,10 +827,165 @@ impl App {
             Some(m) => m.push_front(ChannelMsg::Img(img)),
         }
 
+        if from_other && let Some(unread) = self.get_unread(&unread_channel) {
+            *unread = (*unread + 1).min(UNREAD_COUNT_MAX);
+        }
+
         Ok(())
     }
 
-    fn draw(&mut self, frame: &mut Frame) {
+    fn handle_msg_refused(&mut self, refusal: MsgRefusal) {
+        let messages = match refusal.to {
+            Channel::Room(id) => self.get_room_messages(id),
+            Channel::User(id) => self.get_direct_messages(id),
+        };
+
+        if let Some(messages) = messages {
+            messages.push_front(ChannelMsg::Refused(refusal));
+        }
+    }
+
+    fn handle_room_removal(&mut self, res: RoomRemovalRes) {
+        match res {
+            Ok(id) => self.remove_room(id),
+            Err(msg) => {
+                if let Some(Channel::Room(id)) = self.get_active_channel()
+                    && let Some(messages) = self.get_room_messages(id)
+                {
+                    messages.push_front(ChannelMsg::Notice(msg));
+                }
+            }
+        }
+    }
+
+    fn handle_transfer_report(&mut self, report: TransferReport) {
+        self.record_transfer_outcome(report.stream_id, &report.res);
+
+        let notice = match (&report.direction, report.res) {
+            (TransferDirection::Sent, Ok(_)) => format!("file {} sent", report.filename),
+            (TransferDirection::Received, Ok(path)) => {
+                let path = path.map(|p| p.display().to_string()).unwrap_or_default();
+                format!("file {} received, saved to {path}", report.filename)
+            }
+            (TransferDirection::Sent, Err(reason)) => {
+                format!("file {} not sent: {reason}", report.filename)
+            }
+            (TransferDirection::Received, Err(reason)) => {
+                format!("file {} not received: {reason}", report.filename)
+            }
+        };
+
+        self.push_notice(&report.channel, notice);
+    }
+
+    pub fn push_notice(&mut self, channel: &Channel, notice: String) {
+        let messages = match channel {
+            Channel::Room(id) => self.get_room_messages(*id),
+            Channel::User(id) => self.get_direct_messages(*id),
+        };
+        if let Some(messages) = messages {
+            messages.push_front(ChannelMsg::Notice(notice));
+        }
+    }
+
+    pub fn push_active_notice(&mut self, notice: String) {
+        if let Some(messages) = self.get_active_messages() {
+            messages.push_front(ChannelMsg::Notice(notice));
+        }
+    }
+
+    fn handle_msg_update_response(&mut self, res: MsgUpdateRes) {
+        // successful changes reach everyone in the conversation as MessageEdited or MessageDeleted
+        if let Err(msg) = res {
+            self.push_active_notice(msg);
+        }
+    }
+
+    fn handle_room_password_response(&mut self, res: RoomPasswordRes) {
+        match res {
+            Err(msg) => self.room_creator.notification = Some(msg),
+            Ok(id) => {
+                self.display_room_creator = false;
+                self.room_creator.notification = None;
+                if let Some(messages) = self.get_room_messages(id) {
+                    let notice = String::from("Room password updated.");
+                    messages.push_front(ChannelMsg::Notice(notice));
+                }
+            }
+        }
+    }
+
+    fn handle_moderation_response(&mut self, res: ModerationRes) {
+        // successful actions are announced to the whole room with RoomModerated
+        if let Err(msg) = res {
+            self.push_active_notice(msg);
+        }
+    }
+
+    fn handle_room_moderated(&mut self, notice: ModerationNotice) {
+        let removed = matches!(
+            notice.action,
+            ModerationAction::Kick | ModerationAction::Ban
+        );
+        let what = match notice.action {
+            ModerationAction::Kick => String::from("kicked"),
+            ModerationAction::Ban => String::from("banned"),
+            ModerationAction::Unban => String::from("unbanned"),
+            ModerationAction::Promote | ModerationAction::Demote => {
+                let role = notice.roles.role_of(notice.target.id);
+                format!("made {}", role_name(role))
+            }
+        };
+
+        // the target is notified twice, by the room and directly, the room is gone after the first
+        if removed && notice.target.id == self.id {
+            let room_name = match self.room_channels.iter().find(|r| r.id == notice.room_id) {
+                Some(room) => room.name.clone(),
+                None => return,
+            };
+            self.remove_room(notice.room_id);
+
+            let public_id = Uuid::from_str(PUBLIC_ROOM_ID).unwrap();
+            if let Some(messages) = self.get_room_messages(public_id) {
+                let text = format!("You were {what} from {room_name} by {}", notice.by.username);
+                messages.push_front(ChannelMsg::Notice(text));
+            }
+            return;
+        }
+
+        let room = match self
+            .room_channels
+            .iter_mut()
+            .find(|r| r.id == notice.room_id)
+        {
+            Some(room) => room,
+            None => return,
+        };
+
+        room.roles = notice.roles;
+        if removed {
+            room.users.retain(|u| u.id != notice.target.id);
+            room.users_online.retain(|u| u.id != notice.target.id);
+        }
+
+        let text = format!(
+            "{} was {what} by {}",
+            notice.target.username, notice.by.username
+        );
+        room.messages.push_front(ChannelMsg::Notice(text));
+    }
+
+    fn remove_room(&mut self, id: Uuid) {
+        self.room_channels.retain(|r| r.id != id);
+
+        if let ChannelKind::Room = self.active_channel.kind
+            && self.active_channel.id == Some(id)
+        {
+            self.active_channel.id = self.room_channels.first().map(|r| r.id);
+        }
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame) {
         let background = Paragraph::new("").style(Style::default().bg(Color::Rgb(
             THEME_GRAY_GREEN_LIGHT.0,
             THEME_GRAY_GREEN_LIGHT.1,
@@ -319,6 +1002,12 @@ impl App {
         if self.display_room_creator {
             frame.render_widget(&mut self.room_creator, frame.area());
         }
+
+        if self.display_transfers {
+            self.render_transfers_panel(frame.area(), frame.buffer_mut());
+        }
+
+        self.place_images(frame.buffer_mut());
     }
 
     pub fn init(&mut self, init: User) {
@@ -329,14 +1018,33 @@ impl App {
     pub async fn logout(&mut self) -> Result<()> {
         let msg = ClientServerMsg::Logout;
         self.tx_tui_ws_msg.send(msg).await?;
+        self.reset_session();
+
+        Ok(())
+    }
+
+    pub fn reset_session(&mut self) {
         self.active_screen = ActiveScreen::Entry;
         self.active_entry_screen = ActiveEntryScreen::ASLogin;
         self.direct_channels = vec![];
         self.room_channels = vec![];
         self.main_text_area = TextArea::default();
         self.login_screen_notification = None;
-
-        Ok(())
+        self.selected_msg = None;
+        self.editing_msg = None;
+        self.failed_msgs.clear();
+        self.undelivered_msgs.clear();
+        self.replying_to = None;
+        self.open_thread = None;
+        self.typing_users.clear();
+        self.last_typing_sent = None;
+        self.presence.clear();
+        self.auto_away = false;
+        // the sending tasks stop once their state sender is dropped
+        self.transfers.clear();
+        self.files = None;
+        self.display_transfers = false;
+        self.transfers_panel = TransfersPanel::default();
     }
 
     async fn handle_events(&mut self, event: Event) -> Result<()> {
@@ -346,6 +1054,9 @@ impl App {
             self.display_room_creator,
         ) {
             (ActiveScreen::Entry, _, _) => self.handle_entry_screen_event(event).await?,
+            (ActiveScreen::Main, false, false) if self.display_transfers => {
+                self.handle_transfers_panel_event(event).await?
+            }
             (ActiveScreen::Main, false, false) => self.handle_main_screen_event(event).await?,
             (ActiveScreen::Main, true, _) => self.handle_file_selector_key_event(event).await?,
             (ActiveScreen::Main, _, true) => self.handle_create_room_event(event).await?,
@@ -355,6 +1066,25 @@ impl App {
     }
 
     pub async fn send_message(&mut self) -> Result<()> {
+        if self.reconnecting {
+            return Ok(());
+        }
+
+        let text = self.main_text_area.lines().join("\n");
+        if let Some(id) = self.editing_msg.take() {
+            self.main_text_area = TextArea::default();
+            if !text.trim().is_empty() {
+                let msg = ClientServerMsg::EditMessage(MsgEdit { id, text });
+                self.tx_tui_ws_msg.send(msg).await?;
+            }
+            return Ok(());
+        }
+
+        if text.starts_with('/') {
+            self.main_text_area = TextArea::default();
+            return self.handle_room_command(&text).await;
+        }
+
         let id = match self.active_channel.id {
             None => return Ok(()),
             Some(id) => id,
@@ -372,12 +1102,31 @@ impl App {
             ChannelKind::Room => Channel::Room(id),
         };
 
-        let msg = TextMsg { text, from, to };
+        // the server builds the quote from the stored message, this one is only shown locally
+        let reply_to = self.replying_to.take().map(|msg| ReplyRef {
+            id: msg.id,
+            quote: String::from(msg.text.lines().next().unwrap_or_default()),
+            from: msg.from,
+        });
 
-        if let Some(messages) = self.get_direct_messages(id) {
-            messages.push_front(ChannelMsg::TextMsg(msg.clone()));
+        // shown right away, the server's ack swaps the local id for the real one
+        let msg = TextMsg {
+            id: Uuid::new_v4(),
+            text,
+            from,
+            to,
+            edited: false,
+            sent_at: 0,
+            reactions: vec![],
+            reply_to,
+            thread: self.active_thread().map(|t| t.id),
+            thread_len: 0,
         };
 
+        if let Some(messages) = self.get_active_messages() {
+            messages.push_front(ChannelMsg::TextMsg(msg.clone()));
+        }
+
         let msg = ClientServerMsg::Text(msg);
 
         self.tx_tui_ws_msg.send(msg).await?;
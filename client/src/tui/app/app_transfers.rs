@@ -0,0 +1,517 @@
+use super::app::App;
+use crate::{
+    data_stream::sanitize_filename,
+    util::{
+        functions::format_size,
+        types::{Transfer, TransferDirection, TransferState, WsStreamMsg},
+    },
+};
+use anyhow::Result;
+use shared::types::{
+    Channel, ClientServerMsg, FileList, FileMetadata, FileRequest, FileResponse, ResumeResponse,
+    StreamCredit, StreamFailure, TransferAction, TransferControl, User,
+};
+use std::{path::PathBuf, sync::Arc, sync::atomic::AtomicU64, time::Instant};
+
+/// the task sending a file follows the state through `tx_state`
+fn set_state(transfer: &mut Transfer, state: TransferState) {
+    transfer.state = state;
+    if state == TransferState::Running && transfer.started_at.is_none() {
+        transfer.started_at = Some(Instant::now());
+    }
+    if !is_active(transfer) {
+        transfer.ended_at = Some(Instant::now());
+    }
+    if let Some(tx) = &transfer.tx_state {
+        tx.send_replace(state);
+    }
+}
+
+pub fn is_active(transfer: &Transfer) -> bool {
+    matches!(
+        transfer.state,
+        TransferState::Offered
+            | TransferState::Running
+            | TransferState::Paused
+            | TransferState::Interrupted
+    )
+}
+
+/// whether `action` can be taken on the transfer in its current state
+pub fn can_apply(transfer: &Transfer, action: TransferAction) -> bool {
+    match action {
+        TransferAction::Accept | TransferAction::Decline => {
+            transfer.direction == TransferDirection::Received
+                && transfer.state == TransferState::Offered
+        }
+        TransferAction::Pause => transfer.state == TransferState::Running,
+        TransferAction::Resume => transfer.state == TransferState::Paused,
+        TransferAction::Cancel => is_active(transfer),
+    }
+}
+
+impl App {
+    /// own offers to a room come back with the broadcast and are left out
+    pub fn handle_file_offer(&mut self, meta: FileMetadata) {
+        if meta.sender.id == self.id {
+            return;
+        }
+
+        let filename = sanitize_filename(&meta.filename);
+        let notice = format!(
+            "{} offers {filename} ({}), /accept or /decline it",
+            meta.sender.username,
+            format_size(meta.size)
+        );
+        let transfer = Transfer {
+            stream_id: meta.stream_id,
+            filename,
+            size: meta.size,
+            channel: meta.from.clone(),
+            peer: meta.sender.clone(),
+            direction: TransferDirection::Received,
+            state: TransferState::Offered,
+            file_id: Some(meta.stream_id),
+            offer: Some(meta),
+            tx_state: None,
+            tx_credit: None,
+            tx_resume: None,
+            progress: Arc::new(AtomicU64::new(0)),
+            started_at: None,
+            ended_at: None,
+            path: None,
+            reason: None,
+        };
+        self.push_notice(&transfer.channel, notice);
+        self.transfers.push(transfer);
+    }
+
+    /// answers of receivers to own files, and changes the sender made to received ones
+    pub async fn handle_transfer_control(&mut self, control: TransferControl) -> Result<()> {
+        let own_id = self.id;
+        let transfer = match self
+            .transfers
+            .iter_mut()
+            .find(|t| t.stream_id == control.stream_id)
+        {
+            Some(transfer) if is_active(transfer) => transfer,
+            _ => return Ok(()),
+        };
+
+        let from_self = control.from.id == own_id;
+        let who = control.from.username;
+        let file = transfer.filename.clone();
+        let notice = match (&transfer.direction, control.action) {
+            // the echo of what this client sent to a room
+            (TransferDirection::Sent, _) if from_self && control.to != Channel::User(own_id) => {
+                return Ok(());
+            }
+            (TransferDirection::Sent, TransferAction::Cancel) if from_self => {
+                transfer.reason = Some(String::from("nobody is receiving it any more"));
+                set_state(transfer, TransferState::Cancelled);
+                format!("file {file} not sent: nobody is receiving it any more")
+            }
+            // the server keeps a copy, the file goes out without waiting for receivers
+            (TransferDirection::Sent, TransferAction::Accept) if from_self => {
+                if transfer.state == TransferState::Offered {
+                    set_state(transfer, TransferState::Running);
+                }
+                format!("file {file} is stored on the server as it is sent")
+            }
+            (TransferDirection::Sent, TransferAction::Decline) if from_self => {
+                format!(
+                    "file {file} is not stored on the server, only users online now can receive it"
+                )
+            }
+            (TransferDirection::Sent, TransferAction::Accept) => {
+                if transfer.state == TransferState::Offered {
+                    set_state(transfer, TransferState::Running);
+                }
+                format!("{who} accepted {file}")
+            }
+            (TransferDirection::Sent, TransferAction::Decline) => format!("{who} declined {file}"),
+            (TransferDirection::Sent, TransferAction::Cancel) => {
+                format!("{who} stopped receiving {file}")
+            }
+            (_, TransferAction::Pause) if transfer.state == TransferState::Running => {
+                set_state(transfer, TransferState::Paused);
+                format!("{who} paused {file}")
+            }
+            (_, TransferAction::Resume) if transfer.state == TransferState::Paused => {
+                set_state(transfer, TransferState::Running);
+                format!("{who} resumed {file}")
+            }
+            (TransferDirection::Received, TransferAction::Cancel) => {
+                transfer.reason = Some(format!("{who} stopped sending it"));
+                set_state(transfer, TransferState::Cancelled);
+                let stream_id = transfer.stream_id;
+                self.tx_tui_stream
+                    .send(WsStreamMsg::Cancel(stream_id))
+                    .await?;
+                format!("{who} stopped sending {file}")
+            }
+            _ => return Ok(()),
+        };
+
+        let channel = transfer.channel.clone();
+        self.push_notice(&channel, notice);
+        Ok(())
+    }
+
+    /// the task sending the file waits for it before each chunk
+    pub fn handle_stream_credit(&mut self, credit: StreamCredit) {
+        let tx_credit = self
+            .transfers
+            .iter()
+            .find(|t| t.stream_id == credit.stream_id)
+            .and_then(|t| t.tx_credit.as_ref());
+        if let Some(tx) = tx_credit {
+            tx.send_if_modified(|seq| {
+                let more = credit.seq > *seq;
+                *seq = credit.seq.max(*seq);
+                more
+            });
+        }
+    }
+
+    /// the server dropped this client from a stream, the partial file is removed
+    pub async fn handle_stream_failure(&mut self, failure: StreamFailure) -> Result<()> {
+        let transfer = match self
+            .transfers
+            .iter_mut()
+            .find(|t| t.stream_id == failure.stream_id)
+        {
+            Some(transfer) if is_active(transfer) => transfer,
+            _ => return Ok(()),
+        };
+
+        let notice = format!("file {} failed: {}", transfer.filename, failure.reason);
+        transfer.reason = Some(failure.reason);
+        set_state(transfer, TransferState::Failed);
+        let channel = transfer.channel.clone();
+        self.tx_tui_stream
+            .send(WsStreamMsg::Cancel(failure.stream_id))
+            .await?;
+        self.push_notice(&channel, notice);
+        Ok(())
+    }
+
+    /// `/accept`, `/decline`, `/pause`, `/resume` and `/cancel` act on the newest transfer
+    /// of the active conversation they apply to, or on the one with the given file name
+    pub async fn handle_transfer_command(&mut self, command: &str, filename: &str) -> Result<()> {
+        let channel = match self.get_active_channel() {
+            Some(channel) => channel,
+            None => return Ok(()),
+        };
+        let action = match command {
+            "/accept" => TransferAction::Accept,
+            "/decline" => TransferAction::Decline,
+            "/pause" => TransferAction::Pause,
+            "/resume" => TransferAction::Resume,
+            _ => TransferAction::Cancel,
+        };
+
+        let index = self.transfers.iter().rposition(|t| {
+            t.channel == channel
+                && can_apply(t, action)
+                && (filename.is_empty() || t.filename == filename)
+        });
+        match index {
+            Some(index) => self.apply_transfer_action(index, action).await,
+            None => {
+                let notice = format!("No transfer here to use {command} on");
+                self.push_active_notice(notice);
+                Ok(())
+            }
+        }
+    }
+
+    /// `index` into `transfers`, the action has to be one `can_apply` allows
+    pub async fn apply_transfer_action(
+        &mut self,
+        index: usize,
+        action: TransferAction,
+    ) -> Result<()> {
+        let (state, done) = match action {
+            TransferAction::Accept => (TransferState::Running, "accepted"),
+            TransferAction::Decline => (TransferState::Cancelled, "declined"),
+            TransferAction::Pause => (TransferState::Paused, "paused"),
+            TransferAction::Resume => (TransferState::Running, "resumed"),
+            TransferAction::Cancel => (TransferState::Cancelled, "cancelled"),
+        };
+        let transfer = match self.transfers.get_mut(index) {
+            Some(transfer) => transfer,
+            None => return Ok(()),
+        };
+
+        let was_running = transfer.state != TransferState::Offered;
+        if state == TransferState::Cancelled {
+            transfer.reason = Some(format!("{done} by you"));
+        }
+        set_state(transfer, state);
+        let stream_id = transfer.stream_id;
+        let file = transfer.filename.clone();
+        let channel = transfer.channel.clone();
+        let to = match transfer.direction {
+            TransferDirection::Sent => transfer.channel.clone(),
+            TransferDirection::Received => Channel::User(transfer.peer.id),
+        };
+
+        // the stream has to know the file before the first chunk can arrive
+        if let Some(offer) = transfer.offer.take_if(|_| action == TransferAction::Accept) {
+            let msg = WsStreamMsg::FileMetadata(offer, transfer.progress.clone());
+            self.tx_tui_stream.send(msg).await?;
+        }
+        if transfer.direction == TransferDirection::Received
+            && action == TransferAction::Cancel
+            && was_running
+        {
+            self.tx_tui_stream
+                .send(WsStreamMsg::Cancel(stream_id))
+                .await?;
+        }
+
+        let control = TransferControl {
+            stream_id,
+            action,
+            from: User {
+                id: self.id,
+                username: self.username.clone(),
+            },
+            to,
+        };
+        self.tx_tui_ws_msg
+            .send(ClientServerMsg::TransferControl(control))
+            .await?;
+        self.push_notice(&channel, format!("file {file} {done}"));
+        Ok(())
+    }
+
+    /// numbered, so `/download` can pick a file by its position
+    pub fn handle_file_list(&mut self, list: FileList) {
+        let notice = match list.files.is_empty() {
+            true => String::from("No files are stored here"),
+            false => String::from("Files stored here, /download <number> or <name> to get one"),
+        };
+        self.push_notice(&list.channel, notice);
+        for (i, file) in list.files.iter().enumerate() {
+            let notice = format!(
+                "{}. {} ({}) from {}",
+                i + 1,
+                sanitize_filename(&file.filename),
+                format_size(file.size),
+                file.sender.username
+            );
+            self.push_notice(&list.channel, notice);
+        }
+        self.files = Some(list);
+    }
+
+    /// `/download` followed by the number or name of a file of the last `/files` listing
+    /// of the active conversation
+    pub async fn handle_download_command(&mut self, arg: &str) -> Result<()> {
+        let channel = match self.get_active_channel() {
+            Some(channel) => channel,
+            None => return Ok(()),
+        };
+        let files = match &self.files {
+            Some(list) if list.channel == channel => &list.files,
+            _ => {
+                let notice = String::from("Use /files first to see what can be downloaded");
+                self.push_active_notice(notice);
+                return Ok(());
+            }
+        };
+        let entry = match arg.parse::<usize>() {
+            Ok(n) => n.checked_sub(1).and_then(|i| files.get(i)),
+            Err(_) => files
+                .iter()
+                .find(|f| f.filename == arg || sanitize_filename(&f.filename) == arg),
+        };
+        let entry = match entry {
+            Some(entry) => entry.clone(),
+            None => {
+                self.push_active_notice(format!("No file {arg} here, /files lists them"));
+                return Ok(());
+            }
+        };
+
+        let stream_id = uuid::Uuid::new_v4();
+        let meta = FileMetadata {
+            filename: entry.filename.clone(),
+            stream_id,
+            to: Channel::User(self.id),
+            from: channel.clone(),
+            sender: entry.sender.clone(),
+            size: entry.size,
+            mime: entry.mime,
+            sha256: entry.sha256,
+        };
+        let mut transfer = Transfer {
+            stream_id,
+            filename: sanitize_filename(&entry.filename),
+            size: entry.size,
+            channel: channel.clone(),
+            peer: entry.sender,
+            direction: TransferDirection::Received,
+            state: TransferState::Offered,
+            offer: None,
+            tx_state: None,
+            tx_credit: None,
+            tx_resume: None,
+            file_id: Some(entry.id),
+            progress: Arc::new(AtomicU64::new(0)),
+            started_at: None,
+            ended_at: None,
+            path: None,
+            reason: None,
+        };
+        set_state(&mut transfer, TransferState::Running);
+
+        // the stream has to know the file before the first chunk can arrive
+        let msg = WsStreamMsg::FileMetadata(meta, transfer.progress.clone());
+        self.tx_tui_stream.send(msg).await?;
+        let req = FileRequest {
+            file_id: entry.id,
+            stream_id,
+        };
+        self.tx_tui_ws_msg
+            .send(ClientServerMsg::FetchFile(req))
+            .await?;
+        self.push_notice(&channel, format!("downloading {}", transfer.filename));
+        self.transfers.push(transfer);
+        Ok(())
+    }
+
+    /// an accepted download is already running, a refused one fails like a dropped stream
+    pub async fn handle_file_response(&mut self, response: FileResponse) -> Result<()> {
+        match response.res {
+            Ok(_) => Ok(()),
+            Err(reason) => {
+                let failure = StreamFailure {
+                    stream_id: response.stream_id,
+                    reason,
+                };
+                self.handle_stream_failure(failure).await
+            }
+        }
+    }
+
+    /// received files are `Ok` with the path they were saved to
+    pub fn record_transfer_outcome(
+        &mut self,
+        stream_id: uuid::Uuid,
+        res: &Result<Option<PathBuf>, String>,
+    ) {
+        let transfer = match self.transfers.iter_mut().find(|t| t.stream_id == stream_id) {
+            Some(transfer) => transfer,
+            None => return,
+        };
+
+        match res {
+            Ok(path) => {
+                if path.is_some() {
+                    transfer.path = path.clone();
+                }
+                set_state(transfer, TransferState::Done);
+            }
+            Err(reason) => {
+                transfer.reason = Some(reason.clone());
+                set_state(transfer, TransferState::Failed);
+            }
+        };
+        transfer.tx_state = None;
+        transfer.tx_credit = None;
+        transfer.tx_resume = None;
+    }
+
+    /// transfers that got under way wait for the connection to come back, offers are
+    /// forgotten by the server with it
+    pub async fn interrupt_transfers(&mut self) -> Result<()> {
+        let mut notices = vec![];
+        for transfer in self.transfers.iter_mut().filter(|t| is_active(t)) {
+            let notice = match transfer.state {
+                TransferState::Offered => {
+                    transfer.reason = Some(String::from("connection lost"));
+                    set_state(transfer, TransferState::Failed);
+                    format!("file {} interrupted: connection lost", transfer.filename)
+                }
+                TransferState::Running | TransferState::Paused => {
+                    set_state(transfer, TransferState::Interrupted);
+                    format!(
+                        "file {} interrupted, it goes on once the connection is back",
+                        transfer.filename
+                    )
+                }
+                _ => continue,
+            };
+            notices.push((transfer.channel.clone(), notice));
+        }
+
+        for (channel, notice) in notices {
+            self.push_notice(&channel, notice);
+        }
+        Ok(())
+    }
+
+    /// called once the session is back. the task sending a file asks for the point to go
+    /// on from itself, received files are continued from the copy of the server
+    pub async fn resume_transfers(&mut self) -> Result<()> {
+        let mut resumed = vec![];
+        for transfer in self
+            .transfers
+            .iter_mut()
+            .filter(|t| t.state == TransferState::Interrupted)
+        {
+            set_state(transfer, TransferState::Running);
+            if transfer.direction == TransferDirection::Received {
+                let file_id = transfer.file_id.unwrap_or(transfer.stream_id);
+                resumed.push((transfer.stream_id, file_id));
+            }
+        }
+
+        for (stream_id, file_id) in resumed {
+            let msg = WsStreamMsg::Resume { stream_id, file_id };
+            self.tx_tui_stream.send(msg).await?;
+        }
+        Ok(())
+    }
+
+    /// a transfer that can not go on fails like a dropped stream
+    pub async fn handle_transfer_resumed(&mut self, response: ResumeResponse) -> Result<()> {
+        let transfer = match self
+            .transfers
+            .iter_mut()
+            .find(|t| t.stream_id == response.stream_id)
+        {
+            Some(transfer) if is_active(transfer) => transfer,
+            _ => return Ok(()),
+        };
+
+        let notice = match response.res {
+            Ok(point) => {
+                if let Some(tx) = &transfer.tx_resume {
+                    tx.send_replace(Some(point));
+                }
+                format!(
+                    "file {} resumed after {}",
+                    transfer.filename,
+                    format_size(point.offset)
+                )
+            }
+            Err(reason) => {
+                let notice = format!("file {} could not be resumed: {reason}", transfer.filename);
+                transfer.reason = Some(reason);
+                set_state(transfer, TransferState::Failed);
+                self.tx_tui_stream
+                    .send(WsStreamMsg::Cancel(response.stream_id))
+                    .await?;
+                notice
+            }
+        };
+
+        let channel = transfer.channel.clone();
+        self.push_notice(&channel, notice);
+        Ok(())
+    }
+}
@@ -1,2 +1,3 @@
 pub mod app;
 pub mod app_display;
+pub mod app_transfers;
@@ -200,7 +200,14 @@ impl App {
             }
         }
 
-        if let Some(notification) = &self.login_screen_notification {
+        if self.reconnecting {
+            Paragraph::new(Line::styled(
+                "reconnecting…",
+                Style::new().fg(Color::Yellow),
+            ))
+            .centered()
+            .render(rect_notification, buf);
+        } else if let Some(notification) = &self.login_screen_notification {
             let (color, text) = match notification {
                 Notification::Success(msg) => (Color::Green, msg),
                 Notification::Failure(msg) => (Color::LightRed, msg),
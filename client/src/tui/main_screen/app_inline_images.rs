@@ -0,0 +1,269 @@
+use crate::{
+    tui::app::app::App,
+    util::{
+        ascii_art::{ansi_line, ascii_art},
+        config::IMAGE_MAX_COLS,
+        graphics::{
+            cell_size, clear_images, decode_picture, delete_image, encode_image, image_cells,
+            image_marker, marker_key, read_marker,
+        },
+        types::{GraphicsProtocol, ImagePlacement, InlineImages},
+    },
+};
+use anyhow::Result;
+use ratatui::{
+    DefaultTerminal,
+    buffer::Buffer,
+    crossterm::{cursor::MoveTo, queue, style::Print},
+    layout::Rect,
+    text::Line,
+};
+use shared::types::{ChannelMsg, ImgRender};
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    io::Write,
+};
+use uuid::Uuid;
+
+impl App {
+    /// decodes the pictures of the active conversation that were not looked at yet off the
+    /// runtime, and sizes images for the messages pane `width` cells wide
+    pub fn load_pictures(&mut self, width: u16) {
+        let ascii = self.inline_images.protocol == GraphicsProtocol::Ascii;
+        if !ascii {
+            self.inline_images.cell = cell_size();
+            self.inline_images.max_cols = width.min(IMAGE_MAX_COLS);
+        }
+
+        let pictures = &self.inline_images.pictures;
+        let unseen: Vec<_> = self
+            .active_messages()
+            .into_iter()
+            .flatten()
+            .filter_map(|m| match m {
+                ChannelMsg::Img(img) if !pictures.contains_key(&img.id) => {
+                    Some((img.id, img.image.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        // images show their ascii rendering until the picture is there
+        for (id, png) in unseen {
+            self.inline_images.pictures.insert(id, None);
+            let Some(png) = png else {
+                continue;
+            };
+            let tx_picture_tui = self.tx_picture_tui.clone();
+            tokio::spawn(async move {
+                let decoded = tokio::task::spawn_blocking(move || decode_picture(&png)).await;
+                if let Ok(picture) = decoded {
+                    tx_picture_tui.send((id, picture)).await.ok();
+                }
+            });
+        }
+        if ascii {
+            self.render_ascii(width);
+        }
+    }
+
+    /// ascii art of the pictures of the active conversation at the width of the pane, all of
+    /// it is drawn again once the width or the ascii options change
+    fn render_ascii(&mut self, width: u16) {
+        let options = &self.ascii_options;
+        let drawn_for = (options.width.unwrap_or(width), options.clone());
+        let ids: Vec<Uuid> = self
+            .active_messages()
+            .into_iter()
+            .flatten()
+            .filter_map(|m| match m {
+                ChannelMsg::Img(img) => Some(img.id),
+                _ => None,
+            })
+            .collect();
+
+        let images = &mut self.inline_images;
+        let (rendered_for, art) = &mut images.ascii;
+        if rendered_for.as_ref() != Some(&drawn_for) {
+            art.clear();
+            *rendered_for = Some(drawn_for);
+        }
+        let Some((cols, options)) = rendered_for.as_ref() else {
+            return;
+        };
+        for id in ids {
+            if let (Entry::Vacant(entry), Some(Some(picture))) =
+                (art.entry(id), images.pictures.get(&id))
+            {
+                entry.insert(ascii_art(picture, *cols, options));
+            }
+        }
+    }
+
+    /// the picture as ascii art drawn for this pane, the art of the sender when there is no
+    /// picture to draw it from
+    pub fn ascii_lines(&self, img: &ImgRender) -> Vec<Line<'static>> {
+        let art = self
+            .inline_images
+            .ascii
+            .1
+            .get(&img.id)
+            .unwrap_or(&img.cache);
+        art.lines().map(ansi_line).collect()
+    }
+
+    /// blank rows the picture is drawn over once the frame is done, `None` when the image
+    /// is shown as ascii
+    pub fn image_lines(&self, img: &ImgRender) -> Option<Vec<Line<'static>>> {
+        let images = &self.inline_images;
+        if images.protocol == GraphicsProtocol::Ascii {
+            return None;
+        }
+        let picture = images.pictures.get(&img.id)?.as_ref()?;
+        let (_, rows) = image_cells(picture, images.max_cols, images.cell);
+        Some(
+            (0..rows)
+                .map(|row| Line::from(image_marker(img.id, row)))
+                .collect(),
+        )
+    }
+
+    /// finds the rows left for pictures in the finished frame, blanks them and keeps the
+    /// terminal from writing over the cells the pictures take up
+    pub fn place_images(&mut self, buf: &mut Buffer) {
+        let area = buf.area;
+        if self.inline_images.protocol == GraphicsProtocol::Ascii {
+            self.inline_images.placements = (area, vec![]);
+            return;
+        }
+
+        // left column and key of an image, with the position and row of each marker found
+        let mut found = HashMap::<(u16, u32), Vec<(u16, u16)>>::new();
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right().saturating_sub(2) {
+                let marker = read_marker(
+                    buf[(x, y)].symbol(),
+                    buf[(x + 1, y)].symbol(),
+                    buf[(x + 2, y)].symbol(),
+                );
+                if let Some((key, row)) = marker {
+                    for dx in 0..3 {
+                        buf[(x + dx, y)].set_symbol(" ");
+                    }
+                    found.entry((x, key)).or_default().push((y, row));
+                }
+            }
+        }
+
+        let images = &self.inline_images;
+        let mut placements = vec![];
+        for ((x, key), rows) in found {
+            let Some((id, picture)) = images
+                .pictures
+                .iter()
+                .filter(|(id, _)| marker_key(**id) == key)
+                .find_map(|(id, picture)| Some((*id, picture.as_ref()?)))
+            else {
+                continue;
+            };
+            let (cols, height) = image_cells(picture, images.max_cols, images.cell);
+            let (y, first) = rows[0];
+            let shown = Rect::new(x, y, cols, rows.len() as u16);
+
+            // something drawn over the picture leaves it out
+            let in_one_piece = rows
+                .iter()
+                .zip(0..)
+                .all(|((ry, row), i)| *ry == y + i && *row == first + i);
+            let covered = shown.positions().any(|p| buf[p].symbol() != " ");
+            if !in_one_piece || covered || shown.right() > area.right() {
+                continue;
+            }
+
+            for position in shown.positions() {
+                buf[position].set_skip(true);
+            }
+            placements.push(ImagePlacement {
+                id,
+                area: shown,
+                cols,
+                height,
+                rows: first..first + shown.height,
+            });
+        }
+
+        placements.sort_by_key(|p| (p.area.y, p.area.x));
+        self.inline_images.placements = (area, placements);
+    }
+
+    /// draws the pictures that are new or moved since the last frame. the frame wrote over
+    /// the cells of pictures that are gone, kitty keeps them apart and deletes them itself
+    pub fn show_images(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        if self.inline_images.placements == self.inline_images.shown {
+            return Ok(());
+        }
+
+        let InlineImages {
+            protocol,
+            cell,
+            pictures,
+            encoded,
+            placements,
+            shown,
+            ..
+        } = &mut self.inline_images;
+        encoded.retain(|(id, cols, height, rows), _| {
+            placements
+                .1
+                .iter()
+                .any(|p| p.id == *id && p.cols == *cols && p.height == *height && p.rows == *rows)
+        });
+
+        let backend = terminal.backend_mut();
+        // a resized frame is drawn from scratch, so is every picture on it
+        let kept: &[ImagePlacement] = match placements.0 == shown.0 {
+            true => &shown.1,
+            false => {
+                if let Some(clear) = clear_images(*protocol) {
+                    queue!(backend, Print(clear))?;
+                }
+                &[]
+            }
+        };
+        for gone in kept.iter().filter(|p| !placements.1.contains(p)) {
+            if let Some(delete) = delete_image(*protocol, (gone.area.x, gone.area.y)) {
+                queue!(backend, Print(delete))?;
+            }
+        }
+
+        for placement in placements.1.iter().filter(|p| !kept.contains(p)) {
+            let key = (
+                placement.id,
+                placement.cols,
+                placement.height,
+                placement.rows.clone(),
+            );
+            let sequence = match encoded.entry(key) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let Some(Some(picture)) = pictures.get(&placement.id) else {
+                        continue;
+                    };
+                    let size = (placement.cols, placement.height);
+                    match encode_image(*protocol, picture, size, placement.rows.clone(), *cell) {
+                        Some(sequence) => entry.insert(sequence),
+                        None => continue,
+                    }
+                }
+            };
+            queue!(
+                backend,
+                MoveTo(placement.area.x, placement.area.y),
+                Print(sequence)
+            )?;
+        }
+        backend.flush()?;
+
+        *shown = placements.clone();
+        Ok(())
+    }
+}
@@ -1,21 +1,34 @@
 use crate::{
     tui::app::app::App,
     util::{
-        config::MESSAGES_SCROLL_RESERVE,
-        types::{ActiveScreen, ChannelKind, Focus, Notification},
+        ascii_art::{charset, charset_name},
+        config::{ASCII_CHARSETS, ASCII_MAX_WIDTH, MESSAGES_SCROLL_RESERVE},
+        functions::{change_thread_len, is_text_msg, thread_of, thread_replies},
+        types::{ActiveScreen, ChannelKind, Focus, Notification, ThreadView},
     },
 };
 use anyhow::Result;
 use ratatui::crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
-use shared::types::{AuthResponse, Channel, ChannelMsg, TextMsg};
-use std::collections::VecDeque;
-use tui_textarea::TextArea;
+use shared::{
+    config::UNREAD_COUNT_MAX,
+    types::{
+        AuthResponse, Channel, ChannelMsg, ClientServerMsg, DeliveryNotice, MsgAck, MsgDeletion,
+        MsgReactions, PresenceStatus, PresenceUpdate, Reaction, ReactionUpdate, RoomMember,
+        TextMsg, ThreadRes, User,
+    },
+};
+use std::collections::{HashSet, VecDeque};
+use tui_textarea::{CursorMove, TextArea};
 use uuid::Uuid;
 
 impl App {
     pub fn handle_auth_response(&mut self, data: AuthResponse) {
         match data {
             AuthResponse::Err(msg) => {
+                // the session could not be resumed or was taken over by another login
+                if self.active_screen == ActiveScreen::Main {
+                    self.reset_session();
+                }
                 self.login_screen_notification = Some(Notification::Failure(msg))
             }
             AuthResponse::Ok(init) => {
@@ -33,19 +46,370 @@ impl App {
     }
 
     pub fn handle_text_message(&mut self, msg: TextMsg) {
-        match msg.to {
-            Channel::Room(id) => {
-                if let Some(messages) = self.get_room_messages(id) {
-                    messages.push_front(ChannelMsg::TextMsg(msg))
-                };
+        // thread replies only count towards their root
+        let counts_unread = msg.from.id != self.id && msg.thread.is_none();
+        let conversation = match msg.to {
+            Channel::Room(id) => Channel::Room(id),
+            Channel::User(_) => Channel::User(msg.from.id),
+        };
+        // the message they were typing arrived
+        self.typing_users
+            .retain(|t| !(t.user.id == msg.from.id && t.channel == conversation));
+
+        if let Some(messages) = self.get_conversation_messages(&msg.from, &msg.to) {
+            // the room echo of an own message that was already acked
+            if messages.iter().any(|m| is_text_msg(m, msg.id)) {
+                return;
+            }
+            if let Some(root_id) = msg.thread {
+                change_thread_len(messages, root_id, true);
+            }
+            messages.push_front(ChannelMsg::TextMsg(msg));
+        }
+
+        if counts_unread && let Some(unread) = self.get_unread(&conversation) {
+            *unread = (*unread + 1).min(UNREAD_COUNT_MAX);
+        }
+    }
+
+    /// replies are merged into the conversation, the thread pane picks them out again
+    pub fn handle_thread(&mut self, res: ThreadRes) {
+        let page = match res {
+            Ok(page) => page,
+            Err(reason) => {
+                self.push_active_notice(reason);
+                return;
             }
-            Channel::User(_) => {
-                if let Some(messages) = self.get_direct_messages(msg.from.id) {
-                    messages.push_front(ChannelMsg::TextMsg(msg));
+        };
+
+        let channel = match &mut self.open_thread {
+            Some(view) if view.id == page.id => {
+                view.loaded = true;
+                view.channel.clone()
+            }
+            _ => return,
+        };
+
+        let messages = match channel {
+            Channel::Room(id) => self.get_room_messages(id),
+            Channel::User(id) => self.get_direct_messages(id),
+        };
+
+        if let Some(messages) = messages {
+            let known: HashSet<Uuid> = messages
+                .iter()
+                .filter_map(|m| m.author_and_id().map(|(_, id)| id))
+                .collect();
+            let len = page
+                .replies
+                .iter()
+                .filter(|m| matches!(m, ChannelMsg::TextMsg(_)))
+                .count();
+
+            messages.extend(
+                page.replies
+                    .into_iter()
+                    .filter(|m| m.author_and_id().is_none_or(|(_, id)| !known.contains(&id))),
+            );
+            if let Some(ChannelMsg::TextMsg(root)) =
+                messages.iter_mut().find(|m| is_text_msg(m, page.id))
+            {
+                root.thread_len = len as u32;
+            }
+        }
+    }
+
+    /// own text messages are kept locally until acked, images only show up once relayed
+    pub fn handle_msg_ack(&mut self, ack: MsgAck) {
+        let me = User {
+            username: self.username.clone(),
+            id: self.id,
+        };
+
+        let pending = match self.get_conversation_messages(&me, &ack.to) {
+            Some(messages) => messages.iter_mut().find_map(|m| match m {
+                ChannelMsg::TextMsg(msg) if msg.id == ack.local_id => Some(msg),
+                _ => None,
+            }),
+            None => None,
+        };
+
+        match (ack.res, pending) {
+            (Ok(stamp), Some(msg)) => {
+                msg.id = stamp.id;
+                msg.sent_at = stamp.sent_at;
+                let thread = msg.thread;
+                if stamp.pending {
+                    self.undelivered_msgs.insert(stamp.id);
+                }
+                if self.selected_msg == Some(ack.local_id) {
+                    self.selected_msg = Some(stamp.id);
+                }
+                // the room echo of an own reply is dropped, so it is counted here
+                if let Some(root_id) = thread
+                    && let Some(messages) = self.get_conversation_messages(&me, &ack.to)
+                {
+                    change_thread_len(messages, root_id, true);
                 }
             }
+            (Ok(_), None) => {}
+            (Err(reason), Some(_)) => {
+                self.failed_msgs.insert(ack.local_id, reason);
+            }
+            (Err(reason), None) => {
+                self.push_active_notice(format!("image not delivered: {reason}"))
+            }
+        }
+    }
+
+    pub fn handle_reactions_changed(&mut self, reactions: MsgReactions) {
+        let messages = match self.get_conversation_messages(&reactions.from, &reactions.to) {
+            Some(messages) => messages,
+            None => return,
+        };
+        for msg in messages.iter_mut() {
+            match msg {
+                ChannelMsg::TextMsg(text) if text.id == reactions.id => {
+                    text.reactions = reactions.reactions;
+                    return;
+                }
+                ChannelMsg::Img(img) if img.id == reactions.id => {
+                    img.reactions = reactions.reactions;
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn handle_delivered(&mut self, notice: DeliveryNotice) {
+        for id in notice.ids {
+            self.undelivered_msgs.remove(&id);
+        }
+    }
+
+    /// acks of messages sent before the connection dropped are lost with it
+    pub fn fail_pending_messages(&mut self) {
+        let rooms = self.room_channels.iter().map(|c| &c.messages);
+        let directs = self.direct_channels.iter().map(|c| &c.messages);
+        for m in rooms.chain(directs).flatten() {
+            if let ChannelMsg::TextMsg(msg) = m
+                && msg.from.id == self.id
+                && msg.sent_at == 0
+            {
+                self.failed_msgs
+                    .entry(msg.id)
+                    .or_insert_with(|| String::from("connection lost"));
+            }
         }
     }
+
+    pub fn handle_message_edited(&mut self, msg: TextMsg) {
+        if let Some(messages) = self.get_conversation_messages(&msg.from, &msg.to)
+            && let Some(entry) = messages.iter_mut().find(|m| is_text_msg(m, msg.id))
+        {
+            *entry = ChannelMsg::TextMsg(msg);
+        }
+    }
+
+    pub fn handle_message_deleted(&mut self, deletion: MsgDeletion) {
+        if self.editing_msg == Some(deletion.id) {
+            self.cancel_edit();
+        }
+        if self.selected_msg == Some(deletion.id) {
+            self.selected_msg = None;
+        }
+
+        let thread = deletion.thread;
+        if let Some(messages) = self.get_conversation_messages(&deletion.from, &deletion.to)
+            && let Some(entry) = messages.iter_mut().find(|m| is_text_msg(m, deletion.id))
+        {
+            *entry = ChannelMsg::Deleted(deletion);
+            if let Some(root_id) = thread {
+                change_thread_len(messages, root_id, false);
+            }
+        }
+    }
+
+    /// direct messages are kept under the other user, whichever side sent them
+    fn get_conversation_messages(
+        &mut self,
+        from: &User,
+        to: &Channel,
+    ) -> Option<&mut VecDeque<ChannelMsg>> {
+        match to {
+            Channel::Room(id) => self.get_room_messages(*id),
+            Channel::User(id) if from.id == self.id => self.get_direct_messages(*id),
+            Channel::User(_) => self.get_direct_messages(from.id),
+        }
+    }
+
+    pub fn get_active_messages(&mut self) -> Option<&mut VecDeque<ChannelMsg>> {
+        match self.get_active_channel() {
+            Some(Channel::Room(id)) => self.get_room_messages(id),
+            Some(Channel::User(id)) => self.get_direct_messages(id),
+            None => None,
+        }
+    }
+
+    /// text messages and images can be selected, `older` moves down the list.
+    /// while a thread is open the selection stays in it
+    fn move_message_selection(&mut self, older: bool) {
+        let thread = self.active_thread().map(|t| t.id);
+        let messages = match self.get_active_messages() {
+            Some(messages) => messages,
+            None => return,
+        };
+
+        let shown: Vec<&ChannelMsg> = match thread {
+            Some(root_id) => messages
+                .iter()
+                .filter(|m| is_text_msg(m, root_id))
+                .chain(thread_replies(messages, root_id))
+                .collect(),
+            None => messages.iter().filter(|m| thread_of(m).is_none()).collect(),
+        };
+        let ids: Vec<Uuid> = shown
+            .into_iter()
+            .filter_map(|m| match m {
+                ChannelMsg::TextMsg(msg) => Some(msg.id),
+                ChannelMsg::Img(img) => Some(img.id),
+                _ => None,
+            })
+            .collect();
+
+        let position = self
+            .selected_msg
+            .and_then(|id| ids.iter().position(|i| *i == id));
+        let next = match (position, older) {
+            (None, _) => 0,
+            (Some(i), true) => (i + 1).min(ids.len() - 1),
+            (Some(i), false) => i.saturating_sub(1),
+        };
+
+        self.selected_msg = ids.get(next).copied();
+    }
+
+    /// selected images can only be reacted to, everything else works on text messages
+    fn get_selected_msg(&mut self) -> Option<TextMsg> {
+        let id = self.selected_msg?;
+        let selected = self.get_active_messages()?.iter().find_map(|m| match m {
+            ChannelMsg::TextMsg(msg) if msg.id == id => Some(Some(msg.clone())),
+            ChannelMsg::Img(img) if img.id == id => Some(None),
+            _ => None,
+        })?;
+        if selected.is_none() {
+            self.push_active_notice(String::from("Images can only be reacted to."));
+        }
+        selected
+    }
+
+    fn get_selected_reactions(&mut self) -> Option<(Uuid, Vec<Reaction>)> {
+        let id = self.selected_msg?;
+        self.get_active_messages()?.iter().find_map(|m| match m {
+            ChannelMsg::TextMsg(msg) if msg.id == id => Some((id, msg.reactions.clone())),
+            ChannelMsg::Img(img) if img.id == id => Some((id, img.reactions.clone())),
+            _ => None,
+        })
+    }
+
+    fn edit_selected_message(&mut self) {
+        if let Some(msg) = self.get_selected_msg() {
+            self.editing_msg = Some(msg.id);
+            self.main_text_area = TextArea::from(msg.text.lines());
+            self.main_text_area.move_cursor(CursorMove::Bottom);
+            self.main_text_area.move_cursor(CursorMove::End);
+        }
+    }
+
+    async fn delete_selected_message(&mut self) -> Result<()> {
+        if let Some(msg) = self.get_selected_msg() {
+            self.tx_tui_ws_msg
+                .send(ClientServerMsg::DeleteMessage(msg.id))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// removes the own reaction when there is one, adds it otherwise
+    async fn toggle_reaction(&mut self, emoji: &str) -> Result<()> {
+        let (id, reactions) = match self.get_selected_reactions() {
+            Some(selected) => selected,
+            None => {
+                self.push_active_notice(String::from("Select a message to react to."));
+                return Ok(());
+            }
+        };
+
+        let reacted = reactions
+            .iter()
+            .any(|r| r.emoji == emoji && r.users.iter().any(|u| u.id == self.id));
+        let update = ReactionUpdate {
+            id,
+            emoji: String::from(emoji),
+        };
+        let msg = match reacted {
+            true => ClientServerMsg::RemoveReaction(update),
+            false => ClientServerMsg::AddReaction(update),
+        };
+        self.tx_tui_ws_msg.send(msg).await?;
+
+        Ok(())
+    }
+
+    fn reply_to_selected_message(&mut self) {
+        match self.get_selected_msg() {
+            Some(msg) => self.replying_to = Some(msg),
+            None if self.selected_msg.is_some() => {}
+            None => self.push_active_notice(String::from("Select a message to reply to.")),
+        }
+    }
+
+    /// the thread pane only shows while the conversation it was opened in is active
+    pub fn active_thread(&self) -> Option<&ThreadView> {
+        let view = self.open_thread.as_ref()?;
+        (Some(&view.channel) == self.get_active_channel().as_ref()).then_some(view)
+    }
+
+    /// opens the thread of the selected message, or the one it was posted in
+    async fn toggle_thread(&mut self) -> Result<()> {
+        if self.active_thread().is_some() {
+            self.close_thread();
+            return Ok(());
+        }
+
+        let (msg, channel) = match (self.get_selected_msg(), self.get_active_channel()) {
+            (Some(msg), Some(channel)) => (msg, channel),
+            (None, _) if self.selected_msg.is_some() => return Ok(()),
+            _ => {
+                self.push_active_notice(String::from("Select a message to open its thread."));
+                return Ok(());
+            }
+        };
+
+        let id = msg.thread.unwrap_or(msg.id);
+        self.open_thread = Some(ThreadView {
+            id,
+            channel,
+            loaded: false,
+        });
+        self.selected_msg = None;
+        self.tx_tui_ws_msg
+            .send(ClientServerMsg::FetchThread(id))
+            .await?;
+
+        Ok(())
+    }
+
+    fn close_thread(&mut self) {
+        self.open_thread = None;
+        self.selected_msg = None;
+    }
+
+    fn cancel_edit(&mut self) {
+        self.editing_msg = None;
+        self.main_text_area = TextArea::default();
+    }
     pub fn get_direct_messages(&mut self, id: Uuid) -> Option<&mut VecDeque<ChannelMsg>> {
         let res = self.direct_channels.iter_mut().find(|c| c.user.id == id);
         match res {
@@ -62,6 +426,213 @@ impl App {
         }
     }
 
+    /// `/status online|away|dnd|invisible [text]` keeps the current text when none is given,
+    /// `/status text [text]` keeps the status and sets or clears the text
+    async fn handle_status_command(&mut self, status: Option<&str>, text: String) -> Result<()> {
+        let own = self.own_presence();
+        let update = match status {
+            Some("online") => PresenceUpdate {
+                status: PresenceStatus::Online,
+                text: own.text,
+            },
+            Some("away") => PresenceUpdate {
+                status: PresenceStatus::Away,
+                text: own.text,
+            },
+            Some("dnd") => PresenceUpdate {
+                status: PresenceStatus::DoNotDisturb,
+                text: own.text,
+            },
+            Some("invisible") => PresenceUpdate {
+                status: PresenceStatus::Invisible,
+                text: own.text,
+            },
+            Some("text") => PresenceUpdate {
+                status: own.status,
+                text: String::new(),
+            },
+            _ => {
+                let usage =
+                    "Usage: /status online|away|dnd|invisible [text] or /status text [text]";
+                self.push_active_notice(String::from(usage));
+                return Ok(());
+            }
+        };
+        let update = match text.is_empty() {
+            true => update,
+            false => PresenceUpdate { text, ..update },
+        };
+
+        self.auto_away = false;
+        self.set_presence(update).await
+    }
+
+    /// `/ascii width|charset|color <value>` sets how pictures are drawn as
+    /// ascii, `/ascii` alone shows the settings. chars of a charset go from dark to bright
+    fn handle_ascii_command(&mut self, setting: Option<&str>, value: &str) {
+        let options = &mut self.ascii_options;
+        let notice = match (setting, value.trim()) {
+            (None, _) => format!(
+                "ascii art: width {}, charset {}, color {}",
+                options
+                    .width
+                    .map_or(String::from("auto"), |w| w.to_string()),
+                charset_name(&options.charset),
+                if options.color { "on" } else { "off" }
+            ),
+            (Some("width"), "auto") => {
+                options.width = None;
+                String::from("ascii art fits the messages pane")
+            }
+            (Some("width"), width) => match width.parse() {
+                Ok(width) if (1..=ASCII_MAX_WIDTH).contains(&width) => {
+                    options.width = Some(width);
+                    format!("ascii art is {width} chars wide")
+                }
+                _ => format!("Usage: /ascii width <1-{ASCII_MAX_WIDTH}|auto>"),
+            },
+            (Some("charset"), _) => match charset(value.trim_end()) {
+                Some(chars) => {
+                    options.charset = chars;
+                    format!("ascii art uses charset {}", charset_name(&options.charset))
+                }
+                None => {
+                    let names: Vec<_> = ASCII_CHARSETS.iter().map(|(name, _)| *name).collect();
+                    format!(
+                        "Usage: /ascii charset <{}|chars from dark to bright>",
+                        names.join("|")
+                    )
+                }
+            },
+            (Some("color"), "on") => {
+                options.color = true;
+                String::from("ascii art is in colour")
+            }
+            (Some("color"), "off") => {
+                options.color = false;
+                String::from("ascii art is plain")
+            }
+            _ => String::from("Usage: /ascii [width <n|auto>|charset <name|chars>|color <on|off>]"),
+        };
+        self.push_active_notice(notice);
+    }
+
+    /// `/kick`, `/ban`, `/unban`, `/promote` and `/demote` followed by a username,
+    /// all of them act on the active room. `/react` followed by an emoji toggles it
+    /// on the selected message, `/status`, `/ascii`, the file transfer commands, `/files` and
+    /// `/download` work in any channel
+    pub async fn handle_room_command(&mut self, text: &str) -> Result<()> {
+        let mut parts = text.split_whitespace();
+        let command = parts.next().unwrap_or_default();
+
+        if matches!(
+            command,
+            "/accept" | "/decline" | "/pause" | "/resume" | "/cancel"
+        ) {
+            let filename = parts.collect::<Vec<_>>().join(" ");
+            return self.handle_transfer_command(command, &filename).await;
+        }
+
+        if command == "/files" {
+            return match self.get_active_channel() {
+                Some(channel) => Ok(self
+                    .tx_tui_ws_msg
+                    .send(ClientServerMsg::FetchFiles(channel))
+                    .await?),
+                None => Ok(()),
+            };
+        }
+
+        if command == "/download" {
+            let arg = parts.collect::<Vec<_>>().join(" ");
+            return self.handle_download_command(&arg).await;
+        }
+
+        if command == "/status" {
+            return self
+                .handle_status_command(parts.next(), parts.collect::<Vec<_>>().join(" "))
+                .await;
+        }
+
+        if command == "/ascii" {
+            // a charset may start with a space, the darkest char, so the value is kept as typed
+            let setting = parts.next();
+            let value = setting
+                .and_then(|setting| text.split_once(setting))
+                .and_then(|(_, rest)| rest.strip_prefix(' '))
+                .unwrap_or_default();
+            self.handle_ascii_command(setting, value);
+            return Ok(());
+        }
+
+        if command == "/react" {
+            return match parts.next() {
+                Some(emoji) => self.toggle_reaction(emoji).await,
+                None => {
+                    self.push_active_notice(String::from("Usage: /react <emoji>"));
+                    Ok(())
+                }
+            };
+        }
+
+        let to_msg: fn(RoomMember) -> ClientServerMsg = match command {
+            "/kick" => ClientServerMsg::KickUser,
+            "/ban" => ClientServerMsg::BanUser,
+            "/unban" => ClientServerMsg::UnbanUser,
+            "/promote" => ClientServerMsg::PromoteUser,
+            "/demote" => ClientServerMsg::DemoteUser,
+            _ => {
+                let notice = format!(
+                    "Unknown command {command}, use /kick, /ban, /unban, /promote, /demote, /react, /status, /ascii, /accept, /decline, /pause, /resume, /cancel, /files or /download"
+                );
+                self.push_active_notice(notice);
+                return Ok(());
+            }
+        };
+
+        let room = match self.get_active_channel() {
+            Some(Channel::Room(id)) => self.room_channels.iter().find(|r| r.id == id),
+            _ => None,
+        };
+        let room = match room {
+            Some(room) => room,
+            None => {
+                self.push_active_notice(String::from("Room commands only work in a room."));
+                return Ok(());
+            }
+        };
+
+        let username = match parts.next() {
+            Some(username) => username,
+            None => {
+                self.push_active_notice(format!("Usage: {command} <username>"));
+                return Ok(());
+            }
+        };
+
+        // banned users are no longer members, but they can still be unbanned
+        let user_id = room
+            .users
+            .iter()
+            .chain(room.roles.banned.iter())
+            .find(|u| u.username == username)
+            .map(|u| u.id);
+
+        let room_id = room.id;
+        let user_id = match user_id {
+            Some(id) => id,
+            None => {
+                self.push_active_notice(format!("No user named {username} in this room."));
+                return Ok(());
+            }
+        };
+
+        let msg = to_msg(RoomMember { room_id, user_id });
+        self.tx_tui_ws_msg.send(msg).await?;
+
+        Ok(())
+    }
+
     pub async fn handle_main_screen_event(&mut self, event: Event) -> Result<()> {
         match event {
             Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
@@ -79,6 +650,28 @@ impl App {
                     self.display_room_creator = true
                 }
 
+                if ((key_event.code == KeyCode::Char('l'))
+                    || (key_event.code == KeyCode::Char('L')))
+                    && key_event.modifiers.contains(KeyModifiers::CONTROL)
+                {
+                    self.leave_active_room().await?
+                }
+
+                if ((key_event.code == KeyCode::Char('d'))
+                    || (key_event.code == KeyCode::Char('D')))
+                    && key_event.modifiers.contains(KeyModifiers::CONTROL)
+                {
+                    self.delete_active_room().await?
+                }
+
+                if ((key_event.code == KeyCode::Char('p'))
+                    || (key_event.code == KeyCode::Char('P')))
+                    && key_event.modifiers.contains(KeyModifiers::CONTROL)
+                {
+                    self.display_transfers = true;
+                    return Ok(());
+                }
+
                 match self.focus {
                     Focus::Contacts => self.handle_contacts_event(key_event).await?,
                     Focus::Messages => self.handle_messages_event(key_event).await?,
@@ -90,6 +683,24 @@ impl App {
         Ok(())
     }
 
+    async fn leave_active_room(&mut self) -> Result<()> {
+        if let Some(Channel::Room(id)) = self.get_active_channel() {
+            self.tx_tui_ws_msg
+                .send(ClientServerMsg::LeaveRoom(id))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn delete_active_room(&mut self) -> Result<()> {
+        if let Some(Channel::Room(id)) = self.get_active_channel() {
+            self.tx_tui_ws_msg
+                .send(ClientServerMsg::DeleteRoom(id))
+                .await?;
+        }
+        Ok(())
+    }
+
     async fn handle_contacts_event(&mut self, key_event: KeyEvent) -> Result<()> {
         match key_event.code {
             KeyCode::Char('c') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
@@ -97,6 +708,9 @@ impl App {
             }
             KeyCode::Tab => self.switch_focus(),
 
+            KeyCode::Esc if self.editing_msg.is_some() => self.cancel_edit(),
+            KeyCode::Esc if self.replying_to.is_some() => self.replying_to = None,
+            KeyCode::Esc if self.active_thread().is_some() => self.close_thread(),
             KeyCode::Esc => self.logout().await?,
             KeyCode::Up => self.move_active_channel_up(),
             KeyCode::Down => self.move_active_channel_down(),
@@ -104,7 +718,9 @@ impl App {
             KeyCode::Right => self.switch_channel_kind(),
             KeyCode::Enter => self.send_message().await?,
             _ => {
-                self.main_text_area.input(key_event);
+                if self.main_text_area.input(key_event) {
+                    self.send_typing().await?;
+                }
             }
         };
         Ok(())
@@ -118,11 +734,55 @@ impl App {
             KeyCode::Tab => self.switch_focus(),
 
             KeyCode::Enter => self.send_message().await?,
+            KeyCode::Esc if self.editing_msg.is_some() => self.cancel_edit(),
+            KeyCode::Esc if self.replying_to.is_some() => self.replying_to = None,
+            KeyCode::Esc if self.active_thread().is_some() => self.close_thread(),
             KeyCode::Esc => self.logout().await?,
+            KeyCode::Up if key_event.modifiers.contains(KeyModifiers::SHIFT) => {
+                self.move_message_selection(false)
+            }
+            KeyCode::Down if key_event.modifiers.contains(KeyModifiers::SHIFT) => {
+                self.move_message_selection(true)
+            }
+            KeyCode::Char('e') | KeyCode::Char('E')
+                if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                self.edit_selected_message()
+            }
+            KeyCode::Char('x') | KeyCode::Char('X')
+                if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                self.delete_selected_message().await?
+            }
+            KeyCode::Char('t') | KeyCode::Char('T')
+                if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                self.toggle_reaction("👍").await?
+            }
+            KeyCode::Char('k') | KeyCode::Char('K')
+                if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                self.toggle_reaction("✅").await?
+            }
+            KeyCode::Char('q') | KeyCode::Char('Q')
+                if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                self.reply_to_selected_message()
+            }
+            KeyCode::Char('o') | KeyCode::Char('O')
+                if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                self.toggle_thread().await?
+            }
             KeyCode::Up => self.move_scrollbar_up(),
-            KeyCode::Down => self.move_scrollbar_down(),
+            KeyCode::Down => {
+                self.move_scrollbar_down();
+                self.load_older_history().await?;
+            }
             _ => {
-                self.main_text_area.input(key_event);
+                if self.main_text_area.input(key_event) {
+                    self.send_typing().await?;
+                }
             }
         };
         Ok(())
@@ -135,11 +795,23 @@ impl App {
     }
 
     fn move_scrollbar_down(&mut self) {
-        if self.main_scroll_offset < MESSAGES_SCROLL_RESERVE {
+        if self.main_scroll_offset + 3 < self.messages_line_count {
             self.main_scroll_offset += 3;
         }
     }
 
+    async fn load_older_history(&mut self) -> Result<()> {
+        if self.main_scroll_offset + MESSAGES_SCROLL_RESERVE < self.messages_line_count {
+            return Ok(());
+        }
+
+        if let Some(channel) = self.get_active_channel() {
+            self.fetch_history(channel).await?;
+        }
+
+        Ok(())
+    }
+
     pub fn switch_channel_kind(&mut self) {
         let new_kind = match self.active_channel.kind {
             ChannelKind::Direct => ChannelKind::Room,
@@ -1,11 +1,8 @@
 use crate::{
     tui::{app::app::App, main_screen::main_screen_tui_conversions::LineWrapper},
     util::{
-        config::{
-            MESSAGES_SCROLL_RESERVE, THEME_GRAY_GREEN_DARK, THEME_GREEN, THEME_YELLOW_DARK,
-            THEME_YELLOW_LIGHT,
-        },
-        functions::pad_line_to_width,
+        config::{THEME_GRAY_GREEN_DARK, THEME_GREEN, THEME_YELLOW_DARK, THEME_YELLOW_LIGHT},
+        functions::{pad_line_to_width, presence_label, role_name, thread_of, thread_replies},
         types::{ChannelKind, Contact, Focus},
     },
 };
@@ -20,7 +17,12 @@ use ratatui::{
         Wrap,
     },
 };
-use shared::types::ChannelMsg;
+use shared::{
+    config::PUBLIC_ROOM_ID,
+    types::{Channel, ChannelMsg, Reaction, RoomRole, TextMsg},
+};
+use std::{collections::VecDeque, str::FromStr};
+use uuid::Uuid;
 
 impl App {
     pub fn render_main_screen(&mut self, area: Rect, buf: &mut Buffer) {
@@ -50,24 +52,64 @@ impl App {
         let span_navigate_s = Span::from("< ←,→,↑,↓ >    ").style(Style::new().fg(Color::White));
         let span_file = Span::from(" send file: ").style(Style::new().fg(Color::DarkGray));
         let span_file_s = Span::from("< Ctrl + (f,F) >    ").style(Style::new().fg(Color::White));
+        let span_transfers = Span::from(" transfers: ").style(Style::new().fg(Color::DarkGray));
+        let span_transfers_s =
+            Span::from("< Ctrl + (p,P) >    ").style(Style::new().fg(Color::White));
         let span_room = Span::from(" create room: ").style(Style::new().fg(Color::DarkGray));
         let span_room_s = Span::from("< Ctrl + (r,R) >    ").style(Style::new().fg(Color::White));
+        let span_leave = Span::from(" leave room: ").style(Style::new().fg(Color::DarkGray));
+        let span_leave_s = Span::from("< Ctrl + (l,L) >    ").style(Style::new().fg(Color::White));
+        let span_delete = Span::from(" delete room: ").style(Style::new().fg(Color::DarkGray));
+        let span_delete_s = Span::from("< Ctrl + (d,D) >    ").style(Style::new().fg(Color::White));
+        let span_moderate = Span::from(" moderate: ").style(Style::new().fg(Color::DarkGray));
+        let span_moderate_s = Span::from("< /kick /ban /unban /promote /demote name >    ")
+            .style(Style::new().fg(Color::White));
+        let span_select = Span::from(" select message: ").style(Style::new().fg(Color::DarkGray));
+        let span_select_s = Span::from("< Shift + ↑,↓ >    ").style(Style::new().fg(Color::White));
+        let span_change =
+            Span::from(" edit / delete message: ").style(Style::new().fg(Color::DarkGray));
+        let span_change_s = Span::from("< Ctrl + (e,x) >    ").style(Style::new().fg(Color::White));
+        let span_react = Span::from(" react: ").style(Style::new().fg(Color::DarkGray));
+        let span_react_s =
+            Span::from("< Ctrl + (t,k) /react emoji >    ").style(Style::new().fg(Color::White));
+        let span_reply = Span::from(" reply / thread: ").style(Style::new().fg(Color::DarkGray));
+        let span_reply_s = Span::from("< Ctrl + (q,o) >    ").style(Style::new().fg(Color::White));
         let span_logout = Span::from(" logout: ").style(Style::new().fg(Color::DarkGray));
         let span_logout_s = Span::from("< Esc >").style(Style::new().fg(Color::White));
 
-        let options_line = Line::from(vec![
-            span_switch,
-            span_switch_s,
-            span_navigate,
-            span_navigate_s,
-            span_file,
-            span_file_s,
-            span_room,
-            span_room_s,
-            span_logout,
-            span_logout_s,
-        ])
-        .centered();
+        let options_line = if self.reconnecting {
+            Line::styled("reconnecting…", Style::new().fg(Color::Yellow).bold()).centered()
+        } else {
+            Line::from(vec![
+                span_switch,
+                span_switch_s,
+                span_navigate,
+                span_navigate_s,
+                span_file,
+                span_file_s,
+                span_transfers,
+                span_transfers_s,
+                span_room,
+                span_room_s,
+                span_leave,
+                span_leave_s,
+                span_delete,
+                span_delete_s,
+                span_moderate,
+                span_moderate_s,
+                span_select,
+                span_select_s,
+                span_change,
+                span_change_s,
+                span_react,
+                span_react_s,
+                span_reply,
+                span_reply_s,
+                span_logout,
+                span_logout_s,
+            ])
+            .centered()
+        };
 
         options_line.render(area_bottom_bar.inner(Margin::new(1, 1)), buf);
 
@@ -108,9 +150,13 @@ impl App {
                     .direct_channels
                     .iter()
                     .find(|c| c.user.id == self.active_channel.id.unwrap());
-                match channel {
-                    None => "".to_string(),
-                    Some(c) => format!(" {} ", c.user.username.clone()),
+                let label = channel
+                    .and_then(|c| self.presence.get(&c.user.id))
+                    .and_then(presence_label);
+                match (channel, label) {
+                    (None, _) => "".to_string(),
+                    (Some(c), None) => format!(" {} ", c.user.username.clone()),
+                    (Some(c), Some(label)) => format!(" {} · {} ", c.user.username.clone(), label),
                 }
             }
             (Some(_), ChannelKind::Room) => {
@@ -120,12 +166,15 @@ impl App {
                     .find(|c| c.id == self.active_channel.id.unwrap());
                 match channel {
                     None => "".to_string(),
-                    Some(c) => format!(" {} ", c.name.clone()),
+                    Some(c) => match c.roles.role_of(self.id) {
+                        RoomRole::Member => format!(" {} ", c.name.clone()),
+                        role => format!(" {} · {} ", c.name.clone(), role_name(role)),
+                    },
                 }
             }
         };
 
-        let title_messages = Span::styled(format!("{}", messages_title_text), style_messages_title);
+        let title_messages = Span::styled(messages_title_text, style_messages_title);
 
         let style_contacts_title = match self.focus {
             Focus::Messages => Style::default()
@@ -153,11 +202,25 @@ impl App {
             ChannelKind::Direct => " Users ",
             ChannelKind::Room => " Rooms ",
         };
+        // the own status is shown once it is anything but online
+        let title_contacts_text = match self.presence.get(&self.id).and_then(presence_label) {
+            None => String::from(title_contacts_text),
+            Some(label) => format!("{}· {} ", title_contacts_text, label),
+        };
 
         let title_contacts = Span::styled(title_contacts_text, style_contacts_title);
 
+        let title_input_text = match (self.editing_msg, &self.replying_to) {
+            (Some(_), _) => String::from(" Editing message, Esc cancels "),
+            (None, Some(msg)) => format!(" Replying to {}, Esc cancels ", msg.from.username),
+            (None, None) if self.active_thread().is_some() => {
+                String::from(" Replying in thread, Esc closes it ")
+            }
+            (None, None) => String::from(" Input "),
+        };
+
         let title_input = Span::styled(
-            " Input ",
+            title_input_text,
             Style::default()
                 .fg(Color::Rgb(
                     THEME_GRAY_GREEN_DARK.0,
@@ -173,8 +236,17 @@ impl App {
             .constraints(vec![Constraint::Fill(1), Constraint::Length(5)])
             .split(area_messages_and_intput);
 
-        let area_messages = layout_messages_and_intput[0].inner(Margin::new(0, 0));
+        let mut area_messages = layout_messages_and_intput[0].inner(Margin::new(0, 0));
         let area_input = layout_messages_and_intput[1].inner(Margin::new(0, 0));
+
+        if let Some((root_id, loaded)) = self.active_thread().map(|t| (t.id, t.loaded)) {
+            let layout_thread = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![Constraint::Percentage(60), Constraint::Percentage(40)])
+                .split(area_messages);
+            area_messages = layout_thread[0];
+            self.render_thread_pane(root_id, loaded, layout_thread[1], buf);
+        }
         self.main_text_area
             .render(area_input.inner(Margin::new(2, 2)), buf);
 
@@ -208,10 +280,26 @@ impl App {
 
         match self.active_channel.kind {
             ChannelKind::Direct => {
+                let public_room_id = Uuid::from_str(PUBLIC_ROOM_ID).unwrap();
+                let online = match self.room_channels.iter().find(|r| r.id == public_room_id) {
+                    Some(room) => room.users_online.as_slice(),
+                    None => &[],
+                };
+
                 for c in &self.direct_channels {
                     let contact = Contact::Direct(c);
-                    let mut contact_option =
-                        pad_line_to_width(LineWrapper::from(&contact).into(), area.width);
+                    let mut line: Line = LineWrapper::from(&contact).into();
+                    if let Some(label) = self.presence.get(&c.user.id).and_then(presence_label) {
+                        line.push_span(Span::from(format!(" {label}")).dark_gray().italic());
+                    }
+                    let mut contact_option = pad_line_to_width(line, area.width);
+                    // offline users still get messages, once they log in
+                    if !online.contains(&c.user) {
+                        contact_option = contact_option.dark_gray();
+                    }
+                    if c.unread > 0 {
+                        contact_option = contact_option.bold();
+                    }
                     match self.active_channel.id {
                         Some(id) if id == c.user.id => {
                             contact_option.style = Style::default()
@@ -237,6 +325,9 @@ impl App {
                     let room = Contact::Room(c);
                     let mut contact_item =
                         pad_line_to_width(LineWrapper::from(&room).into(), area.width);
+                    if c.unread > 0 {
+                        contact_item = contact_item.bold();
+                    }
                     match self.active_channel.id {
                         Some(id) if id == c.id => {
                             contact_item.style = Style::default()
@@ -274,62 +365,30 @@ impl App {
             }
         };
 
-        let block_messages = Block::bordered()
+        let mut block_messages = Block::bordered()
             .title(title_messages)
             .border_set(border::PLAIN)
             .border_style(style_messages_border);
+        if let Some(line) = self.typing_line() {
+            block_messages = block_messages.title_bottom(line);
+        }
 
+        // clear of the borders and the scrollbar
+        self.messages_width = area_messages.width.saturating_sub(4);
+        self.load_pictures(self.messages_width);
         let mut messages: Vec<Line> = vec![];
 
-        if let Some(id) = &self.active_channel.id {
-            match &self.active_channel.kind {
-                ChannelKind::Direct => {
-                    match self.direct_channels.iter().find(|c| &c.user.id == id) {
-                        None => {}
-                        Some(c) => {
-                            for m in c.messages.iter() {
-                                match m {
-                                    ChannelMsg::Img(img) => {
-                                        for line in img.cache.lines() {
-                                            messages.push(line.into());
-                                        }
-                                    }
-                                    ChannelMsg::JoinNotification(n) => {
-                                        messages.push(LineWrapper::from(n).into());
-                                    }
-                                    ChannelMsg::TextMsg(msg) => {
-                                        messages.push(LineWrapper::from(msg).into());
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                ChannelKind::Room => match self.room_channels.iter().find(|c| &c.id == id) {
-                    None => {}
-                    Some(c) => {
-                        for m in c.messages.iter() {
-                            match m {
-                                ChannelMsg::Img(img) => {
-                                    for line in img.cache.lines() {
-                                        messages.push(line.into());
-                                    }
-                                }
-                                ChannelMsg::JoinNotification(n) => {
-                                    messages.push(LineWrapper::from(n).into());
-                                }
-                                ChannelMsg::TextMsg(msg) => {
-                                    messages.push(LineWrapper::from(msg).into());
-                                }
-                            }
-                        }
-                    }
-                },
-            };
-        };
+        // thread replies only show in the thread pane
+        if let Some(channel_messages) = self.active_messages() {
+            for m in channel_messages.iter().filter(|m| thread_of(m).is_none()) {
+                messages.extend(self.message_lines(m, true));
+            }
+        }
+
+        self.messages_line_count = messages.len();
 
         let mut scrollbar_state =
-            ScrollbarState::new(MESSAGES_SCROLL_RESERVE).position(self.main_scroll_offset.into());
+            ScrollbarState::new(self.messages_line_count).position(self.main_scroll_offset);
 
         let scrollbar: Scrollbar<'_> = Scrollbar::new(ScrollbarOrientation::VerticalRight)
             .orientation(ScrollbarOrientation::VerticalRight)
@@ -363,4 +422,196 @@ impl App {
             .scroll((self.main_scroll_offset as u16, 0))
             .render(area_messages, buf);
     }
+
+    pub fn active_messages(&self) -> Option<&VecDeque<ChannelMsg>> {
+        match self.get_active_channel()? {
+            Channel::Room(id) => self
+                .room_channels
+                .iter()
+                .find(|c| c.id == id)
+                .map(|c| &c.messages),
+            Channel::User(id) => self
+                .direct_channels
+                .iter()
+                .find(|c| c.user.id == id)
+                .map(|c| &c.messages),
+        }
+    }
+
+    /// `with_thread` adds the reply count under thread roots, the thread pane leaves it out
+    fn message_lines(&self, m: &ChannelMsg, with_thread: bool) -> Vec<Line<'static>> {
+        let mut lines: Vec<Line<'static>> = vec![];
+
+        match m {
+            ChannelMsg::Img(img) => {
+                let line: Line = LineWrapper::from(img).into();
+                match self.selected_msg == Some(img.id) {
+                    true => lines.push(line.reversed()),
+                    false => lines.push(line),
+                }
+                match self.image_lines(img) {
+                    Some(rows) => lines.extend(rows),
+                    None => lines.extend(self.ascii_lines(img)),
+                }
+                if let Some(reactions) = self.reactions_line(&img.reactions) {
+                    lines.push(reactions);
+                }
+            }
+            ChannelMsg::JoinNotification(n) => {
+                lines.push(LineWrapper::from(n).into());
+            }
+            ChannelMsg::TextMsg(msg) => {
+                if let Some(reply) = &msg.reply_to {
+                    lines.push(LineWrapper::from(reply).into());
+                }
+                let mut line: Line = LineWrapper::from(msg).into();
+                if let Some(state) = self.delivery_span(msg) {
+                    line.push_span(state);
+                }
+                match self.selected_msg == Some(msg.id) {
+                    true => lines.push(line.reversed()),
+                    false => lines.push(line),
+                }
+                if let Some(reactions) = self.reactions_line(&msg.reactions) {
+                    lines.push(reactions);
+                }
+                if with_thread && msg.thread_len > 0 {
+                    let text = match msg.thread_len {
+                        1 => String::from("      ↳ 1 reply"),
+                        n => format!("      ↳ {n} replies"),
+                    };
+                    lines.push(Line::from(Span::from(text).dark_gray().italic()));
+                }
+            }
+            ChannelMsg::Refused(refusal) => {
+                lines.push(LineWrapper::from(refusal).into());
+            }
+            ChannelMsg::Notice(text) => {
+                lines.push(Line::from(text.clone()).italic());
+            }
+            ChannelMsg::Deleted(deletion) => {
+                lines.push(LineWrapper::from(deletion).into());
+            }
+        }
+
+        lines
+    }
+
+    /// root on top, replies below it newest first like the conversation
+    fn render_thread_pane(&self, root_id: Uuid, loaded: bool, area: Rect, buf: &mut Buffer) {
+        let mut lines: Vec<Line> = vec![];
+
+        if let Some(messages) = self.active_messages() {
+            if let Some(root) = messages
+                .iter()
+                .find(|m| m.author_and_id().is_some_and(|(_, id)| id == root_id))
+            {
+                lines.extend(self.message_lines(root, false));
+            }
+
+            let replies = thread_replies(messages, root_id);
+            let summary = match (loaded, replies.len()) {
+                (false, _) => String::from("loading…"),
+                (true, 1) => String::from("1 reply"),
+                (true, n) => format!("{n} replies"),
+            };
+            lines.push(Line::from(Span::from(summary).dark_gray().italic()));
+
+            for m in replies {
+                lines.extend(self.message_lines(m, false));
+            }
+        }
+
+        let title = Span::styled(
+            " Thread ",
+            Style::default()
+                .fg(Color::Rgb(
+                    THEME_GRAY_GREEN_DARK.0,
+                    THEME_GRAY_GREEN_DARK.1,
+                    THEME_GRAY_GREEN_DARK.2,
+                ))
+                .bg(Color::Rgb(THEME_GREEN.0, THEME_GREEN.1, THEME_GREEN.2))
+                .bold(),
+        );
+
+        let block = Block::bordered()
+            .title(title)
+            .border_set(border::PLAIN)
+            .border_style(Style::default().fg(Color::Rgb(
+                THEME_GREEN.0,
+                THEME_GREEN.1,
+                THEME_GREEN.2,
+            )));
+
+        Paragraph::new(lines)
+            .block(block)
+            .wrap(Wrap { trim: false })
+            .render(area, buf);
+    }
+
+    fn typing_line(&self) -> Option<Line<'static>> {
+        let channel = self.get_active_channel()?;
+        let names: Vec<&str> = self
+            .typing_users
+            .iter()
+            .filter(|t| t.channel == channel)
+            .map(|t| t.user.username.as_str())
+            .collect();
+
+        let text = match names.as_slice() {
+            [] => return None,
+            [name] => format!(" {name} is typing… "),
+            [first, second] => format!(" {first} and {second} are typing… "),
+            _ => String::from(" several people are typing… "),
+        };
+        Some(Line::from(Span::from(text).italic()))
+    }
+
+    /// only own messages carry a delivery state
+    fn delivery_span(&self, msg: &TextMsg) -> Option<Span<'static>> {
+        if msg.from.id != self.id {
+            return None;
+        }
+
+        let span = match (msg.sent_at, self.failed_msgs.get(&msg.id)) {
+            (0, Some(reason)) => Span::from(format!(" ✗ failed: {reason}")).red().italic(),
+            (0, None) => Span::from(" …").dark_gray(),
+            _ if self.undelivered_msgs.contains(&msg.id) => {
+                Span::from(" ✓ pending").dark_gray().italic()
+            }
+            _ if self.is_seen(msg) => Span::from(" ✓ seen").dark_gray(),
+            _ => Span::from(" ✓").dark_gray(),
+        };
+        Some(span)
+    }
+
+    /// server ids are v7, everything up to the other user's marker was read
+    fn is_seen(&self, msg: &TextMsg) -> bool {
+        let other_id = match msg.to {
+            Channel::User(id) => id,
+            Channel::Room(_) => return false,
+        };
+        self.direct_channels
+            .iter()
+            .find(|d| d.user.id == other_id)
+            .and_then(|d| d.seen)
+            .is_some_and(|seen| msg.id <= seen)
+    }
+
+    /// shown under the message, own reactions stand out
+    fn reactions_line(&self, reactions: &[Reaction]) -> Option<Line<'static>> {
+        if reactions.is_empty() {
+            return None;
+        }
+
+        let mut spans = vec![Span::from("      ")];
+        for reaction in reactions {
+            let span = Span::from(format!("{} {}  ", reaction.emoji, reaction.users.len()));
+            match reaction.users.iter().any(|u| u.id == self.id) {
+                true => spans.push(span.bold().yellow()),
+                false => spans.push(span.dark_gray()),
+            }
+        }
+        Some(Line::from(spans))
+    }
 }
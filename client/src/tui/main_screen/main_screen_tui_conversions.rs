@@ -1,13 +1,18 @@
-use crate::util::types::Contact;
-use shared::types::{ChannelMsg, DirectChannel, ImgRender, TextMsg, TuiRoom, User};
+use crate::util::{
+    functions::{format_sent_at, unread_label},
+    types::Contact,
+};
+use shared::types::{
+    ChannelMsg, DirectChannel, ImgRender, MsgDeletion, MsgRefusal, RefusedMsg, ReplyRef, TextMsg,
+    TuiRoom, User,
+};
 
 use ratatui::{
     style::Stylize,
-    text::{Line, Span, Text},
+    text::{Line, Span},
 };
 
 pub struct LineWrapper(pub Line<'static>);
-pub struct TextWrapper(pub Text<'static>);
 
 impl From<LineWrapper> for Line<'static> {
     fn from(value: LineWrapper) -> Self {
@@ -20,23 +25,74 @@ impl From<&ChannelMsg> for LineWrapper {
         match msg {
             ChannelMsg::JoinNotification(notification) => LineWrapper::from(notification),
             ChannelMsg::TextMsg(msg) => LineWrapper::from(msg),
-            ChannelMsg::Img(_) => todo!(),
+            ChannelMsg::Img(img) => LineWrapper::from(img),
+            ChannelMsg::Refused(refusal) => LineWrapper::from(refusal),
+            ChannelMsg::Notice(text) => LineWrapper(Line::from(Span::from(text.clone()).italic())),
+            ChannelMsg::Deleted(deletion) => LineWrapper::from(deletion),
         }
     }
 }
 
-impl From<&ImgRender> for TextWrapper {
+impl From<&MsgRefusal> for LineWrapper {
+    fn from(refusal: &MsgRefusal) -> Self {
+        let what = match &refusal.msg {
+            RefusedMsg::FileMetadata { filename, .. } => format!("file {filename}"),
+            RefusedMsg::FileChunk { .. } => String::from("file"),
+        };
+        let text = format!("not delivered: {what} ({})", refusal.reason);
+        LineWrapper(Line::from(Span::from(text).italic().red()))
+    }
+}
+
+/// header line above the rendered image
+impl From<&ImgRender> for LineWrapper {
     fn from(img: &ImgRender) -> Self {
-        let text = Span::from(img.cache.clone());
-        TextWrapper(Text::from(text))
+        let sent_at = sent_at_span(img.sent_at);
+        let username = Span::from(img.from.username.clone() + ":").bold();
+        LineWrapper(Line::from(vec![sent_at, username]))
+    }
+}
+
+/// pending messages have no server time yet, a blank of the same width keeps lines aligned
+fn sent_at_span(sent_at: i64) -> Span<'static> {
+    match sent_at {
+        0 => Span::from("      "),
+        _ => Span::from(format_sent_at(sent_at) + " ").dark_gray(),
     }
 }
 
 impl From<&TextMsg> for LineWrapper {
     fn from(msg: &TextMsg) -> Self {
+        let sent_at = sent_at_span(msg.sent_at);
         let text = Span::from(msg.text.clone());
         let username = Span::from(msg.from.username.clone() + ": ").bold();
-        LineWrapper(Line::from(vec![username, text]))
+        let mut spans = vec![sent_at, username, text];
+        if msg.edited {
+            spans.push(Span::from(" (edited)").dark_gray().italic());
+        }
+        LineWrapper(Line::from(spans))
+    }
+}
+
+/// quote shown above a reply
+impl From<&ReplyRef> for LineWrapper {
+    fn from(reply: &ReplyRef) -> Self {
+        let text = format!("      ↪ {}: {}", reply.from.username, reply.quote);
+        LineWrapper(Line::from(Span::from(text).dark_gray().italic()))
+    }
+}
+
+impl From<&MsgDeletion> for LineWrapper {
+    fn from(deletion: &MsgDeletion) -> Self {
+        let username = Span::from(deletion.from.username.clone() + ": ").bold();
+        let text = match deletion.by.id == deletion.from.id {
+            true => String::from("message deleted"),
+            false => format!("message deleted by {}", deletion.by.username),
+        };
+        LineWrapper(Line::from(vec![
+            username,
+            Span::from(text).dark_gray().italic(),
+        ]))
     }
 }
 
@@ -49,13 +105,23 @@ impl From<&User> for LineWrapper {
 }
 impl From<&TuiRoom> for LineWrapper {
     fn from(c: &TuiRoom) -> Self {
-        LineWrapper(Line::from(c.name.clone()))
+        LineWrapper(contact_line(c.name.clone(), c.unread))
     }
 }
 
 impl From<&DirectChannel> for LineWrapper {
     fn from(c: &DirectChannel) -> Self {
-        LineWrapper(Line::from(c.user.username.clone()))
+        LineWrapper(contact_line(c.user.username.clone(), c.unread))
+    }
+}
+
+fn contact_line(name: String, unread: u32) -> Line<'static> {
+    match unread {
+        0 => Line::from(name),
+        n => Line::from(vec![
+            Span::from(name),
+            Span::from(format!(" ({})", unread_label(n))),
+        ]),
     }
 }
 
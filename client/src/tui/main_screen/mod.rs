@@ -1,3 +1,4 @@
+pub mod app_inline_images;
 pub mod app_main_screen;
 pub mod main_screen_display;
 pub mod main_screen_tui_conversions;
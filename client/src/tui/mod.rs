@@ -5,7 +5,7 @@ pub mod main_screen;
 use anyhow::Result;
 use app::app::App;
 
-use crate::util::types::TuiUpdate;
+use crate::util::types::{TuiUpdate, WsStreamMsg};
 use shared::types::{Chunk, ClientServerAuthMsg, ClientServerMsg};
 
 pub async fn app(
@@ -13,9 +13,16 @@ pub async fn app(
     tx_tui_ws_file: tokio::sync::mpsc::Sender<Chunk>,
     tx_tui_ws_msg: tokio::sync::mpsc::Sender<ClientServerMsg>,
     tx_tui_ws_auth: tokio::sync::mpsc::Sender<ClientServerAuthMsg>,
+    tx_tui_stream: tokio::sync::mpsc::Sender<WsStreamMsg>,
 ) -> Result<()> {
     let mut terminal = ratatui::init();
-    let mut app = App::new(rx_ws_tui, tx_tui_ws_file, tx_tui_ws_msg, tx_tui_ws_auth);
+    let mut app = App::new(
+        rx_ws_tui,
+        tx_tui_ws_file,
+        tx_tui_ws_msg,
+        tx_tui_ws_auth,
+        tx_tui_stream,
+    );
 
     app.run(&mut terminal).await?;
     ratatui::restore();
@@ -0,0 +1,149 @@
+use crate::util::{
+    config::{
+        ASCII_CHAR_ASPECT, ASCII_CHARSET_ENV, ASCII_CHARSETS, ASCII_COLOR_ENV, ASCII_MAX_ROWS,
+        ASCII_MAX_WIDTH, ASCII_WIDTH_ENV,
+    },
+    types::AsciiOptions,
+};
+use image::{DynamicImage, imageops::FilterType};
+use ratatui::{
+    style::{Color, Style},
+    text::{Line, Span},
+};
+use std::fmt::Write;
+
+/// options set in the environment, the standard charset in colour at the width of the pane otherwise
+pub fn ascii_options() -> AsciiOptions {
+    let width = std::env::var(ASCII_WIDTH_ENV)
+        .ok()
+        .and_then(|w| w.parse().ok())
+        .filter(|w| (1..=ASCII_MAX_WIDTH).contains(w));
+    let charset = std::env::var(ASCII_CHARSET_ENV)
+        .ok()
+        .and_then(|name| charset(&name))
+        .unwrap_or_else(|| String::from(ASCII_CHARSETS[0].1));
+    let color = std::env::var(ASCII_COLOR_ENV)
+        .map(|c| !["off", "0", "false"].contains(&c.to_lowercase().as_str()))
+        .unwrap_or(true);
+
+    AsciiOptions {
+        width,
+        charset,
+        color,
+    }
+}
+
+/// chars of the charset called `name`, any other text of two chars or more is a charset itself
+pub fn charset(name: &str) -> Option<String> {
+    match ASCII_CHARSETS.iter().find(|(n, _)| *n == name) {
+        Some((_, chars)) => Some(String::from(*chars)),
+        None => (name.chars().count() >= 2).then(|| String::from(name)),
+    }
+}
+
+/// name of the charset, or its chars when it has none
+pub fn charset_name(chars: &str) -> String {
+    match ASCII_CHARSETS.iter().find(|(_, c)| *c == chars) {
+        Some((name, _)) => String::from(*name),
+        None => format!("\"{chars}\""),
+    }
+}
+
+/// `cols` wide keeping the aspect of the picture, with as many rows as that takes
+pub fn ascii_art(picture: &DynamicImage, cols: u16, options: &AsciiOptions) -> String {
+    let chars: Vec<char> = options.charset.chars().collect();
+    let (width, height) = (
+        picture.width().max(1) as f32,
+        picture.height().max(1) as f32,
+    );
+
+    let mut cols = cols.clamp(1, ASCII_MAX_WIDTH) as f32;
+    let mut rows = (cols * height / width * ASCII_CHAR_ASPECT).round().max(1.0);
+    if rows > ASCII_MAX_ROWS as f32 {
+        rows = ASCII_MAX_ROWS as f32;
+        cols = (rows * width / height / ASCII_CHAR_ASPECT)
+            .round()
+            .clamp(1.0, ASCII_MAX_WIDTH as f32);
+    }
+
+    let small = picture
+        .resize_exact(cols as u32, rows as u32, FilterType::Triangle)
+        .to_rgba8();
+    let mut art = String::new();
+    for (y, row) in small.rows().enumerate() {
+        if y > 0 {
+            art.push('\n');
+        }
+        let mut colored = None;
+        for pixel in row {
+            let [r, g, b, a] = pixel.0;
+            // transparent pixels are as dark as the background
+            let brightness =
+                (0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32) * a as f32 / 255.0;
+            let index = (brightness / 256.0 * chars.len() as f32) as usize;
+            let c = chars.get(index).or(chars.last()).copied().unwrap_or(' ');
+
+            if options.color && c != ' ' && colored != Some((r, g, b)) {
+                write!(art, "\x1b[38;2;{r};{g};{b}m").ok();
+                colored = Some((r, g, b));
+            }
+            art.push(c);
+        }
+        if colored.is_some() {
+            art.push_str("\x1b[0m");
+        }
+    }
+    art
+}
+
+/// a line of ascii art with its colour codes turned into styles, other escape sequences are
+/// left out
+pub fn ansi_line(text: &str) -> Line<'static> {
+    let mut spans = vec![];
+    let mut style = Style::default();
+    let mut plain = String::new();
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            plain.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'[').is_none() {
+            continue;
+        }
+
+        let mut params = String::new();
+        let mut end = None;
+        for c in chars.by_ref() {
+            if ('@'..='~').contains(&c) {
+                end = Some(c);
+                break;
+            }
+            params.push(c);
+        }
+        if end != Some('m') {
+            continue;
+        }
+
+        if !plain.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut plain), style));
+        }
+        style = sgr_style(style, &params);
+    }
+
+    if !plain.is_empty() {
+        spans.push(Span::styled(plain, style));
+    }
+    Line::from(spans)
+}
+
+fn sgr_style(style: Style, params: &str) -> Style {
+    let codes: Vec<u16> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+    match codes.as_slice() {
+        [38, 2, r, g, b] => style.fg(Color::Rgb(*r as u8, *g as u8, *b as u8)),
+        [39] => style.fg(Color::Reset),
+        [0] => Style::default(),
+        _ => style,
+    }
+}
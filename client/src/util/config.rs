@@ -1,11 +1,53 @@
+/// received files are saved in a folder per user inside this one
 pub const FILES_DIR: &str = "./files/";
-pub const FILES_FOR_TRANSFER: [&str; 1] = ["txt"];
+pub const DOWNLOAD_DIR_ENV: &str = "DOWNLOAD_DIR";
 pub const FILES_IMG_TO_ASCII: [&str; 2] = ["png", "jpg"];
+/// `kitty`, `iterm`, `sixel` or `ascii`, overrides the graphics protocol detected for the terminal
+pub const IMAGE_PROTOCOL_ENV: &str = "IMAGE_PROTOCOL";
+/// pictures sent along with the ascii rendering fit into this many pixels on each side
+pub const IMAGE_MAX_PX: u32 = 400;
+/// inline images are at most this many cells wide and high
+pub const IMAGE_MAX_COLS: u16 = 40;
+pub const IMAGE_MAX_ROWS: u16 = 20;
+/// pixel size of a cell for terminals that do not report theirs
+pub const DEFAULT_CELL_PX: (u16, u16) = (10, 20);
+pub const SIXEL_COLORS: usize = 256;
+/// columns of ascii art, the width of the messages pane when unset
+pub const ASCII_WIDTH_ENV: &str = "ASCII_WIDTH";
+/// one of `ASCII_CHARSETS` by name, or the chars themselves from sparse to dense
+pub const ASCII_CHARSET_ENV: &str = "ASCII_CHARSET";
+/// `off` sends ascii art without colour
+pub const ASCII_COLOR_ENV: &str = "ASCII_COLOR";
+pub const ASCII_CHARSETS: [(&str, &str); 3] = [
+    ("standard", " .:-=+*#%@"),
+    ("blocks", " ░▒▓█"),
+    (
+        "detailed",
+        " .'`^\",:;Il!i><~+_-?][}{1)(|\\/tfjrxnuvczXYUJCLQ0OZmwqpdbkhao*#MW&8%B@$",
+    ),
+];
+pub const ASCII_MAX_WIDTH: u16 = 200;
+pub const ASCII_MAX_ROWS: u16 = 100;
+/// width of a char over its height, ascii art has fewer rows than columns to look right
+pub const ASCII_CHAR_ASPECT: f32 = 0.5;
+/// proposed to the server after login, it answers with the size both sides use
+pub const PREFERRED_CHUNK_SIZE: u32 = 32 * 1024;
 
 pub const MESSAGES_SCROLL_RESERVE: usize = 50;
+pub const HISTORY_PAGE_SIZE: u32 = 50;
+
+/// typing events are sent at most this often per channel
+pub const TYPING_SEND_INTERVAL_MS: u64 = 2000;
+/// a typing user is no longer shown once nothing came from them for this long
+pub const TYPING_EXPIRY_MS: u64 = 5000;
+/// an online user without input for this long is shown as away
+pub const AWAY_AFTER_SECS: u64 = 300;
 
 pub const THEME_GRAY_GREEN_DARK: (u8, u8, u8) = (43, 51, 57);
 pub const THEME_GRAY_GREEN_LIGHT: (u8, u8, u8) = (50, 61, 67);
 pub const THEME_GREEN: (u8, u8, u8) = (131, 192, 146);
 pub const THEME_YELLOW_DARK: (u8, u8, u8) = (219, 188, 127);
 pub const THEME_YELLOW_LIGHT: (u8, u8, u8) = (92, 107, 85);
+
+pub const RECONNECT_BACKOFF_MIN_MS: u64 = 500;
+pub const RECONNECT_BACKOFF_MAX_MS: u64 = 10_000;
@@ -1,5 +1,12 @@
+use chrono::{Local, TimeZone};
 use ratatui::style::Style;
 use ratatui::text::{Line, Span};
+use shared::{
+    config::UNREAD_COUNT_MAX,
+    types::{ChannelMsg, Presence, PresenceStatus, RoomRole},
+};
+use std::{cmp::Reverse, collections::VecDeque};
+use uuid::Uuid;
 
 pub fn pad_line_to_width(mut line: Line, target_width: u16) -> Line {
     let content_width: usize = line.width();
@@ -9,3 +16,125 @@ pub fn pad_line_to_width(mut line: Line, target_width: u16) -> Line {
     }
     line
 }
+
+pub fn role_name(role: RoomRole) -> &'static str {
+    match role {
+        RoomRole::Member => "member",
+        RoomRole::Moderator => "moderator",
+        RoomRole::Admin => "admin",
+        RoomRole::Owner => "owner",
+    }
+}
+
+pub fn is_text_msg(msg: &ChannelMsg, id: Uuid) -> bool {
+    matches!(msg, ChannelMsg::TextMsg(text) if text.id == id)
+}
+
+/// root of the thread a message was posted in, thread replies stay out of the conversation
+pub fn thread_of(msg: &ChannelMsg) -> Option<Uuid> {
+    match msg {
+        ChannelMsg::TextMsg(text) => text.thread,
+        ChannelMsg::Deleted(deletion) => deletion.thread,
+        _ => None,
+    }
+}
+
+/// newest first like the conversation. server ids are v7 and sort by time,
+/// own replies still waiting for their ack go on top
+pub fn thread_replies(messages: &VecDeque<ChannelMsg>, root_id: Uuid) -> Vec<&ChannelMsg> {
+    let mut replies: Vec<&ChannelMsg> = messages
+        .iter()
+        .filter(|m| thread_of(m) == Some(root_id))
+        .collect();
+
+    replies.sort_by_key(|m| match m {
+        ChannelMsg::TextMsg(text) => Reverse((text.sent_at == 0, text.id)),
+        m => Reverse((
+            false,
+            m.author_and_id().map(|(_, id)| id).unwrap_or_default(),
+        )),
+    });
+    replies
+}
+
+pub fn change_thread_len(messages: &mut VecDeque<ChannelMsg>, root_id: Uuid, added: bool) {
+    if let Some(ChannelMsg::TextMsg(root)) = messages.iter_mut().find(|m| is_text_msg(m, root_id)) {
+        root.thread_len = match added {
+            true => root.thread_len + 1,
+            false => root.thread_len.saturating_sub(1),
+        };
+    }
+}
+
+/// newest message of the conversation that has its server id, thread replies are left out
+pub fn newest_read_id(messages: &VecDeque<ChannelMsg>) -> Option<Uuid> {
+    messages
+        .iter()
+        .filter(|m| thread_of(m).is_none())
+        .find_map(|m| match m {
+            ChannelMsg::TextMsg(text) if text.sent_at == 0 => None,
+            m => m.author_and_id().map(|(_, id)| id),
+        })
+}
+
+/// the server stops counting at `UNREAD_COUNT_MAX`
+pub fn unread_label(count: u32) -> String {
+    match count >= UNREAD_COUNT_MAX {
+        true => format!("{}+", UNREAD_COUNT_MAX - 1),
+        false => count.to_string(),
+    }
+}
+
+/// `sent_at` is in milliseconds since the unix epoch, shown in local time
+pub fn format_sent_at(sent_at: i64) -> String {
+    match Local.timestamp_millis_opt(sent_at).single() {
+        Some(time) => time.format("%H:%M").to_string(),
+        None => String::from("--:--"),
+    }
+}
+
+/// the time for today, the day for anything older
+pub fn format_last_seen(last_seen: i64) -> String {
+    match Local.timestamp_millis_opt(last_seen).single() {
+        Some(time) if time.date_naive() == Local::now().date_naive() => {
+            time.format("%H:%M").to_string()
+        }
+        Some(time) => time.format("%d %b").to_string(),
+        None => String::from("--"),
+    }
+}
+
+/// `None` for users that are simply online
+pub fn presence_label(presence: &Presence) -> Option<String> {
+    let status = match presence.status {
+        PresenceStatus::Online => None,
+        PresenceStatus::Away => Some(String::from("away")),
+        PresenceStatus::DoNotDisturb => Some(String::from("do not disturb")),
+        PresenceStatus::Invisible => Some(String::from("invisible")),
+        PresenceStatus::Offline => presence
+            .last_seen
+            .map(|at| format!("seen {}", format_last_seen(at))),
+    };
+
+    match (status, presence.text.is_empty()) {
+        (None, true) => None,
+        (None, false) => Some(presence.text.clone()),
+        (Some(status), true) => Some(status),
+        (Some(status), false) => Some(format!("{status} — {}", presence.text)),
+    }
+}
+
+/// bytes with one decimal in the largest unit that keeps the number at 1 or more
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{size:.1} {}", units[unit]),
+    }
+}
@@ -0,0 +1,337 @@
+use crate::util::{
+    config::{DEFAULT_CELL_PX, IMAGE_MAX_PX, IMAGE_MAX_ROWS, IMAGE_PROTOCOL_ENV, SIXEL_COLORS},
+    types::GraphicsProtocol,
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use color_quant::NeuQuant;
+use image::{DynamicImage, ImageFormat, ImageReader, Limits, RgbaImage, imageops::FilterType};
+use std::{fmt::Write, io::Cursor, ops::Range};
+use uuid::Uuid;
+
+/// first of the private use chars marking the rows left blank for an image
+const MARKER_BASE: u32 = 0xE000;
+/// the marker of a row holds 12 bits of the message id in each of its first two chars
+const MARKER_KEY_BITS: u32 = 12;
+const KITTY_CHUNK: usize = 4096;
+
+/// the override wins, otherwise the terminal is recognised by what it tells about itself.
+/// multiplexers in between do not pass graphics on, they get the ascii rendering
+pub fn detect_graphics() -> GraphicsProtocol {
+    if let Ok(name) = std::env::var(IMAGE_PROTOCOL_ENV) {
+        return match name.to_lowercase().as_str() {
+            "kitty" => GraphicsProtocol::Kitty,
+            "iterm" | "iterm2" => GraphicsProtocol::Iterm,
+            "sixel" => GraphicsProtocol::Sixel,
+            _ => GraphicsProtocol::Ascii,
+        };
+    }
+
+    let var = |name: &str| std::env::var(name).unwrap_or_default();
+    let (term, program) = (var("TERM"), var("TERM_PROGRAM"));
+
+    if std::env::var_os("TMUX").is_some() || term.starts_with("screen") {
+        GraphicsProtocol::Ascii
+    } else if std::env::var_os("KITTY_WINDOW_ID").is_some()
+        || term.contains("kitty")
+        || term.contains("ghostty")
+    {
+        GraphicsProtocol::Kitty
+    } else if ["iTerm.app", "WezTerm"].contains(&program.as_str()) || var("LC_TERMINAL") == "iTerm2"
+    {
+        GraphicsProtocol::Iterm
+    } else if term.contains("sixel")
+        || ["foot", "mlterm", "contour"]
+            .iter()
+            .any(|t| term.starts_with(t))
+    {
+        GraphicsProtocol::Sixel
+    } else {
+        GraphicsProtocol::Ascii
+    }
+}
+
+/// terminals that do not report their size in pixels get the default
+pub fn cell_size() -> (u16, u16) {
+    match crossterm::terminal::window_size() {
+        Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => {
+            (size.width / size.columns, size.height / size.rows)
+        }
+        _ => DEFAULT_CELL_PX,
+    }
+}
+
+/// cells the picture takes up at most `max_cols` wide, keeping its aspect
+pub fn image_cells(picture: &DynamicImage, max_cols: u16, cell: (u16, u16)) -> (u16, u16) {
+    let (width, height) = (
+        picture.width().max(1) as f64,
+        picture.height().max(1) as f64,
+    );
+    let (cell_w, cell_h) = (cell.0.max(1) as f64, cell.1.max(1) as f64);
+    let max_cols = max_cols.max(1) as f64;
+
+    let cols = (width / cell_w).ceil().clamp(1.0, max_cols);
+    let rows = (cols * cell_w * height / width / cell_h).round().max(1.0);
+    if rows <= IMAGE_MAX_ROWS as f64 {
+        return (cols as u16, rows as u16);
+    }
+
+    let rows = IMAGE_MAX_ROWS as f64;
+    let cols = (rows * cell_h * width / height / cell_w)
+        .round()
+        .clamp(1.0, max_cols);
+    (cols as u16, rows as u16)
+}
+
+/// text of the blank row `row` of the image of message `id`
+pub fn image_marker(id: Uuid, row: u16) -> String {
+    let key = id.as_u128() as u32;
+    let mask = (1 << MARKER_KEY_BITS) - 1;
+    [key >> MARKER_KEY_BITS & mask, key & mask, row as u32]
+        .into_iter()
+        .filter_map(|n| char::from_u32(MARKER_BASE + n))
+        .collect()
+}
+
+/// key and row of a marker starting with `first`, followed by the two chars after it
+pub fn read_marker(first: &str, second: &str, third: &str) -> Option<(u32, u16)> {
+    let code = |s: &str| {
+        let c = s.chars().next()? as u32;
+        c.checked_sub(MARKER_BASE)
+            .filter(|n| *n < 1 << MARKER_KEY_BITS)
+    };
+    let (high, low, row) = (code(first)?, code(second)?, code(third)?);
+    Some((high << MARKER_KEY_BITS | low, row as u16))
+}
+
+/// the part of the message id a marker holds
+pub fn marker_key(id: Uuid) -> u32 {
+    id.as_u128() as u32 & ((1 << (2 * MARKER_KEY_BITS)) - 1)
+}
+
+/// escape sequence drawing `rows` of the picture scaled to `cols` by `height` cells, from the
+/// cursor on. the cursor stays where it was
+pub fn encode_image(
+    protocol: GraphicsProtocol,
+    picture: &DynamicImage,
+    (cols, height): (u16, u16),
+    rows: Range<u16>,
+    cell: (u16, u16),
+) -> Option<String> {
+    let (cell_w, cell_h) = (cell.0.max(1) as u32, cell.1.max(1) as u32);
+    let scaled = picture.resize_exact(
+        cols as u32 * cell_w,
+        height as u32 * cell_h,
+        FilterType::Triangle,
+    );
+    let top = rows.start as u32 * cell_h;
+    let shown = scaled.crop_imm(0, top, scaled.width(), rows.len() as u32 * cell_h);
+
+    let sequence = match protocol {
+        GraphicsProtocol::Kitty => kitty(&png(&shown)?, cols, rows.len() as u16),
+        GraphicsProtocol::Iterm => iterm(&png(&shown)?, cols, rows.len() as u16),
+        GraphicsProtocol::Sixel => sixel(&shown.to_rgba8()),
+        GraphicsProtocol::Ascii => return None,
+    };
+    Some(format!("\x1b7{sequence}\x1b8"))
+}
+
+/// escape sequence removing every picture kitty shows, other terminals drop them with the text
+pub fn clear_images(protocol: GraphicsProtocol) -> Option<&'static str> {
+    match protocol {
+        GraphicsProtocol::Kitty => Some("\x1b_Ga=d,d=A,q=2\x1b\\"),
+        _ => None,
+    }
+}
+
+/// escape sequence removing the kitty picture over the cell at `x`, `y`. other terminals
+/// keep pictures in the cells, drawing the frame writes over them
+pub fn delete_image(protocol: GraphicsProtocol, (x, y): (u16, u16)) -> Option<String> {
+    match protocol {
+        GraphicsProtocol::Kitty => Some(format!("\x1b_Ga=d,d=P,x={},y={},q=2\x1b\\", x + 1, y + 1)),
+        _ => None,
+    }
+}
+
+/// the picture sent along with an image, no larger than `IMAGE_MAX_PX` on either side
+pub fn image_png(picture: &DynamicImage) -> Option<Vec<u8>> {
+    match picture.width().max(picture.height()) > IMAGE_MAX_PX {
+        true => png(&picture.thumbnail(IMAGE_MAX_PX, IMAGE_MAX_PX)),
+        false => png(picture),
+    }
+}
+
+/// a received picture, refused when it claims to be larger than a sent one can be
+pub fn decode_picture(data: &[u8]) -> Option<DynamicImage> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(IMAGE_MAX_PX);
+    limits.max_image_height = Some(IMAGE_MAX_PX);
+    // 16 bit rgba, with as much again for the buffers of the decoder
+    limits.max_alloc = Some(u64::from(IMAGE_MAX_PX).pow(2) * 8 * 2);
+
+    let mut reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?;
+    reader.limits(limits);
+    reader.decode().ok()
+}
+
+fn png(picture: &DynamicImage) -> Option<Vec<u8>> {
+    let mut bytes = Cursor::new(vec![]);
+    picture.write_to(&mut bytes, ImageFormat::Png).ok()?;
+    Some(bytes.into_inner())
+}
+
+fn kitty(png: &[u8], cols: u16, rows: u16) -> String {
+    let data = STANDARD.encode(png);
+    let parts: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+    let mut sequence = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        let more = u8::from(i + 1 < parts.len());
+        let part = String::from_utf8_lossy(part);
+        match i {
+            0 => write!(
+                sequence,
+                "\x1b_Ga=T,f=100,c={cols},r={rows},C=1,q=2,m={more};{part}\x1b\\"
+            ),
+            _ => write!(sequence, "\x1b_Gm={more};{part}\x1b\\"),
+        }
+        .ok();
+    }
+    sequence
+}
+
+fn iterm(png: &[u8], cols: u16, rows: u16) -> String {
+    format!(
+        "\x1b]1337;File=inline=1;size={};width={cols};height={rows};preserveAspectRatio=0;doNotMoveCursor=1:{}\x07",
+        png.len(),
+        STANDARD.encode(png)
+    )
+}
+
+/// bands of six pixel rows, each drawn colour by colour. transparent pixels are left out
+fn sixel(picture: &RgbaImage) -> String {
+    let (width, height) = (picture.width() as usize, picture.height() as usize);
+    let quant = NeuQuant::new(10, SIXEL_COLORS, picture.as_raw());
+    let colors: Vec<Option<usize>> = picture
+        .pixels()
+        .map(|p| (p.0[3] >= 128).then(|| quant.index_of(&p.0)))
+        .collect();
+
+    let mut sequence = format!("\x1bP0;1;0q\"1;1;{width};{height}");
+    for (i, rgb) in quant.color_map_rgb().chunks(3).enumerate() {
+        let [r, g, b] = [rgb[0], rgb[1], rgb[2]].map(|c| c as u32 * 100 / 255);
+        write!(sequence, "#{i};2;{r};{g};{b}").ok();
+    }
+
+    for top in (0..height).step_by(6) {
+        let band = top..(top + 6).min(height);
+        let mut used = vec![false; SIXEL_COLORS];
+        for y in band.clone() {
+            for color in colors[y * width..(y + 1) * width].iter().flatten() {
+                used[*color] = true;
+            }
+        }
+
+        let mut first = true;
+        for color in (0..SIXEL_COLORS).filter(|c| used[*c]) {
+            if !first {
+                sequence.push('$');
+            }
+            first = false;
+            write!(sequence, "#{color}").ok();
+
+            let mut run: Option<(char, usize)> = None;
+            for x in 0..width {
+                let bits = band
+                    .clone()
+                    .enumerate()
+                    .filter(|(_, y)| colors[y * width + x] == Some(color))
+                    .fold(0u8, |bits, (dy, _)| bits | 1 << dy);
+                let c = char::from(63 + bits);
+                run = match run {
+                    Some((last, n)) if last == c => Some((last, n + 1)),
+                    Some((last, n)) => {
+                        push_run(&mut sequence, last, n);
+                        Some((c, 1))
+                    }
+                    None => Some((c, 1)),
+                };
+            }
+            if let Some((last, n)) = run {
+                push_run(&mut sequence, last, n);
+            }
+        }
+        sequence.push('-');
+    }
+
+    sequence.push_str("\x1b\\");
+    sequence
+}
+
+fn push_run(sequence: &mut String, c: char, n: usize) {
+    match n {
+        1..=3 => sequence.extend(std::iter::repeat_n(c, n)),
+        _ => {
+            write!(sequence, "!{n}{c}").ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picture(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::new_rgba8(width, height)
+    }
+
+    #[test]
+    fn markers_read_back_the_id_and_row() {
+        for id in [Uuid::nil(), Uuid::max(), Uuid::new_v4(), Uuid::new_v4()] {
+            for row in [0, 1, IMAGE_MAX_ROWS - 1] {
+                let marker: Vec<String> = image_marker(id, row).chars().map(String::from).collect();
+                assert_eq!(marker.len(), 3);
+                let read = read_marker(&marker[0], &marker[1], &marker[2]);
+                assert_eq!(read, Some((marker_key(id), row)));
+            }
+        }
+        assert_eq!(read_marker("a", "b", "c"), None);
+    }
+
+    #[test]
+    fn images_stay_within_the_rows_and_cols() {
+        let cell = (10, 20);
+        assert_eq!(image_cells(&picture(100, 200), 40, cell), (10, 10));
+        assert_eq!(
+            image_cells(&picture(100, 4000), 40, cell),
+            (1, IMAGE_MAX_ROWS)
+        );
+        let (cols, rows) = image_cells(&picture(400, 800), 40, cell);
+        assert_eq!(rows, IMAGE_MAX_ROWS);
+        assert!(cols < 40);
+        let (cols, rows) = image_cells(&picture(4000, 100), 40, cell);
+        assert_eq!(cols, 40);
+        assert!((1..=IMAGE_MAX_ROWS).contains(&rows));
+        assert_eq!(image_cells(&picture(4000, 100), 12, cell).0, 12);
+        assert_eq!(image_cells(&picture(1, 1), 40, (0, 0)), (1, 1));
+    }
+
+    #[test]
+    fn long_runs_are_counted() {
+        let mut sequence = String::new();
+        push_run(&mut sequence, 'a', 1);
+        push_run(&mut sequence, 'b', 3);
+        push_run(&mut sequence, 'c', 4);
+        push_run(&mut sequence, 'd', 120);
+        assert_eq!(sequence, "abbb!4c!120d");
+    }
+
+    #[test]
+    fn oversized_pictures_are_not_decoded() {
+        let png = |width, height| png(&picture(width, height)).unwrap();
+        assert!(decode_picture(&png(IMAGE_MAX_PX, IMAGE_MAX_PX)).is_some());
+        assert!(decode_picture(&png(IMAGE_MAX_PX + 1, 1)).is_none());
+        assert!(decode_picture(&png(1, IMAGE_MAX_PX + 1)).is_none());
+        assert!(decode_picture(b"not a picture").is_none());
+    }
+}
@@ -1,3 +1,5 @@
+pub mod ascii_art;
 pub mod config;
 pub mod functions;
+pub mod graphics;
 pub mod types;
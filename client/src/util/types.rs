@@ -1,9 +1,23 @@
 use futures::stream::{SplitSink, SplitStream};
+use image::DynamicImage;
+use ratatui::layout::Rect;
+use sha2::Sha256;
 use shared::types::{
-    AuthResponse, Channel, Chunk, DirectChannel, FileMetadata, ImgRender, JoinRoomNotification,
-    LeaveRoomNotification, RegisterResponse, RoomData, TextMsg, TuiRoom, User, UserInitData,
+    AuthResponse, Channel, Chunk, DeliveryNotice, DirectChannel, FileList, FileMetadata,
+    FileResponse, HistoryPage, ImgRender, JoinRoomNotification, LeaveRoomNotification,
+    ModerationNotice, ModerationRes, MsgAck, MsgDeletion, MsgReactions, MsgRefusal, MsgUpdateRes,
+    Presence, ReadReceipt, RegisterResponse, ResumePoint, ResumeResponse, RoomData,
+    RoomPasswordRes, RoomRemovalRes, StreamCredit, StreamFailure, TextMsg, ThreadRes,
+    TransferControl, TuiRoom, TypingNotice, User, UserInitData,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    ops::Range,
+    path::PathBuf,
+    sync::{Arc, Mutex, atomic::AtomicU64, mpsc},
+    time::Instant,
 };
-use std::{collections::HashMap, fs::File, sync::mpsc};
 use tokio::net::TcpStream;
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, tungstenite::Message};
 use uuid::Uuid;
@@ -24,6 +38,79 @@ pub struct ActiveStream {
     pub size: u64,
     pub file_name: String,
     pub from: Channel,
+    /// hash of the bytes written so far, compared to `sha256` after the last chunk
+    pub hasher: Sha256,
+    pub sha256: String,
+    pub dir: PathBuf,
+    /// bytes go here until the file is complete and verified
+    pub temp_path: PathBuf,
+    /// sequence number the next chunk has to carry
+    pub next_seq: u64,
+    /// `written`, shared with the transfers panel
+    pub progress: Arc<AtomicU64>,
+    /// user sending the file, it is told how far the stream got
+    pub sender: Uuid,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TransferDirection {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferState {
+    Offered,
+    Running,
+    Paused,
+    /// the connection dropped, it goes on once the server confirmed where
+    Interrupted,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+/// a file offered by or to this client, `channel` is the conversation it shows up in
+#[derive(Debug)]
+pub struct Transfer {
+    pub stream_id: Uuid,
+    pub filename: String,
+    pub size: u64,
+    pub channel: Channel,
+    /// the sender of received files, this user for sent ones
+    pub peer: User,
+    pub direction: TransferDirection,
+    pub state: TransferState,
+    /// offer of a received file, handed to the file stream once accepted
+    pub offer: Option<FileMetadata>,
+    /// held by the task sending the file, it only sends while `Running`
+    pub tx_state: Option<tokio::sync::watch::Sender<TransferState>>,
+    /// credit of the server for the task sending the file, the chunks before it may go out
+    pub tx_credit: Option<tokio::sync::watch::Sender<u64>>,
+    /// where the task sending the file goes on after an interruption
+    pub tx_resume: Option<tokio::sync::watch::Sender<Option<ResumePoint>>>,
+    /// stored file an interrupted received transfer continues from, the copy the server
+    /// keeps of a stream has the id of the stream
+    pub file_id: Option<Uuid>,
+    /// bytes sent or written so far, counted by the task moving them
+    pub progress: Arc<AtomicU64>,
+    pub started_at: Option<Instant>,
+    pub ended_at: Option<Instant>,
+    /// the file that is sent, or where a received one was saved
+    pub path: Option<PathBuf>,
+    /// why a transfer failed
+    pub reason: Option<String>,
+}
+
+/// outcome of a file transfer, shown in the conversation of `channel`.
+/// received files are `Ok` with the path they were saved to
+#[derive(Debug)]
+pub struct TransferReport {
+    pub stream_id: Uuid,
+    pub filename: String,
+    pub channel: Channel,
+    pub direction: TransferDirection,
+    pub res: Result<Option<PathBuf>, String>,
 }
 
 #[derive(Debug)]
@@ -38,6 +125,35 @@ pub enum TuiUpdate {
     UserDisconnected(User),
     UserConnected(User),
     RegisterResponse(RegisterResponse),
+    History(HistoryPage),
+    MsgRefused(MsgRefusal),
+    RoomRemoval(RoomRemovalRes),
+    RoomDeleted(Uuid),
+    Moderation(ModerationRes),
+    RoomPassword(RoomPasswordRes),
+    RoomModerated(ModerationNotice),
+    MessageEdited(TextMsg),
+    MessageDeleted(MsgDeletion),
+    MsgUpdate(MsgUpdateRes),
+    Ack(MsgAck),
+    Delivered(DeliveryNotice),
+    ReactionsChanged(MsgReactions),
+    Thread(ThreadRes),
+    Seen(ReadReceipt),
+    Typing(TypingNotice),
+    PresenceChanged(Presence),
+    Transfer(TransferReport),
+    ChunkSize(u32),
+    FileOffer(FileMetadata),
+    TransferControl(TransferControl),
+    StreamCredit(StreamCredit),
+    StreamFailed(StreamFailure),
+    Resync(Channel),
+    Files(FileList),
+    FileResponse(FileResponse),
+    TransferResumed(ResumeResponse),
+    Connected,
+    Disconnected,
 }
 
 pub enum Notification {
@@ -65,6 +181,20 @@ pub struct ActiveChannel {
     pub id: Option<Uuid>,
 }
 
+/// thread pane of the root message `id`, its replies are kept with the messages of `channel`
+pub struct ThreadView {
+    pub id: Uuid,
+    pub channel: Channel,
+    pub loaded: bool,
+}
+
+/// `channel` is where `user` is typing, direct channels are keyed by the other user
+pub struct TypingUser {
+    pub user: User,
+    pub channel: Channel,
+    pub at: Instant,
+}
+
 #[derive(PartialEq)]
 pub enum ActiveScreen {
     Main,
@@ -111,7 +241,17 @@ pub enum SelectorEntryKind {
 #[derive(Debug)]
 pub enum WsStreamMsg {
     FileChunk(Chunk),
-    FileMetadata(FileMetadata),
+    /// accepted offer, the written bytes are counted in the shared progress
+    FileMetadata(FileMetadata, Arc<AtomicU64>),
+    /// user of the new session, received files are saved in their folder
+    Session(User),
+    /// the stream stops and its partial file is removed
+    Cancel(Uuid),
+    /// asks the server for the rest of an interrupted stream, read from the stored file
+    Resume {
+        stream_id: Uuid,
+        file_id: Uuid,
+    },
 }
 
 #[derive(Debug)]
@@ -129,6 +269,7 @@ pub enum ActiveCreateRoomInput {
 pub enum RoomAction {
     Create,
     Join,
+    Password,
 }
 
 #[derive(PartialEq)]
@@ -137,5 +278,59 @@ pub enum FileAction {
     File,
 }
 
+/// how images are turned into ascii art before they are sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsciiOptions {
+    /// columns, `None` follows the width of the messages pane
+    pub width: Option<u16>,
+    /// chars from sparse to dense, picked by the brightness of a pixel
+    pub charset: String,
+    /// chars carry the colour of their pixel as ANSI codes
+    pub color: bool,
+}
+
+/// how the terminal draws pictures, `Ascii` shows the ascii rendering of images instead
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraphicsProtocol {
+    Kitty,
+    Iterm,
+    Sixel,
+    Ascii,
+}
+
+/// pictures of image messages drawn over the rows their messages leave blank
+#[derive(Debug)]
+pub struct InlineImages {
+    pub protocol: GraphicsProtocol,
+    /// pixel size of a cell, looked up every frame
+    pub cell: (u16, u16),
+    /// widest an image may be in the messages pane of the last frame
+    pub max_cols: u16,
+    /// decoded pictures by message id, `None` when there is nothing to draw
+    pub pictures: HashMap<Uuid, Option<DynamicImage>>,
+    /// ascii art of the decoded pictures, drawn at the columns and with the options beside it
+    pub ascii: (Option<(u16, AsciiOptions)>, HashMap<Uuid, String>),
+    /// escape sequences by id, size and rows of the pictures on the screen, a picture that
+    /// moves with the same rows showing is not encoded again
+    pub encoded: HashMap<(Uuid, u16, u16, Range<u16>), String>,
+    /// where the last frame puts the pictures, with the area of the frame
+    pub placements: (Rect, Vec<ImagePlacement>),
+    /// what is on the screen
+    pub shown: (Rect, Vec<ImagePlacement>),
+}
+
+/// `rows` of an image `cols` by `height` cells, drawn from the top left of `area`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImagePlacement {
+    pub id: Uuid,
+    pub area: Rect,
+    pub cols: u16,
+    pub height: u16,
+    pub rows: Range<u16>,
+}
+
 pub type WsRead = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
+/// token of the current session, used to resume it after the connection drops
+pub type SessionToken = Arc<Mutex<Option<Uuid>>>;
+
 pub type WsWrite = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
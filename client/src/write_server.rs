@@ -1,38 +1,49 @@
 use anyhow::Result;
 use futures::SinkExt;
 use shared::types::{Chunk, ClientServerAuthMsg, ClientServerMsg};
-use tokio::select;
+use tokio::{select, sync::watch};
 
-use crate::util::types::WsWrite;
+use crate::util::types::{SessionToken, WsWrite};
 
+/// only authentication goes out until the server accepted the session, a refused resume
+/// keeps the queued messages for the next login
 pub async fn write_to_server(
-    mut ws: WsWrite,
-    mut rx_tui_ws_msg: tokio::sync::mpsc::Receiver<ClientServerMsg>,
-    mut rx_tui_ws_file: tokio::sync::mpsc::Receiver<Chunk>,
-    mut rx_tui_ws_auth: tokio::sync::mpsc::Receiver<ClientServerAuthMsg>,
+    ws: &mut WsWrite,
+    rx_tui_ws_msg: &mut tokio::sync::mpsc::Receiver<ClientServerMsg>,
+    rx_tui_ws_file: &mut tokio::sync::mpsc::Receiver<Chunk>,
+    rx_tui_ws_auth: &mut tokio::sync::mpsc::Receiver<ClientServerAuthMsg>,
+    token: &SessionToken,
+    authenticated: &mut watch::Receiver<bool>,
 ) -> Result<()> {
     loop {
-        if let Ok(msg) = rx_tui_ws_msg.try_recv() {
+        let open = *authenticated.borrow_and_update();
+        if open && let Ok(msg) = rx_tui_ws_msg.try_recv() {
+            forget_ended_session(&msg, token);
             let serialized = bincode::serialize(&msg)?;
             // let framed = frame_data(&serialized);
             // let ws_msg= Message::Binary(framed)
             ws.send(serialized.into()).await?;
         }
 
+        // file metadata has to reach the server before the chunks of its stream
         select! {
-            result = rx_tui_ws_file.recv() => match result {
-                Some(chunk) => {
-                    let serialized = bincode::serialize(&ClientServerMsg::FileChunk(chunk))?;
+            biased;
+
+            result = authenticated.changed() => result?,
+
+            result = rx_tui_ws_msg.recv(), if open => match result {
+                Some(msg) => {
+                    forget_ended_session(&msg, token);
+                    let serialized = bincode::serialize(&msg)?;
                     // let framed = frame_data(&serialized);
                     ws.send(serialized.into()).await?;
                 },
                 _ => {}
             },
 
-
-            result = rx_tui_ws_msg.recv() => match result {
-                Some(msg) => {
-                    let serialized = bincode::serialize(&msg)?;
+            result = rx_tui_ws_file.recv(), if open => match result {
+                Some(chunk) => {
+                    let serialized = bincode::serialize(&ClientServerMsg::FileChunk(chunk))?;
                     // let framed = frame_data(&serialized);
                     ws.send(serialized.into()).await?;
                 },
@@ -48,3 +59,9 @@ pub async fn write_to_server(
         }
     }
 }
+
+fn forget_ended_session(msg: &ClientServerMsg, token: &SessionToken) {
+    if let ClientServerMsg::Logout = msg {
+        *token.lock().unwrap() = None;
+    }
+}
@@ -1,16 +1,27 @@
 use super::util::{
-    config::{COMM_CLIENT_CAPACITY, DIRECT_CAPACITY, MANAGER_CLIENT_CAPACITY, ROOM_CAPACITY},
+    config::{
+        COMM_CLIENT_CAPACITY, DIRECT_CAPACITY, MANAGER_CLIENT_CAPACITY, MAX_IMAGE_BYTES,
+        ROOM_CAPACITY, STREAM_STALL_CHECK_SECS, STREAM_STALL_SECS, TYPING_MIN_INTERVAL_MS,
+    },
     types::{
         server_data_types::{
-            BroadcastChannel, Client, ClientManagerMsg, ClientPersistenceMsg, ClientTaskResult,
-            CreateRoomServerTransit, DirectChannelTransitPayload, DirectChannelTxTransit,
-            JoinRoomServerTransit, JoinRoommPersistenceRes, ManagerClientMsg, MpscChannel,
-            MultipleRoomsUpdateTransit, RoomChannelTxTransit, RoomUpdateTransit, UserDataTransit,
+            BroadcastChannel, Client, ClientDroppedTransit, ClientManagerMsg, ClientPersistenceMsg,
+            ClientTaskResult, CreateRoomServerTransit, DeleteRoomTransit, DeliveredTransit,
+            DirectChannelTransitPayload, DirectChannelTxTransit, FileListTransit, FileSpaceTransit,
+            FileTransit, HistoryTransit, JoinRoomServerTransit, JoinRoommPersistenceRes,
+            LeaveRoomTransit, ManagerClientMsg, MemberRemovedTransit, ModerationPersistenceRes,
+            ModerationTransit, MpscChannel, MsgChange, MsgChangeTransit, MsgChanged,
+            MultipleRoomsUpdateTransit, OutgoingStream, ParkedStreams, PresenceListTransit,
+            ReadMarkerTransit, ReplyTransit, ResumeStreamTransit, RoomChannelTxTransit,
+            RoomDeletedTransit, RoomMemberTransit, RoomPasswordTransit, RoomUpdateTransit,
+            StoreMessageTransit, StoredFile, StoredPresence, StreamReceiver, ThreadTransit,
+            UserDataTransit,
         },
         server_error_types::{BincodeErr, WsErr},
         server_error_wrapper_types::WsDataParsingError,
     },
 };
+use crate::storage::file_store::{FileStore, Upload};
 use crate::util::types::{
     server_data_types::{WsRead, WsWrite},
     server_error_types::Bt,
@@ -20,18 +31,37 @@ use bincode::deserialize;
 use bytes::Bytes;
 use futures::{SinkExt, StreamExt, TryFutureExt, future::join_all};
 use log::{debug, error, warn};
-use shared::types::{
-    Channel, ClientServerMsg, CreateRoomRes, JoinRoomNotification, RoomActionRes, RoomData,
-    ServerClientMsg, User, UserInitData,
+use mongodb::bson::DateTime;
+use shared::{
+    config::{
+        CHUNK_SIZE_MAX, CHUNK_SIZE_MIN, MAX_STATUS_TEXT_CHARS, STREAM_WINDOW, TCP_CHUNK_BUFFER_SIZE,
+    },
+    types::{
+        AckRes, Channel, ChannelMsg, Chunk, ClientServerMsg, CreateRoomRes, DeliveryNotice,
+        FileEntry, FileList, FileMetadata, FileRequest, FileResponse, FileTypePolicy, HistoryPage,
+        HistoryRequest, ImgRender, JoinRoomNotification, LeaveRoomNotification, ModerationAction,
+        ModerationNotice, MsgAck, MsgRefusal, MsgStamp, Presence, PresenceStatus, PresenceUpdate,
+        ReadMarker, ReadReceipt, RefusedMsg, ResumePoint, ResumeRequest, ResumeResponse,
+        RoomActionRes, RoomData, RoomMember, RoomPasswordRes, RoomRemovalRes, ServerClientMsg,
+        StreamCredit, StreamFailure, StreamProgress, TextMsg, ThreadRes, TransferAction,
+        TransferControl, TypingNotice, User, UserInitData,
+    },
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io::SeekFrom,
+    time::{Duration, Instant},
 };
-use std::collections::HashMap;
 use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
     select,
     sync::{
         broadcast::{self, error::RecvError},
-        mpsc, oneshot,
+        mpsc::{self, error::TrySendError},
+        oneshot,
     },
-    task,
+    task::{self, AbortHandle},
 };
 use uuid::Uuid;
 use warp::filters::ws::Message;
@@ -42,13 +72,27 @@ pub struct ClientTask<'a> {
     client_manager_channel: MpscChannel<ClientManagerMsg, ManagerClientMsg>,
     comm_client_data_channel: MpscChannel,
     comm_client_drop_channel: MpscChannel<Channel, Channel>,
-    client_comm_cleanup_channel: BroadcastChannel<(), ()>,
+    /// `None` stops every communication task, `Some` only the ones of that channel
+    client_comm_cleanup_channel: BroadcastChannel<Option<Channel>, Option<Channel>>,
     close_channel: MpscChannel<ClientTaskResult, ClientTaskResult>,
     ws_read: &'a mut WsRead,
     ws_write: &'a mut WsWrite,
     room_channels: HashMap<Uuid, broadcast::Sender<Bytes>>,
     direct_channels: HashMap<Uuid, mpsc::Sender<Bytes>>,
     tx_client_persistence: mpsc::Sender<ClientPersistenceMsg>,
+    /// refused or cancelled, their chunks are dropped
+    refused_streams: HashSet<Uuid>,
+    outgoing_streams: HashMap<Uuid, OutgoingStream>,
+    file_policy: FileTypePolicy,
+    file_store: FileStore,
+    /// stored files this client is downloading, by stream id
+    downloads: HashMap<Uuid, AbortHandle>,
+    /// largest chunk relayed for this client, until it proposed one the default
+    chunk_size: usize,
+    conn_id: Uuid,
+    /// channel and time of the last relayed typing event
+    last_typing: Option<(Channel, Instant)>,
+    presence: StoredPresence,
 }
 
 impl<'a> ClientTask<'a> {
@@ -65,7 +109,7 @@ impl<'a> ClientTask<'a> {
         let (tx_manager_client, rx_manager_client) =
             mpsc::channel::<ManagerClientMsg>(MANAGER_CLIENT_CAPACITY);
 
-        let (tx_cleanup, rx_cleanup) = broadcast::channel::<()>(1);
+        let (tx_cleanup, rx_cleanup) = broadcast::channel::<Option<Channel>>(COMM_CLIENT_CAPACITY);
         let client_comm_cleanup_channel = BroadcastChannel {
             tx: tx_cleanup,
             rx: rx_cleanup,
@@ -94,12 +138,15 @@ impl<'a> ClientTask<'a> {
             rx: rx_manager_client,
         };
 
+        let conn_id = Uuid::new_v4();
         let client = Client {
+            conn_id,
             tx: tx_manager_client,
             user: User {
                 username: user.username.clone(),
                 id: user.id,
             },
+            presence: PresenceUpdate::default(),
         };
 
         if let Err(err) = client_manager_channel
@@ -122,6 +169,20 @@ impl<'a> ClientTask<'a> {
             comm_client_drop_channel,
             close_channel,
             tx_client_persistence: _tx_client_persistence,
+            refused_streams: HashSet::new(),
+            outgoing_streams: HashMap::new(),
+            file_policy: FileTypePolicy::from_env(),
+            file_store: FileStore::from_env(),
+            downloads: HashMap::new(),
+            chunk_size: TCP_CHUNK_BUFFER_SIZE,
+            last_typing: None,
+            presence: StoredPresence {
+                user_id: user.id,
+                status: PresenceStatus::Online,
+                text: String::new(),
+                last_seen: None,
+            },
+            conn_id,
         }
     }
 
@@ -131,12 +192,43 @@ impl<'a> ClientTask<'a> {
         let msg = ServerClientMsg::Init(init_data.clone());
         self.send_to_client(msg).await?;
 
+        // queued direct messages stay queued until the init data reached the client
+        let msg_ids: Vec<Uuid> = init_data
+            .queued_messages
+            .iter()
+            .filter_map(|msg| msg.author_and_id().map(|(_, id)| id))
+            .collect();
+        if !msg_ids.is_empty() {
+            let transit = DeliveredTransit {
+                to_id: self.id,
+                msg_ids,
+            };
+            let msg = ClientPersistenceMsg::RemoveDeliveries(transit);
+            if let Err(err) = self.tx_client_persistence.send(msg).await {
+                error!("Persistence task not running {}, {}", err, Bt::new());
+            };
+        }
+
+        // a status set in an earlier session still holds
+        if let Some(own) = init_data.presence.iter().find(|p| p.user.id == self.id) {
+            self.presence.status = own.status;
+            self.presence.text = own.text.clone();
+            self.presence.last_seen = own.last_seen;
+            if own.status != PresenceStatus::Online || !own.text.is_empty() {
+                self.announce_presence().await;
+            }
+        }
+        let invisible = self.presence.status == PresenceStatus::Invisible;
+
         let room_transmitters = self.get_room_transmitters(init_data.rooms.clone()).await;
 
         for (id, tx) in room_transmitters {
             let tx = self.spawn_room_communication_task(tx, id);
 
             self.room_channels.insert(id, tx);
+            if invisible {
+                continue;
+            }
 
             let msg = ServerClientMsg::UserConnected(User {
                 username: self.username.clone(),
@@ -146,6 +238,33 @@ impl<'a> ClientTask<'a> {
             self.send_data_to_channel(msg, Channel::Room(id)).await?;
         }
 
+        self.notify_delivered(&init_data.queued_messages).await?;
+
+        Ok(())
+    }
+
+    /// tells the authors of queued direct messages that they reached this user
+    async fn notify_delivered(
+        &mut self,
+        messages: &[ChannelMsg],
+    ) -> Result<(), WsDataParsingError> {
+        let mut by_author: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        for msg in messages {
+            if let Some((from_id, id)) = msg.author_and_id() {
+                by_author.entry(from_id).or_default().push(id);
+            }
+        }
+
+        for (from_id, ids) in by_author {
+            let notice = DeliveryNotice {
+                to: Channel::User(self.id),
+                ids,
+            };
+            let msg = ServerClientMsg::Delivered(notice);
+            self.send_data_to_channel(msg, Channel::User(from_id))
+                .await?;
+        }
+
         Ok(())
     }
 
@@ -189,8 +308,27 @@ impl<'a> ClientTask<'a> {
             .map_err(|err| anyhow!("{err}{}", Bt::new()))?;
 
         let updated_room_data = rx_ack.await.map_err(|err| anyhow!("{err}{}", Bt::new()))?;
+
+        let (tx_ack, rx_ack) = oneshot::channel();
+        let transit = PresenceListTransit {
+            tx_ack,
+            viewer: self.id,
+            presence: init_server_data.presence,
+        };
+        self.client_manager_channel
+            .tx
+            .send(ClientManagerMsg::ResolvePresence(transit))
+            .await
+            .map_err(|err| anyhow!("{err}{}", Bt::new()))?;
+        let presence = rx_ack.await.map_err(|err| anyhow!("{err}{}", Bt::new()))?;
+
         let init_client_data = UserInitData {
             rooms: updated_room_data,
+            queued_messages: init_server_data.queued_messages,
+            undelivered: init_server_data.undelivered,
+            unread: init_server_data.unread,
+            seen: init_server_data.seen,
+            presence,
         };
 
         Ok(init_client_data)
@@ -252,19 +390,26 @@ impl<'a> ClientTask<'a> {
     pub async fn run(mut self) -> ClientTaskResult {
         if let Err(err) = self.init().await {
             error!("{err}");
-            self.cleanup().await;
+            self.cleanup(&ClientTaskResult::Close).await;
             return ClientTaskResult::Close;
         };
 
+        let mut stall_check = tokio::time::interval(Duration::from_secs(STREAM_STALL_CHECK_SECS));
+
         let result = loop {
             select! {
-                result = self.ws_read.next() => if let Err(err) = self.handle_ws_msg(result).await {
+                // boxed, the handler future is large enough to overflow the stack in debug builds
+                result = self.ws_read.next() => if let Err(err) = Box::pin(self.handle_ws_msg(result)).await {
                     error!("data processing error: {}", err);
                     break ClientTaskResult::Close;
                 },
 
                 result = self.client_manager_channel.rx.recv() =>  self.handle_manager_msg(result).await,
 
+                _ = stall_check.tick(), if !self.outgoing_streams.is_empty() => {
+                    self.drop_stalled_receivers().await
+                }
+
                 result = self.comm_client_data_channel.rx.recv() => {
                     let result = match result {
                         Some(r) => r,
@@ -300,19 +445,42 @@ impl<'a> ClientTask<'a> {
             }
         };
 
-        self.cleanup().await;
+        self.cleanup(&result).await;
         result
     }
 
-    async fn cleanup(&mut self) {
-        self.client_comm_cleanup_channel.tx.send(()).ok();
+    async fn cleanup(&mut self, result: &ClientTaskResult) {
+        self.client_comm_cleanup_channel.tx.send(None).ok();
+
+        // the client may come back and resume what it was sending
+        if !matches!(result, ClientTaskResult::Logout) {
+            self.park_streams().await;
+        }
+
+        // the user is still online through the connection that replaced this one
+        if let ClientTaskResult::Replaced = result {
+            return;
+        }
 
-        let msg = ClientManagerMsg::ClientDropped(self.id);
+        let msg = ClientManagerMsg::ClientDropped(ClientDroppedTransit {
+            user_id: self.id,
+            conn_id: self.conn_id,
+        });
         if let Err(err) = self.client_manager_channel.tx.send(msg).await {
             error!("rx_client_manager dropped, error: {}, {}", err, Bt::new())
         };
 
-        for (_, tx) in &self.room_channels {
+        // invisible users went offline for everyone else already
+        if self.presence.status == PresenceStatus::Invisible {
+            return;
+        }
+        self.presence.last_seen = Some(DateTime::now().timestamp_millis());
+        let msg = ClientPersistenceMsg::SetPresence(self.presence.clone());
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+        };
+
+        for tx in self.room_channels.values() {
             let msg = ServerClientMsg::UserDisconnected(User {
                 username: self.username.clone(),
                 id: self.id,
@@ -332,6 +500,31 @@ impl<'a> ClientTask<'a> {
         }
     }
 
+    /// streams that got under way wait in the manager, the others end with the connection
+    async fn park_streams(&mut self) {
+        let streams: HashMap<Uuid, OutgoingStream> = self
+            .outgoing_streams
+            .drain()
+            .filter(|(_, s)| s.started || s.upload.is_some())
+            .collect();
+        if streams.is_empty() {
+            return;
+        }
+
+        let parked = ParkedStreams {
+            user_id: self.id,
+            streams,
+        };
+        if let Err(err) = self
+            .client_manager_channel
+            .tx
+            .send(ClientManagerMsg::ParkStreams(parked))
+            .await
+        {
+            error!("rx_client_manager dropped, error: {}, {}", err, Bt::new())
+        };
+    }
+
     fn handle_comm_drop(&mut self, channel: Channel) {
         match channel {
             Channel::Room(id) => {
@@ -364,28 +557,89 @@ impl<'a> ClientTask<'a> {
                 // deserialize(&bytes).map_err(|err| BincodeErr(err, Bt::new()))?;
 
                 let message: ClientServerMsg =
-                    deserialize(&bytes).map_err(|err| BincodeErr(err, Bt::new()))?;
+                    deserialize(bytes).map_err(|err| BincodeErr(err, Bt::new()))?;
 
                 match message {
-                    ClientServerMsg::ASCII(img) => {
-                        let target = img.to.clone();
-                        let msg = ServerClientMsg::ASCII(img);
-                        self.send_data_to_channel(msg, target).await?;
+                    ClientServerMsg::ASCII(img) => self.handle_ascii_msg(img).await?,
+                    ClientServerMsg::Text(text_msg) => self.handle_text_msg(text_msg).await?,
+                    ClientServerMsg::EditMessage(edit) => {
+                        self.handle_change_message(edit.id, MsgChange::Edit(edit.text))
+                            .await?
+                    }
+                    ClientServerMsg::DeleteMessage(id) => {
+                        self.handle_change_message(id, MsgChange::Delete).await?
+                    }
+                    ClientServerMsg::AddReaction(r) => {
+                        self.handle_change_message(r.id, MsgChange::AddReaction(r.emoji))
+                            .await?
+                    }
+                    ClientServerMsg::RemoveReaction(r) => {
+                        self.handle_change_message(r.id, MsgChange::RemoveReaction(r.emoji))
+                            .await?
                     }
-                    ClientServerMsg::Text(text_msg) => {
-                        let target = text_msg.to.clone();
-                        let msg = ServerClientMsg::Text(text_msg);
-                        self.send_data_to_channel(msg, target).await?;
+                    ClientServerMsg::FetchHistory(req) => self.handle_fetch_history(req).await?,
+                    ClientServerMsg::FetchThread(id) => self.handle_fetch_thread(id).await?,
+                    // boxed, each inline arm grows the stack frame of this handler in debug builds
+                    ClientServerMsg::MarkRead(marker) => {
+                        Box::pin(self.handle_mark_read(marker)).await?
+                    }
+                    ClientServerMsg::Typing(channel) => {
+                        Box::pin(self.handle_typing(channel)).await?
+                    }
+                    ClientServerMsg::SetPresence(update) => {
+                        Box::pin(self.handle_set_presence(update)).await
+                    }
+                    ClientServerMsg::LeaveRoom(room_id) => self.handle_leave_room(room_id).await?,
+                    ClientServerMsg::DeleteRoom(room_id) => {
+                        self.handle_delete_room(room_id).await?
+                    }
+                    ClientServerMsg::SetRoomPassword(t) => {
+                        self.handle_set_room_password(t.room_id, t.room_password)
+                            .await?
+                    }
+                    ClientServerMsg::KickUser(target) => {
+                        self.handle_moderation(target, ModerationAction::Kick)
+                            .await?
+                    }
+                    ClientServerMsg::BanUser(target) => {
+                        self.handle_moderation(target, ModerationAction::Ban)
+                            .await?
+                    }
+                    ClientServerMsg::UnbanUser(target) => {
+                        self.handle_moderation(target, ModerationAction::Unban)
+                            .await?
+                    }
+                    ClientServerMsg::PromoteUser(target) => {
+                        self.handle_moderation(target, ModerationAction::Promote)
+                            .await?
+                    }
+                    ClientServerMsg::DemoteUser(target) => {
+                        self.handle_moderation(target, ModerationAction::Demote)
+                            .await?
                     }
                     ClientServerMsg::FileChunk(chunk) => {
-                        let target = chunk.to.clone();
-                        let msg = ServerClientMsg::FileChunk(chunk);
-                        self.send_data_to_channel(msg, target).await?;
+                        Box::pin(self.handle_file_chunk(chunk)).await?
+                    }
+                    ClientServerMsg::ProposeChunkSize(size) => {
+                        Box::pin(self.handle_propose_chunk_size(size)).await?
+                    }
+                    ClientServerMsg::TransferControl(control) => {
+                        Box::pin(self.handle_transfer_control(control)).await?
+                    }
+                    ClientServerMsg::StreamProgress(progress) => {
+                        Box::pin(self.handle_stream_progress(progress)).await?
                     }
                     ClientServerMsg::FileMetadata(data) => {
-                        let target = data.to.clone();
-                        let msg = ServerClientMsg::FileMetadata(data);
-                        self.send_data_to_channel(msg, target).await?;
+                        Box::pin(self.handle_file_metadata(data)).await?
+                    }
+                    ClientServerMsg::FetchFiles(channel) => {
+                        Box::pin(self.handle_fetch_files(channel)).await?
+                    }
+                    ClientServerMsg::FetchFile(req) => {
+                        Box::pin(self.handle_fetch_file(req)).await?
+                    }
+                    ClientServerMsg::ResumeTransfer(req) => {
+                        Box::pin(self.handle_resume_transfer(req)).await?
                     }
                     ClientServerMsg::Logout => {
                         if let Err(err) = self.close_channel.tx.send(ClientTaskResult::Logout).await
@@ -394,144 +648,11 @@ impl<'a> ClientTask<'a> {
                         };
                     }
                     ClientServerMsg::CreateRoom(t) => {
-                        let (tx_ack, rx_ack) = oneshot::channel::<CreateRoomRes>();
-
-                        let transit = CreateRoomServerTransit {
-                            tx: tx_ack,
-                            room_name: t.room_name,
-                            room_pwd: t.room_password,
-                            username: self.username.clone(),
-                        };
-
-                        let msg = ClientPersistenceMsg::CreateRoom(transit);
-
-                        if let Err(err) = self.tx_client_persistence.send(msg).await {
-                            error!("Persistence task not running {}, {}", err, Bt::new());
-                            let res =
-                                Err(String::from("Internal server error, creating room failed"));
-                            let msg = ServerClientMsg::CreateRoomResponse(res);
-                            self.send_to_client(msg).await?;
-                            return Ok(());
-                        };
-
-                        let res = match rx_ack.await {
-                            Err(err) => {
-                                error!(
-                                    " create room tx dropped before returning answer, err: {}, {}",
-                                    err,
-                                    Bt::new()
-                                );
-                                let res = Err(String::from(
-                                    "Internal server error, creating room failed",
-                                ));
-                                let msg = ServerClientMsg::CreateRoomResponse(res);
-                                self.send_to_client(msg).await?;
-                                return Ok(());
-                            }
-                            Ok(res) => res,
-                        };
-
-                        let msg = ServerClientMsg::CreateRoomResponse(res.clone());
-
-                        self.send_to_client(msg).await?;
-
-                        if let Ok(room) = res {
-                            let (tx, _) = broadcast::channel(ROOM_CAPACITY);
-                            self.room_channels.insert(room.id, tx.clone());
-                            self.spawn_room_communication_task(tx, room.id);
-                        };
+                        self.handle_create_room(t.room_name, t.room_password)
+                            .await?
                     }
                     ClientServerMsg::JoinRoom(t) => {
-                        let (tx_ack, rx_ack) = oneshot::channel::<JoinRoommPersistenceRes>();
-
-                        let transit = JoinRoomServerTransit {
-                            tx: tx_ack,
-                            room_name: t.room_name,
-                            room_pwd: t.room_password,
-                            user: User {
-                                id: self.id,
-                                username: self.username.clone(),
-                            },
-                        };
-
-                        let msg = ClientPersistenceMsg::JoinRoom(transit);
-
-                        let server_err_res =
-                            Err(String::from("Internal server error, joining room failed"));
-
-                        let server_err_msg = ServerClientMsg::JoinRoomResponse(server_err_res);
-
-                        if let Err(err) = self.tx_client_persistence.send(msg).await {
-                            warn!("Persistence task not running {}, {}", err, Bt::new());
-                            self.send_to_client(server_err_msg).await?;
-                            return Ok(());
-                        };
-
-                        let room_data = match rx_ack.await {
-                            Err(err) => {
-                                error!(
-                                    " oneshot tx_ack dropped in persistence_task before answering {}, {}",
-                                    err,
-                                    Bt::new()
-                                );
-                                self.send_to_client(server_err_msg).await?;
-                                return Ok(());
-                            }
-                            Ok(res) => match res {
-                                Err(reason) => {
-                                    let res = Err(reason);
-                                    let msg = ServerClientMsg::JoinRoomResponse(res);
-                                    self.send_to_client(msg).await?;
-                                    return Ok(());
-                                }
-
-                                Ok(mut data) => {
-                                    data.users.retain(|u| u.username != self.username);
-                                    data
-                                }
-                            },
-                        };
-
-                        let (tx_ack, rx_ack) = oneshot::channel();
-
-                        let transit = RoomUpdateTransit {
-                            tx_ack: tx_ack,
-                            room: room_data,
-                        };
-
-                        let msg = ClientManagerMsg::UpdateRoom(transit);
-
-                        if let Err(err) = self.client_manager_channel.tx.send(msg).await {
-                            error!("rx_client_manager dropped, error: {}, {}", err, Bt::new())
-                        };
-
-                        let room_data = match rx_ack.await {
-                            Err(err) => {
-                                warn!("client_manager_channel.rx dropped, {} {}", err, Bt::new());
-                                self.send_to_client(server_err_msg).await?;
-                                return Ok(());
-                            }
-                            Ok(users) => users,
-                        };
-
-                        self.establish_room_comm(room_data.id, room_data.users.clone())
-                            .await;
-
-                        let notification = JoinRoomNotification {
-                            room_id: room_data.id,
-                            user: User {
-                                username: self.username.clone(),
-                                id: self.id,
-                            },
-                        };
-
-                        let msg = ServerClientMsg::UserJoinedRoom(notification);
-                        let target = Channel::Room(room_data.id);
-                        self.send_data_to_channel(msg, target).await?;
-
-                        let res = RoomActionRes::Ok(room_data);
-                        let msg = ServerClientMsg::JoinRoomResponse(res);
-                        self.send_to_client(msg).await?;
+                        self.handle_join_room(t.room_name, t.room_password).await?
                     }
                 };
             }
@@ -544,165 +665,1917 @@ impl<'a> ClientTask<'a> {
         Ok(())
     }
 
-    async fn handle_manager_msg(&mut self, result: Option<ManagerClientMsg>) {
-        if let Some(msg) = result {
-            match msg {
-                ManagerClientMsg::EstablishDirectComm(c) => {
-                    self.direct_channels
-                        .insert(c.payload.from, c.payload.tx_client_client);
-                    let tx_client_client = self.spawn_direct_communication_task(c.payload.from);
-
-                    if let Err(err) = c.ack.send(tx_client_client) {
-                        error!(
-                            "oneshot rx not dropped during establishing direct communication. transit data: {:?}, {}",
-                            err,
-                            Bt::new()
-                        );
-                    };
-                }
-
-                ManagerClientMsg::GetRoomTransmitter(t) => {
-                    let room_transmitter = match self.room_channels.get(&t.room_id) {
-                        None => {
-                            debug!(
-                                "romm transmitter not found after fetching for another user! {}",
-                                Bt::new()
-                            );
-                            return;
-                        }
-                        Some(tx) => tx,
-                    };
-                    t.tx_ack.send(room_transmitter.clone()).ok();
-                }
-            };
-        }
-    }
-
-    async fn send_data_to_channel(
+    async fn handle_create_room(
         &mut self,
-        msg: ServerClientMsg,
-        target: Channel,
+        room_name: String,
+        room_pwd: Option<String>,
     ) -> Result<(), WsDataParsingError> {
-        let serialized = bincode::serialize(&msg).map_err(|err| BincodeErr(err, Bt::new()))?;
-        let data = Bytes::from(serialized);
+        let (tx_ack, rx_ack) = oneshot::channel::<CreateRoomRes>();
 
-        match target {
-            Channel::User(target_id) => {
-                let tx = self.direct_channels.get(&target_id);
+        let transit = CreateRoomServerTransit {
+            tx: tx_ack,
+            room_name,
+            room_pwd,
+            username: self.username.clone(),
+        };
 
-                let tx = match tx {
-                    Some(tx) => tx,
-                    None => {
-                        self.establish_direct_comm(target_id).await;
-                        let tx = match self.direct_channels.get(&target_id) {
-                            Some(tx) => tx,
-                            None => {
-                                warn!(
-                                    "establishing direct communication failed {}, msg not sent",
-                                    Bt::new()
-                                );
-                                return Ok(());
-                            }
-                        };
-                        tx
-                    }
-                };
+        let msg = ClientPersistenceMsg::CreateRoom(transit);
 
-                if tx.send(data).await.is_err() {
-                    if self.comm_client_drop_channel.tx.send(target).await.is_err() {
-                        warn!(
-                            "rx comm_client_drop_channel dropped, should be saved in client_task instance!!"
-                        );
-                    };
-                };
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+            let res = Err(String::from("Internal server error, creating room failed"));
+            let msg = ServerClientMsg::CreateRoomResponse(res);
+            self.send_to_client(msg).await?;
+            return Ok(());
+        };
+
+        let res = match rx_ack.await {
+            Err(err) => {
+                error!(
+                    " create room tx dropped before returning answer, err: {}, {}",
+                    err,
+                    Bt::new()
+                );
+                let res = Err(String::from("Internal server error, creating room failed"));
+                let msg = ServerClientMsg::CreateRoomResponse(res);
+                self.send_to_client(msg).await?;
+                return Ok(());
             }
-            Channel::Room(room_id) => {
-                let tx = self.room_channels.get(&room_id);
+            Ok(res) => res,
+        };
 
-                let tx = match tx {
-                    Some(tx) => tx,
-                    None => {
-                        warn!("Room transmiter not found {}", Bt::new());
-                        return Ok(());
-                    }
-                };
+        let msg = ServerClientMsg::CreateRoomResponse(res.clone());
 
-                if let Err(err) = tx.send(data) {
-                    let tx = self.spawn_room_communication_task(tx.clone(), room_id);
-                    if tx.send(err.0).is_err() {
-                        error!("unable to establish room communication!!{}", Bt::new());
-                    };
-                };
-            }
+        self.send_to_client(msg).await?;
+
+        if let Ok(room) = res {
+            let (tx, _) = broadcast::channel(ROOM_CAPACITY);
+            self.room_channels.insert(room.id, tx.clone());
+            self.spawn_room_communication_task(tx, room.id);
         };
         Ok(())
     }
 
-    async fn establish_direct_comm(&mut self, target_id: Uuid) {
-        let tx_client_client = self.spawn_direct_communication_task(target_id);
-        let (tx_ack, rx_ack) = oneshot::channel::<mpsc::Sender<Bytes>>();
+    async fn handle_join_room(
+        &mut self,
+        room_name: String,
+        room_pwd: Option<String>,
+    ) -> Result<(), WsDataParsingError> {
+        let (tx_ack, rx_ack) = oneshot::channel::<JoinRoommPersistenceRes>();
 
-        let channel_transit = DirectChannelTxTransit {
-            payload: DirectChannelTransitPayload {
-                tx_client_client,
-                from: self.id,
-                to: target_id,
+        let transit = JoinRoomServerTransit {
+            tx: tx_ack,
+            room_name,
+            room_pwd,
+            user: User {
+                id: self.id,
+                username: self.username.clone(),
             },
-            ack: tx_ack,
         };
 
-        if let Err(err) = self
-            .client_manager_channel
-            .tx
-            .send(ClientManagerMsg::GetDirectChannelTx(channel_transit))
-            .await
-        {
-            warn!("tx_client_manager dropped, {},{}", err, Bt::new());
-            return;
+        let msg = ClientPersistenceMsg::JoinRoom(transit);
+
+        let server_err_res = Err(String::from("Internal server error, joining room failed"));
+
+        let server_err_msg = ServerClientMsg::JoinRoomResponse(server_err_res);
+
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            warn!("Persistence task not running {}, {}", err, Bt::new());
+            self.send_to_client(server_err_msg).await?;
+            return Ok(());
         };
 
-        let new_direct = match rx_ack.await {
+        let room_data = match rx_ack.await {
             Err(err) => {
-                warn!(
-                    "oneshot transmitter for establishing direct communication dropped, {}, {}",
+                error!(
+                    " oneshot tx_ack dropped in persistence_task before answering {}, {}",
                     err,
                     Bt::new()
                 );
-                return;
+                self.send_to_client(server_err_msg).await?;
+                return Ok(());
             }
-            Ok(tx) => tx,
-        };
+            Ok(res) => match res {
+                Err(reason) => {
+                    let res = Err(reason);
+                    let msg = ServerClientMsg::JoinRoomResponse(res);
+                    self.send_to_client(msg).await?;
+                    return Ok(());
+                }
 
-        let direct_channels = &mut self.direct_channels;
-        direct_channels.insert(target_id, new_direct);
-    }
+                Ok(mut data) => {
+                    data.users.retain(|u| u.username != self.username);
+                    data
+                }
+            },
+        };
 
-    async fn establish_room_comm(&mut self, room_id: Uuid, room_users: Vec<User>) {
-        let (tx_ack, rx_ack) = oneshot::channel::<broadcast::Sender<Bytes>>();
+        let (tx_ack, rx_ack) = oneshot::channel();
 
-        let transit = RoomChannelTxTransit {
-            room_id,
-            room_users,
-            ack: tx_ack,
+        let transit = RoomUpdateTransit {
+            tx_ack: tx_ack,
+            room: room_data,
         };
 
-        if let Err(err) = self
-            .client_manager_channel
-            .tx
-            .send(ClientManagerMsg::GetRoomChannelTx(transit))
-            .await
-        {
-            warn!("tx_client_manager dropped, {},{}", err, Bt::new());
-            return;
+        let msg = ClientManagerMsg::UpdateRoom(transit);
+
+        if let Err(err) = self.client_manager_channel.tx.send(msg).await {
+            error!("rx_client_manager dropped, error: {}, {}", err, Bt::new())
         };
 
-        let tx = match rx_ack.await {
+        let room_data = match rx_ack.await {
             Err(err) => {
-                debug!(
-                    "oneshot transmitter for establishing room communication dropped, {}, {}",
-                    err,
-                    Bt::new()
-                );
+                warn!("client_manager_channel.rx dropped, {} {}", err, Bt::new());
+                self.send_to_client(server_err_msg).await?;
+                return Ok(());
+            }
+            Ok(users) => users,
+        };
+
+        self.establish_room_comm(room_data.id, room_data.users.clone())
+            .await;
+
+        let notification = JoinRoomNotification {
+            room_id: room_data.id,
+            user: User {
+                username: self.username.clone(),
+                id: self.id,
+            },
+        };
+
+        let msg = ServerClientMsg::UserJoinedRoom(notification);
+        let target = Channel::Room(room_data.id);
+        self.send_data_to_channel(msg, target).await?;
+
+        let res = RoomActionRes::Ok(room_data);
+        let msg = ServerClientMsg::JoinRoomResponse(res);
+        self.send_to_client(msg).await?;
+        Ok(())
+    }
+
+    fn get_user(&self) -> User {
+        User {
+            username: self.username.clone(),
+            id: self.id,
+        }
+    }
+
+    /// room channels are only held for rooms the user is a member of
+    fn can_send_to(&self, target: &Channel) -> bool {
+        match target {
+            Channel::Room(id) => self.room_channels.contains_key(id),
+            Channel::User(_) => true,
+        }
+    }
+
+    /// one refusal per stream, not one per chunk
+    async fn handle_file_chunk(&mut self, mut chunk: Chunk) -> Result<(), WsDataParsingError> {
+        chunk.from = self.get_user();
+        let target = chunk.to.clone();
+        // the refusal of the metadata already told the sender
+        if self.refused_streams.contains(&chunk.stream_id) {
+            return Ok(());
+        }
+
+        let refusal = match self.can_send_to(&target) {
+            false => Some(String::from("You are not a member of this room")),
+            true if chunk.data.len() > self.chunk_size => Some(format!(
+                "Chunks of {} bytes are larger than the agreed {}",
+                chunk.data.len(),
+                self.chunk_size
+            )),
+            true => None,
+        };
+        if let Some(reason) = refusal {
+            self.refused_streams.insert(chunk.stream_id);
+            let refused = RefusedMsg::FileChunk {
+                stream_id: chunk.stream_id,
+            };
+            return self.refuse_msg(target, refused, reason).await;
+        }
+
+        // never offered, or over already
+        let stream = match self.outgoing_streams.get_mut(&chunk.stream_id) {
+            Some(stream) => stream,
+            None => return Ok(()),
+        };
+        // everyone who accepted cancelled before the first chunk, and no copy is kept
+        if stream.receivers.is_empty() && stream.upload.is_none() {
+            self.end_stream(chunk.stream_id).await;
+            return Ok(());
+        }
+
+        let stream_id = chunk.stream_id;
+        // a client not waiting for credit would pile its chunks up in the server
+        if chunk.seq >= stream.credit {
+            let receivers: Vec<Uuid> = stream.receivers.keys().copied().collect();
+            self.outgoing_streams.remove(&stream_id);
+            self.refused_streams.insert(stream_id);
+            for id in receivers {
+                self.send_stream_failure(id, stream_id, "the sender did not wait for room");
+            }
+            let refused = RefusedMsg::FileChunk { stream_id };
+            let reason = format!(
+                "Chunk {} was sent before the receivers had room for it",
+                chunk.seq
+            );
+            return self.refuse_msg(target, refused, reason).await;
+        }
+
+        // sent again after a resume, the chunk got through before the connection dropped
+        if chunk.offset < stream.offset {
+            return Ok(());
+        }
+
+        stream.started = true;
+        stream.next_seq = chunk.seq + 1;
+        stream.offset = chunk.offset + chunk.data.len() as u64;
+        if stream.next_seq == stream.credit {
+            stream.blocked_since = Some(Instant::now());
+        }
+        // the copy kept by the server is written before the chunk moves on
+        if let Some(upload) = &mut stream.upload
+            && let Err(err) = upload.write(&chunk.data).await
+        {
+            warn!("file {} not stored, {err}", stream.file.filename);
+            stream.upload = None;
+            if stream.receivers.is_empty() {
+                self.end_stream(stream_id).await;
+                return Ok(());
+            }
+        }
+        let receivers: Vec<Uuid> = stream.receivers.keys().copied().collect();
+        let finished = match chunk.last {
+            true => self.outgoing_streams.remove(&stream_id),
+            false => None,
+        };
+
+        let msg = ServerClientMsg::FileChunk(chunk);
+        let serialized = bincode::serialize(&msg).map_err(|err| BincodeErr(err, Bt::new()))?;
+        let data = Bytes::from(serialized);
+        // a receiver missing a chunk could never complete the file, it is dropped instead
+        let mut dropped = vec![];
+        for id in receivers {
+            match self.try_send_direct(id, data.clone()).await {
+                Ok(_) => {}
+                Err(TrySendError::Full(_)) => dropped.push((id, "fell behind the sender")),
+                Err(TrySendError::Closed(_)) => dropped.push((id, "went offline")),
+            }
+        }
+        for (id, reason) in dropped {
+            self.drop_receiver(stream_id, id, reason).await;
+        }
+
+        // without receivers it is the copy that makes room for the next chunks
+        match finished {
+            Some(stream) => self.store_upload(stream).await,
+            None => self.grant_credit(stream_id).await,
+        }
+        Ok(())
+    }
+
+    /// the record is only written once the copy is complete and matches its hash
+    async fn store_upload(&mut self, stream: OutgoingStream) {
+        let upload = match stream.upload {
+            Some(upload) => upload,
+            None => return,
+        };
+        let file = stream.file;
+        if let Err(err) = upload.finish().await {
+            warn!("file {} not stored, {err}", file.filename);
+            return;
+        }
+
+        let stored_at = DateTime::now().timestamp_millis();
+        let stored = StoredFile {
+            id: file.stream_id,
+            from_id: self.id,
+            to: file.to.clone(),
+            size: file.size,
+            created_at: stored_at,
+            entry: FileEntry {
+                id: file.stream_id,
+                filename: file.filename,
+                size: file.size,
+                mime: file.mime,
+                sha256: file.sha256,
+                sender: file.sender,
+                to: file.to,
+                stored_at,
+            },
+        };
+        let msg = ClientPersistenceMsg::StoreFile(stored);
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+        };
+    }
+
+    /// the sender pauses, resumes or cancels for the whole channel of the offer,
+    /// a receiver only answers for itself and goes through the manager to the sender
+    async fn handle_transfer_control(
+        &mut self,
+        mut control: TransferControl,
+    ) -> Result<(), WsDataParsingError> {
+        control.from = self.get_user();
+
+        // a download only has the server on the other side
+        if control.action == TransferAction::Cancel
+            && let Some(download) = self.downloads.remove(&control.stream_id)
+        {
+            download.abort();
+            return Ok(());
+        }
+
+        let stream = match self.outgoing_streams.get(&control.stream_id) {
+            Some(stream) => stream,
+            None => {
+                if let Err(err) = self
+                    .client_manager_channel
+                    .tx
+                    .send(ClientManagerMsg::TransferControl(control))
+                    .await
+                {
+                    warn!("tx_client_manager dropped, {},{}", err, Bt::new());
+                };
+                return Ok(());
+            }
+        };
+
+        let to = stream.to.clone();
+        match control.action {
+            TransferAction::Accept | TransferAction::Decline => return Ok(()),
+            TransferAction::Pause | TransferAction::Resume => {}
+            TransferAction::Cancel => {
+                self.outgoing_streams.remove(&control.stream_id);
+                self.refused_streams.insert(control.stream_id);
+            }
+        };
+        control.to = to.clone();
+        let msg = ServerClientMsg::TransferControl(control);
+        self.send_data_to_channel(msg, to).await
+    }
+
+    /// answer of a receiver to a file this client sends. a stream is over once no one
+    /// is left to receive it, which the client learns from a `Cancel` sent by itself
+    async fn handle_receiver_control(&mut self, control: TransferControl) {
+        let to = match self.outgoing_streams.get(&control.stream_id) {
+            Some(stream) => stream.to.clone(),
+            None => return,
+        };
+
+        let receiver = control.from.id;
+        if control.action == TransferAction::Accept && !self.was_offered_to(&to, receiver).await {
+            self.send_stream_failure(receiver, control.stream_id, "it was not offered to you");
+            return;
+        }
+
+        let stream = match self.outgoing_streams.get_mut(&control.stream_id) {
+            Some(stream) => stream,
+            None => return,
+        };
+        match control.action {
+            TransferAction::Accept if stream.started && stream.upload.is_some() => {
+                let reason = "it already started, /download it once it is stored";
+                self.send_stream_failure(receiver, control.stream_id, reason);
+                return;
+            }
+            TransferAction::Accept if stream.started => {
+                let too_late = TransferControl {
+                    stream_id: control.stream_id,
+                    action: TransferAction::Cancel,
+                    from: self.get_user(),
+                    to: Channel::User(receiver),
+                };
+                if let Ok(serialized) =
+                    bincode::serialize(&ServerClientMsg::TransferControl(too_late))
+                {
+                    self.send_direct(receiver, Bytes::from(serialized)).await;
+                }
+                return;
+            }
+            TransferAction::Accept => {
+                let receiver = StreamReceiver {
+                    user: control.from.clone(),
+                    seq: 0,
+                };
+                stream.receivers.insert(control.from.id, receiver);
+            }
+            TransferAction::Decline | TransferAction::Cancel => {
+                stream.receivers.remove(&receiver);
+            }
+            TransferAction::Pause | TransferAction::Resume => {}
+        };
+
+        // other members of a room may still accept an offer nobody started receiving
+        let over = stream.receivers.is_empty()
+            && stream.upload.is_none()
+            && (stream.started || matches!(stream.to, Channel::User(_)))
+            && matches!(
+                control.action,
+                TransferAction::Decline | TransferAction::Cancel
+            );
+        let stream_id = control.stream_id;
+
+        if let Err(err) = self
+            .send_to_client(ServerClientMsg::TransferControl(control))
+            .await
+        {
+            warn!("transfer answer not sent, {err}");
+        };
+
+        // the receiver that left may have been the one holding the sender back
+        match over {
+            true => self.end_stream(stream_id).await,
+            false => self.grant_credit(stream_id).await,
+        }
+    }
+
+    /// offers to a room reach its members, direct ones the other user only
+    async fn was_offered_to(&self, to: &Channel, user_id: Uuid) -> bool {
+        let room_id = match to {
+            Channel::Room(room_id) => *room_id,
+            Channel::User(peer_id) => return *peer_id == user_id,
+        };
+
+        let (tx_ack, rx_ack) = oneshot::channel::<bool>();
+        let transit = RoomMemberTransit {
+            tx: tx_ack,
+            room_id,
+            user_id,
+        };
+        let msg = ClientPersistenceMsg::IsRoomMember(transit);
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+            return false;
+        };
+        match rx_ack.await {
+            Ok(is_member) => is_member,
+            Err(err) => {
+                error!("room member tx dropped before returning answer, err: {err}");
+                false
+            }
+        }
+    }
+
+    /// the sender may run `STREAM_WINDOW` chunks ahead of its slowest receiver
+    async fn grant_credit(&mut self, stream_id: Uuid) {
+        let stream = match self.outgoing_streams.get_mut(&stream_id) {
+            Some(stream) => stream,
+            None => return,
+        };
+        let slowest = stream.receivers.values().map(|r| r.seq).min();
+        let credit = match (slowest, &stream.upload) {
+            (Some(slowest), _) => slowest + STREAM_WINDOW,
+            // the copy is written as the chunks arrive, it never holds the sender back
+            (None, Some(_)) => stream.next_seq + STREAM_WINDOW,
+            (None, None) => return,
+        };
+        if credit <= stream.credit {
+            return;
+        }
+
+        stream.credit = credit;
+        stream.blocked_since = None;
+        let msg = ServerClientMsg::StreamCredit(StreamCredit {
+            stream_id,
+            seq: credit,
+        });
+        if let Err(err) = self.send_to_client(msg).await {
+            warn!("stream credit not sent, {err}");
+        };
+    }
+
+    /// the sender of the stream gets it through the manager
+    async fn handle_stream_progress(
+        &mut self,
+        mut progress: StreamProgress,
+    ) -> Result<(), WsDataParsingError> {
+        progress.from = self.get_user();
+        if let Err(err) = self
+            .client_manager_channel
+            .tx
+            .send(ClientManagerMsg::StreamProgress(progress))
+            .await
+        {
+            warn!("tx_client_manager dropped, {},{}", err, Bt::new());
+        };
+        Ok(())
+    }
+
+    /// progress of a receiver of a file this client sends
+    async fn handle_receiver_progress(&mut self, progress: StreamProgress) {
+        let stream = match self.outgoing_streams.get_mut(&progress.stream_id) {
+            Some(stream) => stream,
+            None => return,
+        };
+        let next_seq = stream.next_seq;
+        let receiver = match stream.receivers.get_mut(&progress.from.id) {
+            Some(receiver) => receiver,
+            None => return,
+        };
+
+        // nobody can have written chunks that were not sent yet
+        let seq = progress.seq.min(next_seq);
+        if seq <= receiver.seq {
+            return;
+        }
+        receiver.seq = seq;
+        self.grant_credit(progress.stream_id).await;
+    }
+
+    /// receivers that held the sender back for `STREAM_STALL_SECS`
+    async fn drop_stalled_receivers(&mut self) {
+        let stall = Duration::from_secs(STREAM_STALL_SECS);
+        let stalled: Vec<(Uuid, Uuid)> = self
+            .outgoing_streams
+            .iter()
+            .filter(|(_, s)| s.blocked_since.is_some_and(|at| at.elapsed() > stall))
+            .flat_map(|(stream_id, s)| {
+                s.receivers
+                    .iter()
+                    .filter(|(_, r)| r.seq + STREAM_WINDOW <= s.next_seq)
+                    .map(|(id, _)| (*stream_id, *id))
+            })
+            .collect();
+
+        for (stream_id, receiver) in stalled {
+            self.drop_receiver(stream_id, receiver, "stopped keeping up with the sender")
+                .await;
+        }
+    }
+
+    /// the receiver learns why, the sender gets it as a cancel of that receiver
+    async fn drop_receiver(&mut self, stream_id: Uuid, receiver: Uuid, reason: &str) {
+        self.send_stream_failure(receiver, stream_id, reason);
+
+        let user = match self
+            .outgoing_streams
+            .get(&stream_id)
+            .and_then(|s| s.receivers.get(&receiver))
+        {
+            Some(receiver) => receiver.user.clone(),
+            None => return,
+        };
+        let control = TransferControl {
+            stream_id,
+            action: TransferAction::Cancel,
+            from: user,
+            to: Channel::User(self.id),
+        };
+        self.handle_receiver_control(control).await;
+    }
+
+    /// a member kicked or banned from the room stops getting the files offered there
+    async fn drop_removed_member(&mut self, notice: ModerationNotice) {
+        let room = Channel::Room(notice.room_id);
+        let streams: Vec<Uuid> = self
+            .outgoing_streams
+            .iter()
+            .filter(|(_, s)| s.to == room && s.receivers.contains_key(&notice.target.id))
+            .map(|(id, _)| *id)
+            .collect();
+        for stream_id in streams {
+            self.drop_receiver(stream_id, notice.target.id, "was removed from the room")
+                .await;
+        }
+    }
+
+    /// queued behind the chunks the receiver already has, without waiting for room
+    fn send_stream_failure(&mut self, receiver: Uuid, stream_id: Uuid, reason: &str) {
+        let msg = ServerClientMsg::StreamFailed(StreamFailure {
+            stream_id,
+            reason: String::from(reason),
+        });
+        let data = match bincode::serialize(&msg) {
+            Ok(serialized) => Bytes::from(serialized),
+            Err(err) => {
+                warn!("stream failure not sent, {err}");
+                return;
+            }
+        };
+
+        // an offline receiver has no channel and no stream to fail any more
+        if let Some(tx) = self.direct_channels.get(&receiver).cloned() {
+            task::spawn(async move { tx.send(data).await.ok() });
+        }
+    }
+
+    /// tells the sending client to stop, its remaining chunks are dropped
+    async fn end_stream(&mut self, stream_id: Uuid) {
+        self.outgoing_streams.remove(&stream_id);
+        self.refused_streams.insert(stream_id);
+        let ended = TransferControl {
+            stream_id,
+            action: TransferAction::Cancel,
+            from: self.get_user(),
+            to: Channel::User(self.id),
+        };
+        if let Err(err) = self
+            .send_to_client(ServerClientMsg::TransferControl(ended))
+            .await
+        {
+            warn!("end of transfer not sent, {err}");
+        };
+    }
+
+    async fn handle_propose_chunk_size(&mut self, size: u32) -> Result<(), WsDataParsingError> {
+        self.chunk_size = (size as usize).clamp(CHUNK_SIZE_MIN, CHUNK_SIZE_MAX);
+        let msg = ServerClientMsg::ChunkSize(self.chunk_size as u32);
+        self.send_to_client(msg).await
+    }
+
+    /// files show up in the room they were sent to, direct ones under the sender
+    async fn handle_file_metadata(
+        &mut self,
+        mut data: FileMetadata,
+    ) -> Result<(), WsDataParsingError> {
+        data.from = match &data.to {
+            Channel::Room(id) => Channel::Room(*id),
+            Channel::User(_) => Channel::User(self.id),
+        };
+        let target = data.to.clone();
+
+        let refusal = match self.can_send_to(&target) {
+            false => Some(String::from("You are not a member of this room")),
+            true => self.check_file_type(&data),
+        };
+        if let Some(reason) = refusal {
+            self.refused_streams.insert(data.stream_id);
+            let refused = RefusedMsg::FileMetadata {
+                stream_id: data.stream_id,
+                filename: data.filename,
+            };
+            return self.refuse_msg(target, refused, reason).await;
+        }
+
+        data.sender = self.get_user();
+        let upload = self.start_upload(&data).await;
+        let action = match upload {
+            Some(_) => TransferAction::Accept,
+            None => TransferAction::Decline,
+        };
+        let stream_id = data.stream_id;
+        let stream = OutgoingStream {
+            to: target.clone(),
+            file: data.clone(),
+            upload,
+            receivers: HashMap::new(),
+            started: false,
+            next_seq: 0,
+            offset: 0,
+            credit: 0,
+            blocked_since: None,
+        };
+        self.outgoing_streams.insert(stream_id, stream);
+
+        let msg = ServerClientMsg::FileMetadata(data);
+        self.send_data_to_channel(msg, target).await?;
+
+        // the server answers for its copy like a receiver, a stored file starts right away
+        let control = TransferControl {
+            stream_id,
+            action,
+            from: self.get_user(),
+            to: Channel::User(self.id),
+        };
+        self.send_to_client(ServerClientMsg::TransferControl(control))
+            .await?;
+        self.grant_credit(stream_id).await;
+        Ok(())
+    }
+
+    /// `None` when the file does not fit into what is left of the quota of the user
+    async fn start_upload(&self, data: &FileMetadata) -> Option<Upload> {
+        if self.file_store.quota == 0 {
+            return None;
+        }
+
+        let (tx_ack, rx_ack) = oneshot::channel::<u64>();
+        let transit = FileSpaceTransit {
+            tx: tx_ack,
+            user_id: self.id,
+        };
+        let msg = ClientPersistenceMsg::GetFileSpace(transit);
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+            return None;
+        };
+        let used = match rx_ack.await {
+            Ok(used) => used,
+            Err(err) => {
+                error!("file space tx dropped before returning answer, err: {err}");
+                return None;
+            }
+        };
+
+        let uploading: u64 = self
+            .outgoing_streams
+            .values()
+            .filter_map(|s| s.upload.as_ref())
+            .map(|u| u.size)
+            .sum();
+        if used.saturating_add(uploading).saturating_add(data.size) > self.file_store.quota {
+            debug!(
+                "file {} of {} is over the quota",
+                data.filename, self.username
+            );
+            return None;
+        }
+
+        match self.file_store.start_upload(data.size, &data.sha256).await {
+            Ok(upload) => Some(upload),
+            Err(err) => {
+                warn!("file {} not stored, {err}", data.filename);
+                None
+            }
+        }
+    }
+
+    async fn handle_fetch_files(&mut self, channel: Channel) -> Result<(), WsDataParsingError> {
+        let (tx_ack, rx_ack) = oneshot::channel::<FileList>();
+
+        let transit = FileListTransit {
+            tx: tx_ack,
+            user: self.get_user(),
+            channel: channel.clone(),
+        };
+
+        let empty_list = FileList {
+            channel,
+            files: vec![],
+        };
+
+        let msg = ClientPersistenceMsg::GetFiles(transit);
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+            self.send_to_client(ServerClientMsg::Files(empty_list))
+                .await?;
+            return Ok(());
+        };
+
+        let list = match rx_ack.await {
+            Ok(list) => list,
+            Err(err) => {
+                error!(
+                    "files tx dropped before returning answer, err: {}, {}",
+                    err,
+                    Bt::new()
+                );
+                empty_list
+            }
+        };
+
+        self.send_to_client(ServerClientMsg::Files(list)).await
+    }
+
+    /// the chunks are read from the store by a task of their own, without credit,
+    /// waiting for the connection of this client is enough
+    async fn handle_fetch_file(&mut self, req: FileRequest) -> Result<(), WsDataParsingError> {
+        let (file, blob) = match self.open_stored_file(req.file_id).await {
+            Ok(found) => found,
+            Err(reason) => {
+                let response = FileResponse {
+                    stream_id: req.stream_id,
+                    res: Err(reason),
+                };
+                return self
+                    .send_to_client(ServerClientMsg::FileResponse(response))
+                    .await;
+            }
+        };
+
+        let response = FileResponse {
+            stream_id: req.stream_id,
+            res: Ok(file.entry.clone()),
+        };
+        self.send_to_client(ServerClientMsg::FileResponse(response))
+            .await?;
+
+        let start = ResumePoint { seq: 0, offset: 0 };
+        self.start_download(req.stream_id, file, blob, start);
+        Ok(())
+    }
+
+    /// the record, when the user may read it, and its content
+    async fn open_stored_file(&self, file_id: Uuid) -> Result<(StoredFile, fs::File), String> {
+        let (tx_ack, rx_ack) = oneshot::channel::<Result<StoredFile, String>>();
+
+        let transit = FileTransit {
+            tx: tx_ack,
+            user: self.get_user(),
+            id: file_id,
+        };
+
+        let server_err_res = Err(String::from("Internal server error, loading file failed"));
+
+        let msg = ClientPersistenceMsg::GetFile(transit);
+        let file = match self.tx_client_persistence.send(msg).await {
+            Ok(_) => match rx_ack.await {
+                Ok(res) => res,
+                Err(err) => {
+                    error!(
+                        "file tx dropped before returning answer, err: {}, {}",
+                        err,
+                        Bt::new()
+                    );
+                    server_err_res
+                }
+            },
+            Err(err) => {
+                error!("Persistence task not running {}, {}", err, Bt::new());
+                server_err_res
+            }
+        }?;
+
+        match self.file_store.open(&file.entry.sha256).await {
+            Ok(blob) => Ok((file, blob)),
+            Err(err) => {
+                error!("stored file {} can not be read, {err}", file.id);
+                Err(String::from("The file is no longer stored."))
+            }
+        }
+    }
+
+    /// `blob` has to be at `start.offset` already
+    fn start_download(
+        &mut self,
+        stream_id: Uuid,
+        file: StoredFile,
+        blob: fs::File,
+        start: ResumePoint,
+    ) {
+        let size = file.size;
+        let chunk = Chunk {
+            from: file.entry.sender,
+            data: vec![],
+            to: Channel::User(self.id),
+            stream_id,
+            seq: start.seq,
+            offset: start.offset,
+            last: false,
+        };
+
+        let tx = self.comm_client_data_channel.tx.clone();
+        let chunk_size = self.chunk_size;
+        let download = task::spawn(async move {
+            if let Err(err) = ClientTask::send_stored_file(blob, size, chunk, chunk_size, tx).await
+            {
+                debug!("download of stored file stopped, {err}");
+            }
+        });
+        self.downloads.retain(|_, d| !d.is_finished());
+        self.downloads.insert(stream_id, download.abort_handle());
+    }
+
+    /// a sender gets its parked stream back, a receiver the rest of the stored file
+    async fn handle_resume_transfer(
+        &mut self,
+        req: ResumeRequest,
+    ) -> Result<(), WsDataParsingError> {
+        match req.file_id {
+            None => self.resume_stream(req).await,
+            Some(file_id) => self.resume_download(file_id, req).await,
+        }
+    }
+
+    /// receivers that are still there get the chunks from where the server stopped
+    async fn resume_stream(&mut self, req: ResumeRequest) -> Result<(), WsDataParsingError> {
+        let stream_id = req.stream_id;
+        let (tx_ack, rx_ack) = oneshot::channel::<Option<OutgoingStream>>();
+
+        let transit = ResumeStreamTransit {
+            ack: tx_ack,
+            user_id: self.id,
+            stream_id,
+            sha256: req.sha256,
+        };
+
+        let stream = match self
+            .client_manager_channel
+            .tx
+            .send(ClientManagerMsg::ResumeStream(transit))
+            .await
+        {
+            Ok(_) => rx_ack.await.unwrap_or_else(|err| {
+                error!("parked stream tx dropped before returning answer, err: {err}");
+                None
+            }),
+            Err(err) => {
+                warn!("tx_client_manager dropped, {},{}", err, Bt::new());
+                None
+            }
+        };
+
+        let res = match stream {
+            None => Err(String::from("The server no longer has this transfer")),
+            Some(stream) if !self.can_send_to(&stream.to) => {
+                Err(String::from("You are not a member of this room"))
+            }
+            Some(mut stream) => {
+                // progress reported while the sender was away got lost, what was relayed
+                // before had the time to arrive
+                for receiver in stream.receivers.values_mut() {
+                    receiver.seq = stream.next_seq;
+                }
+                stream.blocked_since = None;
+                let point = ResumePoint {
+                    seq: stream.next_seq,
+                    offset: stream.offset,
+                };
+                self.outgoing_streams.insert(stream_id, stream);
+                Ok(point)
+            }
+        };
+
+        let resumed = res.is_ok();
+        let response = ResumeResponse { stream_id, res };
+        self.send_to_client(ServerClientMsg::TransferResumed(response))
+            .await?;
+        if resumed {
+            self.grant_credit(stream_id).await;
+        }
+        Ok(())
+    }
+
+    /// downloads and streams the server kept a copy of continue from the stored file,
+    /// `id` of the copy of a stream is the id of the stream
+    async fn resume_download(
+        &mut self,
+        file_id: Uuid,
+        req: ResumeRequest,
+    ) -> Result<(), WsDataParsingError> {
+        let point = req.point;
+        let found = match self.open_stored_file(file_id).await {
+            Ok((file, _)) if file.entry.sha256 != req.sha256 => Err(String::from(
+                "The stored file is not the one that was being received",
+            )),
+            Ok((file, _)) if point.offset > file.size => {
+                Err(format!("The file only has {} bytes", file.size))
+            }
+            Ok((file, mut blob)) => match blob.seek(SeekFrom::Start(point.offset)).await {
+                Ok(_) => Ok((file, blob)),
+                Err(err) => {
+                    error!("stored file {} can not be read, {err}", file.id);
+                    Err(String::from("The file is no longer stored."))
+                }
+            },
+            Err(reason) => Err(reason),
+        };
+
+        let (file, blob) = match found {
+            Ok(found) => found,
+            Err(reason) => {
+                let response = ResumeResponse {
+                    stream_id: req.stream_id,
+                    res: Err(reason),
+                };
+                return self
+                    .send_to_client(ServerClientMsg::TransferResumed(response))
+                    .await;
+            }
+        };
+
+        let response = ResumeResponse {
+            stream_id: req.stream_id,
+            res: Ok(point),
+        };
+        self.send_to_client(ServerClientMsg::TransferResumed(response))
+            .await?;
+
+        self.start_download(req.stream_id, file, blob, point);
+        Ok(())
+    }
+
+    /// `chunk` carries what every chunk of the download shares and where it starts,
+    /// an empty file still gets its last chunk
+    async fn send_stored_file(
+        mut blob: fs::File,
+        size: u64,
+        chunk: Chunk,
+        chunk_size: usize,
+        tx: mpsc::Sender<Bytes>,
+    ) -> Result<()> {
+        let mut buffer = vec![0u8; chunk_size];
+        let mut sent = chunk.offset;
+        let mut seq = chunk.seq;
+        loop {
+            let n = blob.read(&mut buffer).await?;
+            let last = n == 0 || sent + n as u64 >= size;
+            let msg = ServerClientMsg::FileChunk(Chunk {
+                data: buffer[..n].to_vec(),
+                seq,
+                offset: sent,
+                last,
+                ..chunk.clone()
+            });
+            tx.send(Bytes::from(bincode::serialize(&msg)?)).await?;
+            if last {
+                return Ok(());
+            }
+            sent += n as u64;
+            seq += 1;
+        }
+    }
+
+    /// the declared type has to be the one of the file name, so a denied file can not be
+    /// passed off as an allowed type
+    fn check_file_type(&self, data: &FileMetadata) -> Option<String> {
+        let mime = FileTypePolicy::mime_of(&data.filename);
+        if data.mime != mime {
+            return Some(format!("File type {} does not match its name", data.mime));
+        }
+        if !self.file_policy.permits(&mime) {
+            return Some(format!("Files of type {mime} are not allowed"));
+        }
+        None
+    }
+
+    async fn refuse_msg(
+        &mut self,
+        to: Channel,
+        msg: RefusedMsg,
+        reason: String,
+    ) -> Result<(), WsDataParsingError> {
+        warn!(
+            "User {} sent a message to {:?} that was refused: {}",
+            self.username, to, reason
+        );
+
+        let refusal = MsgRefusal { to, msg, reason };
+
+        self.send_to_client(ServerClientMsg::MsgRefused(refusal))
+            .await
+    }
+
+    async fn handle_text_msg(&mut self, mut text_msg: TextMsg) -> Result<(), WsDataParsingError> {
+        let local_id = text_msg.id;
+        let target = text_msg.to.clone();
+        if !self.can_send_to(&target) {
+            return self.refuse_unstamped(local_id, target).await;
+        }
+
+        let stamp = Self::stamp();
+        text_msg.id = stamp.id;
+        text_msg.sent_at = stamp.sent_at;
+        text_msg.edited = false;
+        text_msg.reactions = vec![];
+        text_msg.thread_len = 0;
+        text_msg.from = self.get_user();
+
+        if text_msg.reply_to.is_some() || text_msg.thread.is_some() {
+            text_msg = match self.resolve_reply(text_msg).await {
+                Ok(msg) => msg,
+                Err(reason) => return self.ack_msg(local_id, target, Err(reason)).await,
+            };
+        }
+
+        let stored = ChannelMsg::TextMsg(text_msg.clone());
+        let msg = ServerClientMsg::Text(text_msg);
+        self.relay_msg(local_id, stamp, target, stored, msg).await
+    }
+
+    /// fills in the quote and checks that the replied to message and thread belong to
+    /// the same conversation, so membership is the one of the parent
+    async fn resolve_reply(&self, msg: TextMsg) -> Result<TextMsg, String> {
+        let (tx_ack, rx_ack) = oneshot::channel::<Result<TextMsg, String>>();
+
+        let transit = ReplyTransit {
+            tx: tx_ack,
+            user: self.get_user(),
+            msg,
+        };
+
+        let server_err = String::from("Internal server error, reply failed");
+
+        let msg = ClientPersistenceMsg::ResolveReply(transit);
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+            return Err(server_err);
+        };
+
+        match rx_ack.await {
+            Ok(res) => res,
+            Err(err) => {
+                error!(
+                    "reply tx dropped before returning answer, err: {}, {}",
+                    err,
+                    Bt::new()
+                );
+                Err(server_err)
+            }
+        }
+    }
+
+    async fn handle_ascii_msg(&mut self, mut img: ImgRender) -> Result<(), WsDataParsingError> {
+        let local_id = img.id;
+        let target = img.to.clone();
+        if !self.can_send_to(&target) {
+            return self.refuse_unstamped(local_id, target).await;
+        }
+
+        if img
+            .image
+            .as_ref()
+            .is_some_and(|p| p.len() > MAX_IMAGE_BYTES)
+        {
+            img.image = None;
+        }
+
+        let stamp = Self::stamp();
+        img.id = stamp.id;
+        img.sent_at = stamp.sent_at;
+        img.from = self.get_user();
+        img.reactions = vec![];
+
+        let stored = ChannelMsg::Img(img.clone());
+        let msg = ServerClientMsg::ASCII(img);
+        self.relay_msg(local_id, stamp, target, stored, msg).await
+    }
+
+    /// room messages are acked before the broadcast echoes them, so the author already knows
+    /// the id. direct messages are acked after the attempt, offline recipients get them queued
+    async fn relay_msg(
+        &mut self,
+        local_id: Uuid,
+        mut stamp: MsgStamp,
+        target: Channel,
+        stored: ChannelMsg,
+        msg: ServerClientMsg,
+    ) -> Result<(), WsDataParsingError> {
+        match target {
+            Channel::Room(_) => {
+                self.persist_message(&stamp, target.clone(), stored).await;
+                self.ack_msg(local_id, target.clone(), Ok(stamp)).await?;
+                self.send_data_to_channel(msg, target).await
+            }
+            Channel::User(target_id) => {
+                let serialized =
+                    bincode::serialize(&msg).map_err(|err| BincodeErr(err, Bt::new()))?;
+                stamp.pending = !self.send_direct(target_id, Bytes::from(serialized)).await;
+                self.persist_message(&stamp, target.clone(), stored).await;
+                self.ack_msg(local_id, target, Ok(stamp)).await
+            }
+        }
+    }
+
+    /// v7 ids grow with time, so they break ties between messages sent in the same millisecond
+    fn stamp() -> MsgStamp {
+        MsgStamp {
+            id: Uuid::now_v7(),
+            sent_at: DateTime::now().timestamp_millis(),
+            pending: false,
+        }
+    }
+
+    async fn ack_msg(
+        &mut self,
+        local_id: Uuid,
+        to: Channel,
+        res: AckRes,
+    ) -> Result<(), WsDataParsingError> {
+        let ack = MsgAck { local_id, to, res };
+        self.send_to_client(ServerClientMsg::Ack(ack)).await
+    }
+
+    async fn refuse_unstamped(
+        &mut self,
+        local_id: Uuid,
+        to: Channel,
+    ) -> Result<(), WsDataParsingError> {
+        warn!(
+            "User {} sent a message to {:?} without being a member, msg refused",
+            self.username, to
+        );
+
+        let reason = String::from("You are not a member of this room");
+        self.ack_msg(local_id, to, Err(reason)).await
+    }
+
+    async fn persist_message(&self, stamp: &MsgStamp, to: Channel, msg: ChannelMsg) {
+        let transit = StoreMessageTransit {
+            id: stamp.id,
+            sent_at: stamp.sent_at,
+            pending: stamp.pending,
+            from: User {
+                username: self.username.clone(),
+                id: self.id,
+            },
+            to,
+            msg,
+        };
+
+        let msg = ClientPersistenceMsg::StoreMessage(transit);
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+        };
+    }
+
+    async fn handle_fetch_history(
+        &mut self,
+        req: HistoryRequest,
+    ) -> Result<(), WsDataParsingError> {
+        let (tx_ack, rx_ack) = oneshot::channel::<HistoryPage>();
+
+        let transit = HistoryTransit {
+            tx: tx_ack,
+            user: User {
+                username: self.username.clone(),
+                id: self.id,
+            },
+            channel: req.channel.clone(),
+            before: req.before,
+            limit: req.limit,
+        };
+
+        let empty_page = HistoryPage {
+            channel: req.channel,
+            messages: vec![],
+            next_before: None,
+        };
+
+        if let Err(err) = self
+            .tx_client_persistence
+            .send(ClientPersistenceMsg::GetHistory(transit))
+            .await
+        {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+            self.send_to_client(ServerClientMsg::History(empty_page))
+                .await?;
+            return Ok(());
+        };
+
+        let page = match rx_ack.await {
+            Ok(page) => page,
+            Err(err) => {
+                error!(
+                    "history tx dropped before returning answer, err: {}, {}",
+                    err,
+                    Bt::new()
+                );
+                empty_page
+            }
+        };
+
+        self.send_to_client(ServerClientMsg::History(page)).await?;
+        Ok(())
+    }
+
+    async fn handle_fetch_thread(&mut self, id: Uuid) -> Result<(), WsDataParsingError> {
+        let (tx_ack, rx_ack) = oneshot::channel::<ThreadRes>();
+
+        let transit = ThreadTransit {
+            tx: tx_ack,
+            user: self.get_user(),
+            id,
+        };
+
+        let server_err_res = Err(String::from("Internal server error, loading thread failed"));
+
+        let msg = ClientPersistenceMsg::GetThread(transit);
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+            self.send_to_client(ServerClientMsg::Thread(server_err_res))
+                .await?;
+            return Ok(());
+        };
+
+        let res = match rx_ack.await {
+            Ok(res) => res,
+            Err(err) => {
+                error!(
+                    "thread tx dropped before returning answer, err: {}, {}",
+                    err,
+                    Bt::new()
+                );
+                server_err_res
+            }
+        };
+
+        self.send_to_client(ServerClientMsg::Thread(res)).await?;
+        Ok(())
+    }
+
+    /// going invisible counts as the last time the user was seen
+    async fn handle_set_presence(&mut self, update: PresenceUpdate) {
+        if update.status == PresenceStatus::Offline {
+            return;
+        }
+
+        let was_invisible = self.presence.status == PresenceStatus::Invisible;
+        if update.status == PresenceStatus::Invisible && !was_invisible {
+            self.presence.last_seen = Some(DateTime::now().timestamp_millis());
+        }
+        self.presence.status = update.status;
+        self.presence.text = update.text.chars().take(MAX_STATUS_TEXT_CHARS).collect();
+
+        let msg = ClientPersistenceMsg::SetPresence(self.presence.clone());
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+        };
+        self.announce_presence().await;
+    }
+
+    async fn announce_presence(&mut self) {
+        let presence = Presence {
+            user: self.get_user(),
+            status: self.presence.status,
+            text: self.presence.text.clone(),
+            last_seen: self.presence.last_seen,
+        };
+        let msg = ClientManagerMsg::PresenceChanged(presence);
+        if let Err(err) = self.client_manager_channel.tx.send(msg).await {
+            error!("rx_client_manager dropped, error: {}, {}", err, Bt::new())
+        };
+    }
+
+    /// relayed as it is, only rooms the user is in and at most once per `TYPING_MIN_INTERVAL_MS`
+    async fn handle_typing(&mut self, channel: Channel) -> Result<(), WsDataParsingError> {
+        if let Channel::Room(room_id) = channel
+            && !self.room_channels.contains_key(&room_id)
+        {
+            return Ok(());
+        }
+        if let Channel::User(target_id) = channel
+            && target_id == self.id
+        {
+            return Ok(());
+        }
+
+        let min_interval = Duration::from_millis(TYPING_MIN_INTERVAL_MS);
+        if let Some((last_channel, at)) = &self.last_typing
+            && *last_channel == channel
+            && at.elapsed() < min_interval
+        {
+            return Ok(());
+        }
+        self.last_typing = Some((channel.clone(), Instant::now()));
+
+        let notice = TypingNotice {
+            from: self.get_user(),
+            to: channel.clone(),
+        };
+        self.send_data_to_channel(ServerClientMsg::Typing(notice), channel)
+            .await
+    }
+
+    /// the other side of a direct conversation is told how far it was read
+    async fn handle_mark_read(&mut self, marker: ReadMarker) -> Result<(), WsDataParsingError> {
+        let (tx_ack, rx_ack) = oneshot::channel::<bool>();
+
+        let transit = ReadMarkerTransit {
+            tx: tx_ack,
+            user: self.get_user(),
+            marker: marker.clone(),
+        };
+
+        let msg = ClientPersistenceMsg::MarkRead(transit);
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+            return Ok(());
+        };
+
+        let moved = match rx_ack.await {
+            Ok(moved) => moved,
+            Err(err) => {
+                error!(
+                    "read marker tx dropped before returning answer, err: {}, {}",
+                    err,
+                    Bt::new()
+                );
+                false
+            }
+        };
+
+        if let (true, Channel::User(other_id)) = (moved, marker.channel) {
+            let receipt = ReadReceipt {
+                by: self.get_user(),
+                id: marker.id,
+            };
+            self.send_data_to_channel(ServerClientMsg::Seen(receipt), Channel::User(other_id))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_leave_room(&mut self, room_id: Uuid) -> Result<(), WsDataParsingError> {
+        let (tx_ack, rx_ack) = oneshot::channel::<RoomRemovalRes>();
+
+        let transit = LeaveRoomTransit {
+            tx: tx_ack,
+            user: self.get_user(),
+            room_id,
+        };
+
+        let server_err_res = Err(String::from("Internal server error, leaving room failed"));
+        let server_err_msg = ServerClientMsg::LeaveRoomResponse(server_err_res);
+
+        let msg = ClientPersistenceMsg::UserLeftRoom(transit);
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+            self.send_to_client(server_err_msg).await?;
+            return Ok(());
+        };
+
+        let res = match rx_ack.await {
+            Ok(res) => res,
+            Err(err) => {
+                error!(
+                    "leave room tx dropped before returning answer, err: {}, {}",
+                    err,
+                    Bt::new()
+                );
+                self.send_to_client(server_err_msg).await?;
+                return Ok(());
+            }
+        };
+
+        if res.is_ok() {
+            let notification = LeaveRoomNotification {
+                user: self.get_user(),
+                room_id,
+            };
+            let msg = ServerClientMsg::UserLeftRoom(notification);
+            self.send_data_to_channel(msg, Channel::Room(room_id))
+                .await?;
+            self.drop_room_comm(room_id);
+        }
+
+        self.send_to_client(ServerClientMsg::LeaveRoomResponse(res))
+            .await?;
+        Ok(())
+    }
+
+    async fn handle_delete_room(&mut self, room_id: Uuid) -> Result<(), WsDataParsingError> {
+        let (tx_ack, rx_ack) = oneshot::channel();
+
+        let transit = DeleteRoomTransit {
+            tx: tx_ack,
+            user: self.get_user(),
+            room_id,
+        };
+
+        let server_err_res = Err(String::from("Internal server error, deleting room failed"));
+        let server_err_msg = ServerClientMsg::DeleteRoomResponse(server_err_res);
+
+        let msg = ClientPersistenceMsg::DeleteRoom(transit);
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+            self.send_to_client(server_err_msg).await?;
+            return Ok(());
+        };
+
+        let user_ids = match rx_ack.await {
+            Ok(Ok(user_ids)) => user_ids,
+            Ok(Err(reason)) => {
+                let msg = ServerClientMsg::DeleteRoomResponse(Err(reason));
+                self.send_to_client(msg).await?;
+                return Ok(());
+            }
+            Err(err) => {
+                error!(
+                    "delete room tx dropped before returning answer, err: {}, {}",
+                    err,
+                    Bt::new()
+                );
+                self.send_to_client(server_err_msg).await?;
+                return Ok(());
+            }
+        };
+
+        // members, this client included, tear down their room channel once notified
+        let transit = RoomDeletedTransit { room_id, user_ids };
+        let msg = ClientManagerMsg::RoomDeleted(transit);
+        if let Err(err) = self.client_manager_channel.tx.send(msg).await {
+            error!("rx_client_manager dropped, error: {}, {}", err, Bt::new())
+        };
+
+        self.send_to_client(ServerClientMsg::DeleteRoomResponse(Ok(room_id)))
+            .await?;
+        Ok(())
+    }
+
+    async fn handle_set_room_password(
+        &mut self,
+        room_id: Uuid,
+        room_pwd: Option<String>,
+    ) -> Result<(), WsDataParsingError> {
+        let (tx_ack, rx_ack) = oneshot::channel::<RoomPasswordRes>();
+
+        let transit = RoomPasswordTransit {
+            tx: tx_ack,
+            user: self.get_user(),
+            room_id,
+            room_pwd,
+        };
+
+        let server_err_res = Err(String::from(
+            "Internal server error, changing room password failed",
+        ));
+        let server_err_msg = ServerClientMsg::SetRoomPasswordResponse(server_err_res);
+
+        let msg = ClientPersistenceMsg::SetRoomPassword(transit);
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+            self.send_to_client(server_err_msg).await?;
+            return Ok(());
+        };
+
+        let res = match rx_ack.await {
+            Ok(res) => res,
+            Err(err) => {
+                error!(
+                    "room password tx dropped before returning answer, err: {}, {}",
+                    err,
+                    Bt::new()
+                );
+                self.send_to_client(server_err_msg).await?;
+                return Ok(());
+            }
+        };
+
+        self.send_to_client(ServerClientMsg::SetRoomPasswordResponse(res))
+            .await?;
+        Ok(())
+    }
+
+    async fn handle_change_message(
+        &mut self,
+        id: Uuid,
+        change: MsgChange,
+    ) -> Result<(), WsDataParsingError> {
+        let (tx_ack, rx_ack) = oneshot::channel::<Result<MsgChanged, String>>();
+
+        let transit = MsgChangeTransit {
+            tx: tx_ack,
+            user: self.get_user(),
+            id,
+            change,
+        };
+
+        let server_err_res = Err(String::from(
+            "Internal server error, changing message failed",
+        ));
+        let server_err_msg = ServerClientMsg::MsgUpdateResponse(server_err_res);
+
+        let msg = ClientPersistenceMsg::ChangeMessage(transit);
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+            self.send_to_client(server_err_msg).await?;
+            return Ok(());
+        };
+
+        let changed = match rx_ack.await {
+            Ok(Ok(changed)) => changed,
+            Ok(Err(reason)) => {
+                self.send_to_client(ServerClientMsg::MsgUpdateResponse(Err(reason)))
+                    .await?;
+                return Ok(());
+            }
+            Err(err) => {
+                error!(
+                    "message change tx dropped before returning answer, err: {}, {}",
+                    err,
+                    Bt::new()
+                );
+                self.send_to_client(server_err_msg).await?;
+                return Ok(());
+            }
+        };
+
+        let target = match &changed {
+            MsgChanged::Edited(text) => self.conversation_of(&text.from, &text.to),
+            MsgChanged::Deleted(deletion) => self.conversation_of(&deletion.from, &deletion.to),
+            MsgChanged::Reactions(reactions) => {
+                self.conversation_of(&reactions.from, &reactions.to)
+            }
+        };
+
+        let to_msg = |changed: MsgChanged| match changed {
+            MsgChanged::Edited(text) => ServerClientMsg::MessageEdited(text),
+            MsgChanged::Deleted(deletion) => ServerClientMsg::MessageDeleted(deletion),
+            MsgChanged::Reactions(reactions) => ServerClientMsg::ReactionsChanged(reactions),
+        };
+
+        if let Channel::User(_) = target {
+            self.send_to_client(to_msg(changed.clone())).await?;
+        }
+        self.send_data_to_channel(to_msg(changed), target).await?;
+
+        self.send_to_client(ServerClientMsg::MsgUpdateResponse(Ok(id)))
+            .await?;
+        Ok(())
+    }
+
+    /// where this client sends to reach everyone in the conversation of a message,
+    /// direct messages go to the other user
+    fn conversation_of(&self, from: &User, to: &Channel) -> Channel {
+        match to {
+            Channel::Room(id) => Channel::Room(*id),
+            Channel::User(to_id) if from.id == self.id => Channel::User(*to_id),
+            Channel::User(_) => Channel::User(from.id),
+        }
+    }
+
+    async fn handle_moderation(
+        &mut self,
+        target: RoomMember,
+        action: ModerationAction,
+    ) -> Result<(), WsDataParsingError> {
+        let (tx_ack, rx_ack) = oneshot::channel::<ModerationPersistenceRes>();
+
+        let transit = ModerationTransit {
+            tx: tx_ack,
+            user: self.get_user(),
+            target,
+            action,
+        };
+
+        let server_err_res = Err(String::from("Internal server error, moderation failed"));
+        let server_err_msg = ServerClientMsg::ModerationResponse(server_err_res);
+
+        let msg = ClientPersistenceMsg::ModerateRoom(transit);
+        if let Err(err) = self.tx_client_persistence.send(msg).await {
+            error!("Persistence task not running {}, {}", err, Bt::new());
+            self.send_to_client(server_err_msg).await?;
+            return Ok(());
+        };
+
+        let res = match rx_ack.await {
+            Ok(res) => res,
+            Err(err) => {
+                error!(
+                    "moderation tx dropped before returning answer, err: {}, {}",
+                    err,
+                    Bt::new()
+                );
+                self.send_to_client(server_err_msg).await?;
+                return Ok(());
+            }
+        };
+
+        let res = match res {
+            Ok((notice, user_ids)) => {
+                let msg = ServerClientMsg::RoomModerated(notice.clone());
+                self.send_data_to_channel(msg, Channel::Room(notice.room_id))
+                    .await?;
+
+                // the target still got the notice above, now it stops receiving the room
+                if let ModerationAction::Kick | ModerationAction::Ban = notice.action {
+                    let transit = MemberRemovedTransit {
+                        notice: notice.clone(),
+                        user_ids,
+                    };
+                    let msg = ClientManagerMsg::UserRemovedFromRoom(transit);
+                    if let Err(err) = self.client_manager_channel.tx.send(msg).await {
+                        error!("rx_client_manager dropped, error: {}, {}", err, Bt::new())
+                    };
+                }
+                Ok(notice)
+            }
+            Err(err) => Err(err),
+        };
+
+        self.send_to_client(ServerClientMsg::ModerationResponse(res))
+            .await?;
+        Ok(())
+    }
+
+    fn drop_room_comm(&mut self, room_id: Uuid) {
+        self.room_channels.remove(&room_id);
+        self.client_comm_cleanup_channel
+            .tx
+            .send(Some(Channel::Room(room_id)))
+            .ok();
+    }
+
+    async fn handle_manager_msg(&mut self, result: Option<ManagerClientMsg>) {
+        if let Some(msg) = result {
+            match msg {
+                ManagerClientMsg::EstablishDirectComm(c) => {
+                    self.direct_channels
+                        .insert(c.payload.from, c.payload.tx_client_client);
+                    let tx_client_client = self.spawn_direct_communication_task(c.payload.from);
+
+                    if let Err(err) = c.ack.send(tx_client_client) {
+                        error!(
+                            "oneshot rx not dropped during establishing direct communication. transit data: {:?}, {}",
+                            err,
+                            Bt::new()
+                        );
+                    };
+                }
+
+                ManagerClientMsg::RoomDeleted(room_id) => {
+                    self.drop_room_comm(room_id);
+                    if let Err(err) = self
+                        .send_to_client(ServerClientMsg::RoomDeleted(room_id))
+                        .await
+                    {
+                        warn!("room deleted notification not sent, {err}");
+                    };
+                }
+
+                ManagerClientMsg::RemovedFromRoom(notice) => {
+                    self.drop_room_comm(notice.room_id);
+                    if let Err(err) = self
+                        .send_to_client(ServerClientMsg::RoomModerated(notice))
+                        .await
+                    {
+                        warn!("room removal notification not sent, {err}");
+                    };
+                }
+
+                ManagerClientMsg::MemberRemoved(notice) => self.drop_removed_member(notice).await,
+
+                ManagerClientMsg::Replaced => {
+                    let msg = ServerClientMsg::Auth(Err(String::from(
+                        "Logged in from another connection",
+                    )));
+                    if let Err(err) = self.send_to_client(msg).await {
+                        debug!("replaced notification not sent, {err}");
+                    };
+                    if let Err(err) = self.close_channel.tx.send(ClientTaskResult::Replaced).await {
+                        error!("rx close_channel dropped: {}, {}", err, Bt::new())
+                    }
+                }
+
+                ManagerClientMsg::GetRoomTransmitter(t) => {
+                    let room_transmitter = match self.room_channels.get(&t.room_id) {
+                        None => {
+                            debug!(
+                                "romm transmitter not found after fetching for another user! {}",
+                                Bt::new()
+                            );
+                            return;
+                        }
+                        Some(tx) => tx,
+                    };
+                    t.tx_ack.send(room_transmitter.clone()).ok();
+                }
+
+                ManagerClientMsg::PresenceChanged(presence) => {
+                    let msg = ServerClientMsg::PresenceChanged(presence);
+                    if let Err(err) = self.send_to_client(msg).await {
+                        warn!("presence change not sent, {err}");
+                    };
+                }
+
+                ManagerClientMsg::TransferControl(control) => {
+                    self.handle_receiver_control(control).await
+                }
+
+                ManagerClientMsg::StreamProgress(progress) => {
+                    self.handle_receiver_progress(progress).await
+                }
+
+                ManagerClientMsg::StreamFailed(failure) => {
+                    let msg = ServerClientMsg::StreamFailed(failure);
+                    if let Err(err) = self.send_to_client(msg).await {
+                        warn!("stream failure not sent, {err}");
+                    };
+                }
+            };
+        }
+    }
+
+    async fn send_data_to_channel(
+        &mut self,
+        msg: ServerClientMsg,
+        target: Channel,
+    ) -> Result<(), WsDataParsingError> {
+        let serialized = bincode::serialize(&msg).map_err(|err| BincodeErr(err, Bt::new()))?;
+        let data = Bytes::from(serialized);
+
+        match target {
+            Channel::User(target_id) => {
+                if !self.send_direct(target_id, data).await {
+                    debug!("user {target_id} is offline, msg not sent");
+                }
+            }
+            Channel::Room(room_id) => {
+                let tx = self.room_channels.get(&room_id);
+
+                let tx = match tx {
+                    Some(tx) => tx,
+                    None => {
+                        warn!("Room transmiter not found {}", Bt::new());
+                        return Ok(());
+                    }
+                };
+
+                if let Err(err) = tx.send(data) {
+                    let tx = self.spawn_room_communication_task(tx.clone(), room_id);
+                    if tx.send(err.0).is_err() {
+                        error!("unable to establish room communication!!{}", Bt::new());
+                    };
+                };
+            }
+        };
+        Ok(())
+    }
+
+    /// false when the recipient is offline
+    async fn send_direct(&mut self, target_id: Uuid, data: Bytes) -> bool {
+        if let Some(tx) = self.direct_channels.get(&target_id) {
+            if tx.send(data.clone()).await.is_ok() {
+                return true;
+            }
+            // the recipient disconnected or reconnected since, the channel is stale
+            self.direct_channels.remove(&target_id);
+        }
+
+        self.establish_direct_comm(target_id).await;
+        match self.direct_channels.get(&target_id) {
+            Some(tx) => tx.send(data).await.is_ok(),
+            None => false,
+        }
+    }
+
+    /// like `send_direct` without waiting for room in the channel of the recipient,
+    /// a full channel means it does not keep up
+    async fn try_send_direct(
+        &mut self,
+        target_id: Uuid,
+        data: Bytes,
+    ) -> Result<(), TrySendError<Bytes>> {
+        let data = match self.direct_channels.get(&target_id) {
+            Some(tx) => match tx.try_send(data) {
+                Err(TrySendError::Closed(data)) => {
+                    self.direct_channels.remove(&target_id);
+                    data
+                }
+                res => return res,
+            },
+            None => data,
+        };
+
+        self.establish_direct_comm(target_id).await;
+        match self.direct_channels.get(&target_id) {
+            Some(tx) => tx.try_send(data),
+            None => Err(TrySendError::Closed(data)),
+        }
+    }
+
+    async fn establish_direct_comm(&mut self, target_id: Uuid) {
+        let tx_client_client = self.spawn_direct_communication_task(target_id);
+        let (tx_ack, rx_ack) = oneshot::channel::<mpsc::Sender<Bytes>>();
+
+        let channel_transit = DirectChannelTxTransit {
+            payload: DirectChannelTransitPayload {
+                tx_client_client,
+                from: self.id,
+                to: target_id,
+            },
+            ack: tx_ack,
+        };
+
+        if let Err(err) = self
+            .client_manager_channel
+            .tx
+            .send(ClientManagerMsg::GetDirectChannelTx(channel_transit))
+            .await
+        {
+            warn!("tx_client_manager dropped, {},{}", err, Bt::new());
+            return;
+        };
+
+        // the manager drops the transmitter when the target is offline
+        let new_direct = match rx_ack.await {
+            Err(_) => return,
+            Ok(tx) => tx,
+        };
+
+        let direct_channels = &mut self.direct_channels;
+        direct_channels.insert(target_id, new_direct);
+    }
+
+    async fn establish_room_comm(&mut self, room_id: Uuid, room_users: Vec<User>) {
+        let (tx_ack, rx_ack) = oneshot::channel::<broadcast::Sender<Bytes>>();
+
+        let transit = RoomChannelTxTransit {
+            room_id,
+            room_users,
+            ack: tx_ack,
+        };
+
+        if let Err(err) = self
+            .client_manager_channel
+            .tx
+            .send(ClientManagerMsg::GetRoomChannelTx(transit))
+            .await
+        {
+            warn!("tx_client_manager dropped, {},{}", err, Bt::new());
+            return;
+        };
+
+        let tx = match rx_ack.await {
+            Err(err) => {
+                debug!(
+                    "oneshot transmitter for establishing room communication dropped, {}, {}",
+                    err,
+                    Bt::new()
+                );
                 return;
             }
             Ok(tx) => tx,
@@ -731,8 +2604,9 @@ impl<'a> ClientTask<'a> {
                              break;
                             }
                         },
-                        _ = rx_cleanup.recv() => {
-                            break;
+                        result = rx_cleanup.recv() => match result {
+                            Ok(Some(_)) | Err(RecvError::Lagged(_)) => {},
+                            _ => break,
                         },
                     };
             }
@@ -768,14 +2642,19 @@ impl<'a> ClientTask<'a> {
                                 },
                                 RecvError::Lagged(n) =>{
                                     warn!("room receiver not handling received messages, missed: {}, {}", n, Bt::new());
-
+                                    // the client reloads the room instead of showing it with gaps
+                                    if let Ok(resync) = bincode::serialize(&ServerClientMsg::Resync(Channel::Room(room_id))) {
+                                        tx_comm_client_data.send(Bytes::from(resync)).await.ok();
+                                    }
                                 }
                             };
                         }
                     },
 
-                    _ = rx_cleanup.recv() => {
-                        break;
+                    result = rx_cleanup.recv() => match result {
+                        Ok(Some(channel)) if channel != Channel::Room(room_id) => {},
+                        Err(RecvError::Lagged(_)) => {},
+                        _ => break,
                     }
                 };
             }
@@ -1,11 +1,13 @@
 use super::util::{
-    server_functions::{authenticate, handle_register, read_client_data, send_server_msg},
+    server_functions::{
+        authenticate, handle_register, read_client_data, resume_session, send_server_msg,
+    },
     types::server_data_types::{ClientManagerMsg, ClientPersistenceMsg, ClientTaskResult},
 };
 use crate::{client_task::ClientTask, util::types::server_error_wrapper_types::WsDataParsingError};
 use anyhow::{Result, anyhow};
 use futures::StreamExt;
-use shared::types::{AuthResponse, ClientServerAuthMsg, ServerClientMsg};
+use shared::types::{ClientServerAuthMsg, ServerClientMsg};
 use tokio::sync::mpsc;
 
 pub async fn handle_connection<'a>(
@@ -24,7 +26,7 @@ pub async fn handle_connection<'a>(
             },
         };
 
-        let user = match client_msg {
+        let res = match client_msg {
             ClientServerAuthMsg::Register(register_data) => {
                 let res =
                     handle_register(register_data, &tx_client_persistence, &tx_client_manager)
@@ -35,25 +37,24 @@ pub async fn handle_connection<'a>(
                 continue;
             }
             ClientServerAuthMsg::Login(auth_data) => {
-                let res =
-                    authenticate(auth_data, &tx_client_persistence, &tx_client_manager).await?;
-
-                let user = match &res {
-                    AuthResponse::Err(_) => {
-                        let msg = ServerClientMsg::Auth(res);
-                        send_server_msg(&msg, &mut ws_write).await?;
-                        continue;
-                    }
-                    AuthResponse::Ok(user) => user.clone(),
-                };
+                authenticate(auth_data, &tx_client_persistence, &tx_client_manager).await?
+            }
+            ClientServerAuthMsg::Resume(token) => resume_session(token, &tx_client_manager).await?,
+        };
 
+        let session = match &res {
+            Ok(session) => session.clone(),
+            Err(_) => {
                 let msg = ServerClientMsg::Auth(res);
                 send_server_msg(&msg, &mut ws_write).await?;
-
-                user
+                continue;
             }
         };
 
+        let msg = ServerClientMsg::Auth(res);
+        send_server_msg(&msg, &mut ws_write).await?;
+        let user = session.user;
+
         let client: ClientTask = ClientTask::new(
             user,
             &mut ws_read,
@@ -66,8 +67,13 @@ pub async fn handle_connection<'a>(
         let res = client.run().await;
 
         match res {
-            ClientTaskResult::Close => return Ok(()),
-            ClientTaskResult::Logout => continue,
+            // the session is kept so that the client can resume it
+            ClientTaskResult::Close | ClientTaskResult::Replaced => return Ok(()),
+            ClientTaskResult::Logout => {
+                let msg = ClientManagerMsg::EndSession(session.token);
+                tx_client_manager.send(msg).await?;
+                continue;
+            }
         }
     }
 }
@@ -3,4 +3,5 @@ pub mod client_task;
 pub mod handle_connection;
 pub mod manager_task;
 pub mod persistence_task;
+pub mod storage;
 pub mod util;
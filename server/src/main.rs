@@ -32,7 +32,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         mpsc::channel::<ClientPersistenceMsg>(CLIENT_PERSISTENCE_CAPACITY);
 
     spawn_manager_task(rx_client_manager);
-    spawn_persistence_task(rx_client_persistence);
+    spawn_persistence_task(rx_client_persistence).await?;
 
     let tx_cm_filter = warp::any().map(move || tx_client_manager.clone());
     let tx_cp_filter = warp::any().map(move || tx_client_persistence.clone());
@@ -1,23 +1,47 @@
 use crate::util::types::server_error_types::Bt;
 
-use super::util::config::ROOM_CAPACITY;
+use super::util::config::{
+    ROOM_CAPACITY, SESSION_TTL_SECS, STREAM_RESUME_SECS, STREAM_STALL_CHECK_SECS,
+};
 use super::util::types::server_data_types::{
-    Client, ClientManagerMsg, DirectChannelTxTransit, GetRoomTxTransit, IsOnlineTransit,
-    ManagerClientMsg, MultipleRoomsUpdateTransit, RoomChannelTxTransit, RoomUpdateTransit,
+    Client, ClientDroppedTransit, ClientManagerMsg, CreateSessionTransit, DirectChannelTxTransit,
+    GetRoomTxTransit, ManagerClientMsg, MemberRemovedTransit, MultipleRoomsUpdateTransit,
+    OutgoingStream, ParkedStreams, PresenceListTransit, ResumeSessionTransit, ResumeStreamTransit,
+    RoomChannelTxTransit, RoomDeletedTransit, RoomUpdateTransit,
 };
 use bytes::Bytes;
 use log::{debug, error, info, warn};
 use shared::config::PUBLIC_ROOM_ID;
-use shared::types::{JoinRoomNotification, RoomData, ServerClientMsg, User};
+use shared::types::{
+    Channel, JoinRoomNotification, Presence, PresenceStatus, PresenceUpdate, RoomData,
+    ServerClientMsg, StreamFailure, StreamProgress, TransferControl, User,
+};
 use std::collections::HashMap;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::select;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio::task;
 use uuid::Uuid;
 
+struct Session {
+    user: User,
+    expires_at: Instant,
+}
+
+/// stream of a sender that lost its connection
+struct ParkedStream {
+    user_id: Uuid,
+    stream: OutgoingStream,
+    expires_at: Instant,
+}
+
 struct ManagerTask {
     rx_client_manager: mpsc::Receiver<ClientManagerMsg>,
     connected_users: HashMap<Uuid, Client>,
+    sessions: HashMap<Uuid, Session>,
+    parked_streams: HashMap<Uuid, ParkedStream>,
 }
 
 pub fn spawn_manager_task(rx_client_persistence: mpsc::Receiver<ClientManagerMsg>) {
@@ -32,29 +56,60 @@ impl ManagerTask {
         Self {
             rx_client_manager,
             connected_users: HashMap::new(),
+            sessions: HashMap::new(),
+            parked_streams: HashMap::new(),
         }
     }
 
     async fn run(&mut self) {
         info!("Manager task running");
+        let mut expiry = tokio::time::interval(Duration::from_secs(STREAM_STALL_CHECK_SECS));
         loop {
-            let msg = self.rx_client_manager.recv().await.expect(
-                "all tx_client_manager transmitters got dropped, one needs to live in server.rs to clone for new connections!!",
-            );
+            let msg = select! {
+                msg = self.rx_client_manager.recv() => msg.expect(
+                    "all tx_client_manager transmitters got dropped, one needs to live in server.rs to clone for new connections!!",
+                ),
+                _ = expiry.tick(), if !self.parked_streams.is_empty() => {
+                    self.expire_parked_streams().await;
+                    continue;
+                }
+            };
 
             // debug!("msg");
 
             match msg {
-                ClientManagerMsg::ClientConnected(client) => self.handle_client_connected(client),
-                ClientManagerMsg::ClientDropped(id) => self.handle_client_dropped(id),
+                ClientManagerMsg::ClientConnected(client) => {
+                    self.handle_client_connected(client).await
+                }
+                ClientManagerMsg::ClientDropped(t) => self.handle_client_dropped(t),
                 ClientManagerMsg::GetDirectChannelTx(t) => {
                     self.handle_establish_direct_comm(t).await
                 }
                 ClientManagerMsg::GetRoomChannelTx(t) => self.handle_get_room_channel_tx(t).await,
                 ClientManagerMsg::UserRegistered(user) => self.handle_user_registered(user).await,
-                ClientManagerMsg::IsOnline(t) => self.handle_is_online(t),
+                ClientManagerMsg::CreateSession(t) => self.handle_create_session(t),
+                ClientManagerMsg::ResumeSession(t) => self.handle_resume_session(t),
+                ClientManagerMsg::EndSession(token) => {
+                    self.sessions.remove(&token);
+                }
                 ClientManagerMsg::UpdateRoom(t) => self.handle_update_room(t),
                 ClientManagerMsg::UpdateMultipleRooms(t) => self.handle_update_multiple_rooms(t),
+                ClientManagerMsg::RoomDeleted(t) => self.handle_room_deleted(t).await,
+                ClientManagerMsg::UserRemovedFromRoom(notice) => {
+                    self.handle_user_removed_from_room(notice).await
+                }
+                ClientManagerMsg::PresenceChanged(presence) => {
+                    self.handle_presence_changed(presence)
+                }
+                ClientManagerMsg::ResolvePresence(t) => self.handle_resolve_presence(t),
+                ClientManagerMsg::TransferControl(control) => {
+                    self.handle_transfer_control(control).await
+                }
+                ClientManagerMsg::StreamProgress(progress) => {
+                    self.handle_stream_progress(progress).await
+                }
+                ClientManagerMsg::ParkStreams(parked) => self.handle_park_streams(parked),
+                ClientManagerMsg::ResumeStream(t) => self.handle_resume_stream(t),
             }
         }
     }
@@ -95,30 +150,211 @@ impl ManagerTask {
         t.ack.send(room_tx).ok();
     }
 
-    fn handle_is_online(&self, t: IsOnlineTransit) {
-        let is_online = self
-            .connected_users
+    async fn handle_room_deleted(&self, t: RoomDeletedTransit) {
+        for id in &t.user_ids {
+            if let Some(client) = self.connected_users.get(id) {
+                let msg = ManagerClientMsg::RoomDeleted(t.room_id);
+                if let Err(err) = client.tx.send(msg).await {
+                    warn!(
+                        "connected clients hasmap is not synhronized with running client_tasts!!, {} {}",
+                        err,
+                        Bt::new()
+                    )
+                };
+            }
+        }
+    }
+
+    /// room files go over direct channels, so the senders drop the target from their streams.
+    /// only the target and the members left in the room hear of it
+    async fn handle_user_removed_from_room(&mut self, t: MemberRemovedTransit) {
+        let notice = t.notice;
+        let room = Channel::Room(notice.room_id);
+        for parked in self.parked_streams.values_mut() {
+            if parked.stream.to == room {
+                parked.stream.receivers.remove(&notice.target.id);
+            }
+        }
+
+        for id in std::iter::once(notice.target.id).chain(t.user_ids) {
+            let Some(client) = self.connected_users.get(&id) else {
+                continue;
+            };
+            let msg = match id == notice.target.id {
+                true => ManagerClientMsg::RemovedFromRoom(notice.clone()),
+                false => ManagerClientMsg::MemberRemoved(notice.clone()),
+            };
+            if let Err(err) = client.tx.send(msg).await {
+                warn!(
+                    "connected clients hasmap is not synhronized with running client_tasts!!, {} {}",
+                    err,
+                    Bt::new()
+                )
+            };
+        }
+    }
+
+    /// the sender went offline when it is not connected, the transfer is over then anyway
+    async fn handle_transfer_control(&self, control: TransferControl) {
+        let sender_id = match control.to {
+            Channel::User(id) => id,
+            Channel::Room(_) => return,
+        };
+        if let Some(client) = self.connected_users.get(&sender_id) {
+            let msg = ManagerClientMsg::TransferControl(control);
+            if let Err(err) = client.tx.send(msg).await {
+                warn!(
+                    "connected clients hasmap is not synhronized with running client_tasts!!, {} {}",
+                    err,
+                    Bt::new()
+                )
+            };
+        }
+    }
+
+    async fn handle_stream_progress(&self, progress: StreamProgress) {
+        if let Some(client) = self.connected_users.get(&progress.to) {
+            let msg = ManagerClientMsg::StreamProgress(progress);
+            if let Err(err) = client.tx.send(msg).await {
+                warn!(
+                    "connected clients hasmap is not synhronized with running client_tasts!!, {} {}",
+                    err,
+                    Bt::new()
+                )
+            };
+        }
+    }
+
+    /// receivers of the streams keep waiting, the chunks go on where they stopped
+    fn handle_park_streams(&mut self, parked: ParkedStreams) {
+        let expires_at = Instant::now() + Duration::from_secs(STREAM_RESUME_SECS);
+        for (stream_id, stream) in parked.streams {
+            let parked_stream = ParkedStream {
+                user_id: parked.user_id,
+                stream,
+                expires_at,
+            };
+            self.parked_streams.insert(stream_id, parked_stream);
+        }
+    }
+
+    /// the hash makes sure the client still sends the same file
+    fn handle_resume_stream(&mut self, t: ResumeStreamTransit) {
+        let found = match self.parked_streams.get(&t.stream_id) {
+            Some(parked)
+                if parked.user_id == t.user_id && parked.stream.file.sha256 == t.sha256 =>
+            {
+                self.parked_streams.remove(&t.stream_id)
+            }
+            _ => None,
+        };
+
+        let user_id = t.user_id;
+        // the stream stays parked for a client that went away again while asking
+        if let Err(Some(stream)) = t.ack.send(found.map(|p| p.stream)) {
+            let parked = ParkedStreams {
+                user_id,
+                streams: HashMap::from([(t.stream_id, stream)]),
+            };
+            self.handle_park_streams(parked);
+        }
+    }
+
+    /// dropping a stream removes its partial copy, its receivers are told it failed
+    async fn expire_parked_streams(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<Uuid> = self
+            .parked_streams
             .iter()
-            .any(|(_, c)| c.user.username == t.username);
+            .filter(|(_, p)| p.expires_at <= now)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for stream_id in expired {
+            let parked = match self.parked_streams.remove(&stream_id) {
+                Some(parked) => parked,
+                None => continue,
+            };
+            debug!("parked stream {stream_id} expired");
+
+            for receiver in parked.stream.receivers.keys() {
+                let client = match self.connected_users.get(receiver) {
+                    Some(client) => client,
+                    None => continue,
+                };
+                let failure = StreamFailure {
+                    stream_id,
+                    reason: String::from("the sender did not come back"),
+                };
+                client
+                    .tx
+                    .send(ManagerClientMsg::StreamFailed(failure))
+                    .await
+                    .ok();
+            }
+        }
+    }
 
-        if t.ack.send(is_online).is_err() {
+    /// logging in again invalidates the older sessions of the user
+    fn handle_create_session(&mut self, t: CreateSessionTransit) {
+        let now = Instant::now();
+        self.sessions
+            .retain(|_, s| s.expires_at > now && s.user.id != t.user.id);
+
+        let token = Uuid::new_v4();
+        let session = Session {
+            user: t.user,
+            expires_at: now + Duration::from_secs(SESSION_TTL_SECS),
+        };
+        self.sessions.insert(token, session);
+
+        if t.ack.send(token).is_err() {
             debug!("oneshot acknowledge receiver dropped {}", Bt::new());
         };
     }
 
-    fn handle_client_connected(&mut self, client: Client) {
-        self.connected_users.insert(client.user.id, client);
+    fn handle_resume_session(&mut self, t: ResumeSessionTransit) {
+        let now = Instant::now();
+
+        let user = match self.sessions.get_mut(&t.token) {
+            Some(session) if session.expires_at > now => {
+                session.expires_at = now + Duration::from_secs(SESSION_TTL_SECS);
+                Some(session.user.clone())
+            }
+            _ => {
+                self.sessions.remove(&t.token);
+                None
+            }
+        };
+
+        if t.ack.send(user).is_err() {
+            debug!("oneshot acknowledge receiver dropped {}", Bt::new());
+        };
     }
 
-    fn handle_client_dropped(&mut self, id: Uuid) {
-        self.connected_users.remove(&id);
+    /// a user can only have one connection, the stale one is closed in favour of the new
+    async fn handle_client_connected(&mut self, client: Client) {
+        if let Some(old) = self.connected_users.insert(client.user.id, client) {
+            old.tx.send(ManagerClientMsg::Replaced).await.ok();
+        }
+    }
+
+    fn handle_client_dropped(&mut self, t: ClientDroppedTransit) {
+        if let Some(client) = self.connected_users.get(&t.user_id)
+            && client.conn_id == t.conn_id
+        {
+            self.connected_users.remove(&t.user_id);
+        }
     }
 
     async fn handle_establish_direct_comm(&mut self, t: DirectChannelTxTransit) {
         let client = match self.connected_users.get(&t.payload.to) {
             Some(c) => c,
             None => {
-                warn!("Client not found among online clients, {}", Bt::new());
+                debug!(
+                    "{} is offline, direct channel not established",
+                    t.payload.to
+                );
                 return;
             }
         };
@@ -136,8 +372,10 @@ impl ManagerTask {
         };
     }
 
+    /// the transmitter is awaited off the manager task, the client may itself be waiting on
+    /// the manager while it initializes
     async fn handle_user_registered(&mut self, user: User) {
-        for (_, client) in &self.connected_users {
+        for client in self.connected_users.values() {
             let (tx_ack, rx_ack) = oneshot::channel::<broadcast::Sender<Bytes>>();
 
             let transit = GetRoomTxTransit {
@@ -145,18 +383,33 @@ impl ManagerTask {
                 tx_ack,
             };
             let msg = ManagerClientMsg::GetRoomTransmitter(transit);
-            client.tx.send(msg).await.unwrap();
+            if let Err(err) = client.tx.send(msg).await {
+                warn!(
+                    "connected clients hasmap is not synhronized with running client_tasts!!, {} {}",
+                    err,
+                    Bt::new()
+                );
+                continue;
+            };
 
-            let tx = rx_ack.await.unwrap();
+            task::spawn(async move {
+                let tx = match rx_ack.await {
+                    Ok(tx) => tx,
+                    Err(_) => {
+                        debug!("public room transmitter not found {}", Bt::new());
+                        return;
+                    }
+                };
 
-            let transit = JoinRoomNotification {
-                room_id: Uuid::from_str(PUBLIC_ROOM_ID).unwrap(),
-                user,
-            };
-            let msg = ServerClientMsg::UserJoinedRoom(transit);
-            let serialized = bincode::serialize(&msg).unwrap();
+                let transit = JoinRoomNotification {
+                    room_id: Uuid::from_str(PUBLIC_ROOM_ID).unwrap(),
+                    user,
+                };
+                let msg = ServerClientMsg::UserJoinedRoom(transit);
+                let serialized = bincode::serialize(&msg).unwrap();
 
-            tx.send(serialized.into()).unwrap();
+                tx.send(serialized.into()).ok();
+            });
             break;
         }
     }
@@ -165,8 +418,84 @@ impl ManagerTask {
         room.users_online = room
             .users
             .iter()
+            .filter(|u| {
+                self.connected_users
+                    .get(&u.id)
+                    .is_some_and(|c| c.presence.status != PresenceStatus::Invisible)
+            })
             .cloned()
-            .filter(|u| self.connected_users.get(&u.id).is_some())
             .collect();
     }
+
+    /// the user gets their own presence back as it is, everyone else what they may see of it.
+    /// a client that does not keep up misses the update instead of holding up the manager
+    fn handle_presence_changed(&mut self, presence: Presence) {
+        if let Some(client) = self.connected_users.get_mut(&presence.user.id) {
+            client.presence = PresenceUpdate {
+                status: presence.status,
+                text: presence.text.clone(),
+            };
+        }
+
+        let visible = visible_to_others(presence.clone());
+        for (id, client) in &self.connected_users {
+            let presence = match *id == presence.user.id {
+                true => presence.clone(),
+                false => visible.clone(),
+            };
+            match client
+                .tx
+                .try_send(ManagerClientMsg::PresenceChanged(presence))
+            {
+                Ok(_) => {}
+                Err(TrySendError::Full(_)) => {
+                    debug!("presence update dropped for a busy client {}", id)
+                }
+                Err(TrySendError::Closed(_)) => warn!(
+                    "connected clients hasmap is not synhronized with running client_tasts!!, {}",
+                    Bt::new()
+                ),
+            };
+        }
+    }
+
+    /// connected users show their current status, the others are offline since `last_seen`
+    fn handle_resolve_presence(&self, t: PresenceListTransit) {
+        let presence = t
+            .presence
+            .into_iter()
+            .map(|p| {
+                if p.user.id == t.viewer {
+                    return p;
+                }
+                match self.connected_users.get(&p.user.id) {
+                    Some(client) if client.presence.status != PresenceStatus::Invisible => {
+                        Presence {
+                            status: client.presence.status,
+                            text: client.presence.text.clone(),
+                            last_seen: None,
+                            ..p
+                        }
+                    }
+                    _ => visible_to_others(Presence {
+                        status: PresenceStatus::Offline,
+                        ..p
+                    }),
+                }
+            })
+            .collect();
+
+        t.tx_ack.send(presence).ok();
+    }
+}
+
+fn visible_to_others(presence: Presence) -> Presence {
+    match presence.status {
+        PresenceStatus::Invisible | PresenceStatus::Offline => Presence {
+            status: PresenceStatus::Offline,
+            text: String::new(),
+            ..presence
+        },
+        _ => presence,
+    }
 }
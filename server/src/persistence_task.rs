@@ -1,91 +1,121 @@
 use super::util::types::server_data_types::{
-    AuthTransit, ClientPersistenceMsg, CreateRoomServerTransit, DbRoom, DbUser,
-    JoinRoomServerTransit, RegisterDataTransit, UserDataTransit, UserRoomData,
+    AuthTransit, ClientPersistenceMsg, CreateRoomServerTransit, DeleteRoomTransit, FileListTransit,
+    FileSpaceTransit, FileTransit, HistoryTransit, JoinRoomServerTransit, LeaveRoomTransit,
+    ModerationTransit, MsgChange, MsgChangeTransit, MsgChanged, PendingDelivery, ReadMarkerTransit,
+    RegisterDataTransit, ReplyTransit, RoomMemberTransit, RoomPasswordTransit, StoreMessageTransit,
+    StoredMessage, StoredReadMarker, StoredRoom, StoredUser, ThreadTransit, UserDataTransit,
+    UserRoomData,
 };
-use crate::util::{
-    server_functions::{bson_to_uuid, uuid_to_bson},
-    types::{server_data_types::JoinRoommPersistenceRes, server_error_types::Bt},
+use crate::{
+    storage::storage_backend::{Storage, connect_storage},
+    util::{
+        config::{HISTORY_PAGE_LIMIT_MAX, MAX_QUOTE_CHARS, MAX_REACTION_CHARS},
+        types::{server_data_types::JoinRoommPersistenceRes, server_error_types::Bt},
+    },
 };
 use anyhow::{Result, anyhow};
 use argon2::{
     Argon2, PasswordHash, PasswordVerifier,
     password_hash::{PasswordHasher, SaltString, rand_core::OsRng},
 };
-use futures::StreamExt;
 use log::error;
 use log::{debug, info, warn};
-use mongodb::{
-    Client, Collection,
-    bson::{Document, doc},
-    options::ClientOptions,
-};
 use regex::Regex;
 use shared::{
     config::{
         PASSWORD_ERROR_MSG, PASSWORD_RE_PATTERN, PUBLIC_ROOM_ID, PUBLIC_ROOM_NAME,
-        USERNAME_ERROR_MSG, USERNAME_RE_PATTERN,
+        UNREAD_COUNT_MAX, USERNAME_ERROR_MSG, USERNAME_RE_PATTERN,
+    },
+    types::{
+        AuthResponse, Channel, ChannelMsg, CreateRoomRes, FileList, HistoryPage, ModerationAction,
+        ModerationNotice, MsgDeletion, MsgReactions, Presence, Reaction, ReadMarker,
+        RegisterResponse, ReplyRef, RoomData, RoomRole, RoomRoles, TextMsg, ThreadPage,
+        UnreadCount, User, UserInitData,
     },
-    types::{AuthResponse, CreateRoomRes, RegisterResponse, RoomData, User, UserInitData},
 };
-use std::str::FromStr;
-use tokio::{sync::mpsc, task};
+use std::{str::FromStr, sync::Arc};
+use tokio::{
+    sync::{Mutex, mpsc},
+    task,
+};
 use uuid::Uuid;
 
 struct PersistenceTask {
     username_re: Regex,
     pwd_re: Regex,
     rx_client_persistence: mpsc::Receiver<ClientPersistenceMsg>,
-    users_collection: Collection<DbUser>,
-    rooms_collection: Collection<DbRoom>,
+    storage: Arc<dyn Storage>,
+    /// held while a stored message is read, changed and written back, messages are handled
+    /// by concurrent tasks and would overwrite each other's changes
+    message_lock: Arc<Mutex<()>>,
 }
 
-pub fn spawn_persistence_task(rx_client_persistence: mpsc::Receiver<ClientPersistenceMsg>) {
+/// connects to the configured storage before spawning, so the server does not start
+/// accepting logins it can never answer
+pub async fn spawn_persistence_task(
+    rx_client_persistence: mpsc::Receiver<ClientPersistenceMsg>,
+) -> Result<()> {
+    let storage = connect_storage().await?;
+    let mut handler = PersistenceTask::new(rx_client_persistence, storage).await?;
+
     task::spawn(async move {
-        let mut handler = match PersistenceTask::new(rx_client_persistence).await {
-            Ok(handler) => handler,
-            Err(err) => {
-                error!("Error while connecting to MongoDB: {}", err);
-                return;
-            }
-        };
         handler.run().await;
     });
+
+    Ok(())
 }
 
 impl PersistenceTask {
-    async fn new(rx_client_persistence: mpsc::Receiver<ClientPersistenceMsg>) -> Result<Self> {
-        let mongo_addr = std::env::var("DB_URL").unwrap();
-
-        let options = ClientOptions::parse(mongo_addr).await?;
-
-        let client = Client::with_options(options)?;
-        let db = client.database("chatapp");
-
-        let users_collection = db.collection::<DbUser>("User");
-        let rooms_collection = db.collection::<DbRoom>("Room");
-
-        let bson_id = uuid_to_bson(Uuid::from_str(PUBLIC_ROOM_ID)?);
-
-        let filter = doc! { "id": bson_id };
-        let room = rooms_collection.find_one(filter).await?;
+    async fn new(
+        rx_client_persistence: mpsc::Receiver<ClientPersistenceMsg>,
+        storage: Arc<dyn Storage>,
+    ) -> Result<Self> {
+        let public_room_id = Uuid::from_str(PUBLIC_ROOM_ID)?;
+
+        if storage.get_room(public_room_id).await?.is_none() {
+            let public_room = StoredRoom {
+                id: public_room_id,
+                name: PUBLIC_ROOM_NAME.into(),
+                user_ids: vec![],
+                pwd: None,
+                owner_id: None,
+                admin_ids: vec![],
+                moderator_ids: vec![],
+                banned_ids: vec![],
+            };
+            storage.insert_room(public_room).await?;
+        }
 
-        let public_room = DbRoom {
-            id: uuid_to_bson(Uuid::from_str(PUBLIC_ROOM_ID)?),
-            name: PUBLIC_ROOM_NAME.into(),
-            user_ids: vec![],
-            pwd: None,
-        };
+        for room in storage.get_all_rooms().await? {
+            // rooms created before owners were kept go to their first member
+            if room.id != public_room_id
+                && room.owner_id.is_none()
+                && let Some(first) = room.user_ids.first()
+            {
+                let owned = StoredRoom {
+                    owner_id: Some(*first),
+                    ..room.clone()
+                };
+                storage.update_room_roles(owned).await?;
+                info!("Gave room {} to its first member", room.name);
+            }
 
-        if room.is_none() {
-            rooms_collection.insert_one(public_room).await?;
+            // room passwords used to be stored in plain text
+            if let Some(pwd) = &room.pwd
+                && PasswordHash::new(pwd).is_err()
+            {
+                let hash = PersistenceTask::hash_password(pwd)?;
+                storage.update_room_pwd(room.id, Some(hash)).await?;
+                info!("Hashed plain text password of room {}", room.name);
+            }
         }
 
         Ok(Self {
             pwd_re: Regex::new(PASSWORD_RE_PATTERN)?,
             username_re: Regex::new(USERNAME_RE_PATTERN)?,
             rx_client_persistence,
-            rooms_collection,
-            users_collection,
+            storage,
+            message_lock: Arc::new(Mutex::new(())),
         })
     }
 
@@ -93,34 +123,79 @@ impl PersistenceTask {
         info!("Persistence task running");
         loop {
             if let Some(msg) = self.rx_client_persistence.recv().await {
-                let users = self.users_collection.clone();
-                let rooms = self.rooms_collection.clone();
+                let storage = self.storage.clone();
                 let pwd_re = self.pwd_re.clone();
                 let username_re = self.username_re.clone();
+                let message_lock = self.message_lock.clone();
 
                 task::spawn(async move {
                     let res = match msg {
                         ClientPersistenceMsg::Authenticate(t) => {
-                            PersistenceTask::handle_auth(t, users).await
+                            PersistenceTask::handle_auth(t, storage).await
                         }
                         ClientPersistenceMsg::Register(t) => {
-                            PersistenceTask::handle_register(t, users, rooms, pwd_re, username_re)
-                                .await
+                            PersistenceTask::handle_register(t, storage, pwd_re, username_re).await
                         }
                         ClientPersistenceMsg::GetUserData(t) => {
-                            PersistenceTask::get_user_data(t, users, rooms).await
+                            PersistenceTask::get_user_data(t, storage).await
                         }
                         ClientPersistenceMsg::UserJoinedRoom(t) => {
-                            PersistenceTask::handle_user_joined_room(t, users, rooms).await
+                            PersistenceTask::handle_user_joined_room(t, storage).await
                         }
                         ClientPersistenceMsg::UserLeftRoom(t) => {
-                            PersistenceTask::handle_user_left_room(t, users, rooms).await
+                            PersistenceTask::handle_user_left_room(t, storage).await
+                        }
+                        ClientPersistenceMsg::DeleteRoom(t) => {
+                            PersistenceTask::handle_delete_room(t, storage).await
+                        }
+                        ClientPersistenceMsg::ModerateRoom(t) => {
+                            PersistenceTask::handle_moderate_room(t, storage).await
+                        }
+                        ClientPersistenceMsg::SetRoomPassword(t) => {
+                            PersistenceTask::handle_set_room_password(t, storage).await
                         }
                         ClientPersistenceMsg::CreateRoom(t) => {
-                            PersistenceTask::handle_create_room(t, users, rooms).await
+                            PersistenceTask::handle_create_room(t, storage).await
                         }
                         ClientPersistenceMsg::JoinRoom(t) => {
-                            PersistenceTask::handle_join_room(t, users, rooms).await
+                            PersistenceTask::handle_join_room(t, storage).await
+                        }
+                        ClientPersistenceMsg::StoreMessage(t) => {
+                            PersistenceTask::handle_store_message(t, storage, message_lock).await
+                        }
+                        ClientPersistenceMsg::ChangeMessage(t) => {
+                            PersistenceTask::handle_change_message(t, storage, message_lock).await
+                        }
+                        ClientPersistenceMsg::GetHistory(t) => {
+                            PersistenceTask::handle_get_history(t, storage).await
+                        }
+                        ClientPersistenceMsg::ResolveReply(t) => {
+                            PersistenceTask::handle_resolve_reply(t, storage).await
+                        }
+                        ClientPersistenceMsg::GetThread(t) => {
+                            PersistenceTask::handle_get_thread(t, storage).await
+                        }
+                        ClientPersistenceMsg::MarkRead(t) => {
+                            PersistenceTask::handle_mark_read(t, storage).await
+                        }
+                        ClientPersistenceMsg::SetPresence(presence) => {
+                            storage.set_presence(presence).await
+                        }
+                        ClientPersistenceMsg::RemoveDeliveries(t) => {
+                            storage.remove_deliveries(t.to_id, &t.msg_ids).await
+                        }
+                        ClientPersistenceMsg::StoreFile(file) => storage.insert_file(file).await,
+                        ClientPersistenceMsg::GetFiles(t) => {
+                            PersistenceTask::handle_get_files(t, storage).await
+                        }
+                        ClientPersistenceMsg::GetFile(t) => {
+                            PersistenceTask::handle_get_file(t, storage).await
+                        }
+                        ClientPersistenceMsg::GetFileSpace(t) => {
+                            PersistenceTask::handle_get_file_space(t, storage).await
+                        }
+                        ClientPersistenceMsg::IsRoomMember(t) => {
+                            PersistenceTask::handle_is_room_member(t, storage).await
                         }
                     };
 
@@ -132,19 +207,67 @@ impl PersistenceTask {
         }
     }
 
+    async fn get_room_users(room: &StoredRoom, storage: &Arc<dyn Storage>) -> Result<Vec<User>> {
+        let users = storage
+            .get_users(&room.user_ids)
+            .await?
+            .into_iter()
+            .map(|u| User {
+                id: u.id,
+                username: u.username,
+            })
+            .collect();
+
+        Ok(users)
+    }
+
+    fn hash_password(pwd: &str) -> Result<String> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(pwd.as_bytes(), &salt)
+            .map_err(|err| anyhow!("error hashing password: {err}"))?;
+
+        Ok(hash.to_string())
+    }
+
+    fn verify_password(pwd: &str, hash: &str) -> Result<bool> {
+        let parsed_hash =
+            PasswordHash::new(hash).map_err(|err| anyhow!("error parsing password hash: {err}"))?;
+
+        match Argon2::default().verify_password(pwd.as_bytes(), &parsed_hash) {
+            Ok(_) => Ok(true),
+            Err(argon2::password_hash::Error::Password) => Ok(false),
+            Err(err) => Err(anyhow!("error verifying password: {err}")),
+        }
+    }
+
+    async fn get_room_roles(room: &StoredRoom, storage: &Arc<dyn Storage>) -> Result<RoomRoles> {
+        let banned = storage
+            .get_users(&room.banned_ids)
+            .await?
+            .into_iter()
+            .map(|u| User {
+                id: u.id,
+                username: u.username,
+            })
+            .collect();
+
+        Ok(RoomRoles {
+            owner_id: room.owner_id,
+            admin_ids: room.admin_ids.clone(),
+            moderator_ids: room.moderator_ids.clone(),
+            banned,
+        })
+    }
+
     async fn handle_create_room(
         t: CreateRoomServerTransit,
-        users_collection: Collection<DbUser>,
-        rooms_collection: Collection<DbRoom>,
+        storage: Arc<dyn Storage>,
     ) -> Result<()> {
-        let filter = doc! { "name": t.room_name.clone() };
-        let room_res = rooms_collection.find_one(filter).await?;
+        let room_res = storage.get_room_by_name(&t.room_name).await?;
 
         if room_res.is_some() {
-            let res = CreateRoomRes::Err(String::from(format!(
-                "Room name {} is already taken",
-                t.room_name
-            )));
+            let res = CreateRoomRes::Err(format!("Room name {} is already taken", t.room_name));
             if let Err(err) = t.tx.send(res) {
                 debug!(
                     "oneshot receiver dropped before auth finished {err:?} {}",
@@ -154,13 +277,10 @@ impl PersistenceTask {
             return Ok(());
         };
 
-        let filter = doc! { "username": &t.username.to_string() };
-        let db_user = match users_collection.find_one(filter).await? {
+        let db_user = match storage.get_user_by_name(&t.username).await? {
             None => {
                 warn!("Not registered user attepmted to create room");
-                let res = CreateRoomRes::Err(String::from(format!(
-                    "Provided username is not registered"
-                )));
+                let res = CreateRoomRes::Err(String::from("Provided username is not registered"));
                 if let Err(err) = t.tx.send(res) {
                     debug!(
                         "oneshot receiver dropped before auth finished {err:?} {}",
@@ -173,29 +293,37 @@ impl PersistenceTask {
         };
 
         let user = User {
-            id: bson_to_uuid(&db_user.id).ok_or(anyhow!("expected uuid value"))?,
+            id: db_user.id,
             username: t.username,
         };
 
-        let new_db_room = DbRoom {
-            id: uuid_to_bson(Uuid::new_v4()),
+        let pwd = match &t.room_pwd {
+            Some(pwd) => Some(PersistenceTask::hash_password(pwd)?),
+            None => None,
+        };
+
+        let new_room = StoredRoom {
+            id: Uuid::new_v4(),
             name: t.room_name,
-            user_ids: vec![db_user.id.clone()],
-            pwd: t.room_pwd,
+            user_ids: vec![],
+            pwd,
+            owner_id: Some(user.id),
+            admin_ids: vec![],
+            moderator_ids: vec![],
+            banned_ids: vec![],
         };
 
         let room_data = RoomData {
-            id: bson_to_uuid(&new_db_room.id).ok_or(anyhow!("expected uuid value"))?,
-            name: new_db_room.name.clone(),
+            id: new_room.id,
+            name: new_room.name.clone(),
             users: vec![user.clone()],
             users_online: vec![user.clone()],
+            roles: PersistenceTask::get_room_roles(&new_room, &storage).await?,
         };
 
-        let filter = doc! { "id": db_user.id };
-        let update = doc! { "$push": { "room_ids": new_db_room.id.clone() } };
-
-        rooms_collection.insert_one(new_db_room).await?;
-        users_collection.find_one_and_update(filter, update).await?;
+        let room_id = new_room.id;
+        storage.insert_room(new_room).await?;
+        storage.add_room_member(room_id, user.id).await?;
 
         let res = CreateRoomRes::Ok(room_data);
         if let Err(err) = t.tx.send(res) {
@@ -208,15 +336,8 @@ impl PersistenceTask {
         Ok(())
     }
 
-    async fn handle_join_room(
-        t: JoinRoomServerTransit,
-        users_collection: Collection<DbUser>,
-        rooms_collection: Collection<DbRoom>,
-    ) -> Result<()> {
-        let filter = doc! { "name": &t.room_name };
-        let res = rooms_collection.find_one(filter).await?;
-
-        let room = match res {
+    async fn handle_join_room(t: JoinRoomServerTransit, storage: Arc<dyn Storage>) -> Result<()> {
+        let room = match storage.get_room_by_name(&t.room_name).await? {
             None => {
                 let msg = format!(
                     "No room named {} is registered, but you can create one!",
@@ -229,29 +350,31 @@ impl PersistenceTask {
             Some(r) => r,
         };
 
-        if room.user_ids.iter().any(|bson_id| {
-            bson_to_uuid(bson_id)
-                // .ok_or(anyhow!("expected uuid value"))
-                .unwrap()
-                == t.user.id
-        }) {
+        if room.user_ids.contains(&t.user.id) {
             let msg = format!("User {} already is in the room.", t.user.username);
             let res = JoinRoommPersistenceRes::Err(msg);
             t.tx.send(res).ok();
             return Ok(());
         }
 
+        if room.banned_ids.contains(&t.user.id) {
+            let msg = String::from("You are banned from this room.");
+            let res = JoinRoommPersistenceRes::Err(msg);
+            t.tx.send(res).ok();
+            return Ok(());
+        }
+
         match (&room.pwd, &t.room_pwd) {
-            (Some(correct_pwd), Some(provided_pwd)) => {
-                if correct_pwd != provided_pwd {
-                    let msg = format!("Incorrect room password.");
-                    let res = JoinRoommPersistenceRes::Err(msg);
-                    t.tx.send(res).ok();
-                    return Ok(());
-                }
+            (Some(pwd_hash), Some(provided_pwd))
+                if !PersistenceTask::verify_password(provided_pwd, pwd_hash)? =>
+            {
+                let msg = String::from("Incorrect room password.");
+                let res = JoinRoommPersistenceRes::Err(msg);
+                t.tx.send(res).ok();
+                return Ok(());
             }
             (Some(_), None) => {
-                let msg = format!("Room password required.");
+                let msg = String::from("Room password required.");
                 let res = JoinRoommPersistenceRes::Err(msg);
                 t.tx.send(res).ok();
                 return Ok(());
@@ -259,34 +382,17 @@ impl PersistenceTask {
             _ => {}
         }
 
-        let mut users_cursor = users_collection
-            .find(doc! { "id": { "$in": room.user_ids } })
-            .await?;
-
-        let mut users = vec![];
-
-        while let Some(user_res) = users_cursor.next().await {
-            let user = user_res?;
-
-            users.push(User {
-                id: bson_to_uuid(&user.id).ok_or(anyhow!("expected uuid value"))?,
-                username: user.username,
-            })
-        }
+        let users = PersistenceTask::get_room_users(&room, &storage).await?;
 
         let data = RoomData {
-            id: bson_to_uuid(&room.id).ok_or(anyhow!("expected uuid value"))?,
+            id: room.id,
             name: t.room_name,
             users,
             users_online: vec![],
+            roles: PersistenceTask::get_room_roles(&room, &storage).await?,
         };
 
-        let filter = doc! { "username": &t.user.username };
-        let update = doc! { "$push": { "room_ids": room.id } };
-
-        users_collection.find_one_and_update(filter, update).await?;
-
-        // user.rooms.push(room.id);
+        storage.add_room_member(room.id, t.user.id).await?;
 
         let res = JoinRoommPersistenceRes::Ok(data);
         t.tx.send(res).ok();
@@ -294,14 +400,11 @@ impl PersistenceTask {
         Ok(())
     }
 
-    async fn handle_auth(t: AuthTransit, users_collection: Collection<DbUser>) -> Result<()> {
+    async fn handle_auth(t: AuthTransit, storage: Arc<dyn Storage>) -> Result<()> {
         let err_msg = String::from("Internal server error");
         let err_res = AuthResponse::Err(err_msg);
 
-        let filter = doc! { "username": &t.data.username };
-        let user_res = users_collection.find_one(filter).await?;
-
-        let db_user = match user_res {
+        let db_user = match storage.get_user_by_name(&t.data.username).await? {
             Some(c) => c,
             None => {
                 let res = AuthResponse::Err(format!(
@@ -332,15 +435,15 @@ impl PersistenceTask {
         let argon2 = Argon2::default();
         let res = match argon2.verify_password(t.data.pwd.as_bytes(), &parsed_hash) {
             Err(argon2::password_hash::Error::Password) => {
-                AuthResponse::Err(format!("Incorrect password"))
+                AuthResponse::Err(String::from("Incorrect password"))
             }
             Err(err) => {
                 error!("error hashing password: {err}");
-                AuthResponse::Err(format!("Internal server error"))
+                AuthResponse::Err(String::from("Internal server error"))
             }
             Ok(_) => AuthResponse::Ok(User {
                 username: t.data.username,
-                id: bson_to_uuid(&db_user.id).ok_or(anyhow!("expected uuid value"))?,
+                id: db_user.id,
             }),
         };
 
@@ -353,52 +456,60 @@ impl PersistenceTask {
         Ok(())
     }
 
-    async fn get_user_data(
-        t: UserDataTransit,
-        users_collection: Collection<DbUser>,
-        rooms_collection: Collection<DbRoom>,
-    ) -> Result<()> {
-        let filter = doc! { "username": t.user.username };
-        let user_res = users_collection.find_one(filter).await?;
-
-        let user = match user_res {
+    async fn get_user_data(t: UserDataTransit, storage: Arc<dyn Storage>) -> Result<()> {
+        let user = match storage.get_user_by_name(&t.user.username).await? {
             Some(user) => user,
             None => return Ok(()),
         };
 
         let mut user_rooms = vec![];
 
-        let filter = doc! { "id": { "$in": user.room_ids } };
-
-        let mut rooms_cursor = rooms_collection.find(filter).await?;
-
-        while let Some(room_res) = rooms_cursor.next().await {
-            let room = room_res?;
-
-            let mut users_cursor = users_collection
-                .find(doc! { "id": { "$in": room.user_ids } })
-                .await?;
-
-            let mut users = vec![];
-
-            while let Some(user_res) = users_cursor.next().await {
-                let user = user_res?;
-                users.push(User {
-                    id: bson_to_uuid(&user.id).unwrap(),
-                    username: user.username,
-                });
-            }
+        for room in storage.get_rooms(&user.room_ids).await? {
+            let users = PersistenceTask::get_room_users(&room, &storage).await?;
 
             let room_data = RoomData {
-                id: bson_to_uuid(&room.id).ok_or(anyhow!("expected uuid value"))?,
+                id: room.id,
                 name: room.name.clone(),
                 users,
                 users_online: vec![],
+                roles: PersistenceTask::get_room_roles(&room, &storage).await?,
             };
             user_rooms.push(room_data);
         }
 
-        let data = UserInitData { rooms: user_rooms };
+        // the queue is emptied by the client task once the init data went out
+        let mut queued_messages = vec![];
+        let mut gone = vec![];
+        for delivery in storage.get_deliveries(user.id).await? {
+            match storage.get_message(delivery.msg_id).await? {
+                Some(msg) => queued_messages.push(msg.msg),
+                None => gone.push(delivery.msg_id),
+            }
+        }
+        if !gone.is_empty() {
+            storage.remove_deliveries(user.id, &gone).await?;
+        }
+
+        let unread = PersistenceTask::get_unread_counts(user.id, &user_rooms, &storage).await?;
+        let seen = storage
+            .get_direct_read_markers(user.id)
+            .await?
+            .into_iter()
+            .map(|m| ReadMarker {
+                channel: Channel::User(m.user_id),
+                id: m.msg_id,
+            })
+            .collect();
+        let presence = PersistenceTask::get_presence(&user_rooms, &storage).await?;
+
+        let data = UserInitData {
+            rooms: user_rooms,
+            queued_messages,
+            undelivered: storage.get_undelivered(user.id).await?,
+            unread,
+            seen,
+            presence,
+        };
         if let Err(err) = t.tx.send(data) {
             debug!(
                 "oneshot receiver for get_user_data dropped {err:?} {}",
@@ -408,10 +519,79 @@ impl PersistenceTask {
         Ok(())
     }
 
+    /// stored as it is, the manager decides what the user gets to see of it
+    async fn get_presence(rooms: &[RoomData], storage: &Arc<dyn Storage>) -> Result<Vec<Presence>> {
+        let public_room_id = Uuid::from_str(PUBLIC_ROOM_ID)?;
+        let users = match rooms.iter().find(|r| r.id == public_room_id) {
+            Some(room) => &room.users,
+            None => return Ok(vec![]),
+        };
+
+        let ids: Vec<Uuid> = users.iter().map(|u| u.id).collect();
+        let presence = storage
+            .get_presence(&ids)
+            .await?
+            .into_iter()
+            .filter_map(|p| {
+                let user = users.iter().find(|u| u.id == p.user_id)?.clone();
+                Some(Presence {
+                    user,
+                    status: p.status,
+                    text: p.text,
+                    last_seen: p.last_seen,
+                })
+            })
+            .collect();
+
+        Ok(presence)
+    }
+
+    /// every room of the user and a direct conversation with every member of the public room
+    async fn get_unread_counts(
+        user_id: Uuid,
+        rooms: &[RoomData],
+        storage: &Arc<dyn Storage>,
+    ) -> Result<Vec<UnreadCount>> {
+        let public_room_id = Uuid::from_str(PUBLIC_ROOM_ID)?;
+        let markers = storage.get_read_markers(user_id).await?;
+
+        let directs = rooms
+            .iter()
+            .filter(|r| r.id == public_room_id)
+            .flat_map(|r| r.users.iter())
+            .filter(|u| u.id != user_id)
+            .map(|u| Channel::User(u.id));
+        let channels = rooms.iter().map(|r| Channel::Room(r.id)).chain(directs);
+
+        let channels: Vec<(Channel, Option<(i64, Uuid)>)> = channels
+            .map(|channel| {
+                let after = markers
+                    .iter()
+                    .find(|m| m.channel == channel)
+                    .map(|m| (m.created_at, m.msg_id));
+                (channel, after)
+            })
+            .collect();
+        let counts = storage
+            .count_unread_many(user_id, &channels, UNREAD_COUNT_MAX as usize)
+            .await?;
+
+        let unread = channels
+            .into_iter()
+            .zip(counts)
+            .filter(|(_, count)| *count > 0)
+            .map(|((channel, _), count)| UnreadCount {
+                channel,
+                count: count as u32,
+            })
+            .collect();
+
+        Ok(unread)
+    }
+
     async fn handle_register(
         t: RegisterDataTransit,
-        users_collection: Collection<DbUser>,
-        rooms_collection: Collection<DbRoom>,
+        storage: Arc<dyn Storage>,
         pwd_re: Regex,
         username_re: Regex,
     ) -> Result<()> {
@@ -427,10 +607,7 @@ impl PersistenceTask {
             return Ok(());
         };
 
-        let filter = doc! { "username": &t.data.username };
-        let res = users_collection.find_one(filter).await?;
-
-        if res.is_some() {
+        if storage.get_user_by_name(&t.data.username).await?.is_some() {
             let res = RegisterResponse::Err(String::from("Username already taken"));
             if let Err(err) = t.tx.send(res) {
                 debug!("oneshot register res receiver dropped{err:?} {}", Bt::new());
@@ -450,7 +627,7 @@ impl PersistenceTask {
             return Ok(());
         }
 
-        let public_room_id = uuid_to_bson(Uuid::from_str(PUBLIC_ROOM_ID)?);
+        let public_room_id = Uuid::from_str(PUBLIC_ROOM_ID)?;
 
         let salt = SaltString::generate(&mut OsRng);
         let argon2 = Argon2::default();
@@ -465,26 +642,22 @@ impl PersistenceTask {
             }
         };
 
-        let new_db_user = DbUser {
-            id: uuid_to_bson(Uuid::new_v4()),
+        let new_db_user = StoredUser {
+            id: Uuid::new_v4(),
             pwd: password_hash,
             username: t.data.username,
-            room_ids: vec![public_room_id.clone()],
+            room_ids: vec![],
         };
 
         let new_user = User {
-            id: bson_to_uuid(&new_db_user.id).ok_or(anyhow!("expected uuid value"))?,
+            id: new_db_user.id,
             username: new_db_user.username.clone(),
         };
 
-        let filter = doc! { "id": public_room_id };
-        let update: Document = doc! { "$push": { "user_ids": new_db_user.id.clone() } };
-
-        rooms_collection.update_one(filter, update).await?;
+        storage.insert_user(new_db_user).await?;
+        storage.add_room_member(public_room_id, new_user.id).await?;
 
         let res = RegisterResponse::Ok(new_user);
-        users_collection.insert_one(new_db_user).await?;
-
         if let Err(err) = t.tx.send(res) {
             debug!("oneshot register res receiver dropped{err:?} {}", Bt::new());
         };
@@ -492,45 +665,745 @@ impl PersistenceTask {
         Ok(())
     }
 
-    async fn handle_user_joined_room(
-        t: UserRoomData,
-        users_collection: Collection<DbUser>,
-        rooms_collection: Collection<DbRoom>,
+    async fn handle_user_joined_room(t: UserRoomData, storage: Arc<dyn Storage>) -> Result<()> {
+        storage.add_room_member(t.room_id, t.user.id).await
+    }
+
+    async fn handle_user_left_room(t: LeaveRoomTransit, storage: Arc<dyn Storage>) -> Result<()> {
+        if t.room_id == Uuid::from_str(PUBLIC_ROOM_ID)? {
+            let res = Err(String::from("The public room can not be left."));
+            t.tx.send(res).ok();
+            return Ok(());
+        }
+
+        let mut room = match storage.get_room(t.room_id).await? {
+            Some(room) if room.user_ids.contains(&t.user.id) => room,
+            _ => {
+                let res = Err(String::from("You are not a member of this room."));
+                t.tx.send(res).ok();
+                return Ok(());
+            }
+        };
+        // nobody could moderate or delete the room any more
+        if room.owner_id == Some(t.user.id) {
+            let res = Err(String::from(
+                "The owner can not leave the room, delete it instead.",
+            ));
+            t.tx.send(res).ok();
+            return Ok(());
+        }
+
+        // roles are not kept for members that come back later
+        room.admin_ids.retain(|id| *id != t.user.id);
+        room.moderator_ids.retain(|id| *id != t.user.id);
+        storage.update_room_roles(room).await?;
+
+        storage.remove_room_member(t.room_id, t.user.id).await?;
+        t.tx.send(Ok(t.room_id)).ok();
+
+        Ok(())
+    }
+
+    async fn handle_delete_room(t: DeleteRoomTransit, storage: Arc<dyn Storage>) -> Result<()> {
+        let room = match storage.get_room(t.room_id).await? {
+            Some(room) => room,
+            None => {
+                let res = Err(String::from("Room does not exist."));
+                t.tx.send(res).ok();
+                return Ok(());
+            }
+        };
+
+        let refusal = if room.id == Uuid::from_str(PUBLIC_ROOM_ID)? {
+            Some("The public room can not be deleted.")
+        } else if room.owner_id != Some(t.user.id) {
+            Some("Only the owner can delete the room.")
+        } else {
+            None
+        };
+        if let Some(msg) = refusal {
+            t.tx.send(Err(String::from(msg))).ok();
+            return Ok(());
+        }
+
+        storage.delete_room(room.id).await?;
+        t.tx.send(Ok(room.user_ids)).ok();
+
+        Ok(())
+    }
+
+    async fn handle_set_room_password(
+        t: RoomPasswordTransit,
+        storage: Arc<dyn Storage>,
     ) -> Result<()> {
-        let user_bson_id = uuid_to_bson(t.user.id);
-        let room_bson_id = uuid_to_bson(t.room_id);
+        let room = match storage.get_room(t.room_id).await? {
+            Some(room) => room,
+            None => {
+                let res = Err(String::from("Room does not exist."));
+                t.tx.send(res).ok();
+                return Ok(());
+            }
+        };
+
+        let refusal = if room.id == Uuid::from_str(PUBLIC_ROOM_ID)? {
+            Some("The public room can not have a password.")
+        } else if room.owner_id != Some(t.user.id) {
+            Some("Only the owner can change the room password.")
+        } else {
+            None
+        };
+        if let Some(msg) = refusal {
+            t.tx.send(Err(String::from(msg))).ok();
+            return Ok(());
+        }
+
+        let pwd = match &t.room_pwd {
+            Some(pwd) => Some(PersistenceTask::hash_password(pwd)?),
+            None => None,
+        };
 
-        let filter = doc! { "id": room_bson_id.clone() };
-        let update = doc! { "$push": { "user_ids": user_bson_id.clone() } };
+        storage.update_room_pwd(room.id, pwd).await?;
+        t.tx.send(Ok(room.id)).ok();
 
-        rooms_collection.find_one_and_update(filter, update).await?;
+        Ok(())
+    }
 
-        let filter = doc! { "id": user_bson_id };
-        let update = doc! { "$push": { "room_ids": room_bson_id } };
+    /// a role can only act on lower roles and only hand out roles below its own
+    async fn handle_moderate_room(t: ModerationTransit, storage: Arc<dyn Storage>) -> Result<()> {
+        let mut room = match storage.get_room(t.target.room_id).await? {
+            Some(room) => room,
+            None => {
+                let res = Err(String::from("Room does not exist."));
+                t.tx.send(res).ok();
+                return Ok(());
+            }
+        };
 
-        users_collection.find_one_and_update(filter, update).await?;
+        let target = match storage.get_users(&[t.target.user_id]).await?.pop() {
+            Some(u) => User {
+                id: u.id,
+                username: u.username,
+            },
+            None => {
+                let res = Err(String::from("User does not exist."));
+                t.tx.send(res).ok();
+                return Ok(());
+            }
+        };
+
+        let roles = PersistenceTask::get_room_roles(&room, &storage).await?;
+        let actor_role = roles.role_of(t.user.id);
+        let target_role = roles.role_of(target.id);
+        let is_member = room.user_ids.contains(&target.id);
+        let is_banned = room.banned_ids.contains(&target.id);
+        let promoted_role = match target_role {
+            RoomRole::Member => RoomRole::Moderator,
+            _ => RoomRole::Admin,
+        };
+
+        let refusal = if room.id == Uuid::from_str(PUBLIC_ROOM_ID)? {
+            Some("The public room can not be moderated.")
+        } else if !room.user_ids.contains(&t.user.id) {
+            Some("You are not a member of this room.")
+        } else if target.id == t.user.id {
+            Some("You can not moderate yourself.")
+        } else {
+            match t.action {
+                ModerationAction::Ban if is_banned => Some("User is already banned."),
+                ModerationAction::Unban if !is_banned => Some("User is not banned."),
+                ModerationAction::Kick | ModerationAction::Promote | ModerationAction::Demote
+                    if !is_member =>
+                {
+                    Some("User is not a member of this room.")
+                }
+                ModerationAction::Promote if target_role >= RoomRole::Admin => {
+                    Some("User already has the highest role.")
+                }
+                ModerationAction::Demote if target_role == RoomRole::Member => {
+                    Some("User has no role to remove.")
+                }
+                ModerationAction::Kick | ModerationAction::Ban | ModerationAction::Demote
+                    if actor_role <= target_role =>
+                {
+                    Some("You do not have the permission to do that.")
+                }
+                ModerationAction::Unban if actor_role < RoomRole::Moderator => {
+                    Some("You do not have the permission to do that.")
+                }
+                ModerationAction::Promote if actor_role <= promoted_role => {
+                    Some("You do not have the permission to do that.")
+                }
+                _ => None,
+            }
+        };
+
+        if let Some(reason) = refusal {
+            t.tx.send(Err(String::from(reason))).ok();
+            return Ok(());
+        }
+
+        match t.action {
+            ModerationAction::Kick | ModerationAction::Ban => {
+                room.admin_ids.retain(|id| *id != target.id);
+                room.moderator_ids.retain(|id| *id != target.id);
+                if t.action == ModerationAction::Ban {
+                    room.banned_ids.push(target.id);
+                }
+            }
+            ModerationAction::Unban => room.banned_ids.retain(|id| *id != target.id),
+            ModerationAction::Promote => {
+                room.moderator_ids.retain(|id| *id != target.id);
+                match promoted_role {
+                    RoomRole::Moderator => room.moderator_ids.push(target.id),
+                    _ => room.admin_ids.push(target.id),
+                }
+            }
+            ModerationAction::Demote => {
+                room.admin_ids.retain(|id| *id != target.id);
+                room.moderator_ids.retain(|id| *id != target.id);
+                if target_role == RoomRole::Admin {
+                    room.moderator_ids.push(target.id);
+                }
+            }
+        }
+
+        storage.update_room_roles(room.clone()).await?;
+        if is_member && let ModerationAction::Kick | ModerationAction::Ban = t.action {
+            storage.remove_room_member(room.id, target.id).await?;
+            room.user_ids.retain(|id| *id != target.id);
+        }
+
+        let notice = ModerationNotice {
+            room_id: room.id,
+            target,
+            by: t.user,
+            action: t.action,
+            roles: PersistenceTask::get_room_roles(&room, &storage).await?,
+        };
+        t.tx.send(Ok((notice, room.user_ids))).ok();
+
+        Ok(())
+    }
+
+    async fn handle_store_message(
+        t: StoreMessageTransit,
+        storage: Arc<dyn Storage>,
+        message_lock: Arc<Mutex<()>>,
+    ) -> Result<()> {
+        let delivery = match (t.pending, &t.to) {
+            (true, Channel::User(to_id)) => Some(PendingDelivery {
+                msg_id: t.id,
+                from_id: t.from.id,
+                to_id: *to_id,
+            }),
+            _ => None,
+        };
+
+        let thread_id = match &t.msg {
+            ChannelMsg::TextMsg(text) => text.thread,
+            _ => None,
+        };
+
+        let msg = StoredMessage {
+            id: t.id,
+            from_id: t.from.id,
+            to: t.to,
+            created_at: t.sent_at,
+            thread_id,
+            msg: t.msg,
+        };
+        storage.insert_message(msg).await?;
+
+        // queued after the message, so whoever takes the queue finds it stored
+        if let Some(delivery) = delivery {
+            storage.queue_delivery(delivery).await?;
+        }
+
+        if let Some(root_id) = thread_id {
+            let _held = message_lock.lock().await;
+            PersistenceTask::update_thread_len(root_id, &storage).await?;
+        }
+
+        Ok(())
+    }
+
+    /// counted again instead of incremented. callers hold the message lock, so the last count
+    /// written saw every reply stored before it
+    async fn update_thread_len(root_id: Uuid, storage: &Arc<dyn Storage>) -> Result<()> {
+        let replies = storage.get_thread(root_id).await?;
+        let len = replies
+            .iter()
+            .filter(|m| matches!(m.msg, ChannelMsg::TextMsg(_)))
+            .count();
+
+        if let Some(mut root) = storage.get_message(root_id).await?
+            && let ChannelMsg::TextMsg(text) = &mut root.msg
+        {
+            text.thread_len = len as u32;
+            storage.update_message(root).await?;
+        }
+
+        Ok(())
+    }
+
+    /// the text message `id` together with its thread, if it belongs to the conversation
+    /// `user_id` sees as `to`
+    async fn get_conversation_text(
+        id: Uuid,
+        user_id: Uuid,
+        to: &Channel,
+        storage: &Arc<dyn Storage>,
+    ) -> Result<Option<(TextMsg, Option<Uuid>)>> {
+        let stored = match storage.get_message(id).await? {
+            Some(msg) => msg,
+            None => return Ok(None),
+        };
+
+        let same_conversation = PersistenceTask::in_conversation(&stored, user_id, to);
+        match stored.msg {
+            ChannelMsg::TextMsg(text) if same_conversation => Ok(Some((text, stored.thread_id))),
+            _ => Ok(None),
+        }
+    }
+
+    /// `to` is seen from `user_id`, so `Channel::User` means the direct conversation of the two
+    fn in_conversation(stored: &StoredMessage, user_id: Uuid, to: &Channel) -> bool {
+        match (to, &stored.to) {
+            (Channel::Room(room_id), Channel::Room(stored_room_id)) => room_id == stored_room_id,
+            (Channel::User(other_id), Channel::User(to_id)) => {
+                (stored.from_id == user_id && to_id == other_id)
+                    || (stored.from_id == *other_id && *to_id == user_id)
+            }
+            _ => false,
+        }
+    }
+
+    /// answers whether the marker moved, it never goes back to an older message
+    async fn handle_mark_read(t: ReadMarkerTransit, storage: Arc<dyn Storage>) -> Result<()> {
+        let stored = match storage.get_message(t.marker.id).await? {
+            Some(msg) if PersistenceTask::in_conversation(&msg, t.user.id, &t.marker.channel) => {
+                msg
+            }
+            _ => {
+                t.tx.send(false).ok();
+                return Ok(());
+            }
+        };
+
+        if let Channel::Room(room_id) = &t.marker.channel {
+            let is_member = match storage.get_room(*room_id).await? {
+                Some(room) => room.user_ids.contains(&t.user.id),
+                None => false,
+            };
+            if !is_member {
+                t.tx.send(false).ok();
+                return Ok(());
+            }
+        }
+
+        let position = (stored.created_at, stored.id);
+        let moved = storage
+            .get_read_markers(t.user.id)
+            .await?
+            .iter()
+            .find(|m| m.channel == t.marker.channel)
+            .is_none_or(|m| (m.created_at, m.msg_id) < position);
+
+        if moved {
+            let marker = StoredReadMarker {
+                user_id: t.user.id,
+                channel: t.marker.channel,
+                msg_id: stored.id,
+                created_at: stored.created_at,
+            };
+            storage.set_read_marker(marker).await?;
+        }
+        t.tx.send(moved).ok();
+
+        Ok(())
+    }
+
+    fn quote_of(text: &str) -> String {
+        let line = text.lines().next().unwrap_or_default();
+        match line.char_indices().nth(MAX_QUOTE_CHARS) {
+            Some((end, _)) => format!("{}…", &line[..end]),
+            None => String::from(line),
+        }
+    }
+
+    /// replies can only point into their own conversation. threads are one level deep,
+    /// so replying to a thread reply posts into the same thread
+    async fn handle_resolve_reply(t: ReplyTransit, storage: Arc<dyn Storage>) -> Result<()> {
+        let mut msg = t.msg;
+
+        if let Some(reply) = &msg.reply_to {
+            match PersistenceTask::get_conversation_text(reply.id, t.user.id, &msg.to, &storage)
+                .await?
+            {
+                Some((parent, thread_id)) => {
+                    msg.reply_to = Some(ReplyRef {
+                        id: parent.id,
+                        quote: PersistenceTask::quote_of(&parent.text),
+                        from: parent.from,
+                    });
+                    msg.thread = msg.thread.or(thread_id);
+                }
+                None => {
+                    let reason =
+                        String::from("The message you replied to is not in this conversation.");
+                    t.tx.send(Err(reason)).ok();
+                    return Ok(());
+                }
+            }
+        }
+
+        if let Some(root_id) = msg.thread {
+            match PersistenceTask::get_conversation_text(root_id, t.user.id, &msg.to, &storage)
+                .await?
+            {
+                Some((root, thread_id)) => msg.thread = Some(thread_id.unwrap_or(root.id)),
+                None => {
+                    let reason = String::from("The thread is not in this conversation.");
+                    t.tx.send(Err(reason)).ok();
+                    return Ok(());
+                }
+            }
+        }
+
+        t.tx.send(Ok(msg)).ok();
 
         Ok(())
     }
 
-    async fn handle_user_left_room(
-        t: UserRoomData,
-        users_collection: Collection<DbUser>,
-        rooms_collection: Collection<DbRoom>,
+    async fn handle_get_thread(t: ThreadTransit, storage: Arc<dyn Storage>) -> Result<()> {
+        let root = match storage.get_message(t.id).await? {
+            Some(root) => root,
+            None => {
+                t.tx.send(Err(String::from("Thread does not exist."))).ok();
+                return Ok(());
+            }
+        };
+
+        let can_read = match &root.to {
+            Channel::Room(room_id) => match storage.get_room(*room_id).await? {
+                Some(room) => room.user_ids.contains(&t.user.id),
+                None => false,
+            },
+            Channel::User(to_id) => *to_id == t.user.id || root.from_id == t.user.id,
+        };
+
+        if !can_read {
+            warn!(
+                "User {} requested a thread of a conversation they are not part of",
+                t.user.username
+            );
+            t.tx.send(Err(String::from("Thread does not exist."))).ok();
+            return Ok(());
+        }
+
+        let replies = storage.get_thread(t.id).await?;
+        let page = ThreadPage {
+            id: t.id,
+            root: root.msg,
+            replies: replies.into_iter().map(|m| m.msg).collect(),
+        };
+
+        if let Err(err) = t.tx.send(Ok(page)) {
+            debug!("oneshot thread receiver dropped {err:?} {}", Bt::new());
+        };
+
+        Ok(())
+    }
+
+    /// authors can change their messages, moderators and above any text message of their room.
+    /// anyone in the conversation can react to text and images
+    async fn handle_change_message(
+        t: MsgChangeTransit,
+        storage: Arc<dyn Storage>,
+        message_lock: Arc<Mutex<()>>,
     ) -> Result<()> {
-        let user_bson_id = uuid_to_bson(t.user.id);
-        let room_bson_id = uuid_to_bson(t.room_id);
+        let _held = message_lock.lock().await;
+
+        let mut stored = match storage.get_message(t.id).await? {
+            Some(msg) => msg,
+            None => {
+                t.tx.send(Err(String::from("Message does not exist."))).ok();
+                return Ok(());
+            }
+        };
+
+        let role = match &stored.to {
+            Channel::Room(room_id) => match storage.get_room(*room_id).await? {
+                Some(room) if room.user_ids.contains(&t.user.id) => {
+                    let roles = PersistenceTask::get_room_roles(&room, &storage).await?;
+                    Some(roles.role_of(t.user.id))
+                }
+                _ => {
+                    let msg = String::from("You are not a member of this room.");
+                    t.tx.send(Err(msg)).ok();
+                    return Ok(());
+                }
+            },
+            Channel::User(to_id) if *to_id == t.user.id || stored.from_id == t.user.id => None,
+            Channel::User(_) => {
+                t.tx.send(Err(String::from("Message does not exist."))).ok();
+                return Ok(());
+            }
+        };
+
+        let is_reaction = matches!(
+            t.change,
+            MsgChange::AddReaction(_) | MsgChange::RemoveReaction(_)
+        );
+        let is_author = stored.from_id == t.user.id;
+        if !is_reaction && !is_author && role.is_none_or(|role| role < RoomRole::Moderator) {
+            let msg = String::from("You can only change your own messages.");
+            t.tx.send(Err(msg)).ok();
+            return Ok(());
+        }
+
+        let changed = match (t.change, &mut stored.msg) {
+            (_, ChannelMsg::Deleted(_)) => Err("Message was already deleted."),
+            (MsgChange::Edit(new_text), ChannelMsg::TextMsg(text)) => {
+                text.text = new_text;
+                text.edited = true;
+                Ok(MsgChanged::Edited(text.clone()))
+            }
+            (MsgChange::Delete, ChannelMsg::TextMsg(text)) => {
+                let deletion = MsgDeletion {
+                    id: text.id,
+                    from: text.from.clone(),
+                    to: text.to.clone(),
+                    by: t.user,
+                    thread: text.thread,
+                };
+                stored.msg = ChannelMsg::Deleted(deletion.clone());
+                Ok(MsgChanged::Deleted(deletion))
+            }
+            (MsgChange::Edit(_) | MsgChange::Delete, _) => {
+                Err("Only text messages can be changed.")
+            }
+            (change, msg) => PersistenceTask::change_reactions(change, t.user, msg),
+        };
+        let changed = match changed {
+            Ok(changed) => changed,
+            Err(refusal) => {
+                t.tx.send(Err(String::from(refusal))).ok();
+                return Ok(());
+            }
+        };
+
+        let thread_id = stored.thread_id;
+        storage.update_message(stored).await?;
+
+        if let (MsgChanged::Deleted(_), Some(root_id)) = (&changed, thread_id) {
+            PersistenceTask::update_thread_len(root_id, &storage).await?;
+        }
+        t.tx.send(Ok(changed)).ok();
+
+        Ok(())
+    }
+
+    /// text messages and images carry reactions
+    fn change_reactions(
+        change: MsgChange,
+        user: User,
+        msg: &mut ChannelMsg,
+    ) -> Result<MsgChanged, &'static str> {
+        let (id, from, to, reactions) = match msg {
+            ChannelMsg::TextMsg(text) => (text.id, &text.from, &text.to, &mut text.reactions),
+            ChannelMsg::Img(img) => (img.id, &img.from, &img.to, &mut img.reactions),
+            _ => return Err("Only text messages and images can be reacted to."),
+        };
+
+        match change {
+            MsgChange::AddReaction(emoji) => {
+                let emoji = emoji.trim();
+                if emoji.is_empty() || emoji.chars().count() > MAX_REACTION_CHARS {
+                    return Err("Reactions are a single emoji.");
+                }
+
+                match reactions.iter_mut().find(|r| r.emoji == emoji) {
+                    Some(reaction) if reaction.users.contains(&user) => {}
+                    Some(reaction) => reaction.users.push(user),
+                    None => reactions.push(Reaction {
+                        emoji: String::from(emoji),
+                        users: vec![user],
+                    }),
+                }
+            }
+            MsgChange::RemoveReaction(emoji) => {
+                let emoji = emoji.trim();
+                for reaction in reactions.iter_mut().filter(|r| r.emoji == emoji) {
+                    reaction.users.retain(|u| u.id != user.id);
+                }
+                reactions.retain(|r| !r.users.is_empty());
+            }
+            MsgChange::Edit(_) | MsgChange::Delete => {}
+        }
+
+        Ok(MsgChanged::Reactions(MsgReactions {
+            id,
+            from: from.clone(),
+            to: to.clone(),
+            reactions: reactions.clone(),
+        }))
+    }
+
+    async fn handle_get_history(t: HistoryTransit, storage: Arc<dyn Storage>) -> Result<()> {
+        if let Channel::Room(id) = &t.channel {
+            let is_member = match storage.get_room(*id).await? {
+                Some(room) => room.user_ids.contains(&t.user.id),
+                None => false,
+            };
+
+            if !is_member {
+                warn!(
+                    "User {} requested history of a room they are not a member of",
+                    t.user.username
+                );
+                let page = HistoryPage {
+                    channel: t.channel,
+                    messages: vec![],
+                    next_before: None,
+                };
+                t.tx.send(page).ok();
+                return Ok(());
+            }
+        }
+
+        let limit = t.limit.clamp(1, HISTORY_PAGE_LIMIT_MAX) as usize;
+
+        // messages can share a timestamp, so the cursor is resolved to (created_at, id).
+        // a cursor that is gone would otherwise serve the newest page again
+        let before = match t.before {
+            Some(id) => match storage.get_message(id).await? {
+                Some(m) => Some((m.created_at, m.id)),
+                None => {
+                    let page = HistoryPage {
+                        channel: t.channel,
+                        messages: vec![],
+                        next_before: None,
+                    };
+                    t.tx.send(page).ok();
+                    return Ok(());
+                }
+            },
+            None => None,
+        };
+
+        let mut messages = storage
+            .get_messages(t.user.id, &t.channel, before, limit + 1)
+            .await?;
+
+        let next_before = if messages.len() > limit {
+            messages.truncate(limit);
+            messages.last().map(|m| m.id)
+        } else {
+            None
+        };
+
+        let page = HistoryPage {
+            channel: t.channel,
+            messages: messages.into_iter().map(|m| m.msg).collect(),
+            next_before,
+        };
+
+        if let Err(err) = t.tx.send(page) {
+            debug!("oneshot history receiver dropped {err:?} {}", Bt::new());
+        };
+
+        Ok(())
+    }
+    async fn handle_get_files(t: FileListTransit, storage: Arc<dyn Storage>) -> Result<()> {
+        let mut list = FileList {
+            channel: t.channel,
+            files: vec![],
+        };
+
+        if let Channel::Room(id) = &list.channel {
+            let is_member = match storage.get_room(*id).await? {
+                Some(room) => room.user_ids.contains(&t.user.id),
+                None => false,
+            };
+
+            if !is_member {
+                warn!(
+                    "User {} requested files of a room they are not a member of",
+                    t.user.username
+                );
+                t.tx.send(list).ok();
+                return Ok(());
+            }
+        }
 
-        let filter = doc! { "id": room_bson_id.clone() };
-        let update = doc! { "$pull": { "user_ids": user_bson_id.clone() } };
+        let files = storage.get_files(t.user.id, &list.channel).await?;
+        list.files = files.into_iter().map(|f| f.entry).collect();
 
-        rooms_collection.find_one_and_update(filter, update).await?;
+        if let Err(err) = t.tx.send(list) {
+            debug!("oneshot files receiver dropped {err:?} {}", Bt::new());
+        };
+
+        Ok(())
+    }
 
-        let filter = doc! { "id": user_bson_id };
-        let update = doc! { "$pull": { "room_ids": room_bson_id } };
+    async fn handle_get_file_space(t: FileSpaceTransit, storage: Arc<dyn Storage>) -> Result<()> {
+        let used = storage.get_used_file_space(t.user_id).await?;
 
-        users_collection.find_one_and_update(filter, update).await?;
+        if let Err(err) = t.tx.send(used) {
+            debug!("oneshot file space receiver dropped {err:?} {}", Bt::new());
+        };
+
+        Ok(())
+    }
+
+    async fn handle_is_room_member(t: RoomMemberTransit, storage: Arc<dyn Storage>) -> Result<()> {
+        let is_member = match storage.get_room(t.room_id).await? {
+            Some(room) => room.user_ids.contains(&t.user_id),
+            None => false,
+        };
+
+        if let Err(err) = t.tx.send(is_member) {
+            debug!("oneshot room member receiver dropped {err:?} {}", Bt::new());
+        };
+
+        Ok(())
+    }
+
+    /// the same answer for files that do not exist and ones the user may not see
+    async fn handle_get_file(t: FileTransit, storage: Arc<dyn Storage>) -> Result<()> {
+        let file = match storage.get_file(t.id).await? {
+            Some(file) => file,
+            None => {
+                t.tx.send(Err(String::from("File does not exist."))).ok();
+                return Ok(());
+            }
+        };
+
+        let can_read = match &file.to {
+            Channel::Room(room_id) => match storage.get_room(*room_id).await? {
+                Some(room) => room.user_ids.contains(&t.user.id),
+                None => false,
+            },
+            Channel::User(to_id) => *to_id == t.user.id || file.from_id == t.user.id,
+        };
+
+        if !can_read {
+            warn!(
+                "User {} requested a file of a conversation they are not part of",
+                t.user.username
+            );
+            t.tx.send(Err(String::from("File does not exist."))).ok();
+            return Ok(());
+        }
+
+        if let Err(err) = t.tx.send(Ok(file)) {
+            debug!("oneshot file receiver dropped {err:?} {}", Bt::new());
+        };
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests;
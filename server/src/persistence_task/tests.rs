@@ -0,0 +1,144 @@
+use super::PersistenceTask;
+use crate::{
+    storage::{
+        storage_backend::Storage,
+        tests::{backends, room, user},
+    },
+    util::types::server_data_types::{
+        LeaveRoomTransit, ModerationPersistenceRes, ModerationTransit, StoredUser,
+    },
+};
+use shared::types::{ModerationAction, RoomMember, RoomRemovalRes, User};
+use std::sync::Arc;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+fn as_user(stored: &StoredUser) -> User {
+    User {
+        username: stored.username.clone(),
+        id: stored.id,
+    }
+}
+
+async fn leave(storage: &Arc<dyn Storage>, who: &StoredUser, room_id: Uuid) -> RoomRemovalRes {
+    let (tx, rx) = oneshot::channel();
+    let transit = LeaveRoomTransit {
+        tx,
+        user: as_user(who),
+        room_id,
+    };
+    PersistenceTask::handle_user_left_room(transit, storage.clone())
+        .await
+        .unwrap();
+    rx.await.unwrap()
+}
+
+async fn moderate(
+    storage: &Arc<dyn Storage>,
+    who: &StoredUser,
+    target: &StoredUser,
+    room_id: Uuid,
+    action: ModerationAction,
+) -> ModerationPersistenceRes {
+    let (tx, rx) = oneshot::channel();
+    let transit = ModerationTransit {
+        tx,
+        user: as_user(who),
+        target: RoomMember {
+            room_id,
+            user_id: target.id,
+        },
+        action,
+    };
+    PersistenceTask::handle_moderate_room(transit, storage.clone())
+        .await
+        .unwrap();
+    rx.await.unwrap()
+}
+
+#[tokio::test]
+async fn the_owner_can_not_leave_their_room() {
+    for (name, storage) in backends().await {
+        let (alice, bob) = (user("alice"), user("bob"));
+        let chat = room("chat", alice.id);
+        storage.insert_room(chat.clone()).await.unwrap();
+        for member in [&alice, &bob] {
+            storage.insert_user(member.clone()).await.unwrap();
+            storage.add_room_member(chat.id, member.id).await.unwrap();
+        }
+
+        let refused = leave(&storage, &alice, chat.id).await;
+        assert!(refused.is_err(), "{name}: the owner left");
+        let stored = storage.get_room(chat.id).await.unwrap().unwrap();
+        assert_eq!(stored.owner_id, Some(alice.id), "{name}");
+        assert_eq!(stored.user_ids, vec![alice.id, bob.id], "{name}");
+
+        let left = leave(&storage, &bob, chat.id).await;
+        assert_eq!(left, Ok(chat.id), "{name}");
+        let stored = storage.get_room(chat.id).await.unwrap().unwrap();
+        assert_eq!(stored.user_ids, vec![alice.id], "{name}");
+    }
+}
+
+#[tokio::test]
+async fn moderators_only_act_on_lower_roles() {
+    use ModerationAction::{Ban, Kick, Promote};
+    // actor, target and action by index into owner, admin, moderator, member, other member
+    let cases = [
+        (0, 1, Kick, true),
+        (0, 3, Ban, true),
+        (0, 2, Promote, true),
+        (1, 2, Kick, true),
+        (1, 3, Promote, true),
+        (1, 0, Kick, false),
+        (1, 0, Ban, false),
+        (1, 2, Promote, false),
+        (2, 3, Kick, true),
+        (2, 1, Kick, false),
+        (2, 0, Ban, false),
+        (2, 3, Promote, false),
+        (3, 4, Kick, false),
+        (3, 2, Ban, false),
+        (3, 0, Kick, false),
+        (3, 4, Promote, false),
+    ];
+
+    for (name, storage) in backends().await {
+        for (i, (actor, target, action, allowed)) in cases.into_iter().enumerate() {
+            let users: Vec<StoredUser> = ["owner", "admin", "moderator", "member", "other"]
+                .iter()
+                .map(|role| user(&format!("{role}{i}")))
+                .collect();
+            let mut chat = room(&format!("chat{i}"), users[0].id);
+            storage.insert_room(chat.clone()).await.unwrap();
+            for member in &users {
+                storage.insert_user(member.clone()).await.unwrap();
+                storage.add_room_member(chat.id, member.id).await.unwrap();
+            }
+            chat.admin_ids = vec![users[1].id];
+            chat.moderator_ids = vec![users[2].id];
+            storage.update_room_roles(chat.clone()).await.unwrap();
+
+            let case = format!("{name}: {action:?} of {target} by {actor}");
+            let res = moderate(&storage, &users[actor], &users[target], chat.id, action).await;
+            let stored = storage.get_room(chat.id).await.unwrap().unwrap();
+            let target = users[target].id;
+            match res {
+                Ok((notice, user_ids)) => {
+                    assert!(allowed, "{case} was allowed");
+                    assert_eq!(notice.target.id, target, "{case}");
+                    assert_eq!(user_ids, stored.user_ids, "{case}");
+                    let removed = action != Promote;
+                    assert_eq!(!stored.user_ids.contains(&target), removed, "{case}");
+                }
+                Err(_) => {
+                    assert!(!allowed, "{case} was refused");
+                    assert_eq!(stored.user_ids.len(), users.len(), "{case}");
+                    assert_eq!(stored.admin_ids, vec![users[1].id], "{case}");
+                    assert_eq!(stored.moderator_ids, vec![users[2].id], "{case}");
+                    assert!(stored.banned_ids.is_empty(), "{case}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use crate::util::config::{
+    DEFAULT_FILE_QUOTA, DEFAULT_FILE_STORE_DIR, FILE_QUOTA_ENV, FILE_STORE_DIR_ENV,
+};
+use anyhow::{Result, anyhow, bail};
+use sha2::{Digest, Sha256};
+use std::{env::var, path::PathBuf};
+use tokio::{fs, io::AsyncWriteExt};
+use uuid::Uuid;
+
+/// Uploaded files on local disk, named by the hex SHA-256 of their content so a file
+/// uploaded several times is kept once. Records of who sent what live in the storage.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+    /// bytes each user may keep stored
+    pub quota: u64,
+}
+
+impl FileStore {
+    /// reads `FILE_STORE_DIR` and `FILE_QUOTA_BYTES`, unset ones fall back to the defaults
+    pub fn from_env() -> Self {
+        let quota = var(FILE_QUOTA_ENV)
+            .ok()
+            .and_then(|quota| quota.parse().ok())
+            .unwrap_or(DEFAULT_FILE_QUOTA);
+
+        Self {
+            dir: PathBuf::from(
+                var(FILE_STORE_DIR_ENV).unwrap_or(String::from(DEFAULT_FILE_STORE_DIR)),
+            ),
+            quota,
+        }
+    }
+
+    /// `None` for anything that is not a hash, so no path outside the store can be named
+    fn blob_path(&self, sha256: &str) -> Option<PathBuf> {
+        let is_hash = sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit());
+        is_hash.then(|| self.dir.join(sha256.to_lowercase()))
+    }
+
+    /// bytes go to a temp file until `Upload::finish` checked them
+    pub async fn start_upload(&self, size: u64, sha256: &str) -> Result<Upload> {
+        let path = self
+            .blob_path(sha256)
+            .ok_or(anyhow!("{sha256} is not a SHA-256"))?;
+        fs::create_dir_all(&self.dir).await?;
+        let temp_path = self.dir.join(format!(".{}.part", Uuid::new_v4()));
+        let file = fs::File::create(&temp_path).await?;
+
+        Ok(Upload {
+            file,
+            temp_path,
+            path,
+            hasher: Sha256::new(),
+            written: 0,
+            size,
+            finished: false,
+        })
+    }
+
+    pub async fn open(&self, sha256: &str) -> Result<fs::File> {
+        let path = self
+            .blob_path(sha256)
+            .ok_or(anyhow!("{sha256} is not a SHA-256"))?;
+        Ok(fs::File::open(path).await?)
+    }
+}
+
+/// file being uploaded, its temp file is removed when it is dropped unfinished
+#[derive(Debug)]
+pub struct Upload {
+    file: fs::File,
+    temp_path: PathBuf,
+    path: PathBuf,
+    hasher: Sha256,
+    written: u64,
+    /// announced size, nothing past it is written
+    pub size: u64,
+    finished: bool,
+}
+
+impl Upload {
+    pub async fn write(&mut self, data: &[u8]) -> Result<()> {
+        if self.written + data.len() as u64 > self.size {
+            bail!("larger than the announced {} bytes", self.size);
+        }
+        self.file.write_all(data).await?;
+        self.hasher.update(data);
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    /// moves the file in place once it has the announced size and hash
+    pub async fn finish(mut self) -> Result<()> {
+        if self.written != self.size {
+            bail!(
+                "incomplete, {} of {} bytes arrived",
+                self.written,
+                self.size
+            );
+        }
+        let sha256 = format!("{:x}", self.hasher.clone().finalize());
+        if Some(sha256.as_str()) != self.path.file_name().and_then(|name| name.to_str()) {
+            bail!("checksum mismatch");
+        }
+
+        self.file.flush().await?;
+        fs::rename(&self.temp_path, &self.path).await?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        if !self.finished {
+            std::fs::remove_file(&self.temp_path).ok();
+        }
+    }
+}
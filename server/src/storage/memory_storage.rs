@@ -0,0 +1,378 @@
+use super::storage_backend::Storage;
+use crate::util::types::server_data_types::{
+    PendingDelivery, StoredFile, StoredMessage, StoredPresence, StoredReadMarker, StoredRoom,
+    StoredUser,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use shared::types::Channel;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+#[derive(Default)]
+struct MemoryState {
+    users: HashMap<Uuid, StoredUser>,
+    rooms: HashMap<Uuid, StoredRoom>,
+    messages: Vec<StoredMessage>,
+    deliveries: Vec<PendingDelivery>,
+    read_markers: Vec<StoredReadMarker>,
+    presence: HashMap<Uuid, StoredPresence>,
+    files: Vec<StoredFile>,
+}
+
+/// Keeps everything in process memory, data is lost on restart.
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn get_user_by_name(&self, username: &str) -> Result<Option<StoredUser>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .users
+            .values()
+            .find(|u| u.username == username)
+            .cloned())
+    }
+
+    async fn get_users(&self, ids: &[Uuid]) -> Result<Vec<StoredUser>> {
+        let state = self.state.lock().unwrap();
+        Ok(ids
+            .iter()
+            .filter_map(|id| state.users.get(id))
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_user(&self, user: StoredUser) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.users.insert(user.id, user);
+        Ok(())
+    }
+
+    async fn get_room(&self, id: Uuid) -> Result<Option<StoredRoom>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.rooms.get(&id).cloned())
+    }
+
+    async fn get_room_by_name(&self, name: &str) -> Result<Option<StoredRoom>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.rooms.values().find(|r| r.name == name).cloned())
+    }
+
+    async fn get_rooms(&self, ids: &[Uuid]) -> Result<Vec<StoredRoom>> {
+        let state = self.state.lock().unwrap();
+        Ok(ids
+            .iter()
+            .filter_map(|id| state.rooms.get(id))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_all_rooms(&self) -> Result<Vec<StoredRoom>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.rooms.values().cloned().collect())
+    }
+
+    async fn insert_room(&self, room: StoredRoom) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.rooms.insert(room.id, room);
+        Ok(())
+    }
+
+    async fn delete_room(&self, id: Uuid) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        state.rooms.remove(&id);
+        for user in state.users.values_mut() {
+            user.room_ids.retain(|room_id| *room_id != id);
+        }
+        state.messages.retain(|m| m.to != Channel::Room(id));
+        state.files.retain(|f| f.to != Channel::Room(id));
+
+        Ok(())
+    }
+
+    async fn update_room_pwd(&self, id: Uuid, pwd: Option<String>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(room) = state.rooms.get_mut(&id) {
+            room.pwd = pwd;
+        }
+
+        Ok(())
+    }
+
+    async fn update_room_roles(&self, room: StoredRoom) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(stored) = state.rooms.get_mut(&room.id) {
+            stored.owner_id = room.owner_id;
+            stored.admin_ids = room.admin_ids;
+            stored.moderator_ids = room.moderator_ids;
+            stored.banned_ids = room.banned_ids;
+        }
+
+        Ok(())
+    }
+
+    async fn add_room_member(&self, room_id: Uuid, user_id: Uuid) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(room) = state.rooms.get_mut(&room_id)
+            && !room.user_ids.contains(&user_id)
+        {
+            room.user_ids.push(user_id);
+        }
+
+        if let Some(user) = state.users.get_mut(&user_id)
+            && !user.room_ids.contains(&room_id)
+        {
+            user.room_ids.push(room_id);
+        }
+
+        Ok(())
+    }
+
+    async fn remove_room_member(&self, room_id: Uuid, user_id: Uuid) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(room) = state.rooms.get_mut(&room_id) {
+            room.user_ids.retain(|id| *id != user_id);
+        }
+
+        if let Some(user) = state.users.get_mut(&user_id) {
+            user.room_ids.retain(|id| *id != room_id);
+        }
+
+        Ok(())
+    }
+
+    async fn insert_message(&self, msg: StoredMessage) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.messages.push(msg);
+        Ok(())
+    }
+
+    async fn get_message(&self, id: Uuid) -> Result<Option<StoredMessage>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.messages.iter().find(|m| m.id == id).cloned())
+    }
+
+    async fn update_message(&self, msg: StoredMessage) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(stored) = state.messages.iter_mut().find(|m| m.id == msg.id) {
+            stored.msg = msg.msg;
+        }
+
+        Ok(())
+    }
+
+    async fn get_messages(
+        &self,
+        user_id: Uuid,
+        channel: &Channel,
+        before: Option<(i64, Uuid)>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>> {
+        let state = self.state.lock().unwrap();
+
+        let mut messages: Vec<StoredMessage> = state
+            .messages
+            .iter()
+            .filter(|m| match (channel, &m.to) {
+                (Channel::Room(id), Channel::Room(to)) => id == to,
+                (Channel::User(id), Channel::User(to)) => {
+                    (m.from_id == user_id && to == id) || (m.from_id == *id && *to == user_id)
+                }
+                _ => false,
+            })
+            .filter(|m| m.thread_id.is_none())
+            .filter(|m| before.is_none_or(|before| (m.created_at, m.id) < before))
+            .cloned()
+            .collect();
+
+        messages.sort_by_key(|m| std::cmp::Reverse((m.created_at, m.id)));
+        messages.truncate(limit);
+
+        Ok(messages)
+    }
+
+    async fn get_thread(&self, root_id: Uuid) -> Result<Vec<StoredMessage>> {
+        let state = self.state.lock().unwrap();
+
+        let mut messages: Vec<StoredMessage> = state
+            .messages
+            .iter()
+            .filter(|m| m.thread_id == Some(root_id))
+            .cloned()
+            .collect();
+
+        messages.sort_by_key(|m| (m.created_at, m.id));
+
+        Ok(messages)
+    }
+
+    async fn queue_delivery(&self, delivery: PendingDelivery) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.deliveries.push(delivery);
+        Ok(())
+    }
+
+    async fn get_deliveries(&self, to_id: Uuid) -> Result<Vec<PendingDelivery>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .deliveries
+            .iter()
+            .filter(|d| d.to_id == to_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn remove_deliveries(&self, to_id: Uuid, msg_ids: &[Uuid]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .deliveries
+            .retain(|d| d.to_id != to_id || !msg_ids.contains(&d.msg_id));
+        Ok(())
+    }
+
+    async fn get_undelivered(&self, from_id: Uuid) -> Result<Vec<Uuid>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .deliveries
+            .iter()
+            .filter(|d| d.from_id == from_id)
+            .map(|d| d.msg_id)
+            .collect())
+    }
+
+    async fn set_read_marker(&self, marker: StoredReadMarker) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .read_markers
+            .retain(|m| m.user_id != marker.user_id || m.channel != marker.channel);
+        state.read_markers.push(marker);
+        Ok(())
+    }
+
+    async fn get_read_markers(&self, user_id: Uuid) -> Result<Vec<StoredReadMarker>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .read_markers
+            .iter()
+            .filter(|m| m.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_direct_read_markers(&self, user_id: Uuid) -> Result<Vec<StoredReadMarker>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .read_markers
+            .iter()
+            .filter(|m| m.channel == Channel::User(user_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn count_unread_many(
+        &self,
+        user_id: Uuid,
+        channels: &[(Channel, Option<(i64, Uuid)>)],
+        limit: usize,
+    ) -> Result<Vec<usize>> {
+        let state = self.state.lock().unwrap();
+
+        let counts = channels
+            .iter()
+            .map(|(channel, after)| {
+                state
+                    .messages
+                    .iter()
+                    .filter(|m| match (channel, &m.to) {
+                        (Channel::Room(id), Channel::Room(to)) => id == to && m.from_id != user_id,
+                        (Channel::User(id), Channel::User(to)) => {
+                            m.from_id == *id && *to == user_id
+                        }
+                        _ => false,
+                    })
+                    .filter(|m| m.thread_id.is_none())
+                    .filter(|m| after.is_none_or(|after| (m.created_at, m.id) > after))
+                    .take(limit)
+                    .count()
+            })
+            .collect();
+
+        Ok(counts)
+    }
+
+    async fn set_presence(&self, presence: StoredPresence) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.presence.insert(presence.user_id, presence);
+        Ok(())
+    }
+
+    async fn get_presence(&self, user_ids: &[Uuid]) -> Result<Vec<StoredPresence>> {
+        let state = self.state.lock().unwrap();
+        Ok(user_ids
+            .iter()
+            .filter_map(|id| state.presence.get(id))
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_file(&self, file: StoredFile) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.files.push(file);
+        Ok(())
+    }
+
+    async fn get_file(&self, id: Uuid) -> Result<Option<StoredFile>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.files.iter().find(|f| f.id == id).cloned())
+    }
+
+    async fn get_files(&self, user_id: Uuid, channel: &Channel) -> Result<Vec<StoredFile>> {
+        let state = self.state.lock().unwrap();
+
+        let mut files: Vec<StoredFile> = state
+            .files
+            .iter()
+            .filter(|f| match (channel, &f.to) {
+                (Channel::Room(id), Channel::Room(to)) => id == to,
+                (Channel::User(id), Channel::User(to)) => {
+                    (f.from_id == user_id && to == id) || (f.from_id == *id && *to == user_id)
+                }
+                _ => false,
+            })
+            .cloned()
+            .collect();
+
+        files.sort_by_key(|f| std::cmp::Reverse((f.created_at, f.id)));
+
+        Ok(files)
+    }
+
+    async fn get_used_file_space(&self, user_id: Uuid) -> Result<u64> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .files
+            .iter()
+            .filter(|f| f.from_id == user_id)
+            .map(|f| f.size)
+            .sum())
+    }
+}
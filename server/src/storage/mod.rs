@@ -0,0 +1,8 @@
+pub mod file_store;
+pub mod memory_storage;
+pub mod mongo_storage;
+pub mod sqlite_storage;
+pub mod storage_backend;
+
+#[cfg(test)]
+pub(crate) mod tests;
@@ -0,0 +1,770 @@
+use super::storage_backend::Storage;
+use crate::util::{
+    config::{
+        DB_DELIVERIES, DB_FILES, DB_MESSAGES, DB_PRESENCE, DB_READ_MARKERS, DB_ROOMS, DB_USERS,
+    },
+    server_functions::{bson_to_uuid, uuid_to_bson},
+    types::server_data_types::{
+        DbDelivery, DbFile, DbMessage, DbPresence, DbReadMarker, DbRoom, DbUser, PendingDelivery,
+        StoredFile, StoredMessage, StoredPresence, StoredReadMarker, StoredRoom, StoredUser,
+    },
+};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use futures::StreamExt;
+use mongodb::{
+    Client, Collection,
+    bson::{Bson, DateTime, Document, doc, to_bson},
+    options::ClientOptions,
+};
+use shared::types::Channel;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(Clone)]
+pub struct MongoStorage {
+    users_collection: Collection<DbUser>,
+    rooms_collection: Collection<DbRoom>,
+    messages_collection: Collection<DbMessage>,
+    deliveries_collection: Collection<DbDelivery>,
+    read_markers_collection: Collection<DbReadMarker>,
+    presence_collection: Collection<DbPresence>,
+    files_collection: Collection<DbFile>,
+}
+
+impl MongoStorage {
+    pub async fn connect(mongo_addr: &str) -> Result<Self> {
+        let options = ClientOptions::parse(mongo_addr).await?;
+
+        let client = Client::with_options(options)?;
+        let db = client.database("chatapp");
+
+        let storage = Self {
+            users_collection: db.collection::<DbUser>(DB_USERS),
+            rooms_collection: db.collection::<DbRoom>(DB_ROOMS),
+            messages_collection: db.collection::<DbMessage>(DB_MESSAGES),
+            deliveries_collection: db.collection::<DbDelivery>(DB_DELIVERIES),
+            read_markers_collection: db.collection::<DbReadMarker>(DB_READ_MARKERS),
+            presence_collection: db.collection::<DbPresence>(DB_PRESENCE),
+            files_collection: db.collection::<DbFile>(DB_FILES),
+        };
+
+        // client connects lazily, fail here instead of on first request
+        storage.rooms_collection.find_one(doc! {}).await?;
+
+        Ok(storage)
+    }
+}
+
+fn bson_ids_to_uuids(ids: &[Bson]) -> Result<Vec<Uuid>> {
+    ids.iter()
+        .map(|id| bson_to_uuid(id).ok_or(anyhow!("expected uuid value")))
+        .collect()
+}
+
+impl TryFrom<DbUser> for StoredUser {
+    type Error = anyhow::Error;
+
+    fn try_from(user: DbUser) -> Result<Self> {
+        Ok(Self {
+            id: bson_to_uuid(&user.id).ok_or(anyhow!("expected uuid value"))?,
+            username: user.username,
+            pwd: user.pwd,
+            room_ids: bson_ids_to_uuids(&user.room_ids)?,
+        })
+    }
+}
+
+impl From<StoredUser> for DbUser {
+    fn from(user: StoredUser) -> Self {
+        Self {
+            id: uuid_to_bson(user.id),
+            username: user.username,
+            pwd: user.pwd,
+            room_ids: user.room_ids.into_iter().map(uuid_to_bson).collect(),
+        }
+    }
+}
+
+impl TryFrom<DbRoom> for StoredRoom {
+    type Error = anyhow::Error;
+
+    fn try_from(room: DbRoom) -> Result<Self> {
+        Ok(Self {
+            id: bson_to_uuid(&room.id).ok_or(anyhow!("expected uuid value"))?,
+            name: room.name,
+            user_ids: bson_ids_to_uuids(&room.user_ids)?,
+            pwd: room.pwd,
+            owner_id: room.owner_id.as_ref().and_then(bson_to_uuid),
+            admin_ids: bson_ids_to_uuids(&room.admin_ids)?,
+            moderator_ids: bson_ids_to_uuids(&room.moderator_ids)?,
+            banned_ids: bson_ids_to_uuids(&room.banned_ids)?,
+        })
+    }
+}
+
+impl From<StoredRoom> for DbRoom {
+    fn from(room: StoredRoom) -> Self {
+        Self {
+            id: uuid_to_bson(room.id),
+            name: room.name,
+            user_ids: room.user_ids.into_iter().map(uuid_to_bson).collect(),
+            pwd: room.pwd,
+            owner_id: room.owner_id.map(uuid_to_bson),
+            admin_ids: room.admin_ids.into_iter().map(uuid_to_bson).collect(),
+            moderator_ids: room.moderator_ids.into_iter().map(uuid_to_bson).collect(),
+            banned_ids: room.banned_ids.into_iter().map(uuid_to_bson).collect(),
+        }
+    }
+}
+
+impl TryFrom<DbMessage> for StoredMessage {
+    type Error = anyhow::Error;
+
+    fn try_from(msg: DbMessage) -> Result<Self> {
+        let to = match (&msg.room_id, &msg.to_user_id) {
+            (Some(id), _) => Channel::Room(bson_to_uuid(id).ok_or(anyhow!("expected uuid value"))?),
+            (None, Some(id)) => {
+                Channel::User(bson_to_uuid(id).ok_or(anyhow!("expected uuid value"))?)
+            }
+            (None, None) => return Err(anyhow!("message without recipient")),
+        };
+
+        Ok(Self {
+            id: bson_to_uuid(&msg.id).ok_or(anyhow!("expected uuid value"))?,
+            from_id: bson_to_uuid(&msg.from_id).ok_or(anyhow!("expected uuid value"))?,
+            to,
+            created_at: msg.created_at.timestamp_millis(),
+            thread_id: match &msg.thread_id {
+                Some(id) => Some(bson_to_uuid(id).ok_or(anyhow!("expected uuid value"))?),
+                None => None,
+            },
+            msg: msg.msg,
+        })
+    }
+}
+
+impl From<StoredMessage> for DbMessage {
+    fn from(msg: StoredMessage) -> Self {
+        let (room_id, to_user_id) = match msg.to {
+            Channel::Room(id) => (Some(uuid_to_bson(id)), None),
+            Channel::User(id) => (None, Some(uuid_to_bson(id))),
+        };
+
+        Self {
+            id: uuid_to_bson(msg.id),
+            from_id: uuid_to_bson(msg.from_id),
+            room_id,
+            to_user_id,
+            created_at: DateTime::from_millis(msg.created_at),
+            thread_id: msg.thread_id.map(uuid_to_bson),
+            msg: msg.msg,
+        }
+    }
+}
+
+impl TryFrom<DbDelivery> for PendingDelivery {
+    type Error = anyhow::Error;
+
+    fn try_from(delivery: DbDelivery) -> Result<Self> {
+        Ok(Self {
+            msg_id: bson_to_uuid(&delivery.msg_id).ok_or(anyhow!("expected uuid value"))?,
+            from_id: bson_to_uuid(&delivery.from_id).ok_or(anyhow!("expected uuid value"))?,
+            to_id: bson_to_uuid(&delivery.to_id).ok_or(anyhow!("expected uuid value"))?,
+        })
+    }
+}
+
+impl From<PendingDelivery> for DbDelivery {
+    fn from(delivery: PendingDelivery) -> Self {
+        Self {
+            msg_id: uuid_to_bson(delivery.msg_id),
+            from_id: uuid_to_bson(delivery.from_id),
+            to_id: uuid_to_bson(delivery.to_id),
+        }
+    }
+}
+
+impl TryFrom<DbReadMarker> for StoredReadMarker {
+    type Error = anyhow::Error;
+
+    fn try_from(marker: DbReadMarker) -> Result<Self> {
+        let channel = match (&marker.room_id, &marker.to_user_id) {
+            (Some(id), _) => Channel::Room(bson_to_uuid(id).ok_or(anyhow!("expected uuid value"))?),
+            (None, Some(id)) => {
+                Channel::User(bson_to_uuid(id).ok_or(anyhow!("expected uuid value"))?)
+            }
+            (None, None) => return Err(anyhow!("read marker without channel")),
+        };
+
+        Ok(Self {
+            user_id: bson_to_uuid(&marker.user_id).ok_or(anyhow!("expected uuid value"))?,
+            channel,
+            msg_id: bson_to_uuid(&marker.msg_id).ok_or(anyhow!("expected uuid value"))?,
+            created_at: marker.created_at.timestamp_millis(),
+        })
+    }
+}
+
+impl From<StoredReadMarker> for DbReadMarker {
+    fn from(marker: StoredReadMarker) -> Self {
+        let (room_id, to_user_id) = match marker.channel {
+            Channel::Room(id) => (Some(uuid_to_bson(id)), None),
+            Channel::User(id) => (None, Some(uuid_to_bson(id))),
+        };
+
+        Self {
+            user_id: uuid_to_bson(marker.user_id),
+            room_id,
+            to_user_id,
+            msg_id: uuid_to_bson(marker.msg_id),
+            created_at: DateTime::from_millis(marker.created_at),
+        }
+    }
+}
+
+impl TryFrom<DbPresence> for StoredPresence {
+    type Error = anyhow::Error;
+
+    fn try_from(presence: DbPresence) -> Result<Self> {
+        Ok(Self {
+            user_id: bson_to_uuid(&presence.user_id).ok_or(anyhow!("expected uuid value"))?,
+            status: presence.status,
+            text: presence.text,
+            last_seen: presence.last_seen.map(|at| at.timestamp_millis()),
+        })
+    }
+}
+
+impl From<StoredPresence> for DbPresence {
+    fn from(presence: StoredPresence) -> Self {
+        Self {
+            user_id: uuid_to_bson(presence.user_id),
+            status: presence.status,
+            text: presence.text,
+            last_seen: presence.last_seen.map(DateTime::from_millis),
+        }
+    }
+}
+
+impl TryFrom<DbFile> for StoredFile {
+    type Error = anyhow::Error;
+
+    fn try_from(file: DbFile) -> Result<Self> {
+        let to = match (&file.room_id, &file.to_user_id) {
+            (Some(id), _) => Channel::Room(bson_to_uuid(id).ok_or(anyhow!("expected uuid value"))?),
+            (None, Some(id)) => {
+                Channel::User(bson_to_uuid(id).ok_or(anyhow!("expected uuid value"))?)
+            }
+            (None, None) => return Err(anyhow!("file without recipient")),
+        };
+
+        Ok(Self {
+            id: bson_to_uuid(&file.id).ok_or(anyhow!("expected uuid value"))?,
+            from_id: bson_to_uuid(&file.from_id).ok_or(anyhow!("expected uuid value"))?,
+            to,
+            size: file.size as u64,
+            created_at: file.created_at.timestamp_millis(),
+            entry: file.entry,
+        })
+    }
+}
+
+impl From<StoredFile> for DbFile {
+    fn from(file: StoredFile) -> Self {
+        let (room_id, to_user_id) = match file.to {
+            Channel::Room(id) => (Some(uuid_to_bson(id)), None),
+            Channel::User(id) => (None, Some(uuid_to_bson(id))),
+        };
+
+        Self {
+            id: uuid_to_bson(file.id),
+            from_id: uuid_to_bson(file.from_id),
+            room_id,
+            to_user_id,
+            size: file.size as i64,
+            created_at: DateTime::from_millis(file.created_at),
+            entry: file.entry,
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for MongoStorage {
+    async fn get_user_by_name(&self, username: &str) -> Result<Option<StoredUser>> {
+        let filter = doc! { "username": username };
+        match self.users_collection.find_one(filter).await? {
+            Some(user) => Ok(Some(user.try_into()?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_users(&self, ids: &[Uuid]) -> Result<Vec<StoredUser>> {
+        let ids: Vec<Bson> = ids.iter().copied().map(uuid_to_bson).collect();
+        let mut cursor = self
+            .users_collection
+            .find(doc! { "id": { "$in": ids } })
+            .await?;
+
+        let mut users = vec![];
+        while let Some(user) = cursor.next().await {
+            users.push(user?.try_into()?);
+        }
+
+        Ok(users)
+    }
+
+    async fn insert_user(&self, user: StoredUser) -> Result<()> {
+        self.users_collection.insert_one(DbUser::from(user)).await?;
+        Ok(())
+    }
+
+    async fn get_room(&self, id: Uuid) -> Result<Option<StoredRoom>> {
+        let filter = doc! { "id": uuid_to_bson(id) };
+        match self.rooms_collection.find_one(filter).await? {
+            Some(room) => Ok(Some(room.try_into()?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_room_by_name(&self, name: &str) -> Result<Option<StoredRoom>> {
+        let filter = doc! { "name": name };
+        match self.rooms_collection.find_one(filter).await? {
+            Some(room) => Ok(Some(room.try_into()?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_rooms(&self, ids: &[Uuid]) -> Result<Vec<StoredRoom>> {
+        let ids: Vec<Bson> = ids.iter().copied().map(uuid_to_bson).collect();
+        let mut cursor = self
+            .rooms_collection
+            .find(doc! { "id": { "$in": ids } })
+            .await?;
+
+        let mut rooms = vec![];
+        while let Some(room) = cursor.next().await {
+            rooms.push(room?.try_into()?);
+        }
+
+        Ok(rooms)
+    }
+
+    async fn get_all_rooms(&self) -> Result<Vec<StoredRoom>> {
+        let mut cursor = self.rooms_collection.find(doc! {}).await?;
+
+        let mut rooms = vec![];
+        while let Some(room) = cursor.next().await {
+            rooms.push(room?.try_into()?);
+        }
+
+        Ok(rooms)
+    }
+
+    async fn insert_room(&self, room: StoredRoom) -> Result<()> {
+        self.rooms_collection.insert_one(DbRoom::from(room)).await?;
+        Ok(())
+    }
+
+    async fn delete_room(&self, id: Uuid) -> Result<()> {
+        let room_bson_id = uuid_to_bson(id);
+
+        let filter = doc! { "room_ids": room_bson_id.clone() };
+        let update = doc! { "$pull": { "room_ids": room_bson_id.clone() } };
+        self.users_collection.update_many(filter, update).await?;
+
+        self.rooms_collection
+            .delete_one(doc! { "id": room_bson_id.clone() })
+            .await?;
+        self.messages_collection
+            .delete_many(doc! { "room_id": room_bson_id.clone() })
+            .await?;
+        self.files_collection
+            .delete_many(doc! { "room_id": room_bson_id })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update_room_pwd(&self, id: Uuid, pwd: Option<String>) -> Result<()> {
+        let filter = doc! { "id": uuid_to_bson(id) };
+        let update = doc! { "$set": { "pwd": pwd } };
+        self.rooms_collection.update_one(filter, update).await?;
+
+        Ok(())
+    }
+
+    async fn update_room_roles(&self, room: StoredRoom) -> Result<()> {
+        let room = DbRoom::from(room);
+
+        let filter = doc! { "id": room.id };
+        let update = doc! { "$set": {
+            "owner_id": room.owner_id,
+            "admin_ids": room.admin_ids,
+            "moderator_ids": room.moderator_ids,
+            "banned_ids": room.banned_ids,
+        } };
+        self.rooms_collection.update_one(filter, update).await?;
+
+        Ok(())
+    }
+
+    async fn add_room_member(&self, room_id: Uuid, user_id: Uuid) -> Result<()> {
+        let user_bson_id = uuid_to_bson(user_id);
+        let room_bson_id = uuid_to_bson(room_id);
+
+        let filter = doc! { "id": room_bson_id.clone() };
+        let update = doc! { "$addToSet": { "user_ids": user_bson_id.clone() } };
+        self.rooms_collection.update_one(filter, update).await?;
+
+        let filter = doc! { "id": user_bson_id };
+        let update = doc! { "$addToSet": { "room_ids": room_bson_id } };
+        self.users_collection.update_one(filter, update).await?;
+
+        Ok(())
+    }
+
+    async fn remove_room_member(&self, room_id: Uuid, user_id: Uuid) -> Result<()> {
+        let user_bson_id = uuid_to_bson(user_id);
+        let room_bson_id = uuid_to_bson(room_id);
+
+        let filter = doc! { "id": room_bson_id.clone() };
+        let update = doc! { "$pull": { "user_ids": user_bson_id.clone() } };
+        self.rooms_collection.update_one(filter, update).await?;
+
+        let filter = doc! { "id": user_bson_id };
+        let update = doc! { "$pull": { "room_ids": room_bson_id } };
+        self.users_collection.update_one(filter, update).await?;
+
+        Ok(())
+    }
+
+    async fn insert_message(&self, msg: StoredMessage) -> Result<()> {
+        self.messages_collection
+            .insert_one(DbMessage::from(msg))
+            .await?;
+        Ok(())
+    }
+
+    async fn get_message(&self, id: Uuid) -> Result<Option<StoredMessage>> {
+        let filter = doc! { "id": uuid_to_bson(id) };
+        match self.messages_collection.find_one(filter).await? {
+            Some(msg) => Ok(Some(msg.try_into()?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn update_message(&self, msg: StoredMessage) -> Result<()> {
+        let filter = doc! { "id": uuid_to_bson(msg.id) };
+        let update = doc! { "$set": { "msg": to_bson(&msg.msg)? } };
+        self.messages_collection.update_one(filter, update).await?;
+
+        Ok(())
+    }
+
+    async fn get_messages(
+        &self,
+        user_id: Uuid,
+        channel: &Channel,
+        before: Option<(i64, Uuid)>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>> {
+        let mut filter = match channel {
+            Channel::Room(id) => doc! { "room_id": uuid_to_bson(*id) },
+            Channel::User(id) => {
+                let user_bson_id = uuid_to_bson(user_id);
+                let other_bson_id = uuid_to_bson(*id);
+                doc! { "$or": [
+                    { "from_id": user_bson_id.clone(), "to_user_id": other_bson_id.clone() },
+                    { "from_id": other_bson_id, "to_user_id": user_bson_id },
+                ] }
+            }
+        };
+
+        // a missing field matches null as well, older messages have none
+        filter = doc! { "$and": [filter, { "thread_id": null }] };
+
+        if let Some((created_at, id)) = before {
+            let created_at = DateTime::from_millis(created_at);
+            let older = doc! { "$or": [
+                { "created_at": { "$lt": created_at } },
+                { "created_at": created_at, "id": { "$lt": uuid_to_bson(id) } },
+            ] };
+            filter = doc! { "$and": [filter, older] };
+        }
+
+        let mut cursor = self
+            .messages_collection
+            .find(filter)
+            .sort(doc! { "created_at": -1, "id": -1 })
+            .limit(limit as i64)
+            .await?;
+
+        let mut messages = vec![];
+        while let Some(msg) = cursor.next().await {
+            messages.push(msg?.try_into()?);
+        }
+
+        Ok(messages)
+    }
+
+    async fn get_thread(&self, root_id: Uuid) -> Result<Vec<StoredMessage>> {
+        let filter = doc! { "thread_id": uuid_to_bson(root_id) };
+        let mut cursor = self
+            .messages_collection
+            .find(filter)
+            .sort(doc! { "created_at": 1, "id": 1 })
+            .await?;
+
+        let mut messages = vec![];
+        while let Some(msg) = cursor.next().await {
+            messages.push(msg?.try_into()?);
+        }
+
+        Ok(messages)
+    }
+
+    async fn queue_delivery(&self, delivery: PendingDelivery) -> Result<()> {
+        self.deliveries_collection
+            .insert_one(DbDelivery::from(delivery))
+            .await?;
+        Ok(())
+    }
+
+    async fn get_deliveries(&self, to_id: Uuid) -> Result<Vec<PendingDelivery>> {
+        let filter = doc! { "to_id": uuid_to_bson(to_id) };
+        // message ids are v7, so they sort by time
+        let mut cursor = self
+            .deliveries_collection
+            .find(filter)
+            .sort(doc! { "msg_id": 1 })
+            .await?;
+
+        let mut deliveries = vec![];
+        while let Some(delivery) = cursor.next().await {
+            deliveries.push(delivery?.try_into()?);
+        }
+
+        Ok(deliveries)
+    }
+
+    async fn remove_deliveries(&self, to_id: Uuid, msg_ids: &[Uuid]) -> Result<()> {
+        let msg_ids: Vec<Bson> = msg_ids.iter().copied().map(uuid_to_bson).collect();
+        let filter = doc! { "to_id": uuid_to_bson(to_id), "msg_id": { "$in": msg_ids } };
+        self.deliveries_collection.delete_many(filter).await?;
+        Ok(())
+    }
+
+    async fn get_undelivered(&self, from_id: Uuid) -> Result<Vec<Uuid>> {
+        let filter = doc! { "from_id": uuid_to_bson(from_id) };
+        let mut cursor = self.deliveries_collection.find(filter).await?;
+
+        let mut ids = vec![];
+        while let Some(delivery) = cursor.next().await {
+            let delivery: PendingDelivery = delivery?.try_into()?;
+            ids.push(delivery.msg_id);
+        }
+
+        Ok(ids)
+    }
+
+    async fn set_read_marker(&self, marker: StoredReadMarker) -> Result<()> {
+        let marker = DbReadMarker::from(marker);
+        let filter = doc! {
+            "user_id": marker.user_id.clone(),
+            "room_id": marker.room_id.clone(),
+            "to_user_id": marker.to_user_id.clone(),
+        };
+        self.read_markers_collection
+            .replace_one(filter, marker)
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_read_markers(&self, user_id: Uuid) -> Result<Vec<StoredReadMarker>> {
+        let filter = doc! { "user_id": uuid_to_bson(user_id) };
+        let mut cursor = self.read_markers_collection.find(filter).await?;
+
+        let mut markers = vec![];
+        while let Some(marker) = cursor.next().await {
+            markers.push(marker?.try_into()?);
+        }
+
+        Ok(markers)
+    }
+
+    async fn get_direct_read_markers(&self, user_id: Uuid) -> Result<Vec<StoredReadMarker>> {
+        let filter = doc! { "to_user_id": uuid_to_bson(user_id) };
+        let mut cursor = self.read_markers_collection.find(filter).await?;
+
+        let mut markers = vec![];
+        while let Some(marker) = cursor.next().await {
+            markers.push(marker?.try_into()?);
+        }
+
+        Ok(markers)
+    }
+
+    async fn count_unread_many(
+        &self,
+        user_id: Uuid,
+        channels: &[(Channel, Option<(i64, Uuid)>)],
+        limit: usize,
+    ) -> Result<Vec<usize>> {
+        if channels.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let conversations: Vec<Document> = channels
+            .iter()
+            .map(|(channel, after)| {
+                let filter = match channel {
+                    Channel::Room(id) => doc! {
+                        "room_id": uuid_to_bson(*id),
+                        "from_id": { "$ne": uuid_to_bson(user_id) },
+                    },
+                    Channel::User(id) => doc! {
+                        "from_id": uuid_to_bson(*id),
+                        "to_user_id": uuid_to_bson(user_id),
+                    },
+                };
+                match after {
+                    Some((created_at, id)) => {
+                        let created_at = DateTime::from_millis(*created_at);
+                        let newer = doc! { "$or": [
+                            { "created_at": { "$gt": created_at } },
+                            { "created_at": created_at, "id": { "$gt": uuid_to_bson(*id) } },
+                        ] };
+                        doc! { "$and": [filter, newer] }
+                    }
+                    None => filter,
+                }
+            })
+            .collect();
+
+        // one query for all conversations, grouped by room and by the author of direct messages
+        let pipeline = vec![
+            doc! { "$match": { "thread_id": Bson::Null, "$or": conversations } },
+            doc! { "$group": {
+                "_id": { "room_id": "$room_id", "from_id": "$from_id" },
+                "count": { "$sum": 1 },
+            } },
+        ];
+        let mut cursor = self.messages_collection.aggregate(pipeline).await?;
+
+        let (mut rooms, mut directs): (HashMap<Uuid, usize>, HashMap<Uuid, usize>) =
+            (HashMap::new(), HashMap::new());
+        while let Some(group) = cursor.next().await {
+            let group = group?;
+            let key = group.get_document("_id")?;
+            let count = match group.get("count") {
+                Some(Bson::Int32(n)) => *n as usize,
+                Some(Bson::Int64(n)) => *n as usize,
+                _ => 0,
+            };
+            // direct messages have no room, their group is the author alone
+            match (
+                key.get("room_id").and_then(bson_to_uuid),
+                key.get("from_id"),
+            ) {
+                (Some(room_id), _) => *rooms.entry(room_id).or_default() += count,
+                (None, Some(from_id)) => {
+                    if let Some(from_id) = bson_to_uuid(from_id) {
+                        *directs.entry(from_id).or_default() += count;
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+
+        Ok(channels
+            .iter()
+            .map(|(channel, _)| {
+                let count = match channel {
+                    Channel::Room(id) => rooms.get(id),
+                    Channel::User(id) => directs.get(id),
+                };
+                count.copied().unwrap_or(0).min(limit)
+            })
+            .collect())
+    }
+
+    async fn set_presence(&self, presence: StoredPresence) -> Result<()> {
+        let presence = DbPresence::from(presence);
+        let filter = doc! { "user_id": presence.user_id.clone() };
+        self.presence_collection
+            .replace_one(filter, presence)
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_presence(&self, user_ids: &[Uuid]) -> Result<Vec<StoredPresence>> {
+        let ids: Vec<Bson> = user_ids.iter().copied().map(uuid_to_bson).collect();
+        let mut cursor = self
+            .presence_collection
+            .find(doc! { "user_id": { "$in": ids } })
+            .await?;
+
+        let mut presence = vec![];
+        while let Some(p) = cursor.next().await {
+            presence.push(p?.try_into()?);
+        }
+
+        Ok(presence)
+    }
+    async fn insert_file(&self, file: StoredFile) -> Result<()> {
+        self.files_collection.insert_one(DbFile::from(file)).await?;
+        Ok(())
+    }
+
+    async fn get_file(&self, id: Uuid) -> Result<Option<StoredFile>> {
+        let filter = doc! { "id": uuid_to_bson(id) };
+        match self.files_collection.find_one(filter).await? {
+            Some(file) => Ok(Some(file.try_into()?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_files(&self, user_id: Uuid, channel: &Channel) -> Result<Vec<StoredFile>> {
+        let filter = match channel {
+            Channel::Room(id) => doc! { "room_id": uuid_to_bson(*id) },
+            Channel::User(id) => {
+                let user_bson_id = uuid_to_bson(user_id);
+                let other_bson_id = uuid_to_bson(*id);
+                doc! { "$or": [
+                    { "from_id": user_bson_id.clone(), "to_user_id": other_bson_id.clone() },
+                    { "from_id": other_bson_id, "to_user_id": user_bson_id },
+                ] }
+            }
+        };
+
+        let mut cursor = self
+            .files_collection
+            .find(filter)
+            .sort(doc! { "created_at": -1, "id": -1 })
+            .await?;
+
+        let mut files = vec![];
+        while let Some(file) = cursor.next().await {
+            files.push(file?.try_into()?);
+        }
+
+        Ok(files)
+    }
+
+    async fn get_used_file_space(&self, user_id: Uuid) -> Result<u64> {
+        let mut cursor = self
+            .files_collection
+            .find(doc! { "from_id": uuid_to_bson(user_id) })
+            .await?;
+
+        let mut used = 0;
+        while let Some(file) = cursor.next().await {
+            used += file?.size as u64;
+        }
+
+        Ok(used)
+    }
+}
@@ -0,0 +1,741 @@
+use super::storage_backend::Storage;
+use crate::util::types::server_data_types::{
+    PendingDelivery, StoredFile, StoredMessage, StoredPresence, StoredReadMarker, StoredRoom,
+    StoredUser,
+};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Serialize, de::DeserializeOwned};
+use shared::types::Channel;
+use std::sync::{Arc, Mutex};
+use tokio::task;
+use uuid::Uuid;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS users (
+        id TEXT PRIMARY KEY,
+        username TEXT NOT NULL UNIQUE,
+        data BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS rooms (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        data BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS messages (
+        id TEXT PRIMARY KEY,
+        from_id TEXT NOT NULL,
+        room_id TEXT,
+        to_user_id TEXT,
+        created_at INTEGER NOT NULL,
+        data BLOB NOT NULL,
+        thread_id TEXT
+    );
+    CREATE INDEX IF NOT EXISTS messages_created_at ON messages (created_at);
+    CREATE TABLE IF NOT EXISTS deliveries (
+        msg_id TEXT PRIMARY KEY,
+        from_id TEXT NOT NULL,
+        to_id TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS deliveries_to_id ON deliveries (to_id);
+    CREATE TABLE IF NOT EXISTS read_markers (
+        user_id TEXT NOT NULL,
+        channel_id TEXT NOT NULL,
+        data BLOB NOT NULL,
+        PRIMARY KEY (user_id, channel_id)
+    );
+    CREATE INDEX IF NOT EXISTS read_markers_channel_id ON read_markers (channel_id);
+    CREATE TABLE IF NOT EXISTS presence (
+        user_id TEXT PRIMARY KEY,
+        data BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS files (
+        id TEXT PRIMARY KEY,
+        from_id TEXT NOT NULL,
+        room_id TEXT,
+        to_user_id TEXT,
+        size INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        data BLOB NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS files_from_id ON files (from_id);
+    CREATE INDEX IF NOT EXISTS files_room_id ON files (room_id);
+";
+
+/// Embedded single file database. Records are stored as bincode blobs next to the columns
+/// used for lookups, all access goes through one connection so updates never interleave.
+#[derive(Clone)]
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    pub async fn open(path: &str) -> Result<Self> {
+        let path = String::from(path);
+        let conn = task::spawn_blocking(move || -> Result<Connection> {
+            let conn = Connection::open(path)?;
+            conn.execute_batch(SCHEMA)?;
+            migrate_thread_id(&conn)?;
+            Ok(conn)
+        })
+        .await??;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        task::spawn_blocking(move || {
+            let conn = conn
+                .lock()
+                .map_err(|err| anyhow!("sqlite connection poisoned: {err}"))?;
+            f(&conn)
+        })
+        .await?
+    }
+}
+
+/// databases created before threads lack the column, it is added empty
+fn migrate_thread_id(conn: &Connection) -> Result<()> {
+    let has_column = conn
+        .prepare("SELECT 1 FROM pragma_table_info('messages') WHERE name = 'thread_id'")?
+        .exists([])?;
+
+    if !has_column {
+        conn.execute("ALTER TABLE messages ADD COLUMN thread_id TEXT", [])?;
+    }
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS messages_thread_id ON messages (thread_id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    Ok(bincode::serialize(value)?)
+}
+
+fn decode<T: DeserializeOwned>(data: Vec<u8>) -> Result<T> {
+    Ok(bincode::deserialize(&data)?)
+}
+
+fn query_user(conn: &Connection, id: Uuid) -> Result<Option<StoredUser>> {
+    let data: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT data FROM users WHERE id = ?1",
+            params![id.to_string()],
+            |row| row.get(0),
+        )
+        .optional()?;
+    data.map(decode).transpose()
+}
+
+fn query_room(conn: &Connection, id: Uuid) -> Result<Option<StoredRoom>> {
+    let data: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT data FROM rooms WHERE id = ?1",
+            params![id.to_string()],
+            |row| row.get(0),
+        )
+        .optional()?;
+    data.map(decode).transpose()
+}
+
+fn save_user(conn: &Connection, user: &StoredUser) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO users (id, username, data) VALUES (?1, ?2, ?3)",
+        params![user.id.to_string(), user.username, encode(user)?],
+    )?;
+    Ok(())
+}
+
+fn save_room(conn: &Connection, room: &StoredRoom) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO rooms (id, name, data) VALUES (?1, ?2, ?3)",
+        params![room.id.to_string(), room.name, encode(room)?],
+    )?;
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn get_user_by_name(&self, username: &str) -> Result<Option<StoredUser>> {
+        let username = String::from(username);
+        self.with_conn(move |conn| {
+            let data: Option<Vec<u8>> = conn
+                .query_row(
+                    "SELECT data FROM users WHERE username = ?1",
+                    params![username],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            data.map(decode).transpose()
+        })
+        .await
+    }
+
+    async fn get_users(&self, ids: &[Uuid]) -> Result<Vec<StoredUser>> {
+        let ids = ids.to_vec();
+        self.with_conn(move |conn| {
+            let mut users = vec![];
+            for id in ids {
+                if let Some(user) = query_user(conn, id)? {
+                    users.push(user);
+                }
+            }
+            Ok(users)
+        })
+        .await
+    }
+
+    async fn insert_user(&self, user: StoredUser) -> Result<()> {
+        self.with_conn(move |conn| save_user(conn, &user)).await
+    }
+
+    async fn get_room(&self, id: Uuid) -> Result<Option<StoredRoom>> {
+        self.with_conn(move |conn| query_room(conn, id)).await
+    }
+
+    async fn get_room_by_name(&self, name: &str) -> Result<Option<StoredRoom>> {
+        let name = String::from(name);
+        self.with_conn(move |conn| {
+            let data: Option<Vec<u8>> = conn
+                .query_row(
+                    "SELECT data FROM rooms WHERE name = ?1",
+                    params![name],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            data.map(decode).transpose()
+        })
+        .await
+    }
+
+    async fn get_rooms(&self, ids: &[Uuid]) -> Result<Vec<StoredRoom>> {
+        let ids = ids.to_vec();
+        self.with_conn(move |conn| {
+            let mut rooms = vec![];
+            for id in ids {
+                if let Some(room) = query_room(conn, id)? {
+                    rooms.push(room);
+                }
+            }
+            Ok(rooms)
+        })
+        .await
+    }
+
+    async fn get_all_rooms(&self) -> Result<Vec<StoredRoom>> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare("SELECT data FROM rooms")?;
+            let rows: Vec<Vec<u8>> = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+
+            rows.into_iter().map(decode).collect()
+        })
+        .await
+    }
+
+    async fn insert_room(&self, room: StoredRoom) -> Result<()> {
+        self.with_conn(move |conn| save_room(conn, &room)).await
+    }
+
+    async fn delete_room(&self, id: Uuid) -> Result<()> {
+        self.with_conn(move |conn| {
+            if let Some(room) = query_room(conn, id)? {
+                for user_id in room.user_ids {
+                    if let Some(mut user) = query_user(conn, user_id)? {
+                        user.room_ids.retain(|room_id| *room_id != id);
+                        save_user(conn, &user)?;
+                    }
+                }
+            }
+
+            conn.execute("DELETE FROM rooms WHERE id = ?1", params![id.to_string()])?;
+            conn.execute(
+                "DELETE FROM messages WHERE room_id = ?1",
+                params![id.to_string()],
+            )?;
+            conn.execute(
+                "DELETE FROM files WHERE room_id = ?1",
+                params![id.to_string()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_room_pwd(&self, id: Uuid, pwd: Option<String>) -> Result<()> {
+        self.with_conn(move |conn| {
+            if let Some(mut room) = query_room(conn, id)? {
+                room.pwd = pwd;
+                save_room(conn, &room)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_room_roles(&self, room: StoredRoom) -> Result<()> {
+        self.with_conn(move |conn| {
+            if let Some(mut stored) = query_room(conn, room.id)? {
+                stored.owner_id = room.owner_id;
+                stored.admin_ids = room.admin_ids;
+                stored.moderator_ids = room.moderator_ids;
+                stored.banned_ids = room.banned_ids;
+                save_room(conn, &stored)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn add_room_member(&self, room_id: Uuid, user_id: Uuid) -> Result<()> {
+        self.with_conn(move |conn| {
+            if let Some(mut room) = query_room(conn, room_id)?
+                && !room.user_ids.contains(&user_id)
+            {
+                room.user_ids.push(user_id);
+                save_room(conn, &room)?;
+            }
+
+            if let Some(mut user) = query_user(conn, user_id)?
+                && !user.room_ids.contains(&room_id)
+            {
+                user.room_ids.push(room_id);
+                save_user(conn, &user)?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_room_member(&self, room_id: Uuid, user_id: Uuid) -> Result<()> {
+        self.with_conn(move |conn| {
+            if let Some(mut room) = query_room(conn, room_id)? {
+                room.user_ids.retain(|id| *id != user_id);
+                save_room(conn, &room)?;
+            }
+
+            if let Some(mut user) = query_user(conn, user_id)? {
+                user.room_ids.retain(|id| *id != room_id);
+                save_user(conn, &user)?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn insert_message(&self, msg: StoredMessage) -> Result<()> {
+        self.with_conn(move |conn| {
+            let (room_id, to_user_id) = match msg.to {
+                Channel::Room(id) => (Some(id.to_string()), None),
+                Channel::User(id) => (None, Some(id.to_string())),
+            };
+
+            conn.execute(
+                "INSERT INTO messages (id, from_id, room_id, to_user_id, created_at, data, thread_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    msg.id.to_string(),
+                    msg.from_id.to_string(),
+                    room_id,
+                    to_user_id,
+                    msg.created_at,
+                    encode(&msg)?,
+                    msg.thread_id.map(|id| id.to_string())
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_message(&self, id: Uuid) -> Result<Option<StoredMessage>> {
+        self.with_conn(move |conn| {
+            let data: Option<Vec<u8>> = conn
+                .query_row(
+                    "SELECT data FROM messages WHERE id = ?1",
+                    params![id.to_string()],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            data.map(decode).transpose()
+        })
+        .await
+    }
+
+    async fn update_message(&self, msg: StoredMessage) -> Result<()> {
+        self.with_conn(move |conn| {
+            let data: Option<Vec<u8>> = conn
+                .query_row(
+                    "SELECT data FROM messages WHERE id = ?1",
+                    params![msg.id.to_string()],
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            if let Some(data) = data {
+                let mut stored: StoredMessage = decode(data)?;
+                stored.msg = msg.msg;
+                conn.execute(
+                    "UPDATE messages SET data = ?2 WHERE id = ?1",
+                    params![stored.id.to_string(), encode(&stored)?],
+                )?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_messages(
+        &self,
+        user_id: Uuid,
+        channel: &Channel,
+        before: Option<(i64, Uuid)>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>> {
+        let channel = channel.clone();
+        self.with_conn(move |conn| {
+            // hyphenated uuid text sorts the same way as the uuid bytes
+            let (before_at, before_id) = match before {
+                Some((created_at, id)) => (created_at, id.to_string()),
+                None => (i64::MAX, String::new()),
+            };
+            let limit = limit as i64;
+
+            let rows: Vec<Vec<u8>> = match channel {
+                Channel::Room(id) => {
+                    let mut stmt = conn.prepare(
+                        "SELECT data FROM messages
+                         WHERE room_id = ?1 AND thread_id IS NULL
+                         AND (created_at < ?2 OR (created_at = ?2 AND id < ?3))
+                         ORDER BY created_at DESC, id DESC LIMIT ?4",
+                    )?;
+                    stmt.query_map(
+                        params![id.to_string(), before_at, before_id, limit],
+                        |row| row.get(0),
+                    )?
+                    .collect::<Result<_, _>>()?
+                }
+                Channel::User(id) => {
+                    let mut stmt = conn.prepare(
+                        "SELECT data FROM messages
+                         WHERE ((from_id = ?1 AND to_user_id = ?2) OR (from_id = ?2 AND to_user_id = ?1))
+                         AND thread_id IS NULL
+                         AND (created_at < ?3 OR (created_at = ?3 AND id < ?4))
+                         ORDER BY created_at DESC, id DESC LIMIT ?5",
+                    )?;
+                    stmt.query_map(
+                        params![user_id.to_string(), id.to_string(), before_at, before_id, limit],
+                        |row| row.get(0),
+                    )?
+                    .collect::<Result<_, _>>()?
+                }
+            };
+
+            rows.into_iter().map(decode).collect()
+        })
+        .await
+    }
+
+    async fn get_thread(&self, root_id: Uuid) -> Result<Vec<StoredMessage>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT data FROM messages WHERE thread_id = ?1 ORDER BY created_at, id",
+            )?;
+            let rows: Vec<Vec<u8>> = stmt
+                .query_map(params![root_id.to_string()], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+
+            rows.into_iter().map(decode).collect()
+        })
+        .await
+    }
+
+    async fn queue_delivery(&self, delivery: PendingDelivery) -> Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO deliveries (msg_id, from_id, to_id) VALUES (?1, ?2, ?3)",
+                params![
+                    delivery.msg_id.to_string(),
+                    delivery.from_id.to_string(),
+                    delivery.to_id.to_string()
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_deliveries(&self, to_id: Uuid) -> Result<Vec<PendingDelivery>> {
+        self.with_conn(move |conn| {
+            // message ids are v7, so they sort by time
+            let mut stmt = conn.prepare(
+                "SELECT msg_id, from_id FROM deliveries WHERE to_id = ?1 ORDER BY msg_id",
+            )?;
+            let rows: Vec<(String, String)> = stmt
+                .query_map(params![to_id.to_string()], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?
+                .collect::<Result<_, _>>()?;
+
+            rows.into_iter()
+                .map(|(msg_id, from_id)| {
+                    Ok(PendingDelivery {
+                        msg_id: Uuid::parse_str(&msg_id)?,
+                        from_id: Uuid::parse_str(&from_id)?,
+                        to_id,
+                    })
+                })
+                .collect()
+        })
+        .await
+    }
+
+    async fn remove_deliveries(&self, to_id: Uuid, msg_ids: &[Uuid]) -> Result<()> {
+        let msg_ids = msg_ids.to_vec();
+        self.with_conn(move |conn| {
+            for msg_id in msg_ids {
+                conn.execute(
+                    "DELETE FROM deliveries WHERE to_id = ?1 AND msg_id = ?2",
+                    params![to_id.to_string(), msg_id.to_string()],
+                )?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_undelivered(&self, from_id: Uuid) -> Result<Vec<Uuid>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare("SELECT msg_id FROM deliveries WHERE from_id = ?1")?;
+            let ids: Vec<String> = stmt
+                .query_map(params![from_id.to_string()], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            ids.iter().map(|id| Ok(Uuid::parse_str(id)?)).collect()
+        })
+        .await
+    }
+
+    async fn set_read_marker(&self, marker: StoredReadMarker) -> Result<()> {
+        self.with_conn(move |conn| {
+            let channel_id = match marker.channel {
+                Channel::Room(id) | Channel::User(id) => id,
+            };
+            conn.execute(
+                "INSERT OR REPLACE INTO read_markers (user_id, channel_id, data) VALUES (?1, ?2, ?3)",
+                params![
+                    marker.user_id.to_string(),
+                    channel_id.to_string(),
+                    encode(&marker)?
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_read_markers(&self, user_id: Uuid) -> Result<Vec<StoredReadMarker>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare("SELECT data FROM read_markers WHERE user_id = ?1")?;
+            let rows: Vec<Vec<u8>> = stmt
+                .query_map(params![user_id.to_string()], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+
+            rows.into_iter().map(decode).collect()
+        })
+        .await
+    }
+
+    async fn get_direct_read_markers(&self, user_id: Uuid) -> Result<Vec<StoredReadMarker>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare("SELECT data FROM read_markers WHERE channel_id = ?1")?;
+            let rows: Vec<Vec<u8>> = stmt
+                .query_map(params![user_id.to_string()], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+
+            let markers: Vec<StoredReadMarker> =
+                rows.into_iter().map(decode).collect::<Result<_>>()?;
+            Ok(markers
+                .into_iter()
+                .filter(|m| m.channel == Channel::User(user_id))
+                .collect())
+        })
+        .await
+    }
+
+    async fn count_unread_many(
+        &self,
+        user_id: Uuid,
+        channels: &[(Channel, Option<(i64, Uuid)>)],
+        limit: usize,
+    ) -> Result<Vec<usize>> {
+        let channels = channels.to_vec();
+        self.with_conn(move |conn| {
+            let mut room_stmt = conn.prepare(
+                "SELECT COUNT(*) FROM (SELECT 1 FROM messages
+                 WHERE room_id = ?1 AND from_id != ?2 AND thread_id IS NULL
+                 AND (created_at > ?3 OR (created_at = ?3 AND id > ?4))
+                 LIMIT ?5)",
+            )?;
+            let mut direct_stmt = conn.prepare(
+                "SELECT COUNT(*) FROM (SELECT 1 FROM messages
+                 WHERE from_id = ?1 AND to_user_id = ?2 AND thread_id IS NULL
+                 AND (created_at > ?3 OR (created_at = ?3 AND id > ?4))
+                 LIMIT ?5)",
+            )?;
+            let limit = limit as i64;
+
+            let mut counts = Vec::with_capacity(channels.len());
+            for (channel, after) in channels {
+                let (after_at, after_id) = match after {
+                    Some((created_at, id)) => (created_at, id.to_string()),
+                    None => (i64::MIN, String::new()),
+                };
+                let (stmt, id) = match channel {
+                    Channel::Room(id) => (&mut room_stmt, id),
+                    Channel::User(id) => (&mut direct_stmt, id),
+                };
+                let count: i64 = stmt.query_row(
+                    params![
+                        id.to_string(),
+                        user_id.to_string(),
+                        after_at,
+                        after_id,
+                        limit
+                    ],
+                    |row| row.get(0),
+                )?;
+                counts.push(count as usize);
+            }
+
+            Ok(counts)
+        })
+        .await
+    }
+
+    async fn set_presence(&self, presence: StoredPresence) -> Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO presence (user_id, data) VALUES (?1, ?2)",
+                params![presence.user_id.to_string(), encode(&presence)?],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_presence(&self, user_ids: &[Uuid]) -> Result<Vec<StoredPresence>> {
+        let user_ids = user_ids.to_vec();
+        self.with_conn(move |conn| {
+            let mut presence = vec![];
+            for id in user_ids {
+                let data: Option<Vec<u8>> = conn
+                    .query_row(
+                        "SELECT data FROM presence WHERE user_id = ?1",
+                        params![id.to_string()],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                if let Some(data) = data {
+                    presence.push(decode(data)?);
+                }
+            }
+            Ok(presence)
+        })
+        .await
+    }
+    async fn insert_file(&self, file: StoredFile) -> Result<()> {
+        self.with_conn(move |conn| {
+            let (room_id, to_user_id) = match file.to {
+                Channel::Room(id) => (Some(id.to_string()), None),
+                Channel::User(id) => (None, Some(id.to_string())),
+            };
+
+            conn.execute(
+                "INSERT INTO files (id, from_id, room_id, to_user_id, size, created_at, data)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    file.id.to_string(),
+                    file.from_id.to_string(),
+                    room_id,
+                    to_user_id,
+                    file.size as i64,
+                    file.created_at,
+                    encode(&file)?
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_file(&self, id: Uuid) -> Result<Option<StoredFile>> {
+        self.with_conn(move |conn| {
+            let data: Option<Vec<u8>> = conn
+                .query_row(
+                    "SELECT data FROM files WHERE id = ?1",
+                    params![id.to_string()],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            data.map(decode).transpose()
+        })
+        .await
+    }
+
+    async fn get_files(&self, user_id: Uuid, channel: &Channel) -> Result<Vec<StoredFile>> {
+        let channel = channel.clone();
+        self.with_conn(move |conn| {
+            let rows: Vec<Vec<u8>> = match channel {
+                Channel::Room(id) => {
+                    let mut stmt = conn.prepare(
+                        "SELECT data FROM files WHERE room_id = ?1
+                         ORDER BY created_at DESC, id DESC",
+                    )?;
+                    stmt.query_map(params![id.to_string()], |row| row.get(0))?
+                        .collect::<Result<_, _>>()?
+                }
+                Channel::User(id) => {
+                    let mut stmt = conn.prepare(
+                        "SELECT data FROM files
+                         WHERE (from_id = ?1 AND to_user_id = ?2) OR (from_id = ?2 AND to_user_id = ?1)
+                         ORDER BY created_at DESC, id DESC",
+                    )?;
+                    stmt.query_map(params![user_id.to_string(), id.to_string()], |row| {
+                        row.get(0)
+                    })?
+                    .collect::<Result<_, _>>()?
+                }
+            };
+
+            rows.into_iter().map(decode).collect()
+        })
+        .await
+    }
+
+    async fn get_used_file_space(&self, user_id: Uuid) -> Result<u64> {
+        self.with_conn(move |conn| {
+            let used: i64 = conn.query_row(
+                "SELECT COALESCE(SUM(size), 0) FROM files WHERE from_id = ?1",
+                params![user_id.to_string()],
+                |row| row.get(0),
+            )?;
+            Ok(used as u64)
+        })
+        .await
+    }
+}
@@ -0,0 +1,119 @@
+use super::{
+    memory_storage::MemoryStorage, mongo_storage::MongoStorage, sqlite_storage::SqliteStorage,
+};
+use crate::util::{
+    config::{DEFAULT_SQLITE_PATH, STORAGE_ENV},
+    types::server_data_types::{
+        PendingDelivery, StoredFile, StoredMessage, StoredPresence, StoredReadMarker, StoredRoom,
+        StoredUser,
+    },
+};
+use anyhow::{Result, bail};
+use async_trait::async_trait;
+use log::info;
+use shared::types::Channel;
+use std::{env::var, sync::Arc};
+use uuid::Uuid;
+
+/// Data access used by the persistence task, validation and hashing stay in the task itself
+/// so every backend behaves the same.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn get_user_by_name(&self, username: &str) -> Result<Option<StoredUser>>;
+    async fn get_users(&self, ids: &[Uuid]) -> Result<Vec<StoredUser>>;
+    async fn insert_user(&self, user: StoredUser) -> Result<()>;
+
+    async fn get_room(&self, id: Uuid) -> Result<Option<StoredRoom>>;
+    async fn get_room_by_name(&self, name: &str) -> Result<Option<StoredRoom>>;
+    async fn get_rooms(&self, ids: &[Uuid]) -> Result<Vec<StoredRoom>>;
+    async fn get_all_rooms(&self) -> Result<Vec<StoredRoom>>;
+    async fn insert_room(&self, room: StoredRoom) -> Result<()>;
+    /// removes the room together with its memberships, messages and file records
+    async fn delete_room(&self, id: Uuid) -> Result<()>;
+
+    /// `pwd` is expected to be hashed already
+    async fn update_room_pwd(&self, id: Uuid, pwd: Option<String>) -> Result<()>;
+    /// writes `owner_id`, `admin_ids`, `moderator_ids` and `banned_ids`, the rest of the room is
+    /// left alone
+    async fn update_room_roles(&self, room: StoredRoom) -> Result<()>;
+
+    /// adds the room to the user and the user to the room
+    async fn add_room_member(&self, room_id: Uuid, user_id: Uuid) -> Result<()>;
+    async fn remove_room_member(&self, room_id: Uuid, user_id: Uuid) -> Result<()>;
+
+    async fn insert_message(&self, msg: StoredMessage) -> Result<()>;
+    async fn get_message(&self, id: Uuid) -> Result<Option<StoredMessage>>;
+    /// writes `msg`, the rest of the message is left alone
+    async fn update_message(&self, msg: StoredMessage) -> Result<()>;
+
+    /// newest first by (created_at, id), `before` excludes that position and everything newer.
+    /// `channel` is seen from `user_id`, so `Channel::User` means the direct
+    /// conversation between the two users. thread replies are left out
+    async fn get_messages(
+        &self,
+        user_id: Uuid,
+        channel: &Channel,
+        before: Option<(i64, Uuid)>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>>;
+    /// replies posted in the thread of `root_id`, oldest first
+    async fn get_thread(&self, root_id: Uuid) -> Result<Vec<StoredMessage>>;
+
+    async fn queue_delivery(&self, delivery: PendingDelivery) -> Result<()>;
+    /// what is queued for `to_id`, oldest first. it stays queued until removed
+    async fn get_deliveries(&self, to_id: Uuid) -> Result<Vec<PendingDelivery>>;
+    /// drops the listed messages from the queue of `to_id`, once they reached the user
+    async fn remove_deliveries(&self, to_id: Uuid, msg_ids: &[Uuid]) -> Result<()>;
+    /// ids of messages sent by `from_id` that are still queued
+    async fn get_undelivered(&self, from_id: Uuid) -> Result<Vec<Uuid>>;
+
+    /// replaces the marker the user had on that channel
+    async fn set_read_marker(&self, marker: StoredReadMarker) -> Result<()>;
+    async fn get_read_markers(&self, user_id: Uuid) -> Result<Vec<StoredReadMarker>>;
+    /// markers other users hold on their direct conversation with `user_id`
+    async fn get_direct_read_markers(&self, user_id: Uuid) -> Result<Vec<StoredReadMarker>>;
+    /// for every `(channel, after)`, the messages of the conversation that `user_id` did not send
+    /// and that are newer than `after`, counted up to `limit`. thread replies are left out.
+    /// the counts come back in the order of `channels`
+    async fn count_unread_many(
+        &self,
+        user_id: Uuid,
+        channels: &[(Channel, Option<(i64, Uuid)>)],
+        limit: usize,
+    ) -> Result<Vec<usize>>;
+
+    /// replaces what was stored for the user
+    async fn set_presence(&self, presence: StoredPresence) -> Result<()>;
+    async fn get_presence(&self, user_ids: &[Uuid]) -> Result<Vec<StoredPresence>>;
+
+    async fn insert_file(&self, file: StoredFile) -> Result<()>;
+    async fn get_file(&self, id: Uuid) -> Result<Option<StoredFile>>;
+    /// newest first, `channel` is seen from `user_id` like in `get_messages`
+    async fn get_files(&self, user_id: Uuid, channel: &Channel) -> Result<Vec<StoredFile>>;
+    /// bytes of the files `user_id` uploaded, counted per upload even when content is shared
+    async fn get_used_file_space(&self, user_id: Uuid) -> Result<u64>;
+}
+
+/// picks backend from STORAGE env variable (mongo, sqlite, memory),
+/// defaults to mongo when DB_URL is set and to memory otherwise
+pub async fn connect_storage() -> Result<Arc<dyn Storage>> {
+    let backend = match var(STORAGE_ENV) {
+        Ok(backend) => backend,
+        Err(_) if var("DB_URL").is_ok() => String::from("mongo"),
+        Err(_) => String::from("memory"),
+    };
+
+    let storage: Arc<dyn Storage> = match backend.as_str() {
+        "mongo" => Arc::new(MongoStorage::connect(&var("DB_URL")?).await?),
+        "sqlite" => {
+            let path = var("SQLITE_PATH").unwrap_or(String::from(DEFAULT_SQLITE_PATH));
+            Arc::new(SqliteStorage::open(&path).await?)
+        }
+        "memory" => Arc::new(MemoryStorage::new()),
+        other => bail!("unknown storage backend \"{other}\", use mongo, sqlite or memory"),
+    };
+
+    info!("Using {backend} storage");
+
+    Ok(storage)
+}
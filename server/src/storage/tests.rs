@@ -0,0 +1,343 @@
+use super::{
+    memory_storage::MemoryStorage, sqlite_storage::SqliteStorage, storage_backend::Storage,
+};
+use crate::util::types::server_data_types::{
+    PendingDelivery, StoredMessage, StoredRoom, StoredUser,
+};
+use shared::types::{Channel, ChannelMsg, TextMsg, User};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// every backend that runs without a server, each scenario is checked against all of them
+pub(crate) async fn backends() -> Vec<(&'static str, Arc<dyn Storage>)> {
+    vec![
+        ("memory", Arc::new(MemoryStorage::new())),
+        (
+            "sqlite",
+            Arc::new(SqliteStorage::open(":memory:").await.unwrap()),
+        ),
+    ]
+}
+
+pub(crate) fn user(name: &str) -> StoredUser {
+    StoredUser {
+        id: Uuid::new_v4(),
+        username: String::from(name),
+        pwd: String::from("$argon2id$hash"),
+        room_ids: vec![],
+    }
+}
+
+pub(crate) fn room(name: &str, owner: Uuid) -> StoredRoom {
+    StoredRoom {
+        id: Uuid::new_v4(),
+        name: String::from(name),
+        user_ids: vec![],
+        pwd: None,
+        owner_id: Some(owner),
+        admin_ids: vec![],
+        moderator_ids: vec![],
+        banned_ids: vec![],
+    }
+}
+
+fn message(from: Uuid, to: Channel, created_at: i64, thread_id: Option<Uuid>) -> StoredMessage {
+    let id = Uuid::now_v7();
+    StoredMessage {
+        id,
+        from_id: from,
+        to: to.clone(),
+        created_at,
+        thread_id,
+        msg: ChannelMsg::TextMsg(TextMsg {
+            id,
+            text: format!("at {created_at}"),
+            from: User {
+                username: String::new(),
+                id: from,
+            },
+            to,
+            edited: false,
+            sent_at: created_at,
+            reactions: vec![],
+            reply_to: None,
+            thread: thread_id,
+            thread_len: 0,
+        }),
+    }
+}
+
+/// pages through the whole conversation the way the history handler does
+async fn all_pages(
+    storage: &Arc<dyn Storage>,
+    user_id: Uuid,
+    channel: &Channel,
+    limit: usize,
+) -> Vec<Uuid> {
+    let mut ids = vec![];
+    let mut before = None;
+    loop {
+        let page = storage
+            .get_messages(user_id, channel, before, limit)
+            .await
+            .unwrap();
+        ids.extend(page.iter().map(|m| m.id));
+        match page.last() {
+            Some(last) if page.len() == limit => before = Some((last.created_at, last.id)),
+            _ => return ids,
+        }
+    }
+}
+
+#[tokio::test]
+async fn users_register_and_authenticate_by_name() {
+    for (name, storage) in backends().await {
+        let alice = user("alice");
+        storage.insert_user(alice.clone()).await.unwrap();
+
+        let found = storage.get_user_by_name("alice").await.unwrap();
+        let found = found.unwrap_or_else(|| panic!("{name}: registered user not found"));
+        assert_eq!(found.id, alice.id, "{name}");
+        assert_eq!(found.pwd, alice.pwd, "{name}: hash changed on the way");
+        assert!(
+            storage.get_user_by_name("bob").await.unwrap().is_none(),
+            "{name}"
+        );
+        assert!(
+            storage.get_user_by_name("Alice").await.unwrap().is_none(),
+            "{name}"
+        );
+
+        let users = storage
+            .get_users(&[alice.id, Uuid::new_v4()])
+            .await
+            .unwrap();
+        assert_eq!(users.len(), 1, "{name}: unknown ids are skipped");
+    }
+}
+
+#[tokio::test]
+async fn rooms_are_created_joined_and_left() {
+    for (name, storage) in backends().await {
+        let (alice, bob) = (user("alice"), user("bob"));
+        storage.insert_user(alice.clone()).await.unwrap();
+        storage.insert_user(bob.clone()).await.unwrap();
+
+        let chat = room("chat", alice.id);
+        storage.insert_room(chat.clone()).await.unwrap();
+        let found = storage.get_room_by_name("chat").await.unwrap();
+        assert_eq!(found.map(|r| r.id), Some(chat.id), "{name}");
+
+        storage.add_room_member(chat.id, alice.id).await.unwrap();
+        storage.add_room_member(chat.id, bob.id).await.unwrap();
+        let joined = storage.get_room(chat.id).await.unwrap().unwrap();
+        assert_eq!(joined.user_ids, vec![alice.id, bob.id], "{name}");
+        let bob_rooms = storage.get_users(&[bob.id]).await.unwrap()[0]
+            .room_ids
+            .clone();
+        assert_eq!(bob_rooms, vec![chat.id], "{name}: room missing on the user");
+
+        storage.remove_room_member(chat.id, bob.id).await.unwrap();
+        let left = storage.get_room(chat.id).await.unwrap().unwrap();
+        assert_eq!(left.user_ids, vec![alice.id], "{name}");
+        let bob_rooms = storage.get_users(&[bob.id]).await.unwrap()[0]
+            .room_ids
+            .clone();
+        assert!(bob_rooms.is_empty(), "{name}: room left on the user");
+
+        storage.delete_room(chat.id).await.unwrap();
+        assert!(storage.get_room(chat.id).await.unwrap().is_none(), "{name}");
+    }
+}
+
+#[tokio::test]
+async fn room_roles_and_owner_leave_the_rest_of_the_room_alone() {
+    for (name, storage) in backends().await {
+        let owner = Uuid::new_v4();
+        let mut chat = room("chat", owner);
+        chat.user_ids = vec![owner];
+        storage.insert_room(chat.clone()).await.unwrap();
+
+        let (admin, moderator, banned) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let heir = Uuid::new_v4();
+        let mut roles = chat.clone();
+        roles.owner_id = Some(heir);
+        roles.name = String::from("renamed");
+        roles.user_ids = vec![];
+        roles.admin_ids = vec![admin];
+        roles.moderator_ids = vec![moderator];
+        roles.banned_ids = vec![banned];
+        storage.update_room_roles(roles).await.unwrap();
+
+        let stored = storage.get_room(chat.id).await.unwrap().unwrap();
+        assert_eq!(stored.name, "chat", "{name}");
+        assert_eq!(stored.user_ids, vec![owner], "{name}");
+        assert_eq!(stored.owner_id, Some(heir), "{name}");
+        assert_eq!(stored.admin_ids, vec![admin], "{name}");
+        assert_eq!(stored.moderator_ids, vec![moderator], "{name}");
+        assert_eq!(stored.banned_ids, vec![banned], "{name}");
+    }
+}
+
+#[tokio::test]
+async fn history_pages_newest_first_without_gaps() {
+    for (name, storage) in backends().await {
+        let (alice, bob, carol) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let chat = Channel::Room(Uuid::new_v4());
+
+        // several messages in the same millisecond, the id decides their order
+        let mut sent = vec![];
+        for created_at in [100, 200, 200, 200, 200, 300, 400] {
+            let msg = message(alice, chat.clone(), created_at, None);
+            sent.push(msg.id);
+            storage.insert_message(msg).await.unwrap();
+        }
+        let reply = message(bob, chat.clone(), 500, Some(sent[0]));
+        storage.insert_message(reply.clone()).await.unwrap();
+        storage
+            .insert_message(message(alice, Channel::Room(Uuid::new_v4()), 250, None))
+            .await
+            .unwrap();
+
+        let newest_first: Vec<Uuid> = sent.iter().rev().copied().collect();
+        for limit in [1, 3, 7, 20] {
+            let paged = all_pages(&storage, bob, &chat, limit).await;
+            assert_eq!(paged, newest_first, "{name}: pages of {limit}");
+        }
+        let thread = storage.get_thread(sent[0]).await.unwrap();
+        assert_eq!(
+            thread.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![reply.id],
+            "{name}"
+        );
+
+        // a direct conversation reads the same from both sides and leaves others out
+        let to_bob = message(alice, Channel::User(bob), 10, None);
+        let to_alice = message(bob, Channel::User(alice), 20, None);
+        let to_carol = message(alice, Channel::User(carol), 30, None);
+        for msg in [to_bob.clone(), to_alice.clone(), to_carol] {
+            storage.insert_message(msg).await.unwrap();
+        }
+        let expected = vec![to_alice.id, to_bob.id];
+        assert_eq!(
+            all_pages(&storage, alice, &Channel::User(bob), 1).await,
+            expected,
+            "{name}"
+        );
+        assert_eq!(
+            all_pages(&storage, bob, &Channel::User(alice), 5).await,
+            expected,
+            "{name}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn deliveries_stay_queued_until_removed() {
+    for (name, storage) in backends().await {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let queued: Vec<Uuid> = (0..3).map(|_| Uuid::now_v7()).collect();
+        for msg_id in &queued {
+            let delivery = PendingDelivery {
+                msg_id: *msg_id,
+                from_id: alice,
+                to_id: bob,
+            };
+            storage.queue_delivery(delivery).await.unwrap();
+        }
+
+        let mut undelivered = storage.get_undelivered(alice).await.unwrap();
+        undelivered.sort();
+        assert_eq!(undelivered, queued, "{name}");
+        assert!(
+            storage.get_deliveries(alice).await.unwrap().is_empty(),
+            "{name}"
+        );
+
+        let peeked = storage.get_deliveries(bob).await.unwrap();
+        let peeked: Vec<Uuid> = peeked.iter().map(|d| d.msg_id).collect();
+        assert_eq!(peeked, queued, "{name}: oldest first");
+        let again = storage.get_deliveries(bob).await.unwrap();
+        assert_eq!(
+            again.len(),
+            queued.len(),
+            "{name}: peeking removed deliveries"
+        );
+
+        // only the listed messages leave the queue, and only the recipient's
+        storage.remove_deliveries(alice, &queued).await.unwrap();
+        storage.remove_deliveries(bob, &queued[..2]).await.unwrap();
+        let left = storage.get_deliveries(bob).await.unwrap();
+        let left: Vec<Uuid> = left.iter().map(|d| d.msg_id).collect();
+        assert_eq!(left, vec![queued[2]], "{name}");
+
+        storage.remove_deliveries(bob, &queued[2..]).await.unwrap();
+        assert!(
+            storage.get_deliveries(bob).await.unwrap().is_empty(),
+            "{name}"
+        );
+        assert!(
+            storage.get_undelivered(alice).await.unwrap().is_empty(),
+            "{name}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn unread_is_counted_for_every_conversation_at_once() {
+    for (name, storage) in backends().await {
+        let (alice, bob, carol) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let (chat, quiet) = (Channel::Room(Uuid::new_v4()), Channel::Room(Uuid::new_v4()));
+
+        let mut seen = None;
+        for created_at in [100, 200, 300, 400] {
+            let msg = message(bob, chat.clone(), created_at, None);
+            if created_at == 200 {
+                seen = Some((msg.created_at, msg.id));
+            }
+            storage.insert_message(msg).await.unwrap();
+        }
+        let own = message(alice, chat.clone(), 500, None);
+        let reply = message(carol, chat.clone(), 600, Some(own.id));
+        for msg in [own, reply] {
+            storage.insert_message(msg).await.unwrap();
+        }
+        for created_at in [10, 20, 30] {
+            let msg = message(carol, Channel::User(alice), created_at, None);
+            storage.insert_message(msg).await.unwrap();
+        }
+        let to_bob = message(carol, Channel::User(bob), 40, None);
+        storage.insert_message(to_bob).await.unwrap();
+
+        let channels = vec![
+            (chat.clone(), seen),
+            (quiet.clone(), None),
+            (Channel::User(carol), None),
+            (Channel::User(bob), None),
+            (chat.clone(), None),
+        ];
+        let counts = storage
+            .count_unread_many(alice, &channels, 10)
+            .await
+            .unwrap();
+        assert_eq!(counts, vec![2, 0, 3, 0, 4], "{name}");
+
+        let capped = storage
+            .count_unread_many(alice, &channels, 3)
+            .await
+            .unwrap();
+        assert_eq!(
+            capped,
+            vec![2, 0, 3, 0, 3],
+            "{name}: counted past the limit"
+        );
+        assert!(
+            storage
+                .count_unread_many(alice, &[], 10)
+                .await
+                .unwrap()
+                .is_empty(),
+            "{name}"
+        );
+    }
+}
@@ -7,5 +7,40 @@ pub const CLIENT_MANAGER_CAPACITY: usize = 30;
 pub const CLIENT_PERSISTENCE_CAPACITY: usize = 30;
 pub const MANAGER_CLIENT_CAPACITY: usize = 10;
 
+/// receivers of a file that wrote nothing for this long while chunks were waiting are dropped
+pub const STREAM_STALL_SECS: u64 = 30;
+/// how often streams are checked for stalled receivers
+pub const STREAM_STALL_CHECK_SECS: u64 = 5;
+/// streams of a sender whose connection dropped wait this long for it to resume them
+pub const STREAM_RESUME_SECS: u64 = 120;
+
 pub const DB_USERS: &str = "User";
 pub const DB_ROOMS: &str = "Room";
+pub const DB_MESSAGES: &str = "Message";
+pub const DB_DELIVERIES: &str = "PendingDelivery";
+pub const DB_READ_MARKERS: &str = "ReadMarker";
+pub const DB_PRESENCE: &str = "Presence";
+pub const DB_FILES: &str = "File";
+
+pub const STORAGE_ENV: &str = "STORAGE";
+pub const DEFAULT_SQLITE_PATH: &str = "chatapp.db";
+
+/// folder uploaded files are kept in, named by their SHA-256
+pub const FILE_STORE_DIR_ENV: &str = "FILE_STORE_DIR";
+pub const DEFAULT_FILE_STORE_DIR: &str = "file_store";
+/// bytes each user may keep stored on the server, 0 keeps nothing
+pub const FILE_QUOTA_ENV: &str = "FILE_QUOTA_BYTES";
+pub const DEFAULT_FILE_QUOTA: u64 = 100 * 1024 * 1024;
+
+pub const HISTORY_PAGE_LIMIT_MAX: u32 = 100;
+
+// sessions stay resumable this long after they were last used
+pub const SESSION_TTL_SECS: u64 = 60 * 60 * 24;
+/// longest reaction accepted, in chars, enough for emoji joined from several code points
+pub const MAX_REACTION_CHARS: usize = 8;
+/// longest quote kept of a replied to message, in chars
+pub const MAX_QUOTE_CHARS: usize = 80;
+/// typing events of a client closer together than this are dropped
+pub const TYPING_MIN_INTERVAL_MS: u64 = 1000;
+/// pictures of images over this size are dropped, the ascii rendering still goes out
+pub const MAX_IMAGE_BYTES: usize = 1024 * 1024;
@@ -1,6 +1,7 @@
 use super::types::{
     server_data_types::{
-        AuthTransit, ClientManagerMsg, ClientPersistenceMsg, IsOnlineTransit, RegisterDataTransit,
+        AuthTransit, ClientManagerMsg, ClientPersistenceMsg, CreateSessionTransit,
+        RegisterDataTransit, ResumeSessionTransit,
     },
     server_error_types::{BincodeErr, Bt, WsErr},
     server_error_wrapper_types::WsDataParsingError,
@@ -11,7 +12,8 @@ use bincode::deserialize;
 use futures::{SinkExt, StreamExt};
 use mongodb::bson::{Binary, Bson, spec::BinarySubtype};
 use shared::types::{
-    AuthData, AuthResponse, ClientServerAuthMsg, RegisterData, RegisterResponse, ServerClientMsg,
+    AuthData, AuthResponse, AuthSession, ClientServerAuthMsg, RegisterData, RegisterResponse,
+    ServerClientMsg, SessionResponse, User,
 };
 use tokio::sync::{mpsc, oneshot};
 use uuid::Uuid;
@@ -45,50 +47,73 @@ pub async fn read_client_data<'a>(
     Ok(auth_data)
 }
 
+/// a user that is still counted as online gets the old connection replaced
 pub async fn authenticate(
     auth_data: AuthData,
     tx_client_persistence: &mpsc::Sender<ClientPersistenceMsg>,
     tx_client_manager: &mpsc::Sender<ClientManagerMsg>,
-) -> Result<AuthResponse, anyhow::Error> {
-    let (tx_manager_ack, rx_manager_ack) = oneshot::channel::<bool>();
-    let manager_transit = IsOnlineTransit {
-        ack: tx_manager_ack,
-        username: auth_data.username.clone(),
+) -> Result<SessionResponse, anyhow::Error> {
+    let (tx_ack, rx_ack) = oneshot::channel::<AuthResponse>();
+
+    let transit = AuthTransit {
+        data: auth_data,
+        tx: tx_ack,
     };
 
-    let manager_msg = ClientManagerMsg::IsOnline(manager_transit);
+    let persistence_msg = ClientPersistenceMsg::Authenticate(transit);
 
-    tx_client_manager
-        .send(manager_msg)
+    tx_client_persistence
+        .send(persistence_msg)
         .await
         .map_err(|err| anyhow!("{}{}", err, Bt::new()))?;
 
-    let is_online = rx_manager_ack
+    let user = match rx_ack
         .await
-        .map_err(|err| anyhow!("{}{}", err, Bt::new()))?;
+        .map_err(|err| anyhow!("{}{}", err, Bt::new()))?
+    {
+        Ok(user) => user,
+        Err(msg) => return Ok(Err(msg)),
+    };
 
-    if is_online {
-        let res = Err(String::from("User is already logged in"));
-        return Ok(res);
-    }
+    let (tx_ack, rx_ack) = oneshot::channel::<Uuid>();
+    let transit = CreateSessionTransit {
+        ack: tx_ack,
+        user: user.clone(),
+    };
 
-    let (tx_ack, rx_ack) = oneshot::channel::<AuthResponse>();
+    tx_client_manager
+        .send(ClientManagerMsg::CreateSession(transit))
+        .await
+        .map_err(|err| anyhow!("{}{}", err, Bt::new()))?;
 
-    let transit = AuthTransit {
-        data: auth_data,
-        tx: tx_ack,
-    };
+    let token = rx_ack
+        .await
+        .map_err(|err| anyhow!("{}{}", err, Bt::new()))?;
 
-    let persistence_msg = ClientPersistenceMsg::Authenticate(transit);
+    Ok(Ok(AuthSession { user, token }))
+}
 
-    tx_client_persistence
-        .send(persistence_msg)
+pub async fn resume_session(
+    token: Uuid,
+    tx_client_manager: &mpsc::Sender<ClientManagerMsg>,
+) -> Result<SessionResponse, anyhow::Error> {
+    let (tx_ack, rx_ack) = oneshot::channel::<Option<User>>();
+    let transit = ResumeSessionTransit { ack: tx_ack, token };
+
+    tx_client_manager
+        .send(ClientManagerMsg::ResumeSession(transit))
         .await
         .map_err(|err| anyhow!("{}{}", err, Bt::new()))?;
 
-    Ok(rx_ack
+    let res = match rx_ack
         .await
-        .map_err(|err| anyhow!("{}{}", err, Bt::new()))?)
+        .map_err(|err| anyhow!("{}{}", err, Bt::new()))?
+    {
+        Some(user) => Ok(AuthSession { user, token }),
+        None => Err(String::from("Session expired, log in again")),
+    };
+
+    Ok(res)
 }
 
 pub async fn handle_register(
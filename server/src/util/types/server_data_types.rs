@@ -1,15 +1,20 @@
 use bytes::Bytes;
 use futures::stream::{SplitSink, SplitStream};
-use mongodb::bson::Bson;
+use mongodb::bson::{Bson, DateTime};
 use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Instant};
 use tokio::sync::{broadcast, mpsc, oneshot};
 
 use tokio_tungstenite::WebSocketStream;
 use uuid::Uuid;
 
+use crate::storage::file_store::Upload;
 use shared::types::{
-    AuthData, AuthResponse, CreateRoomRes, RegisterData, RegisterResponse, RoomData, User,
-    UserInitData,
+    AuthData, AuthResponse, Channel, ChannelMsg, CreateRoomRes, FileEntry, FileList, FileMetadata,
+    HistoryPage, ModerationAction, ModerationNotice, MsgDeletion, MsgReactions, Presence,
+    PresenceStatus, PresenceUpdate, ReadMarker, RegisterData, RegisterResponse, RoomData,
+    RoomMember, RoomPasswordRes, RoomRemovalRes, StreamFailure, StreamProgress, TextMsg, ThreadRes,
+    TransferControl, User, UserInitData,
 };
 use warp::filters::ws::WebSocket;
 
@@ -29,13 +34,56 @@ pub struct DirectChannelTxTransit {
 #[derive(Debug)]
 pub enum ClientManagerMsg {
     ClientConnected(Client),
-    ClientDropped(Uuid),
+    ClientDropped(ClientDroppedTransit),
     GetDirectChannelTx(DirectChannelTxTransit),
     GetRoomChannelTx(RoomChannelTxTransit),
     UpdateRoom(RoomUpdateTransit),
     UpdateMultipleRooms(MultipleRoomsUpdateTransit),
     UserRegistered(User),
-    IsOnline(IsOnlineTransit),
+    CreateSession(CreateSessionTransit),
+    ResumeSession(ResumeSessionTransit),
+    EndSession(Uuid),
+    RoomDeleted(RoomDeletedTransit),
+    UserRemovedFromRoom(MemberRemovedTransit),
+    /// the user changed their own presence, it goes out to every connected client
+    PresenceChanged(Presence),
+    ResolvePresence(PresenceListTransit),
+    /// answer of a receiver, it goes to the client task of the sender in `to`
+    TransferControl(TransferControl),
+    /// goes to the client task of the sender in `to`, like `TransferControl`
+    StreamProgress(StreamProgress),
+    ParkStreams(ParkedStreams),
+    ResumeStream(ResumeStreamTransit),
+}
+
+/// streams a sender had under way when its connection dropped, kept for
+/// `STREAM_RESUME_SECS` so that it can resume them
+#[derive(Debug)]
+pub struct ParkedStreams {
+    pub user_id: Uuid,
+    pub streams: HashMap<Uuid, OutgoingStream>,
+}
+
+/// `None` unless a stream of the user with that id and hash is parked
+#[derive(Debug)]
+pub struct ResumeStreamTransit {
+    pub ack: oneshot::Sender<Option<OutgoingStream>>,
+    pub user_id: Uuid,
+    pub stream_id: Uuid,
+    pub sha256: String,
+}
+
+#[derive(Debug)]
+pub struct RoomDeletedTransit {
+    pub room_id: Uuid,
+    pub user_ids: Vec<Uuid>,
+}
+
+/// `user_ids` are the members left in the room, the removed user is the target of the notice
+#[derive(Debug)]
+pub struct MemberRemovedTransit {
+    pub notice: ModerationNotice,
+    pub user_ids: Vec<Uuid>,
 }
 
 #[derive(Debug)]
@@ -50,10 +98,31 @@ pub struct MultipleRoomsUpdateTransit {
     pub rooms: Vec<RoomData>,
 }
 
+/// stored presence turned into what `viewer` gets to see of it
 #[derive(Debug)]
-pub struct IsOnlineTransit {
-    pub ack: oneshot::Sender<bool>,
-    pub username: String,
+pub struct PresenceListTransit {
+    pub tx_ack: oneshot::Sender<Vec<Presence>>,
+    pub viewer: Uuid,
+    pub presence: Vec<Presence>,
+}
+
+/// `conn_id` tells a dropped connection apart from the one that replaced it
+#[derive(Debug)]
+pub struct ClientDroppedTransit {
+    pub user_id: Uuid,
+    pub conn_id: Uuid,
+}
+
+#[derive(Debug)]
+pub struct CreateSessionTransit {
+    pub ack: oneshot::Sender<Uuid>,
+    pub user: User,
+}
+
+#[derive(Debug)]
+pub struct ResumeSessionTransit {
+    pub ack: oneshot::Sender<Option<User>>,
+    pub token: Uuid,
 }
 
 #[derive(Debug)]
@@ -66,6 +135,48 @@ pub struct RoomChannelTxTransit {
 pub enum ManagerClientMsg {
     EstablishDirectComm(DirectChannelTxTransit),
     GetRoomTransmitter(GetRoomTxTransit),
+    RoomDeleted(Uuid),
+    /// kicked or banned, the room channel has to be dropped
+    RemovedFromRoom(ModerationNotice),
+    /// another member was kicked or banned, files offered to the room stop going to them
+    MemberRemoved(ModerationNotice),
+    /// the same user connected again, this connection gets closed
+    Replaced,
+    PresenceChanged(Presence),
+    TransferControl(TransferControl),
+    StreamProgress(StreamProgress),
+    /// a parked stream this client received was never resumed
+    StreamFailed(StreamFailure),
+}
+
+/// file offered by this client, its chunks only go to the receivers that accepted it
+/// and to the server's own copy
+#[derive(Debug)]
+pub struct OutgoingStream {
+    pub to: Channel,
+    /// the offer, it becomes the record of the stored copy
+    pub file: FileMetadata,
+    /// `None` when the file is not kept, over the quota of the sender or failed to write
+    pub upload: Option<Upload>,
+    pub receivers: HashMap<Uuid, StreamReceiver>,
+    /// set with the first chunk, receivers accepting later would miss the start of the file
+    pub started: bool,
+    /// sequence number of the next chunk
+    pub next_seq: u64,
+    /// bytes of the file that arrived so far, chunks sent again after a resume are dropped
+    pub offset: u64,
+    /// chunks before this one may be sent, the slowest receiver holds it back
+    pub credit: u64,
+    /// since when the sender used up its credit, receivers holding it back for
+    /// `STREAM_STALL_SECS` are dropped
+    pub blocked_since: Option<Instant>,
+}
+
+/// receiver that accepted a file, `seq` is the first chunk it has not written yet
+#[derive(Debug)]
+pub struct StreamReceiver {
+    pub user: User,
+    pub seq: u64,
 }
 
 pub struct GetRoomTxTransit {
@@ -90,23 +201,42 @@ pub struct OneShotChannel<T> {
 #[derive(Debug)]
 pub struct Client {
     pub user: User,
+    pub conn_id: Uuid,
     pub tx: mpsc::Sender<ManagerClientMsg>,
+    pub presence: PresenceUpdate,
 }
 
 pub enum ClientTaskResult {
     Close,
     Logout,
+    Replaced,
 }
 
 #[derive(Debug)]
 pub enum ClientPersistenceMsg {
     GetUserData(UserDataTransit),
     UserJoinedRoom(UserRoomData),
-    UserLeftRoom(UserRoomData),
+    UserLeftRoom(LeaveRoomTransit),
+    DeleteRoom(DeleteRoomTransit),
+    ModerateRoom(ModerationTransit),
+    SetRoomPassword(RoomPasswordTransit),
     Register(RegisterDataTransit),
     Authenticate(AuthTransit),
     CreateRoom(CreateRoomServerTransit),
     JoinRoom(JoinRoomServerTransit),
+    StoreMessage(StoreMessageTransit),
+    ChangeMessage(MsgChangeTransit),
+    GetHistory(HistoryTransit),
+    ResolveReply(ReplyTransit),
+    GetThread(ThreadTransit),
+    MarkRead(ReadMarkerTransit),
+    SetPresence(StoredPresence),
+    RemoveDeliveries(DeliveredTransit),
+    StoreFile(StoredFile),
+    GetFiles(FileListTransit),
+    GetFile(FileTransit),
+    GetFileSpace(FileSpaceTransit),
+    IsRoomMember(RoomMemberTransit),
 }
 
 // pub type CreateRoomRes = Result<RoomData, String>;
@@ -134,6 +264,101 @@ pub struct DbRoom {
     pub name: String,
     pub user_ids: Vec<Bson>,
     pub pwd: Option<String>,
+    #[serde(default)]
+    pub owner_id: Option<Bson>,
+    #[serde(default)]
+    pub admin_ids: Vec<Bson>,
+    #[serde(default)]
+    pub moderator_ids: Vec<Bson>,
+    #[serde(default)]
+    pub banned_ids: Vec<Bson>,
+}
+
+#[derive(Debug)]
+pub struct StoreMessageTransit {
+    pub id: Uuid,
+    pub sent_at: i64,
+    /// direct message the recipient could not get, queued for their next login
+    pub pending: bool,
+    pub from: User,
+    pub to: Channel,
+    pub msg: ChannelMsg,
+}
+
+#[derive(Debug)]
+pub struct HistoryTransit {
+    pub tx: oneshot::Sender<HistoryPage>,
+    pub user: User,
+    pub channel: Channel,
+    pub before: Option<Uuid>,
+    pub limit: u32,
+}
+
+/// answered with `msg` once its `reply_to` and `thread` point at messages of the same conversation
+#[derive(Debug)]
+pub struct ReplyTransit {
+    pub tx: oneshot::Sender<Result<TextMsg, String>>,
+    pub user: User,
+    pub msg: TextMsg,
+}
+
+/// answered with whether the marker moved
+#[derive(Debug)]
+pub struct ReadMarkerTransit {
+    pub tx: oneshot::Sender<bool>,
+    pub user: User,
+    pub marker: ReadMarker,
+}
+
+#[derive(Debug)]
+pub struct ThreadTransit {
+    pub tx: oneshot::Sender<ThreadRes>,
+    pub user: User,
+    pub id: Uuid,
+}
+
+/// direct messages have `to_user_id` set, room messages `room_id`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DbMessage {
+    pub id: Bson,
+    pub from_id: Bson,
+    pub room_id: Option<Bson>,
+    pub to_user_id: Option<Bson>,
+    pub created_at: DateTime,
+    #[serde(default)]
+    pub thread_id: Option<Bson>,
+    pub msg: ChannelMsg,
+}
+
+/// empty for rooms the user is not a member of
+#[derive(Debug)]
+pub struct FileListTransit {
+    pub tx: oneshot::Sender<FileList>,
+    pub user: User,
+    pub channel: Channel,
+}
+
+/// answered with the file once the user is part of the conversation it was sent to
+#[derive(Debug)]
+pub struct FileTransit {
+    pub tx: oneshot::Sender<Result<StoredFile, String>>,
+    pub user: User,
+    pub id: Uuid,
+}
+
+/// answered with the bytes the user keeps stored
+#[derive(Debug)]
+pub struct FileSpaceTransit {
+    pub tx: oneshot::Sender<u64>,
+    pub user_id: Uuid,
+}
+
+/// answered with whether the user is a member of the room
+#[derive(Debug)]
+pub struct RoomMemberTransit {
+    pub tx: oneshot::Sender<bool>,
+    pub room_id: Uuid,
+    pub user_id: Uuid,
 }
 
 #[derive(Debug)]
@@ -148,6 +373,63 @@ pub struct RegisterDataTransit {
     pub data: RegisterData,
 }
 
+#[derive(Debug)]
+pub struct LeaveRoomTransit {
+    pub tx: oneshot::Sender<RoomRemovalRes>,
+    pub user: User,
+    pub room_id: Uuid,
+}
+
+/// answered with ids of the users that were members of the deleted room
+#[derive(Debug)]
+pub struct DeleteRoomTransit {
+    pub tx: oneshot::Sender<Result<Vec<Uuid>, String>>,
+    pub user: User,
+    pub room_id: Uuid,
+}
+
+#[derive(Debug)]
+pub struct RoomPasswordTransit {
+    pub tx: oneshot::Sender<RoomPasswordRes>,
+    pub user: User,
+    pub room_id: Uuid,
+    pub room_pwd: Option<String>,
+}
+
+/// the notice with the ids of the users still in the room
+pub type ModerationPersistenceRes = Result<(ModerationNotice, Vec<Uuid>), String>;
+
+#[derive(Debug)]
+pub struct ModerationTransit {
+    pub tx: oneshot::Sender<ModerationPersistenceRes>,
+    pub user: User,
+    pub target: RoomMember,
+    pub action: ModerationAction,
+}
+
+#[derive(Debug)]
+pub enum MsgChange {
+    Edit(String),
+    Delete,
+    AddReaction(String),
+    RemoveReaction(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum MsgChanged {
+    Edited(TextMsg),
+    Deleted(MsgDeletion),
+    Reactions(MsgReactions),
+}
+
+#[derive(Debug)]
+pub struct MsgChangeTransit {
+    pub tx: oneshot::Sender<Result<MsgChanged, String>>,
+    pub user: User,
+    pub id: Uuid,
+    pub change: MsgChange,
+}
+
 #[derive(Debug)]
 pub struct UserRoomData {
     pub user: User,
@@ -160,6 +442,118 @@ pub struct UserDataTransit {
     pub user: User,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredUser {
+    pub id: Uuid,
+    pub username: String,
+    pub pwd: String,
+    pub room_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredRoom {
+    pub id: Uuid,
+    pub name: String,
+    pub user_ids: Vec<Uuid>,
+    pub pwd: Option<String>,
+    /// `None` for the public room, which nobody owns
+    pub owner_id: Option<Uuid>,
+    pub admin_ids: Vec<Uuid>,
+    pub moderator_ids: Vec<Uuid>,
+    pub banned_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub id: Uuid,
+    pub from_id: Uuid,
+    pub to: Channel,
+    pub created_at: i64,
+    /// root of the thread, thread replies are left out of the conversation history
+    pub thread_id: Option<Uuid>,
+    pub msg: ChannelMsg,
+}
+
+/// direct message waiting for its recipient to come online
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingDelivery {
+    pub msg_id: Uuid,
+    pub from_id: Uuid,
+    pub to_id: Uuid,
+}
+
+/// queued direct messages that went out to their recipient with the init data
+#[derive(Debug)]
+pub struct DeliveredTransit {
+    pub to_id: Uuid,
+    pub msg_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DbDelivery {
+    pub msg_id: Bson,
+    pub from_id: Bson,
+    pub to_id: Bson,
+}
+
+/// how far `user_id` read `channel`, `created_at` is the one of message `msg_id`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredReadMarker {
+    pub user_id: Uuid,
+    pub channel: Channel,
+    pub msg_id: Uuid,
+    pub created_at: i64,
+}
+
+/// room markers have `room_id` set, direct ones `to_user_id`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DbReadMarker {
+    pub user_id: Bson,
+    pub room_id: Option<Bson>,
+    pub to_user_id: Option<Bson>,
+    pub msg_id: Bson,
+    pub created_at: DateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredPresence {
+    pub user_id: Uuid,
+    pub status: PresenceStatus,
+    pub text: String,
+    pub last_seen: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DbPresence {
+    pub user_id: Bson,
+    pub status: PresenceStatus,
+    pub text: String,
+    pub last_seen: Option<DateTime>,
+}
+
+/// file uploaded by `from_id`, its bytes are in the file store under `entry.sha256`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredFile {
+    pub id: Uuid,
+    pub from_id: Uuid,
+    pub to: Channel,
+    pub size: u64,
+    pub created_at: i64,
+    pub entry: FileEntry,
+}
+
+/// direct files have `to_user_id` set, room files `room_id`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DbFile {
+    pub id: Bson,
+    pub from_id: Bson,
+    pub room_id: Option<Bson>,
+    pub to_user_id: Option<Bson>,
+    pub size: i64,
+    pub created_at: DateTime,
+    pub entry: FileEntry,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DbUser {
     pub username: String,
@@ -7,4 +7,24 @@ pub const PASSWORD_RE_PATTERN: &str = r"^[A-Za-z\d!@#$%^&*()_+]{8,32}$";
 pub const USERNAME_ERROR_MSG: &str = "Username must start with a letter, not contain special characters ouside of \"_\" and have length between 7 to 29";
 pub const PASSWORD_ERROR_MSG: &str = "Password must contain at least one lowercase and uppercase letter, digit and have length between 8 to 32";
 
+/// chunk size of file transfers until the server answered the size the client proposed
 pub const TCP_CHUNK_BUFFER_SIZE: usize = 8192;
+/// the server clamps proposed chunk sizes to these bounds, in bytes
+pub const CHUNK_SIZE_MIN: usize = 1024;
+pub const CHUNK_SIZE_MAX: usize = 64 * 1024;
+/// chunks a sender may be ahead of the slowest receiver of its stream
+pub const STREAM_WINDOW: u64 = 8;
+/// receivers report their progress after this many chunks
+pub const STREAM_PROGRESS_INTERVAL: u64 = 2;
+
+/// comma separated MIME types that may be sent, `type/*` matches the whole type
+pub const FILE_TYPES_ALLOW_ENV: &str = "FILE_TYPES_ALLOW";
+/// comma separated MIME types that are refused even when allowed
+pub const FILE_TYPES_DENY_ENV: &str = "FILE_TYPES_DENY";
+pub const FILE_TYPES_ALLOW_DEFAULT: &str = "text/*,image/*,application/pdf";
+pub const FILE_TYPES_DENY_DEFAULT: &str = "text/html,image/svg+xml";
+
+/// unread messages are counted up to this, the contacts list shows more as "99+"
+pub const UNREAD_COUNT_MAX: u32 = 100;
+/// longest custom status accepted, in chars
+pub const MAX_STATUS_TEXT_CHARS: usize = 60;
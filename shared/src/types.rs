@@ -3,7 +3,9 @@ use std::collections::VecDeque;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::config::TCP_CHUNK_BUFFER_SIZE;
+use crate::config::{
+    FILE_TYPES_ALLOW_DEFAULT, FILE_TYPES_ALLOW_ENV, FILE_TYPES_DENY_DEFAULT, FILE_TYPES_DENY_ENV,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct User {
@@ -17,27 +19,238 @@ pub enum Channel {
     User(Uuid),
 }
 
+/// `id` and `sent_at` are assigned by the server, the id the client sends is answered in `MsgAck`
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TextMsg {
+    pub id: Uuid,
     pub text: String,
     pub from: User,
     pub to: Channel,
+    pub edited: bool,
+    /// server time in milliseconds since the unix epoch, 0 until the server stamped it
+    pub sent_at: i64,
+    /// only changed by the server, through `AddReaction` and `RemoveReaction`
+    pub reactions: Vec<Reaction>,
+    /// quoted message, the server fills in `from` and `quote`
+    pub reply_to: Option<ReplyRef>,
+    /// root of the thread this message was posted in, thread replies stay out of the
+    /// conversation itself and are fetched with `FetchThread`
+    pub thread: Option<Uuid>,
+    /// number of replies in the thread started by this message, only changed by the server
+    pub thread_len: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplyRef {
+    pub id: Uuid,
+    pub from: User,
+    /// start of the quoted text
+    pub quote: String,
 }
 
+/// everyone who reacted with `emoji`, in the order they did
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Reaction {
+    pub emoji: String,
+    pub users: Vec<User>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReactionUpdate {
+    pub id: Uuid,
+    pub emoji: String,
+}
+
+/// the reactions of message `id` after one was added or removed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MsgReactions {
+    pub id: Uuid,
+    pub from: User,
+    pub to: Channel,
+    pub reactions: Vec<Reaction>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MsgEdit {
+    pub id: Uuid,
+    pub text: String,
+}
+
+/// tombstone left in place of a deleted message, `by` differs from `from` when a moderator deleted it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MsgDeletion {
+    pub id: Uuid,
+    pub from: User,
+    pub to: Channel,
+    pub by: User,
+    /// root of the thread the message was posted in
+    pub thread: Option<Uuid>,
+}
+
+/// id of the edited or deleted message
+pub type MsgUpdateRes = Result<Uuid, String>;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Chunk {
     pub from: User,
+    /// at most the negotiated chunk size, only the last chunk may be shorter
     #[serde(with = "serde_bytes")]
-    pub data: [u8; TCP_CHUNK_BUFFER_SIZE],
+    pub data: Vec<u8>,
+    pub to: Channel,
+    pub stream_id: Uuid,
+    /// position of the chunk in its stream, starting at 0
+    pub seq: u64,
+    /// position of the first byte of `data` in the file
+    pub offset: u64,
+    /// set on the final chunk of the stream, the receiver checks the hash once it arrives
+    pub last: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TransferAction {
+    Accept,
+    Decline,
+    Pause,
+    Resume,
+    Cancel,
+}
+
+/// answer to a `FileMetadata` offer or a change to a running transfer. receivers address
+/// the sender, the sender addresses the channel of the offer. `from` is set by the server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransferControl {
+    pub stream_id: Uuid,
+    pub action: TransferAction,
+    pub from: User,
+    pub to: Channel,
+}
+
+/// a receiver wrote every chunk of the stream before `seq`, reported to the sender `to`.
+/// `from` is set by the server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamProgress {
+    pub stream_id: Uuid,
+    pub seq: u64,
+    pub from: User,
+    pub to: Uuid,
+}
+
+/// the sender may send every chunk of the stream before `seq`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct StreamCredit {
+    pub stream_id: Uuid,
+    pub seq: u64,
+}
+
+/// a receiver was dropped from a stream, its partial file can not be completed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamFailure {
+    pub stream_id: Uuid,
+    pub reason: String,
+}
+
+/// where a stream goes on after it broke off, chunk `seq` starts at byte `offset`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ResumePoint {
+    pub seq: u64,
+    pub offset: u64,
+}
+
+/// picks up a transfer that broke off with the connection. a receiver names the stored
+/// file the rest is read from and `point` is how far it got, a sender learns from the
+/// answer how far the server got
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResumeRequest {
+    pub stream_id: Uuid,
+    /// hex encoded SHA-256 of the whole file, a different file is not continued
+    pub sha256: String,
+    /// `None` for a file this client sends
+    pub file_id: Option<Uuid>,
+    pub point: ResumePoint,
+}
+
+/// the chunks from `point` on follow an `Ok`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResumeResponse {
+    pub stream_id: Uuid,
+    pub res: Result<ResumePoint, String>,
+}
+
+/// file kept by the server, `id` is the stream it was uploaded with.
+/// `stored_at` is in milliseconds since the unix epoch
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileEntry {
+    pub id: Uuid,
+    pub filename: String,
+    pub size: u64,
+    pub mime: String,
+    /// hex encoded SHA-256 of the whole file, the server keeps one copy per hash
+    pub sha256: String,
+    pub sender: User,
     pub to: Channel,
+    pub stored_at: i64,
+}
+
+/// files of a conversation, newest first. empty for rooms the user is not a member of
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileList {
+    pub channel: Channel,
+    pub files: Vec<FileEntry>,
+}
+
+/// download of a stored file, its chunks come from the server with `stream_id`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileRequest {
+    pub file_id: Uuid,
+    pub stream_id: Uuid,
+}
+
+/// sent before the first chunk of a download, or instead of them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileResponse {
     pub stream_id: Uuid,
+    pub res: Result<FileEntry, String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ImgRender {
+    pub id: Uuid,
+    /// ascii art of the image, its chars may carry ANSI truecolor codes
     pub cache: String,
+    /// the picture as png, scaled down, for terminals that draw graphics. `cache` is the
+    /// ascii rendering shown everywhere else
+    #[serde(with = "serde_bytes")]
+    pub image: Option<Vec<u8>>,
     pub from: User,
     pub to: Channel,
+    pub sent_at: i64,
+    /// only changed by the server, through `AddReaction` and `RemoveReaction`
+    pub reactions: Vec<Reaction>,
+}
+
+/// answers every `Text` and `ASCII`, `local_id` is the id the client sent the message with
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MsgAck {
+    pub local_id: Uuid,
+    pub to: Channel,
+    pub res: AckRes,
+}
+
+pub type AckRes = Result<MsgStamp, String>;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MsgStamp {
+    pub id: Uuid,
+    pub sent_at: i64,
+    /// the recipient is offline, the message is handed over on their next login
+    pub pending: bool,
+}
+
+/// sent to the author once queued direct messages reached `to`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DeliveryNotice {
+    pub to: Channel,
+    pub ids: Vec<Uuid>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -49,6 +262,45 @@ pub enum ClientServerMsg {
     Logout,
     CreateRoom(RoomUpdateTransit),
     JoinRoom(RoomUpdateTransit),
+    /// owner only, `room_password: None` removes the password
+    SetRoomPassword(RoomPasswordUpdate),
+    FetchHistory(HistoryRequest),
+    LeaveRoom(Uuid),
+    DeleteRoom(Uuid),
+    KickUser(RoomMember),
+    BanUser(RoomMember),
+    UnbanUser(RoomMember),
+    PromoteUser(RoomMember),
+    DemoteUser(RoomMember),
+    /// the author, or a moderator of the room the message was sent to
+    EditMessage(MsgEdit),
+    DeleteMessage(Uuid),
+    AddReaction(ReactionUpdate),
+    RemoveReaction(ReactionUpdate),
+    /// id of the root message
+    FetchThread(Uuid),
+    MarkRead(ReadMarker),
+    /// sent while the user edits a message, never stored
+    Typing(Channel),
+    SetPresence(PresenceUpdate),
+    /// chunk size the client would like to send files in, answered with `ChunkSize`
+    ProposeChunkSize(u32),
+    TransferControl(TransferControl),
+    /// sent by receivers every `STREAM_PROGRESS_INTERVAL` chunks
+    StreamProgress(StreamProgress),
+    /// files stored for the channel, answered with `Files`
+    FetchFiles(Channel),
+    /// answered with `FileResponse`, the chunks of the file follow it
+    FetchFile(FileRequest),
+    /// after a reconnect, answered with `TransferResumed`
+    ResumeTransfer(ResumeRequest),
+}
+
+/// target of a moderation action, `user_id` does not have to be a member for `UnbanUser`
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct RoomMember {
+    pub room_id: Uuid,
+    pub user_id: Uuid,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -57,8 +309,73 @@ pub struct RoomUpdateTransit {
     pub room_password: Option<String>,
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RoomPasswordUpdate {
+    pub room_id: Uuid,
+    pub room_password: Option<String>,
+}
+
 pub type CreateRoomRes = Result<RoomData, String>;
 
+/// id of the room the user is no longer a member of
+pub type RoomRemovalRes = Result<Uuid, String>;
+
+/// id of the room whose password was set, changed or removed
+pub type RoomPasswordRes = Result<Uuid, String>;
+
+/// ordered by rank, a role can only act on the roles below it
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RoomRole {
+    Member,
+    Moderator,
+    Admin,
+    Owner,
+}
+
+/// members not listed in `admin_ids` or `moderator_ids` are plain members
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct RoomRoles {
+    pub owner_id: Option<Uuid>,
+    pub admin_ids: Vec<Uuid>,
+    pub moderator_ids: Vec<Uuid>,
+    pub banned: Vec<User>,
+}
+
+impl RoomRoles {
+    pub fn role_of(&self, user_id: Uuid) -> RoomRole {
+        if self.owner_id == Some(user_id) {
+            RoomRole::Owner
+        } else if self.admin_ids.contains(&user_id) {
+            RoomRole::Admin
+        } else if self.moderator_ids.contains(&user_id) {
+            RoomRole::Moderator
+        } else {
+            RoomRole::Member
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum ModerationAction {
+    Kick,
+    Ban,
+    Unban,
+    Promote,
+    Demote,
+}
+
+/// `roles` are the roles of the room after the action
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ModerationNotice {
+    pub room_id: Uuid,
+    pub target: User,
+    pub by: User,
+    pub action: ModerationAction,
+    pub roles: RoomRoles,
+}
+
+pub type ModerationRes = Result<ModerationNotice, String>;
+
 #[derive(Deserialize, Serialize, Debug)]
 pub enum ServerClientMsg {
     Text(TextMsg),
@@ -67,17 +384,62 @@ pub enum ServerClientMsg {
     FileMetadata(FileMetadata),
     UserJoinedRoom(JoinRoomNotification),
     UserLeftRoom(LeaveRoomNotification),
-    Auth(AuthResponse),
+    Auth(SessionResponse),
     Register(RegisterResponse),
     Init(UserInitData),
     UserConnected(User),
     UserDisconnected(User),
     CreateRoomResponse(CreateRoomRes),
     JoinRoomResponse(RoomActionRes),
+    History(HistoryPage),
+    MsgRefused(MsgRefusal),
+    LeaveRoomResponse(RoomRemovalRes),
+    DeleteRoomResponse(RoomRemovalRes),
+    RoomDeleted(Uuid),
+    ModerationResponse(ModerationRes),
+    SetRoomPasswordResponse(RoomPasswordRes),
+    RoomModerated(ModerationNotice),
+    MessageEdited(TextMsg),
+    MessageDeleted(MsgDeletion),
+    MsgUpdateResponse(MsgUpdateRes),
+    Ack(MsgAck),
+    Delivered(DeliveryNotice),
+    Seen(ReadReceipt),
+    ReactionsChanged(MsgReactions),
+    Thread(ThreadRes),
+    Typing(TypingNotice),
+    PresenceChanged(Presence),
+    /// chunk size accepted for the files this client sends, larger chunks are refused
+    ChunkSize(u32),
+    /// a `Cancel` from the sender to itself ends a stream nobody receives any more
+    TransferControl(TransferControl),
+    /// no chunk may be sent before the first credit, it comes with the first accept
+    StreamCredit(StreamCredit),
+    StreamFailed(StreamFailure),
+    /// messages of the channel were missed, what the client shows of it has to be reloaded
+    Resync(Channel),
+    Files(FileList),
+    FileResponse(FileResponse),
+    TransferResumed(ResumeResponse),
 }
 
 pub type RoomActionRes = Result<RoomData, String>;
 
+/// sent back to the author when the server drops a file instead of forwarding it,
+/// text and images are answered with a failed `MsgAck`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MsgRefusal {
+    pub to: Channel,
+    pub msg: RefusedMsg,
+    pub reason: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum RefusedMsg {
+    FileMetadata { stream_id: Uuid, filename: String },
+    FileChunk { stream_id: Uuid },
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct JoinRoomNotification {
     pub user: User,
@@ -90,6 +452,41 @@ pub struct LeaveRoomNotification {
     pub room_id: Uuid,
 }
 
+/// `before` is the id of the oldest loaded message as returned in `HistoryPage::next_before`,
+/// `None` loads the most recent messages
+#[derive(Deserialize, Serialize, Debug)]
+pub struct HistoryRequest {
+    pub channel: Channel,
+    pub before: Option<Uuid>,
+    pub limit: u32,
+}
+
+/// messages are ordered newest first, `next_before` is `None` once there is nothing older to load
+#[derive(Deserialize, Serialize, Debug)]
+pub struct HistoryPage {
+    pub channel: Channel,
+    pub messages: Vec<ChannelMsg>,
+    pub next_before: Option<Uuid>,
+}
+
+/// `replies` are ordered oldest first, deleted ones are left as tombstones
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ThreadPage {
+    pub id: Uuid,
+    pub root: ChannelMsg,
+    pub replies: Vec<ChannelMsg>,
+}
+
+pub type ThreadRes = Result<ThreadPage, String>;
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum HistoryCursor {
+    Unloaded,
+    Pending,
+    Before(Uuid),
+    Exhausted,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct TuiRoom {
     pub id: Uuid,
@@ -97,11 +494,79 @@ pub struct TuiRoom {
     pub messages: VecDeque<ChannelMsg>,
     pub users: Vec<User>,
     pub users_online: Vec<User>,
+    pub history: HistoryCursor,
+    pub roles: RoomRoles,
+    pub unread: u32,
+    /// newest message this client marked read
+    pub last_read: Option<Uuid>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct UserInitData {
     pub rooms: Vec<RoomData>,
+    /// direct messages sent while the user was offline, oldest first
+    pub queued_messages: Vec<ChannelMsg>,
+    /// own direct messages still waiting for their recipient
+    pub undelivered: Vec<Uuid>,
+    /// channels with messages the user has not read, counts stop at `UNREAD_COUNT_MAX`
+    pub unread: Vec<UnreadCount>,
+    /// how far the other side read each direct conversation, `channel` is the reader
+    pub seen: Vec<ReadMarker>,
+    /// members of the public room that set a status or were seen before, the user included
+    pub presence: Vec<Presence>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct UnreadCount {
+    pub channel: Channel,
+    pub count: u32,
+}
+
+/// the user read `channel` up to message `id`, markers only move forward
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ReadMarker {
+    pub channel: Channel,
+    pub id: Uuid,
+}
+
+/// sent to the other side of a direct conversation once `by` read it up to `id`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ReadReceipt {
+    pub by: User,
+    pub id: Uuid,
+}
+
+/// `Invisible` is only known to the user, everyone else sees `Offline`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum PresenceStatus {
+    #[default]
+    Online,
+    Away,
+    DoNotDisturb,
+    Invisible,
+    Offline,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PresenceUpdate {
+    pub status: PresenceStatus,
+    pub text: String,
+}
+
+/// `last_seen` is in milliseconds since the unix epoch, kept for users that are offline
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Presence {
+    pub user: User,
+    pub status: PresenceStatus,
+    pub text: String,
+    pub last_seen: Option<i64>,
+}
+
+/// `from` is typing in `to`, for direct messages `to` is the recipient
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TypingNotice {
+    pub from: User,
+    pub to: Channel,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -110,21 +575,79 @@ pub struct RoomData {
     pub name: String,
     pub users: Vec<User>,
     pub users_online: Vec<User>,
+    pub roles: RoomRoles,
 }
 
+/// offer of a file, its chunks only follow once the receiver accepted it
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct FileMetadata {
     pub filename: String,
     pub stream_id: Uuid,
     pub to: Channel,
     pub from: Channel,
+    /// set by the server, receivers answer the offer to this user
+    pub sender: User,
     pub size: u64,
+    /// guessed from the file name, the server refuses a type that does not match it
+    pub mime: String,
+    /// hex encoded SHA-256 of the whole file
+    pub sha256: String,
+}
+
+/// file types that may be transferred, checked by the sending client and the server.
+/// denied types win over allowed ones
+#[derive(Debug, Clone)]
+pub struct FileTypePolicy {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl FileTypePolicy {
+    /// reads `FILE_TYPES_ALLOW` and `FILE_TYPES_DENY`, unset ones fall back to the defaults
+    pub fn from_env() -> Self {
+        let list = |env: &str, default: &str| {
+            std::env::var(env)
+                .unwrap_or(String::from(default))
+                .split(',')
+                .map(|t| t.trim().to_lowercase())
+                .filter(|t| !t.is_empty())
+                .collect()
+        };
+
+        Self {
+            allow: list(FILE_TYPES_ALLOW_ENV, FILE_TYPES_ALLOW_DEFAULT),
+            deny: list(FILE_TYPES_DENY_ENV, FILE_TYPES_DENY_DEFAULT),
+        }
+    }
+
+    pub fn permits(&self, mime: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix("/*") {
+            Some(kind) => mime.split('/').next() == Some(kind),
+            None => pattern == mime,
+        };
+
+        self.allow.iter().any(matches) && !self.deny.iter().any(matches)
+    }
+
+    /// unknown extensions are `application/octet-stream`
+    pub fn mime_of(filename: &str) -> String {
+        mime_guess::from_path(filename)
+            .first_or_octet_stream()
+            .essence_str()
+            .to_string()
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct DirectChannel {
     pub user: User,
     pub messages: VecDeque<ChannelMsg>,
+    pub history: HistoryCursor,
+    pub unread: u32,
+    /// newest message this client marked read
+    pub last_read: Option<Uuid>,
+    /// newest message `user` has read
+    pub seen: Option<Uuid>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -132,6 +655,23 @@ pub enum ChannelMsg {
     TextMsg(TextMsg),
     JoinNotification(User),
     Img(ImgRender),
+    Refused(MsgRefusal),
+    Notice(String),
+    Deleted(MsgDeletion),
+}
+
+impl ChannelMsg {
+    /// author and id of a message the server stores, `None` for notices that are never stored
+    pub fn author_and_id(&self) -> Option<(Uuid, Uuid)> {
+        match self {
+            ChannelMsg::TextMsg(text) => Some((text.from.id, text.id)),
+            ChannelMsg::Img(img) => Some((img.from.id, img.id)),
+            ChannelMsg::Deleted(deletion) => Some((deletion.from.id, deletion.id)),
+            ChannelMsg::JoinNotification(_) | ChannelMsg::Refused(_) | ChannelMsg::Notice(_) => {
+                None
+            }
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -142,12 +682,22 @@ pub struct AuthData {
 
 pub type AuthResponse = Result<User, String>;
 
+/// `token` resumes the session with `ClientServerAuthMsg::Resume` after the connection drops
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AuthSession {
+    pub user: User,
+    pub token: Uuid,
+}
+
+pub type SessionResponse = Result<AuthSession, String>;
+
 pub type RegisterResponse = Result<User, String>;
 
 #[derive(Deserialize, Serialize, Debug)]
 pub enum ClientServerAuthMsg {
     Login(AuthData),
     Register(RegisterData),
+    Resume(Uuid),
 }
 
 #[derive(Deserialize, Serialize, Debug)]